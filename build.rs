@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Builds do not need a system protoc
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/routing.proto")?;
+    tonic_build::compile_protos("proto/vector_tile.proto")?;
+    Ok(())
+}
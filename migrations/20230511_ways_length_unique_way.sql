@@ -0,0 +1 @@
+CREATE UNIQUE INDEX IF NOT EXISTS ways_length_ways_id_idx ON public.ways_length (ways_id);
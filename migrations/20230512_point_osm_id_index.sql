@@ -0,0 +1 @@
+create index if not exists planet_osm_point_osm_id_idx on planet_osm_point (osm_id);
@@ -0,0 +1,4 @@
+create table if not exists way_popularity (
+    way_id int8 primary key,
+    traces int4 not null
+);
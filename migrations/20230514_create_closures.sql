@@ -0,0 +1,8 @@
+create table if not exists closures (
+    id serial primary key,
+    way_id int8,
+    area geometry(Polygon, 4326),
+    reason text,
+    until timestamptz not null
+);
+create index if not exists closures_until_idx on closures (until);
@@ -0,0 +1,4 @@
+create table if not exists node_components (
+    node_id int8 primary key,
+    component int8 not null
+);
@@ -0,0 +1,9 @@
+create table if not exists collisions (
+    id serial primary key,
+    way geometry(Point, 3857) not null
+);
+
+create table if not exists way_collisions (
+    way_id int8 primary key,
+    collisions_per_km int4 not null
+);
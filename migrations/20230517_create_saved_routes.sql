@@ -0,0 +1,6 @@
+create table if not exists saved_routes (
+    id text primary key,
+    request text not null,
+    body text not null,
+    created_at timestamptz not null default now()
+);
@@ -0,0 +1,7 @@
+-- Where an interrupted computation of the ways lengths resumes, a single row
+create table if not exists ways_length_progress (
+    id boolean primary key default true check (id),
+    last_way_id int8 not null,
+    measured int8 not null,
+    updated_at timestamptz not null default now()
+);
@@ -0,0 +1,5 @@
+-- When each maintenance job last ran, so that the replicas run it once per interval
+create table if not exists job_runs (
+    job text primary key,
+    ran_at timestamptz not null
+);
@@ -0,0 +1,11 @@
+-- The edges of the graph, from a node along a way up to the next junction, written by
+-- the import so that loading a node is a single lookup
+create table if not exists node_adjacency (
+    node_id int8 not null,
+    neighbor_id int8 not null,
+    way_id int8 not null,
+    distance int4 not null,
+    intermediate_nodes int8[]
+);
+create index if not exists node_adjacency_node_id_idx on node_adjacency (node_id);
+create index if not exists node_adjacency_way_id_idx on node_adjacency (way_id);
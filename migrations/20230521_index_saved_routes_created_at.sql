@@ -0,0 +1,2 @@
+-- For deleting the routes saved before the retention
+create index if not exists saved_routes_created_at_idx on saved_routes (created_at);
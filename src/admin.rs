@@ -0,0 +1,259 @@
+use crate::{
+    config::CONFIG,
+    data::{
+        bbox::BoundingBox,
+        cache::FlushScope,
+        closure::Closure,
+        node::Node,
+        way::{LengthProgress, Way},
+    },
+    region::Region,
+};
+use actix_web::{
+    delete, dev::Payload, error::ErrorUnauthorized, get, http::header, post, web, FromRequest,
+    HttpRequest, HttpResponse, Responder,
+};
+use futures::future::{ready, Ready};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, sync::Mutex};
+
+/// Extracting this checks the request carries the `ADMIN_TOKEN` bearer token.
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        ready(match (&CONFIG.admin_token, token) {
+            (Some(expected), Some(token)) if expected == token => Ok(Admin),
+            _ => Err(ErrorUnauthorized("Invalid or missing admin token")),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct RegionQuery {
+    /// Restricts the operation to a region, instead of all of them.
+    region: Option<String>,
+}
+
+impl RegionQuery {
+    fn regions(&self) -> Result<Vec<&'static Region>, Box<dyn Error>> {
+        match &self.region {
+            Some(name) => Ok(vec![Region::get(name)?]),
+            None => Ok(Region::all().iter().collect()),
+        }
+    }
+}
+
+#[get("/admin/cache")]
+async fn cache(_: Admin) -> impl Responder {
+    let mut stats = BTreeMap::new();
+    for region in Region::all() {
+        stats.insert(region.name.as_str(), region.cache_stats().await);
+    }
+    HttpResponse::Ok().json(stats)
+}
+
+#[derive(Serialize)]
+struct FlushResponse {
+    flushed: usize,
+}
+
+/// Flushes the whole cache, or only the nodes listed or inside a bounding box:
+/// `{"nodes": [1, 2]}` or `{"bounding_box": {"min_lat": ..., ...}}`.
+#[post("/admin/cache/flush")]
+async fn flush_cache(
+    _: Admin,
+    query: web::Query<RegionQuery>,
+    scope: Option<web::Json<FlushScope>>,
+) -> Result<impl Responder, Box<dyn Error>> {
+    let scope = scope.map(|s| s.into_inner()).unwrap_or_default();
+    let mut flushed = 0;
+    for region in query.regions()? {
+        flushed += region.flush_cache(&scope).await?;
+    }
+    Ok(HttpResponse::Ok().json(FlushResponse { flushed }))
+}
+
+#[derive(Serialize)]
+struct WarmResponse {
+    loaded: usize,
+}
+
+/// Loads every routable node inside a bounding box into the cache, meant to be
+/// called before a freshly deployed server is put into rotation.
+#[post("/admin/cache/warm")]
+async fn warm_cache(
+    _: Admin,
+    query: web::Query<RegionQuery>,
+    bbox: web::Json<BoundingBox>,
+) -> Result<impl Responder, Box<dyn Error>> {
+    let region = match &query.region {
+        Some(name) => Region::get(name)?,
+        None => Region::containing(&[(bbox.min_lat, bbox.min_lng), (bbox.max_lat, bbox.max_lng)])
+            .ok_or("No region covers the bounding box")?,
+    };
+    let loaded = Node::warm_cache(region, &bbox).await?;
+    Ok(HttpResponse::Ok().json(WarmResponse { loaded }))
+}
+
+/// The region of a closure: the requested one, or else the one covering its area,
+/// or the only one.
+fn closure_region(
+    query: &RegionQuery,
+    closure: &Closure,
+) -> Result<&'static Region, Box<dyn Error>> {
+    if let Some(name) = &query.region {
+        return Region::get(name);
+    }
+    match (closure.bounding_box, Region::all()) {
+        (Some(bbox), _) => {
+            Region::containing(&[(bbox.min_lat, bbox.min_lng), (bbox.max_lat, bbox.max_lng)])
+                .ok_or_else(|| "No region covers the bounding box".into())
+        }
+        (None, [region]) => Ok(region),
+        (None, _) => Err("The region of a way closure is required".into()),
+    }
+}
+
+#[derive(Serialize)]
+struct ClosureCreated {
+    id: i32,
+}
+
+/// Closes a way, or the ways crossing a bounding box, until a Unix timestamp:
+/// `{"way_id": 1, "until": 1684000000, "reason": "Street festival"}`.
+#[post("/admin/closures")]
+async fn create_closure(
+    _: Admin,
+    query: web::Query<RegionQuery>,
+    closure: web::Json<Closure>,
+) -> Result<HttpResponse, Box<dyn Error>> {
+    if let Err(e) = closure.validate() {
+        return Ok(HttpResponse::BadRequest().body(e.to_string()));
+    }
+    let region = closure_region(&query, &closure)?;
+    let id = closure.create(region.client().await?).await?;
+    region.reload_closures().await?;
+    Ok(HttpResponse::Created().json(ClosureCreated { id }))
+}
+
+/// The closures not over yet, by region.
+#[get("/admin/closures")]
+async fn closures(
+    _: Admin,
+    query: web::Query<RegionQuery>,
+) -> Result<impl Responder, Box<dyn Error>> {
+    let mut closures = BTreeMap::new();
+    for region in query.regions()? {
+        closures.insert(region.name.as_str(), Closure::active(region.client().await?).await?);
+    }
+    Ok(HttpResponse::Ok().json(closures))
+}
+
+/// Reopens the ways of a closure. The ids are only unique within a region, which
+/// has to be given unless there is only one.
+#[delete("/admin/closures/{id}")]
+async fn delete_closure(
+    _: Admin,
+    query: web::Query<RegionQuery>,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, Box<dyn Error>> {
+    let region = match (&query.region, Region::all()) {
+        (Some(name), _) => Region::get(name)?,
+        (None, [region]) => region,
+        (None, _) => {
+            return Ok(HttpResponse::BadRequest().body("The region of the closure is required"))
+        }
+    };
+    if !Closure::delete(region.client().await?, *id).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    region.reload_closures().await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The computation of the ways lengths of a region.
+#[derive(Clone, Default, Serialize)]
+struct LengthJob {
+    running: bool,
+    #[serde(flatten)]
+    progress: LengthProgress,
+    error: Option<String>,
+}
+
+lazy_static! {
+    /// The lengths computations started since the server started, by region.
+    static ref LENGTH_JOBS: Mutex<BTreeMap<String, LengthJob>> = Mutex::new(BTreeMap::new());
+}
+
+/// Computes the lengths of the ways of `region`, keeping its job up to date. The
+/// cached nodes are flushed after, as they hold the tags of the ways relations.
+async fn compute_lengths(region: &'static Region, restart: bool) {
+    let result: Result<LengthProgress, Box<dyn Error>> = async {
+        let client = region.client().await?;
+        let progress = Way::calculate_all_lengths(client, restart, |progress| {
+            if let Some(job) = LENGTH_JOBS.lock().unwrap().get_mut(&region.name) {
+                job.progress = progress;
+            }
+        })
+        .await?;
+        region.flush_cache(&FlushScope::default()).await?;
+        Ok(progress)
+    }
+    .await;
+    let mut jobs = LENGTH_JOBS.lock().unwrap();
+    let job = jobs.entry(region.name.clone()).or_default();
+    job.running = false;
+    if let Err(e) = result {
+        eprintln!("Computing the ways lengths of the {} region failed: {e}", region.name);
+        job.error = Some(e.to_string());
+    }
+}
+
+#[derive(Deserialize)]
+struct RecomputeQuery {
+    /// Starts over instead of resuming an interrupted computation.
+    #[serde(default)]
+    restart: bool,
+}
+
+/// Starts computing the ways lengths in the background, to run after each data
+/// import. Its progress is at `GET /admin/recompute-lengths`.
+#[post("/admin/recompute-lengths")]
+async fn recompute_lengths(
+    _: Admin,
+    query: web::Query<RegionQuery>,
+    options: web::Query<RecomputeQuery>,
+) -> Result<HttpResponse, Box<dyn Error>> {
+    let regions = query.regions()?;
+    let mut jobs = LENGTH_JOBS.lock().unwrap();
+    if regions
+        .iter()
+        .any(|region| jobs.get(&region.name).is_some_and(|job| job.running))
+    {
+        return Ok(HttpResponse::Conflict().body("The lengths are already being computed"));
+    }
+    for region in regions {
+        let job = LengthJob {
+            running: true,
+            ..Default::default()
+        };
+        jobs.insert(region.name.clone(), job);
+        actix_web::rt::spawn(compute_lengths(region, options.restart));
+    }
+    Ok(HttpResponse::Accepted().json(&*jobs))
+}
+
+/// The lengths computations by region.
+#[get("/admin/recompute-lengths")]
+async fn lengths_progress(_: Admin) -> impl Responder {
+    HttpResponse::Ok().json(&*LENGTH_JOBS.lock().unwrap())
+}
@@ -0,0 +1,109 @@
+//! Alternative routes, found by searching again with the edges of the routes found
+//! before made costlier, and kept when they differ enough from all of them without
+//! being much longer than the route.
+
+use crate::{data::node::Node, region::Region, route::RouteRequest};
+use std::{collections::HashSet, error::Error, sync::Arc};
+
+/// The most alternatives a request may ask for.
+pub const MAX_ALTERNATIVES: u32 = 3;
+
+/// How much costlier the edges of the routes found before are.
+pub const PENALTY: f64 = 1.5;
+
+/// The searches made for each alternative requested, some finding routes too close
+/// to the ones before.
+const SEARCHES_PER_ALTERNATIVE: usize = 2;
+
+const DEFAULT_MAX_DETOUR_FACTOR: f64 = 1.4;
+
+const DEFAULT_MIN_OVERLAP_DIFFERENCE: f64 = 0.3;
+
+/// The edge between the nodes `a` and `b`, either way.
+pub fn edge_key(a: i64, b: i64) -> (i64, i64) {
+    (a.min(b), a.max(b))
+}
+
+/// The edges of `path` with their length, in meters.
+fn edges(path: &[Node]) -> impl Iterator<Item = ((i64, i64), i32)> + '_ {
+    path.windows(2)
+        .map(|pair| (edge_key(pair[0].id, pair[1].id), pair[0].distance(&pair[1])))
+}
+
+fn length(path: &[Node]) -> i32 {
+    edges(path).map(|(_, length)| length).sum()
+}
+
+/// The share of the length of `path` off the `taken` edges.
+fn difference(path: &[Node], taken: &HashSet<(i64, i64)>) -> f64 {
+    let off: i32 = edges(path)
+        .filter(|(edge, _)| !taken.contains(edge))
+        .map(|(_, length)| length)
+        .sum();
+    off as f64 / length(path).max(1) as f64
+}
+
+/// The alternatives to the route along `path` found for `coords`, from the best, the
+/// ones found before the deadline of `coords`.
+pub async fn find(
+    region: &'static Region,
+    coords: &RouteRequest,
+    path: &[Node],
+) -> Result<Vec<Vec<Node>>, Box<dyn Error>> {
+    let count = coords.alternatives.min(MAX_ALTERNATIVES) as usize;
+    let max_length = length(path) as f64
+        * coords.max_detour_factor.unwrap_or(DEFAULT_MAX_DETOUR_FACTOR);
+    let min_difference = coords
+        .min_overlap_difference
+        .unwrap_or(DEFAULT_MIN_OVERLAP_DIFFERENCE);
+    let mut penalized: HashSet<(i64, i64)> = edges(path).map(|(edge, _)| edge).collect();
+    // The edges of the route and of each alternative kept
+    let mut kept = vec![penalized.clone()];
+    let mut alternatives = vec![];
+    for _ in 0..count * SEARCHES_PER_ALTERNATIVE {
+        if alternatives.len() == count {
+            break;
+        }
+        let request = RouteRequest {
+            alternatives: 0,
+            penalized: Arc::new(penalized.clone()),
+            ..coords.clone()
+        };
+        let searched = Node::route_with_progress(region, &request, |_| {}).await;
+        let Some((alternative, _, _)) = searched.ok().filter(|(_, _, stats)| !stats.partial)
+        else {
+            break;
+        };
+        let taken: HashSet<(i64, i64)> = edges(&alternative).map(|(edge, _)| edge).collect();
+        // The same route again, the penalties do not change anything anymore
+        if taken.is_subset(&penalized) {
+            break;
+        }
+        penalized.extend(&taken);
+        if length(&alternative) as f64 > max_length
+            || kept.iter().any(|edges| difference(&alternative, edges) < min_difference)
+        {
+            continue;
+        }
+        kept.push(taken);
+        alternatives.push(alternative);
+    }
+    Ok(alternatives)
+}
+
+#[test]
+fn measures_the_difference_with_the_edges_taken() {
+    let node = |id: i64| Node {
+        id,
+        lat: 455_000_000 + id as i32 * 1000,
+        lon: -736_000_000,
+        adjacent_nodes: vec![],
+        highway: None,
+        elevation: None,
+    };
+    let path: Vec<Node> = [1, 2, 3, 4, 5].map(node).to_vec();
+    assert_eq!(length(&path), length(&path[..3]) * 2);
+    let taken = HashSet::from([edge_key(2, 1), edge_key(3, 2)]);
+    assert!((difference(&path, &taken) - 0.5).abs() < 0.01);
+    assert_eq!(difference(&path, &HashSet::new()), 1.0);
+}
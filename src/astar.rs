@@ -2,8 +2,8 @@
 //! algorithm](https://en.wikipedia.org/wiki/A*_search_algorithm).
 
 use futures::future::BoxFuture;
+use indexmap::map::MutableKeys;
 use indexmap::IndexMap;
-use indexmap::map::Entry::{Occupied, Vacant};
 use num_traits::Zero;
 use rustc_hash::FxHasher;
 use std::cmp::Ordering;
@@ -47,8 +47,11 @@ where
 /// approximation must not be greater than the real cost, or a wrong shortest path may be returned.
 /// - `success` checks whether the goal has been reached. It is not a node as some problems require
 /// a dynamic solution instead of a fixed node.
+/// - `stop` is checked before each expansion, the search gives up when it returns `true`.
 ///
 /// A node will never be included twice in the path as determined by the `Eq` relationship.
+/// When a cheaper way to a node is found, the node kept is replaced by the successor, so
+/// that the nodes may carry how they were reached besides what `Eq` compares.
 ///
 /// The returned path comprises both the start and end node.
 ///
@@ -100,12 +103,13 @@ where
 /// assert_eq!(result.expect("no path found").1, 4);
 /// ```
 #[allow(clippy::missing_panics_doc)]
-pub async fn astar<N, C, FN, IN, FH, FS>(
+pub async fn astar<N, C, FN, IN, FH, FS, FT>(
     start: &N,
     mut successors: FN,
     mut heuristic: FH,
     mut success: FS,
-) -> Option<(Vec<N>, C)>
+    mut stop: FT,
+) -> Option<Outcome<N, C>>
 where
     N: Eq + Hash + Clone,
     C: Zero + Ord + Copy,
@@ -113,6 +117,7 @@ where
     IN: IntoIterator<Item = (N, C)>,
     FH: FnMut(&N) -> C,
     FS: FnMut(&N) -> bool,
+    FT: FnMut() -> bool,
 {
     let mut to_see = BinaryHeap::new();
     to_see.push(SmallestCostHolder {
@@ -122,12 +127,20 @@ where
     });
     let mut parents: FxIndexMap<N, (usize, C)> = FxIndexMap::default();
     parents.insert(start.clone(), (usize::max_value(), Zero::zero()));
+    // The node reached closest to the goal, by the heuristic
+    let mut closest = (0, heuristic(start));
     while let Some(SmallestCostHolder { cost, index, .. }) = to_see.pop() {
         let successors = {
             let (node, &(_, c)) = parents.get_index(index).unwrap(); // Cannot fail
             if success(node) {
                 let path = reverse_path(&parents, |&(p, _)| p, index);
-                return Some((path, cost));
+                return Some(Outcome::Found(path, cost));
+            }
+            if stop() {
+                let (index, _) = closest;
+                let (_, &(_, closest_cost)) = parents.get_index(index).unwrap();
+                let path = reverse_path(&parents, |&(p, _)| p, index);
+                return Some(Outcome::Stopped(path, closest_cost));
             }
             // We may have inserted a node several time into the binary heap if we found
             // a better way to access it. Ensure that we are currently dealing with the
@@ -139,25 +152,22 @@ where
         };
         for (successor, move_cost) in successors {
             let new_cost = cost + move_cost;
-            let h; // heuristic(&successor)
-            let n; // index for successor
-            match parents.entry(successor) {
-                Vacant(e) => {
-                    h = heuristic(e.key());
-                    n = e.index();
-                    e.insert((index, new_cost));
-                }
-                Occupied(mut e) => {
-                    if e.get().1 > new_cost {
-                        h = heuristic(e.key());
-                        n = e.index();
-                        e.insert((index, new_cost));
-                    } else {
+            let n = match parents.get_full_mut2(&successor) {
+                Some((n, node, parent)) => {
+                    if parent.1 <= new_cost {
                         continue;
                     }
+                    *node = successor;
+                    *parent = (index, new_cost);
+                    n
                 }
-            }
+                None => parents.insert_full(successor, (index, new_cost)).0,
+            };
+            let h = heuristic(parents.get_index(n).unwrap().0); // Cannot fail
 
+            if h < closest.1 {
+                closest = (n, h);
+            }
             to_see.push(SmallestCostHolder {
                 estimated_cost: new_cost + h,
                 cost: new_cost,
@@ -168,6 +178,96 @@ where
     None
 }
 
+/// Settles the nodes reachable from `start` by increasing cost, like Dijkstra's algorithm,
+/// until `stop` returns `true` for the next node to expand or there are none left.
+///
+/// - `successors` returns a list of successors for a given node, along with the cost for moving
+///   from the node to the successor, like for `astar`.
+/// - `stop` is checked before each expansion, with the node about to be expanded.
+///
+/// The nodes reached are returned along with the cheapest path found to each one.
+pub async fn dijkstra_partial<N, C, FN, IN, FS>(
+    start: &N,
+    mut successors: FN,
+    mut stop: FS,
+) -> Settled<N, C>
+where
+    N: Eq + Hash + Clone,
+    C: Zero + Ord + Copy,
+    FN: FnMut(&N) -> BoxFuture<IN>,
+    IN: IntoIterator<Item = (N, C)>,
+    FS: FnMut(&N) -> bool,
+{
+    let mut to_see = BinaryHeap::new();
+    to_see.push(SmallestCostHolder {
+        estimated_cost: Zero::zero(),
+        cost: Zero::zero(),
+        index: 0,
+    });
+    let mut parents: FxIndexMap<N, (usize, C)> = FxIndexMap::default();
+    parents.insert(start.clone(), (usize::MAX, Zero::zero()));
+    while let Some(SmallestCostHolder { cost, index, .. }) = to_see.pop() {
+        let successors = {
+            let (node, &(_, c)) = parents.get_index(index).unwrap(); // Cannot fail
+            if cost > c {
+                continue;
+            }
+            if stop(node) {
+                break;
+            }
+            successors(node).await
+        };
+        for (successor, move_cost) in successors {
+            let new_cost = cost + move_cost;
+            let n = match parents.get_full_mut2(&successor) {
+                Some((n, node, parent)) => {
+                    if parent.1 <= new_cost {
+                        continue;
+                    }
+                    *node = successor;
+                    *parent = (index, new_cost);
+                    n
+                }
+                None => parents.insert_full(successor, (index, new_cost)).0,
+            };
+            to_see.push(SmallestCostHolder {
+                estimated_cost: new_cost,
+                cost: new_cost,
+                index: n,
+            });
+        }
+    }
+    Settled { parents }
+}
+
+/// The nodes reached by `dijkstra_partial`.
+pub struct Settled<N, C> {
+    parents: FxIndexMap<N, (usize, C)>,
+}
+
+impl<N: Eq + Hash + Clone, C: Ord + Copy> Settled<N, C> {
+    /// The cheapest path to a node for which `target` returns `true`, starting with the
+    /// start node, and its cost.
+    pub fn path_to(&self, mut target: impl FnMut(&N) -> bool) -> Option<(Vec<N>, C)> {
+        let (index, cost) = self
+            .parents
+            .iter()
+            .enumerate()
+            .filter(|(_, (node, _))| target(node))
+            .map(|(i, (_, &(_, cost)))| (i, cost))
+            .min_by_key(|&(_, cost)| cost)?;
+        Some((reverse_path(&self.parents, |&(p, _)| p, index), cost))
+    }
+}
+
+/// How an `astar` search ended, when it did not run out of nodes to expand.
+pub enum Outcome<N, C> {
+    /// The path to a node for which `success` returned `true`, and its cost.
+    Found(Vec<N>, C),
+    /// `stop` returned `true` first: the path to the node reached with the smallest
+    /// heuristic, and its cost.
+    Stopped(Vec<N>, C),
+}
 
 struct SmallestCostHolder<K> {
     estimated_cost: K,
@@ -259,3 +359,47 @@ impl<N: Clone + Eq + Hash> Iterator for AstarSolution<N> {
 }
 
 impl<N: Clone + Eq + Hash> FusedIterator for AstarSolution<N> {}
+
+#[tokio::test]
+async fn stops_at_the_node_closest_to_the_goal() {
+    // A line of nodes from 0 to 10, stopped after 4 expansions
+    let expansions = std::cell::Cell::new(0);
+    let outcome = astar(
+        &0,
+        |&n: &i32| -> BoxFuture<Vec<(i32, i32)>> {
+            expansions.set(expansions.get() + 1);
+            Box::pin(async move { vec![(n - 1, 1), (n + 1, 1)] })
+        },
+        |&n| (10 - n).abs(),
+        |&n| n == 10,
+        || expansions.get() >= 4,
+    )
+    .await;
+    match outcome {
+        Some(Outcome::Stopped(path, cost)) => {
+            assert_eq!(path, vec![0, 1, 2, 3, 4]);
+            assert_eq!(cost, 4);
+        }
+        _ => panic!("the search did not stop"),
+    }
+}
+
+#[tokio::test]
+async fn settles_the_nodes_up_to_the_last_target() {
+    // A line of nodes from 0 to 10, stopped once 3 and 6 are both reached
+    let mut left = vec![3, 6];
+    let settled = dijkstra_partial(
+        &0,
+        |&n: &i32| -> BoxFuture<Vec<(i32, i32)>> {
+            Box::pin(async move { vec![(n - 1, 1), (n + 1, 1)] })
+        },
+        |&n| {
+            left.retain(|&target| target != n);
+            left.is_empty()
+        },
+    )
+    .await;
+    assert_eq!(settled.path_to(|&n| n == 3), Some((vec![0, 1, 2, 3], 3)));
+    assert_eq!(settled.path_to(|&n| n == 6).map(|(_, cost)| cost), Some(6));
+    assert_eq!(settled.path_to(|&n| n == 10), None);
+}
@@ -0,0 +1,182 @@
+//! Replays a fixed set of routes against the local data, to catch the performance
+//! regressions of the search or of the queries before a release:
+//!
+//! `routing-server bench [--pairs <file.csv>] [--runs <n>] [--region <name>]
+//! [--max-p90-ms <ms>]`
+//!
+//! The pairs file has `name,start_lat,start_lng,end_lat,end_lng[,model]` columns,
+//! `bench/pairs.csv` by default. The route caches are bypassed so that every route
+//! is searched, leaving the ones of the servers sharing them alone, the node caches
+//! are used, so the first run is the one with a cold cache.
+
+use crate::{
+    csv::Table,
+    data::node::Node,
+    region::Region,
+    route::{LatLon, Model, RouteRequest},
+};
+use std::{error::Error, path::Path, time::Instant};
+
+const USAGE: &str = "Usage: routing-server bench [--pairs <file.csv>] [--runs <n>] \
+    [--region <name>] [--max-p90-ms <ms>]";
+
+#[derive(Debug, PartialEq)]
+struct BenchArgs {
+    pairs: String,
+    runs: usize,
+    region: Option<String>,
+    max_p90_ms: Option<f64>,
+}
+
+fn parse_args(args: &[String]) -> Result<BenchArgs, String> {
+    let mut parsed = BenchArgs {
+        pairs: "bench/pairs.csv".to_string(),
+        runs: 3,
+        region: None,
+        max_p90_ms: None,
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(USAGE)?.clone();
+        let invalid = || format!("Invalid {flag} {value}");
+        match flag.as_str() {
+            "--pairs" => parsed.pairs = value.clone(),
+            "--runs" => parsed.runs = value.parse().map_err(|_| invalid())?,
+            "--region" => parsed.region = Some(value.clone()),
+            "--max-p90-ms" => parsed.max_p90_ms = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    if parsed.runs == 0 {
+        return Err("--runs must be at least 1".to_string());
+    }
+    Ok(parsed)
+}
+
+/// A route of the bench.
+struct Pair {
+    name: String,
+    request: RouteRequest,
+}
+
+fn read_pairs(table: &Table) -> Result<Vec<Pair>, Box<dyn Error>> {
+    let mut pairs = vec![];
+    for (i, row) in table.rows.iter().enumerate() {
+        let point = |lat: &str, lng: &str| -> Result<LatLon, String> {
+            format!("{},{}", table.get(row, lat), table.get(row, lng))
+                .parse()
+                .map_err(|e| format!("Line {}: {e}", i + 2))
+        };
+        let model = match table.get(row, "model") {
+            "fast" => Model::Fast,
+            "safe" | "" => Model::Safe,
+            "family" => Model::Family,
+            "road" => Model::Road,
+            model => return Err(format!("Line {}: unknown model {model}", i + 2).into()),
+        };
+        let name = match table.get(row, "name") {
+            "" => format!("pair {}", i + 1),
+            name => name.to_string(),
+        };
+        pairs.push(Pair {
+            name,
+            request: RouteRequest {
+                start: point("start_lat", "start_lng")?,
+                end: point("end_lat", "end_lng")?,
+                model,
+                // The forecast would time the weather service too
+                weather: Some(false),
+                uncached: true,
+                ..Default::default()
+            },
+        });
+    }
+    Ok(pairs)
+}
+
+/// The value below which `percent` of the sorted `values` are, the closest one.
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Prints the percentiles of `values`, returning their 90th.
+fn report(label: &str, values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let p = |percent| percentile(values, percent);
+    println!(
+        "{label}: p50 {:.1}, p90 {:.1}, p99 {:.1}, max {:.1}",
+        p(50.0),
+        p(90.0),
+        p(99.0),
+        p(100.0)
+    );
+    p(90.0)
+}
+
+pub async fn bench(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = parse_args(args)?;
+    let pairs = read_pairs(&Table::read(Path::new(&args.pairs))?)?;
+    let (mut latencies, mut expanded, mut failures) = (vec![], vec![], 0);
+    for run in 1..=args.runs {
+        for pair in &pairs {
+            let (start, end) = (&pair.request.start, &pair.request.end);
+            let region = match &args.region {
+                Some(name) => Region::get(name)?,
+                None => Region::containing(&[(start.lat, start.lng), (end.lat, end.lng)])
+                    .ok_or_else(|| format!("{}: no region covers the route", pair.name))?,
+            };
+            let now = Instant::now();
+            let searched = Node::route_with_progress(region, &pair.request, |_| {}).await;
+            let ms = now.elapsed().as_secs_f64() * 1000.0;
+            match searched {
+                Ok((_, _, stats)) => {
+                    println!("run {run}, {}: {ms:.1} ms, {} nodes", pair.name, stats.expanded);
+                    latencies.push(ms);
+                    expanded.push(stats.expanded as f64);
+                }
+                Err(e) => {
+                    println!("run {run}, {}: failed after {ms:.1} ms: {e}", pair.name);
+                    failures += 1;
+                }
+            }
+        }
+    }
+    println!("{} routes, {failures} failed", latencies.len() + failures);
+    let p90 = report("latency (ms)", &mut latencies);
+    report("nodes expanded", &mut expanded);
+    if failures > 0 {
+        return Err(format!("{failures} routes failed").into());
+    }
+    match args.max_p90_ms {
+        Some(max) if p90 > max => {
+            Err(format!("p90 latency of {p90:.1} ms over the {max} ms allowed").into())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn reads_the_pairs_and_their_percentiles() {
+    let table = Table::parse(
+        "name,start_lat,start_lng,end_lat,end_lng,model\n\
+        plateau,45.5246,-73.5818,45.5075,-73.554,fast\n\
+        ,45.4597,-73.57,45.542,-73.588,",
+    );
+    let pairs = read_pairs(&table).unwrap();
+    assert_eq!(pairs[0].name, "plateau");
+    assert!(matches!(pairs[0].request.model, Model::Fast));
+    assert_eq!(pairs[1].name, "pair 2");
+    assert_eq!(pairs[1].request.end.lng, -73.588);
+    assert!(read_pairs(&Table::parse("start_lat,start_lng\nnorth,-73.5")).is_err());
+    let values: Vec<f64> = (1..=10).map(f64::from).collect();
+    assert_eq!(percentile(&values, 50.0), 5.0);
+    assert_eq!(percentile(&values, 90.0), 9.0);
+    assert_eq!(percentile(&values, 100.0), 10.0);
+    let args = parse_args(&["--runs".to_string(), "5".to_string()]).unwrap();
+    assert_eq!((args.runs, args.pairs.as_str()), (5, "bench/pairs.csv"));
+    assert!(parse_args(&["--runs".to_string(), "0".to_string()]).is_err());
+}
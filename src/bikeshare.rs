@@ -0,0 +1,357 @@
+//! Routes for riders without their bike: walking to a bike-share station with bikes
+//! available, riding to a station with free docks near the end, and walking there.
+//! The stations come from the GBFS feed at `GBFS_URL`.
+//! https://github.com/MobilityData/gbfs/blob/v2.3/gbfs.md
+
+use crate::{
+    config::CONFIG,
+    data::node::Node,
+    error::{FieldError, RouteError},
+    profile::WALKING_SPEED,
+    route::{LatLon, RouteRequest},
+    segment::way_segments,
+    transit::{bike_leg, Leg},
+};
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+/// How far riders walk to and from the stations, in meters.
+const WALKING_RADIUS: i32 = 1000;
+
+/// How many of the closest stations to the start and end are considered.
+const CANDIDATE_STATIONS: usize = 3;
+
+/// How long the requests may wait for each file of the feed.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How much longer going along the streets is than the straight-line distance, to
+/// pick the stations before routing to them.
+const DETOUR_FACTOR: f64 = 1.3;
+
+lazy_static! {
+    /// The last stations fetched and when.
+    static ref STATIONS: Mutex<Option<(Instant, Arc<Vec<Station>>)>> = Mutex::new(None);
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Station {
+    pub id: String,
+    pub name: String,
+    pub location: LatLon,
+    pub bikes_available: i32,
+    pub docks_available: i32,
+    /// Whether bikes can be taken from the station.
+    #[serde(skip)]
+    renting: bool,
+    /// Whether bikes can be left at the station.
+    #[serde(skip)]
+    returning: bool,
+}
+
+impl Station {
+    fn can_pick_up(&self) -> bool {
+        self.renting && self.bikes_available > 0
+    }
+
+    fn can_drop_off(&self) -> bool {
+        self.returning && self.docks_available > 0
+    }
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct FeedUrl {
+    name: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct Feeds {
+    feeds: Vec<FeedUrl>,
+}
+
+#[derive(Deserialize)]
+struct Stations<T> {
+    stations: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct StationInformation {
+    station_id: String,
+    name: String,
+    lat: f64,
+    lon: f64,
+}
+
+/// GBFS 1 has 0 and 1 flags, GBFS 2 booleans.
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Bool(flag) => flag,
+        value => value.as_i64() != Some(0),
+    })
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct StationStatus {
+    station_id: String,
+    num_bikes_available: i32,
+    num_docks_available: i32,
+    #[serde(default = "yes", deserialize_with = "flag")]
+    is_renting: bool,
+    #[serde(default = "yes", deserialize_with = "flag")]
+    is_returning: bool,
+}
+
+/// The stations of `information` with their status, the ones without are left out.
+fn stations(information: Vec<StationInformation>, status: Vec<StationStatus>) -> Vec<Station> {
+    let mut status: HashMap<String, StationStatus> = status
+        .into_iter()
+        .map(|status| (status.station_id.clone(), status))
+        .collect();
+    information
+        .into_iter()
+        .filter_map(|info| {
+            let status = status.remove(&info.station_id)?;
+            Some(Station {
+                id: info.station_id,
+                name: info.name,
+                location: LatLon {
+                    lat: info.lat,
+                    lng: info.lon,
+                },
+                bikes_available: status.num_bikes_available,
+                docks_available: status.num_docks_available,
+                renting: status.is_renting,
+                returning: status.is_returning,
+            })
+        })
+        .collect()
+}
+
+async fn get<T: for<'de> Deserialize<'de>>(url: &str) -> Result<T, Box<dyn Error + Send + Sync>> {
+    let response: Response<T> = reqwest::Client::new()
+        .get(url)
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(response.data)
+}
+
+/// Fetches the stations from the feeds listed by the `gbfs.json` at `url`.
+async fn fetch(url: &str) -> Result<Vec<Station>, Box<dyn Error + Send + Sync>> {
+    // The feeds by language
+    let languages: HashMap<String, Feeds> = get(url).await?;
+    let feeds = languages.into_values().next().ok_or("The GBFS feed has no language")?;
+    let url = |name: &str| {
+        feeds
+            .feeds
+            .iter()
+            .find(|feed| feed.name == name)
+            .map(|feed| feed.url.clone())
+            .ok_or_else(|| format!("The GBFS feed has no {name}"))
+    };
+    let (information_url, status_url) = (url("station_information")?, url("station_status")?);
+    let information: Stations<StationInformation> = get(&information_url).await?;
+    let status: Stations<StationStatus> = get(&status_url).await?;
+    Ok(stations(information.stations, status.stations))
+}
+
+/// The stations, fetched at most once every `GBFS_TTL`. The last stations fetched
+/// are kept when fetching fails.
+async fn current_stations() -> Result<Arc<Vec<Station>>, RouteError> {
+    let url = CONFIG.gbfs_url.as_ref().ok_or_else(|| RouteError::NotConfigured {
+        feature: "bike-share feed".to_string(),
+    })?;
+    let stale = {
+        let mut stations = STATIONS.lock().await;
+        match &*stations {
+            Some((fetched, stations)) if fetched.elapsed() < CONFIG.gbfs_ttl => {
+                return Ok(stations.clone());
+            }
+            // The other requests keep the stale stations while this one fetches them
+            Some((_, stale)) => {
+                let stale = stale.clone();
+                *stations = Some((Instant::now(), stale.clone()));
+                Some(stale)
+            }
+            None => None,
+        }
+    };
+    let fetched = fetch(url).await.map_err(|e| e.to_string());
+    match (fetched, stale) {
+        (Ok(fetched), _) => {
+            let fetched = Arc::new(fetched);
+            *STATIONS.lock().await = Some((Instant::now(), fetched.clone()));
+            Ok(fetched)
+        }
+        (Err(e), Some(stale)) => {
+            eprintln!("Cannot fetch the bike-share stations: {e}");
+            Ok(stale)
+        }
+        (Err(message), None) => Err(RouteError::Internal { message }),
+    }
+}
+
+/// The `CANDIDATE_STATIONS` closest stations matching `usable` within walking
+/// distance of `point`, with their distance.
+fn closest<'a>(
+    stations: &'a [Station],
+    point: &LatLon,
+    usable: impl Fn(&Station) -> bool,
+) -> Vec<(&'a Station, i32)> {
+    let mut closest: Vec<(&Station, i32)> = stations
+        .iter()
+        .filter(|station| usable(station))
+        .map(|station| (station, point.distance(&station.location)))
+        .filter(|&(_, distance)| distance <= WALKING_RADIUS)
+        .collect();
+    closest.sort_by_key(|&(_, distance)| distance);
+    closest.truncate(CANDIDATE_STATIONS);
+    closest
+}
+
+/// The pickup and dropoff stations making the quickest trip from `start` to `end`
+/// riding at `speed`, estimated from the straight-line distances.
+fn pick_stations<'a>(
+    stations: &'a [Station],
+    start: &LatLon,
+    end: &LatLon,
+    speed: f64,
+) -> Option<(&'a Station, &'a Station)> {
+    let pickups = closest(stations, start, Station::can_pick_up);
+    let dropoffs = closest(stations, end, Station::can_drop_off);
+    let seconds = |distance: i32, speed: f64| distance as f64 * DETOUR_FACTOR / speed;
+    pickups
+        .iter()
+        .flat_map(|pickup| dropoffs.iter().map(move |dropoff| (pickup, dropoff)))
+        .filter(|((pickup, _), (dropoff, _))| pickup.id != dropoff.id)
+        .map(|(&(pickup, walk_to), &(dropoff, walk_from))| {
+            let ride = pickup.location.distance(&dropoff.location);
+            let duration = seconds(walk_to + walk_from, WALKING_SPEED) + seconds(ride, speed);
+            (pickup, dropoff, duration)
+        })
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(pickup, dropoff, _)| (pickup, dropoff))
+}
+
+/// The walking leg from `start` to `end`, along the ways bikes can take.
+async fn walk_leg(
+    request: &RouteRequest,
+    start: &LatLon,
+    end: &LatLon,
+) -> Result<(Leg, i32), RouteError> {
+    let request = RouteRequest {
+        start: start.clone(),
+        end: end.clone(),
+        ..request.clone()
+    };
+    let region = request.region().await?;
+    let (path, _cost) = Node::route(region, &request).await?;
+    let distance: i32 = way_segments(&path).iter().map(|way| way.length).sum();
+    let duration = (distance as f64 / WALKING_SPEED).round() as i32;
+    let leg = Leg::Walk {
+        path: path.iter().map(LatLon::from).collect(),
+        distance,
+        duration,
+    };
+    Ok((leg, duration))
+}
+
+#[derive(Serialize)]
+pub struct BikeShareRoute {
+    pub pickup: Station,
+    pub dropoff: Station,
+    /// Walking to the pickup, riding to the dropoff, and walking to the end.
+    pub legs: Vec<Leg>,
+    /// In seconds.
+    pub duration: i32,
+}
+
+/// Takes the options of `POST /route`, which apply to the ride between the stations.
+#[post("/route/bikeshare")]
+async fn bikeshare_route(request: web::Json<RouteRequest>) -> Result<impl Responder, RouteError> {
+    let mut request = request.into_inner();
+    request.take_coordinates();
+    request.validate()?;
+    let stations = current_stations().await?;
+    let speed = request
+        .cruising_speed_kmh
+        .map_or(request.model.profile().speed, |speed| speed / 3.6);
+    let (pickup, dropoff) = pick_stations(&stations, &request.start, &request.end, speed)
+        .ok_or_else(|| RouteError::InvalidRequest {
+            errors: vec![FieldError {
+                field: "start".to_string(),
+                message: format!(
+                    "has no bike-share station with bikes within {WALKING_RADIUS} m, or the end \
+                     none with docks"
+                ),
+            }],
+        })?;
+    let (walk_to, walk_to_duration) = walk_leg(&request, &request.start, &pickup.location).await?;
+    let ride_departure = request
+        .departure_time
+        .map(|departure| departure.plus_seconds(walk_to_duration as i64));
+    let (ride, ride_duration) =
+        bike_leg(&request, &pickup.location, &dropoff.location, ride_departure).await?;
+    let (walk_from, walk_from_duration) =
+        walk_leg(&request, &dropoff.location, &request.end).await?;
+    Ok(HttpResponse::Ok().json(BikeShareRoute {
+        pickup: pickup.clone(),
+        dropoff: dropoff.clone(),
+        legs: vec![walk_to, ride, walk_from],
+        duration: walk_to_duration + ride_duration + walk_from_duration,
+    }))
+}
+
+#[test]
+fn picks_stations_with_bikes_and_docks() {
+    let information: Stations<StationInformation> = serde_json::from_str(
+        r#"{"stations": [
+            {"station_id": "1", "name": "Rachel / Papineau", "lat": 45.5300, "lon": -73.5700},
+            {"station_id": "2", "name": "Rachel / Berri", "lat": 45.5250, "lon": -73.5750},
+            {"station_id": "3", "name": "Atwater", "lat": 45.4850, "lon": -73.5800},
+            {"station_id": "4", "name": "Lionel-Groulx", "lat": 45.4830, "lon": -73.5790}
+        ]}"#,
+    )
+    .unwrap();
+    let status: Stations<StationStatus> = serde_json::from_str(
+        r#"{"stations": [
+            {"station_id": "1", "num_bikes_available": 0, "num_docks_available": 10},
+            {"station_id": "2", "num_bikes_available": 3, "num_docks_available": 2},
+            {"station_id": "3", "num_bikes_available": 5, "num_docks_available": 0},
+            {"station_id": "4", "num_bikes_available": 1, "num_docks_available": 4,
+             "is_returning": 1}
+        ]}"#,
+    )
+    .unwrap();
+    let stations = stations(information.stations, status.stations);
+    let start = LatLon {
+        lat: 45.5301,
+        lng: -73.5701,
+    };
+    let end = LatLon {
+        lat: 45.4851,
+        lng: -73.5801,
+    };
+    // The closest ones have no bikes and no docks
+    let (pickup, dropoff) = pick_stations(&stations, &start, &end, 4.0).unwrap();
+    assert_eq!((pickup.id.as_str(), dropoff.id.as_str()), ("2", "4"));
+}
@@ -0,0 +1,143 @@
+//! Imports the open data of the collisions involving cyclists into `way_collisions`,
+//! the collisions per kilometer of each way, so that the Safe model avoids the ways
+//! where cyclists were hit.
+//!
+//! The file is a CSV with latitude and longitude columns, like the `LOC_LAT` and
+//! `LOC_LONG` of Montréal's data, or a GeoJSON of points. It should only hold the
+//! collisions involving cyclists, and replaces the ones imported before.
+
+use crate::{csv::Table, data::node::ROUTABLE_LINE};
+use serde::Deserialize;
+use sqlx::postgres::PgPoolOptions;
+use std::{env, error::Error, fs, path::Path};
+
+/// How far a collision may be from a way to count on it, in meters.
+const MATCH_DISTANCE: f64 = 30.0;
+
+/// The shortest length the collisions of a way are spread over, so that a collision
+/// on a short way does not make it look like the most dangerous one, in meters.
+const MIN_WAY_LENGTH: f64 = 100.0;
+
+const LATITUDE_COLUMNS: [&str; 4] = ["lat", "latitude", "loc_lat", "y"];
+const LONGITUDE_COLUMNS: [&str; 6] = ["lon", "lng", "longitude", "loc_long", "loc_lon", "x"];
+
+#[derive(Deserialize)]
+struct Geometry {
+    #[serde(rename = "type")]
+    kind: String,
+    coordinates: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    geometry: Option<Geometry>,
+}
+
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+/// The `(lon, lat)` of the point features of a GeoJSON document.
+fn geojson_points(content: &str) -> Result<Vec<(f64, f64)>, Box<dyn Error>> {
+    let collection: FeatureCollection = serde_json::from_str(content)?;
+    Ok(collection
+        .features
+        .into_iter()
+        .filter_map(|feature| {
+            let geometry = feature.geometry.filter(|g| g.kind == "Point")?;
+            let coordinates: Vec<f64> = serde_json::from_value(geometry.coordinates).ok()?;
+            Some((*coordinates.first()?, *coordinates.get(1)?))
+        })
+        .collect())
+}
+
+/// The `(lon, lat)` of the rows of a CSV document, the ones without coordinates
+/// are skipped.
+fn csv_points(content: &str) -> Result<Vec<(f64, f64)>, Box<dyn Error>> {
+    let table = Table::parse(content);
+    let lat = table
+        .find_column(&LATITUDE_COLUMNS)
+        .ok_or("No latitude column")?;
+    let lon = table
+        .find_column(&LONGITUDE_COLUMNS)
+        .ok_or("No longitude column")?;
+    Ok(table
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let lon: f64 = table.get(row, &lon).parse().ok()?;
+            let lat: f64 = table.get(row, &lat).parse().ok()?;
+            Some((lon, lat))
+        })
+        .collect())
+}
+
+/// Imports the collisions of the file at `path` and counts them on the ways.
+pub async fn import(path: &str) -> Result<(), Box<dyn Error>> {
+    let url = env::var("DATABASE_URL")?;
+    let pool = PgPoolOptions::new().max_connections(1).connect(&url).await?;
+    sqlx::migrate!().run(&pool).await?;
+
+    let content = fs::read_to_string(path)?;
+    let points = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("json" | "geojson") => geojson_points(&content)?,
+        _ => csv_points(&content)?,
+    };
+    let (lons, lats): (Vec<f64>, Vec<f64>) = points.iter().copied().unzip();
+
+    let mut transaction = pool.begin().await?;
+    sqlx::query("truncate collisions")
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query(
+        r#"
+        insert into collisions (way)
+        select ST_Transform(ST_SetSRID(ST_MakePoint(p.lon, p.lat), 4326), 3857)
+        from unnest($1::float8[], $2::float8[]) as p(lon, lat)
+        "#,
+    )
+    .bind(lons)
+    .bind(lats)
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query("truncate way_collisions")
+        .execute(&mut transaction)
+        .await?;
+    let ways = sqlx::query(&format!(
+        r#"
+        insert into way_collisions (way_id, collisions_per_km)
+        select matched.osm_id, round(count(*) * 1000 / greatest(max(matched.length), $2))
+        from collisions c
+        cross join lateral (
+            select pol.osm_id, ST_Length(ST_Transform(pol.way, 4326)::geography) as length
+            from planet_osm_line pol
+            where {ROUTABLE_LINE}
+            and ST_DWithin(pol.way, c.way, $1)
+            order by pol.way <-> c.way
+            limit 1
+        ) matched
+        group by matched.osm_id
+        "#
+    ))
+    .bind(MATCH_DISTANCE)
+    .bind(MIN_WAY_LENGTH)
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+    println!("{} collisions on {ways} ways", points.len());
+    pool.close().await;
+    Ok(())
+}
+
+#[test]
+fn reads_collision_coordinates() {
+    let csv = "NO_SEQ_COLL,LOC_LAT,LOC_LONG\nSPVM _ 2012 _ 1,45.5264,-73.5812\nSPVM _ 2012 _ 2,,\n";
+    assert_eq!(csv_points(csv).unwrap(), vec![(-73.5812, 45.5264)]);
+    let geojson = r#"{"type": "FeatureCollection", "features": [
+        {"type": "Feature", "geometry": {"type": "Point", "coordinates": [-73.58, 45.52]}},
+        {"type": "Feature", "geometry": null}
+    ]}"#;
+    assert_eq!(geojson_points(geojson).unwrap(), vec![(-73.58, 45.52)]);
+}
@@ -0,0 +1,196 @@
+//! `POST /route/compare`: the same route found for several profiles, Fast and Safe
+//! unless others are given, with how they differ, for the screen where riders choose
+//! their route.
+
+use crate::{
+    error::{FieldError, RouteError},
+    route::{find_route, Model, RouteBody, RouteRequest, RouteResponse},
+};
+use actix_web::{post, web, HttpResponse, Responder};
+use futures::future::try_join_all;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The most profiles compared at once, each one a search.
+const MAX_PROFILES: usize = 5;
+
+/// The options of a profile, overriding the ones of the compared request.
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct CompareProfile {
+    /// The name of the route in the response, the model by default.
+    pub name: Option<String>,
+    pub model: Option<Model>,
+    pub night: Option<bool>,
+    pub prefer_popular: Option<bool>,
+    pub bike_route_preference: Option<f64>,
+    pub max_grade_percent: Option<f64>,
+}
+
+impl CompareProfile {
+    fn model(model: Model) -> Self {
+        CompareProfile {
+            name: None,
+            model: Some(model),
+            night: None,
+            prefer_popular: None,
+            bike_route_preference: None,
+            max_grade_percent: None,
+        }
+    }
+
+    fn name(&self, compared: &RouteRequest) -> String {
+        let model = self.model.as_ref().unwrap_or(&compared.model);
+        self.name.clone().unwrap_or_else(|| format!("{model:?}"))
+    }
+
+    /// The request of the route for this profile, detailed to compare it.
+    fn request(&self, compared: &RouteRequest) -> RouteRequest {
+        RouteRequest {
+            model: self.model.clone().unwrap_or(compared.model.clone()),
+            night: self.night.unwrap_or(compared.night),
+            prefer_popular: self.prefer_popular.unwrap_or(compared.prefer_popular),
+            bike_route_preference: self.bike_route_preference.or(compared.bike_route_preference),
+            max_grade_percent: self.max_grade_percent.or(compared.max_grade_percent),
+            detailed: true,
+            ..compared.clone()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CompareRequest {
+    /// The route to compare, its options and model shared by the profiles not
+    /// setting theirs.
+    #[serde(flatten)]
+    pub route: RouteRequest,
+    /// The profiles to find the route for, Fast and Safe when empty.
+    #[serde(default)]
+    pub profiles: Vec<CompareProfile>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ComparedRoute {
+    /// The name of the profile.
+    pub name: String,
+    /// The length of the route, in meters.
+    pub distance: i32,
+    /// How much longer the route is than the shortest one, in meters.
+    pub extra_distance: i32,
+    /// How much longer riding the route takes than the quickest one, in seconds.
+    pub extra_duration: i32,
+    /// The safety score of the route minus the one of the shortest route.
+    pub safety_score_delta: i32,
+    pub route: RouteResponse,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CompareResponse {
+    /// The routes in the order of the profiles.
+    pub routes: Vec<ComparedRoute>,
+}
+
+fn validate(request: &CompareRequest) -> Result<(), RouteError> {
+    let mut errors = vec![];
+    if request.profiles.len() > MAX_PROFILES {
+        errors.push(FieldError {
+            field: "profiles".to_string(),
+            message: format!("must have at most {MAX_PROFILES} profiles"),
+        });
+    }
+    for (i, profile) in request.profiles.iter().enumerate() {
+        if let Err(RouteError::InvalidRequest { errors: invalid }) =
+            profile.request(&request.route).validate()
+        {
+            errors.extend(invalid.into_iter().map(|error| FieldError {
+                field: format!("profiles[{i}].{}", error.field),
+                message: error.message,
+            }));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(RouteError::InvalidRequest { errors })
+    }
+}
+
+/// How the routes found for the profiles differ from the shortest and the quickest
+/// one.
+fn compare(routes: Vec<(String, RouteResponse)>) -> CompareResponse {
+    let distance = |route: &RouteResponse| route.ways.iter().map(|way| way.length).sum::<i32>();
+    let shortest = routes.iter().min_by_key(|(_, route)| distance(route));
+    let (shortest_distance, shortest_score) = shortest
+        .map(|(_, route)| (distance(route), route.safety.score))
+        .unwrap_or_default();
+    let quickest = routes.iter().map(|(_, route)| route.duration).min().unwrap_or(0);
+    let routes = routes
+        .into_iter()
+        .map(|(name, route)| ComparedRoute {
+            name,
+            distance: distance(&route),
+            extra_distance: distance(&route) - shortest_distance,
+            extra_duration: route.duration - quickest,
+            safety_score_delta: route.safety.score - shortest_score,
+            route,
+        })
+        .collect();
+    CompareResponse { routes }
+}
+
+/// Finds the route for each profile, each one detailed and saved like by
+/// `POST /route`.
+#[utoipa::path(
+    request_body = CompareRequest,
+    responses(
+        (status = 200, description = "The routes of the profiles", body = CompareResponse),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 422, description = "No route can be searched", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+#[post("/route/compare")]
+async fn compare_routes(request: web::Json<CompareRequest>) -> Result<impl Responder, RouteError> {
+    let mut request = request.into_inner();
+    request.route.take_coordinates();
+    validate(&request)?;
+    if request.profiles.is_empty() {
+        request.profiles = vec![
+            CompareProfile::model(Model::Fast),
+            CompareProfile::model(Model::Safe),
+        ];
+    }
+    let searches = request.profiles.iter().map(|profile| async {
+        let (body, _id) = find_route(profile.request(&request.route), |_| {}).await?;
+        match body {
+            RouteBody::Detailed(response) => Ok((profile.name(&request.route), *response)),
+            RouteBody::Path(_) => Err(RouteError::Internal {
+                message: "Undetailed route".to_string(),
+            }),
+        }
+    });
+    let routes = try_join_all(searches).await?;
+    Ok(HttpResponse::Ok().json(compare(routes)))
+}
+
+#[test]
+fn compares_the_profiles_with_the_shortest_route() {
+    let request: CompareRequest = serde_json::from_str(
+        r#"{
+            "start": {"lat": 45.5246, "lng": -73.5818},
+            "end": {"lat": 45.5075, "lng": -73.554},
+            "model": "Safe",
+            "night": true,
+            "profiles": [{"model": "Fast"}, {"name": "Quiet", "night": false}]
+        }"#,
+    )
+    .unwrap();
+    let (fast, quiet) = (&request.profiles[0], &request.profiles[1]);
+    assert_eq!(fast.name(&request.route), "Fast");
+    assert!(fast.request(&request.route).night);
+    assert_eq!(quiet.name(&request.route), "Quiet");
+    let quiet = quiet.request(&request.route);
+    assert!(matches!(quiet.model, Model::Safe) && !quiet.night && quiet.detailed);
+    let mut too_many = request;
+    too_many.profiles = vec![CompareProfile::model(Model::Safe); MAX_PROFILES + 1];
+    assert!(validate(&too_many).is_err());
+}
@@ -0,0 +1,223 @@
+//! What a route is made of, as percentages of its length, for clients to chart
+//! routes and for riders to reject a route at a glance.
+
+use crate::{
+    data::{
+        access::{dismount, Dismount},
+        node::{AdjacentNode, Node},
+    },
+    safety::{is_protected, is_quiet_street},
+    segment::{edges, WaySegment},
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+const PAVED: [&str; 9] = [
+    "asphalt",
+    "paved",
+    "concrete",
+    "concrete:plates",
+    "concrete:lanes",
+    "paving_stones",
+    "chipseal",
+    "metal",
+    "wood",
+];
+const GRAVEL: [&str; 4] = ["gravel", "fine_gravel", "compacted", "pebblestone"];
+const DIRT: [&str; 9] = [
+    "unpaved", "dirt", "earth", "ground", "mud", "grass", "grass_paver", "sand", "woodchips",
+];
+const COBBLES: [&str; 4] = ["sett", "cobblestone", "unhewn_cobblestone", "cobblestone:flattened"];
+
+/// The percentages of the length of a route by surface, from the `surface` tags.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct Surfaces {
+    /// Asphalt, concrete, paving stones and other smooth surfaces.
+    pub paved: f64,
+    /// Gravel and compacted surfaces.
+    pub gravel: f64,
+    /// Dirt, grass, sand and other loose surfaces.
+    pub dirt: f64,
+    /// Sett and cobblestones.
+    pub cobbles: f64,
+    /// The ways without a known surface.
+    pub unknown: f64,
+}
+
+/// The percentages of the length of a route by kind of way, to compare the routes
+/// of the models.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct Highways {
+    /// Cycleways and cycle tracks, apart from cars.
+    pub cycleway: f64,
+    /// Residential and other quiet streets.
+    pub residential: f64,
+    /// Primary, secondary and tertiary roads and trunks.
+    pub arterial: f64,
+    pub other: f64,
+}
+
+/// A section of a route where the bike is pushed, ridden at walking speed.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct DismountSection {
+    pub reason: Dismount,
+    /// The index in the route path of the node starting the section.
+    pub start: usize,
+    /// The index in the route path of the node ending the section.
+    pub end: usize,
+    /// How far along the route the section starts, in meters.
+    pub offset: i32,
+    /// The length in meters.
+    pub length: i32,
+}
+
+/// The surface class of a way, the index of its field in `Surfaces`.
+fn surface_class(edge: &AdjacentNode) -> usize {
+    let surface = edge.tags.get("surface").map_or("", String::as_str);
+    [&PAVED[..], &GRAVEL, &DIRT, &COBBLES]
+        .iter()
+        .position(|class| class.contains(&surface))
+        .unwrap_or(4)
+}
+
+/// The kind of a way, the index of its field in `Highways`.
+fn highway_class(edge: &AdjacentNode) -> usize {
+    if is_protected(edge) {
+        0
+    } else if is_quiet_street(edge) {
+        1
+    } else if edge.is_arterial() {
+        2
+    } else {
+        3
+    }
+}
+
+/// The percentages of `lengths` of their total, to a tenth.
+fn percentages<const N: usize>(lengths: [i32; N]) -> [f64; N] {
+    let total: i32 = lengths.iter().sum();
+    lengths.map(|length| {
+        if total > 0 {
+            (length as f64 * 1000.0 / total as f64).round() / 10.0
+        } else {
+            0.0
+        }
+    })
+}
+
+/// The surfaces of the route along `path`, split into `segments`.
+pub fn surfaces(path: &[Node], segments: &[WaySegment]) -> Surfaces {
+    let mut lengths = [0; 5];
+    for segment in segments {
+        if let Some(edge) = path[segment.start].edge_to(path[segment.start + 1].id) {
+            lengths[surface_class(edge)] += segment.length;
+        }
+    }
+    let [paved, gravel, dirt, cobbles, unknown] = percentages(lengths);
+    Surfaces {
+        paved,
+        gravel,
+        dirt,
+        cobbles,
+        unknown,
+    }
+}
+
+/// The kinds of ways of the route along `path`, split into `segments`.
+pub fn highways(path: &[Node], segments: &[WaySegment]) -> Highways {
+    let mut lengths = [0; 4];
+    for segment in segments {
+        if let Some(edge) = path[segment.start].edge_to(path[segment.start + 1].id) {
+            lengths[highway_class(edge)] += segment.length;
+        }
+    }
+    let [cycleway, residential, arterial, other] = percentages(lengths);
+    Highways {
+        cycleway,
+        residential,
+        arterial,
+        other,
+    }
+}
+
+/// The sections of the route along `path` where the bike is pushed, in route
+/// order, consecutive edges pushed for the same reason merged.
+pub fn dismount_sections(path: &[Node]) -> Vec<DismountSection> {
+    let mut sections: Vec<DismountSection> = vec![];
+    let mut offset = 0;
+    for (i, edge) in edges(path).enumerate() {
+        let length = edge.map_or(0, |edge| edge.distance);
+        if let Some(reason) = edge.and_then(|edge| dismount(&edge.tags)) {
+            match sections.last_mut() {
+                Some(section) if section.end == i && section.reason == reason => {
+                    section.end = i + 1;
+                    section.length += length;
+                }
+                _ => sections.push(DismountSection {
+                    reason,
+                    start: i,
+                    end: i + 1,
+                    offset,
+                    length,
+                }),
+            }
+        }
+        offset += length;
+    }
+    sections
+}
+
+#[test]
+fn sums_the_surfaces_and_kinds_of_the_ways() {
+    use crate::segment::{test_path, way_segments};
+
+    let mut path = test_path(&[1, 2, 3, 4]);
+    let ways = [
+        ("asphalt", "cycleway"),
+        ("fine_gravel", "residential"),
+        ("asphalt", "primary"),
+    ];
+    for (i, (surface, highway)) in ways.iter().enumerate() {
+        let tags = &mut path[i].adjacent_nodes[0].tags;
+        tags.insert("surface".to_string(), surface.to_string());
+        tags.insert("highway".to_string(), highway.to_string());
+    }
+    let segments = way_segments(&path);
+    let surfaces = surfaces(&path, &segments);
+    assert_eq!(
+        surfaces,
+        Surfaces {
+            paved: 50.0,
+            gravel: 25.0,
+            dirt: 0.0,
+            cobbles: 0.0,
+            unknown: 25.0,
+        }
+    );
+    let highways = highways(&path, &segments);
+    assert_eq!((highways.cycleway, highways.residential), (25.0, 25.0));
+    assert_eq!((highways.arterial, highways.other), (25.0, 25.0));
+}
+
+#[test]
+fn lists_the_sections_where_the_bike_is_pushed() {
+    use crate::segment::test_path;
+
+    let mut path = test_path(&[1, 2, 3, 4, 5]);
+    let pushed = [
+        (1, "highway", "pedestrian"),
+        (2, "bicycle", "dismount"),
+        (3, "bicycle", "dismount"),
+    ];
+    for (i, key, value) in pushed {
+        let tags = &mut path[i].adjacent_nodes[0].tags;
+        tags.insert(key.to_string(), value.to_string());
+    }
+    let sections = dismount_sections(&path);
+    let found: Vec<_> = sections.iter().map(|s| (s.reason, s.start, s.end, s.offset)).collect();
+    assert_eq!(
+        found,
+        vec![(Dismount::Pedestrian, 1, 2, 10), (Dismount::Signed, 2, 4, 20)]
+    );
+    assert_eq!(sections[1].length, 20);
+}
@@ -0,0 +1,250 @@
+use crate::{
+    data::bbox::BoundingBox,
+    rules::{self, Rules},
+};
+use std::{env, str::FromStr, time::Duration};
+
+/// Reads `key` from the environment, falling back to `default` when it is unset or
+/// cannot be parsed.
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Reads `key` from the environment, `None` when it is unset or cannot be parsed.
+fn env_opt<T: FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|v| v.parse().ok())
+}
+
+pub struct RegionConfig {
+    pub name: String,
+    pub database_url: Option<String>,
+    /// The read-only replicas of the database, for the routing queries.
+    pub replica_urls: Vec<String>,
+    pub schema: Option<String>,
+    pub bbox: Option<BoundingBox>,
+    /// The graph snapshot written by `dump-graph`, mapped at startup. Without a
+    /// database URL, the region is served from the snapshot alone.
+    pub snapshot: Option<String>,
+    /// The traffic rules of the jurisdiction of the region.
+    pub rules: &'static Rules,
+}
+
+/// Reads a comma-separated list of URLs from `key`.
+fn env_urls(key: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reads the rule pack named by `key`, like `germany`, the default one when unset.
+fn env_rules(key: &str) -> &'static Rules {
+    match env_opt::<String>(key) {
+        Some(name) => rules::by_name(&name).unwrap_or_else(|| panic!("Unknown {key} {name}")),
+        None => rules::DEFAULT,
+    }
+}
+
+/// Reads the regions listed in `REGIONS`, each configured by the
+/// `<NAME>_DATABASE_URL`, `<NAME>_REPLICA_URLS`, `<NAME>_SCHEMA`, `<NAME>_BBOX`,
+/// `<NAME>_SNAPSHOT` and `<NAME>_RULES` variables. Without `REGIONS`, a single region
+/// covers everything using `DATABASE_URL`, `DATABASE_REPLICA_URLS`, `GRAPH_SNAPSHOT`
+/// and `RULES`.
+fn regions() -> Vec<RegionConfig> {
+    let names: Vec<String> = match env::var("REGIONS") {
+        Ok(names) => names
+            .split(',')
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .collect(),
+        Err(_) => {
+            return vec![RegionConfig {
+                name: "default".to_string(),
+                database_url: env_opt("DATABASE_URL"),
+                replica_urls: env_urls("DATABASE_REPLICA_URLS"),
+                schema: None,
+                bbox: None,
+                snapshot: env_opt("GRAPH_SNAPSHOT"),
+                rules: env_rules("RULES"),
+            }]
+        }
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            let prefix = name.to_uppercase().replace('-', "_");
+            let bbox = env::var(format!("{prefix}_BBOX"))
+                .ok()
+                .map(|bbox| bbox.parse().unwrap_or_else(|e| panic!("{e}")));
+            RegionConfig {
+                database_url: env_opt(&format!("{prefix}_DATABASE_URL")),
+                replica_urls: env_urls(&format!("{prefix}_REPLICA_URLS")),
+                schema: env_opt(&format!("{prefix}_SCHEMA")),
+                bbox,
+                snapshot: env_opt(&format!("{prefix}_SNAPSHOT")),
+                rules: env_rules(&format!("{prefix}_RULES")),
+                name,
+            }
+        })
+        .collect()
+}
+
+pub struct Config {
+    /// How long in-flight requests get to finish once a termination signal is received.
+    pub shutdown_timeout: Duration,
+    /// The maximum number of nodes kept in the node cache.
+    pub node_cache_capacity: usize,
+    /// The maximum approximate memory used by the node cache, in bytes.
+    pub node_cache_max_bytes: Option<usize>,
+    /// The redis shared by the replicas to cache nodes, the cache is local only when unset.
+    pub redis_url: Option<String>,
+    /// How long nodes are kept in redis, in seconds.
+    pub redis_ttl: usize,
+    /// The bearer token required by the admin endpoints, which are disabled when unset.
+    pub admin_token: Option<String>,
+    /// The extracts served, requests go to the first one covering them.
+    pub regions: Vec<RegionConfig>,
+    /// The most connections of each region pool.
+    pub db_pool_size: u32,
+    /// How long a request may wait for a connection of a region pool.
+    pub db_acquire_timeout: Duration,
+    /// How long a query may run before Postgres cancels it, none when zero.
+    pub db_statement_timeout: Duration,
+    /// How long a connection may stay unused before it is closed.
+    pub db_idle_timeout: Duration,
+    /// The maximum straight-line distance between the start and end of a route, in meters.
+    pub max_route_distance: i32,
+    /// The maximum size of a JSON request body, in bytes.
+    pub max_body_size: usize,
+    /// How far the ends of a route may be from a routable way when the request
+    /// does not say, in meters.
+    pub snap_radius: i32,
+    /// The maximum number of routes kept in the route cache of each region.
+    pub route_cache_capacity: usize,
+    /// How long computed routes are served from the caches.
+    pub route_cache_ttl: Duration,
+    /// The port of the gRPC service, which is disabled when unset.
+    pub grpc_port: Option<u16>,
+    /// The Unix socket the HTTP server listens on instead of port 3000, for a proxy
+    /// on the same host.
+    pub unix_socket: Option<String>,
+    /// The permissions of the Unix socket in octal, like `660` for the proxy to share
+    /// the group of the server. The umask decides when unset.
+    pub unix_socket_mode: Option<u32>,
+    /// The PostGIS raster table of the elevation model, in EPSG:4326. Grades only
+    /// come from the `incline` tags when unset.
+    pub dem_table: Option<String>,
+    /// The forecast API giving the recent rain and snowfall, with `{lat}` and `{lng}`
+    /// placeholders, and the wind with `current_weather=true`. The weather is
+    /// ignored when unset.
+    pub weather_url: Option<String>,
+    /// How long the weather of a region is reused.
+    pub weather_ttl: Duration,
+    /// The directory of the extracted GTFS feed for bike and transit routes, which
+    /// are disabled when unset.
+    pub gtfs_path: Option<String>,
+    /// The `gbfs.json` of the bike-share system, for the routes of riders without
+    /// their bike, which are disabled when unset.
+    pub gbfs_url: Option<String>,
+    /// How long the bike-share station status is reused.
+    pub gbfs_ttl: Duration,
+    /// The most searches running at once, each one holding a database connection.
+    pub max_concurrent_searches: usize,
+    /// The most requests waiting for a search to finish before the next ones are
+    /// turned away.
+    pub search_queue_size: usize,
+    /// How long a request may wait for a search to finish.
+    pub search_queue_timeout: Duration,
+    /// How long a search may run before giving up.
+    pub search_timeout: Duration,
+    /// The most nodes a search may expand before giving up.
+    pub max_search_nodes: usize,
+    /// The most memory the nodes found by a search may use, roughly, in bytes.
+    pub max_search_memory: usize,
+    /// How far apart the ends of a route must be, in meters, for its search to only
+    /// take the major ways away from them.
+    pub hierarchy_distance: i32,
+    /// How far from the ends of such a route every way is searched, in meters.
+    pub hierarchy_radius: i32,
+    /// Whether the server runs the maintenance jobs, off by default as a `worker` runs
+    /// them.
+    pub scheduled_jobs: bool,
+    /// How often the ways lengths are recomputed, never when zero.
+    pub lengths_refresh_interval: Duration,
+    /// How often the connected components are recomputed, never when zero.
+    pub components_refresh_interval: Duration,
+    /// How often the closures over for longer than `closures_retention` are deleted,
+    /// never when zero.
+    pub closures_expiry_interval: Duration,
+    pub closures_retention: Duration,
+    /// How often the routes saved for longer than `saved_routes_retention` are
+    /// deleted, never when zero.
+    pub saved_routes_expiry_interval: Duration,
+    pub saved_routes_retention: Duration,
+}
+
+lazy_static! {
+    pub static ref CONFIG: Config = Config {
+        shutdown_timeout: Duration::from_secs(env_or("SHUTDOWN_TIMEOUT", 30)),
+        node_cache_capacity: env_or("NODE_CACHE_CAPACITY", 1_000_000),
+        node_cache_max_bytes: env_opt("NODE_CACHE_MAX_BYTES"),
+        redis_url: env_opt("REDIS_URL"),
+        redis_ttl: env_or("REDIS_TTL", 7 * 24 * 60 * 60),
+        admin_token: env_opt("ADMIN_TOKEN"),
+        regions: regions(),
+        db_pool_size: env_or("DB_POOL_SIZE", 15),
+        db_acquire_timeout: Duration::from_secs(env_or("DB_ACQUIRE_TIMEOUT", 30)),
+        db_statement_timeout: Duration::from_secs(env_or("DB_STATEMENT_TIMEOUT", 30)),
+        db_idle_timeout: Duration::from_secs(env_or("DB_IDLE_TIMEOUT", 10 * 60)),
+        max_route_distance: env_or("MAX_ROUTE_DISTANCE", 200_000),
+        max_body_size: env_or("MAX_BODY_SIZE", 64 * 1024),
+        snap_radius: env_or("SNAP_RADIUS", 1000),
+        route_cache_capacity: env_or("ROUTE_CACHE_CAPACITY", 10_000),
+        route_cache_ttl: Duration::from_secs(env_or("ROUTE_CACHE_TTL", 60 * 60)),
+        grpc_port: env_opt("GRPC_PORT"),
+        unix_socket: env_opt("UNIX_SOCKET"),
+        unix_socket_mode: env_opt::<String>("UNIX_SOCKET_MODE").map(|mode| {
+            u32::from_str_radix(&mode, 8)
+                .unwrap_or_else(|_| panic!("Invalid UNIX_SOCKET_MODE {mode}, expected octal"))
+        }),
+        dem_table: env_opt("DEM_TABLE"),
+        weather_url: env_opt("WEATHER_URL"),
+        weather_ttl: Duration::from_secs(env_or("WEATHER_TTL", 30 * 60)),
+        gtfs_path: env_opt("GTFS_PATH"),
+        gbfs_url: env_opt("GBFS_URL"),
+        gbfs_ttl: Duration::from_secs(env_or("GBFS_TTL", 60)),
+        max_concurrent_searches: env_or("MAX_CONCURRENT_SEARCHES", 10),
+        search_queue_size: env_or("SEARCH_QUEUE_SIZE", 50),
+        search_queue_timeout: Duration::from_secs(env_or("SEARCH_QUEUE_TIMEOUT", 10)),
+        search_timeout: Duration::from_secs(env_or("SEARCH_TIMEOUT", 60)),
+        max_search_nodes: env_or("MAX_SEARCH_NODES", 1_000_000),
+        max_search_memory: env_or("MAX_SEARCH_MEMORY", 1024 * 1024 * 1024),
+        hierarchy_distance: env_or("HIERARCHY_DISTANCE", 50_000),
+        hierarchy_radius: env_or("HIERARCHY_RADIUS", 10_000),
+        scheduled_jobs: env_or("SCHEDULED_JOBS", false),
+        lengths_refresh_interval: Duration::from_secs(env_or(
+            "LENGTHS_REFRESH_INTERVAL",
+            24 * 60 * 60,
+        )),
+        components_refresh_interval: Duration::from_secs(env_or(
+            "COMPONENTS_REFRESH_INTERVAL",
+            24 * 60 * 60,
+        )),
+        closures_expiry_interval: Duration::from_secs(env_or("CLOSURES_EXPIRY_INTERVAL", 60 * 60)),
+        closures_retention: Duration::from_secs(env_or("CLOSURES_RETENTION", 7 * 24 * 60 * 60)),
+        saved_routes_expiry_interval: Duration::from_secs(env_or(
+            "SAVED_ROUTES_EXPIRY_INTERVAL",
+            24 * 60 * 60,
+        )),
+        saved_routes_retention: Duration::from_secs(env_or(
+            "SAVED_ROUTES_RETENTION",
+            30 * 24 * 60 * 60,
+        )),
+    };
+}
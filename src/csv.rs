@@ -0,0 +1,84 @@
+//! A minimal reader for the CSV files of the open data imported, with quoted fields
+//! and a header line.
+
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+/// The rows of a CSV file, with fields by column name.
+pub struct Table {
+    columns: HashMap<String, usize>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn read(path: &Path) -> Result<Table, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+        Ok(Table::parse(&content))
+    }
+
+    /// The missing optional files are empty.
+    pub fn read_optional(path: &Path) -> Result<Table, Box<dyn Error>> {
+        if path.exists() {
+            Table::read(path)
+        } else {
+            Ok(Table::parse(""))
+        }
+    }
+
+    pub fn parse(content: &str) -> Table {
+        let mut lines = content
+            .trim_start_matches('\u{feff}')
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(split_line);
+        let columns = lines
+            .next()
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (name.trim().to_string(), i))
+            .collect();
+        Table {
+            columns,
+            rows: lines.collect(),
+        }
+    }
+
+    /// The first of `names` that is a column, ignoring case.
+    pub fn find_column(&self, names: &[&str]) -> Option<String> {
+        names.iter().find_map(|name| {
+            self.columns
+                .keys()
+                .find(|column| column.eq_ignore_ascii_case(name))
+                .cloned()
+        })
+    }
+
+    pub fn get<'a>(&self, row: &'a [String], column: &str) -> &'a str {
+        self.columns
+            .get(column)
+            .and_then(|&i| row.get(i))
+            .map_or("", |value| value.trim())
+    }
+}
+
+/// The fields of a CSV line, unquoted.
+fn split_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
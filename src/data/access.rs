@@ -0,0 +1,144 @@
+//! Whether a bike may use a way, from its access tags.
+//! https://wiki.openstreetmap.org/wiki/Key:access
+
+use super::conditional::{conditional_value, is_open, LocalTime};
+use crate::rules::Rules;
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// The keys giving the access of bikes, from the most to the least specific.
+const ACCESS_KEYS: [&str; 3] = ["bicycle", "vehicle", "access"];
+
+/// The highways closed to bikes unless tagged otherwise.
+const CLOSED_HIGHWAYS: [&str; 4] = ["motorway", "motorway_link", "construction", "proposed"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Yes,
+    /// Only to reach a place along the way.
+    Destination,
+    /// Allowed, but bikes have to use the cycleway next to it.
+    UseSidepath,
+    No,
+}
+
+impl Access {
+    /// The access given by a tag `value`, `None` for the values that say nothing
+    /// about bikes, leaving the decision to a less specific key.
+    fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "yes" | "designated" | "permissive" | "official" | "dismount"
+            | "optional_sidepath" => Some(Access::Yes),
+            "destination" | "customers" | "delivery" => Some(Access::Destination),
+            "use_sidepath" => Some(Access::UseSidepath),
+            "no" | "private" | "agricultural" | "forestry" | "military" => Some(Access::No),
+            _ => None,
+        }
+    }
+
+    pub fn allowed(self) -> bool {
+        self != Access::No
+    }
+}
+
+/// Why the bike is pushed on a way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Dismount {
+    /// Signed with `bicycle=dismount`.
+    Signed,
+    Steps,
+    /// A pedestrian zone not open to bikes.
+    Pedestrian,
+}
+
+/// Whether bikes have to be pushed on a way with `tags`, and why.
+pub fn dismount(tags: &HashMap<String, String>) -> Option<Dismount> {
+    let highway = tags.get("highway").map_or("", String::as_str);
+    let bicycle = tags.get("bicycle").map_or("", String::as_str);
+    if bicycle == "dismount" {
+        Some(Dismount::Signed)
+    } else if highway == "steps" {
+        Some(Dismount::Steps)
+    } else if highway == "pedestrian" && !["yes", "designated", "permissive"].contains(&bicycle) {
+        Some(Dismount::Pedestrian)
+    } else {
+        None
+    }
+}
+
+/// The access of bikes to a way with `tags`: `bicycle` wins over `vehicle`, which
+/// wins over `access`, and without any of them it depends on the `highway` and the
+/// `rules` of the region. At a known `time`, the `*:conditional` restrictions
+/// applying then win over their key, and ways outside their `opening_hours` are
+/// closed.
+pub fn bicycle_access(
+    tags: &HashMap<String, String>,
+    time: Option<&LocalTime>,
+    rules: &Rules,
+) -> Access {
+    if let Some(time) = time {
+        if tags.get("opening_hours").and_then(|hours| is_open(hours, time)) == Some(false) {
+            return Access::No;
+        }
+    }
+    ACCESS_KEYS
+        .iter()
+        .find_map(|key| {
+            let conditional = time.and_then(|time| {
+                let conditional = tags.get(&format!("{key}:conditional"))?;
+                conditional_value(conditional, time)
+            });
+            conditional
+                .or_else(|| tags.get(*key).map(String::as_str))
+                .and_then(Access::from_value)
+        })
+        .unwrap_or_else(|| match tags.get("highway") {
+            Some(highway) if CLOSED_HIGHWAYS.contains(&highway.as_str()) => Access::No,
+            _ => rules.default_access(tags).unwrap_or(Access::Yes),
+        })
+}
+
+#[test]
+fn most_specific_tag_wins() {
+    let access = |tags: &[(&str, &str)]| {
+        let tags = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        bicycle_access(&tags, None, crate::rules::DEFAULT)
+    };
+    assert_eq!(access(&[("highway", "residential")]), Access::Yes);
+    assert_eq!(access(&[("highway", "service"), ("vehicle", "no")]), Access::No);
+    assert_eq!(
+        access(&[("vehicle", "no"), ("bicycle", "yes"), ("access", "no")]),
+        Access::Yes
+    );
+    assert_eq!(
+        access(&[("access", "destination"), ("vehicle", "unknown")]),
+        Access::Destination
+    );
+    assert_eq!(access(&[("highway", "motorway")]), Access::No);
+    assert_eq!(
+        access(&[("highway", "primary"), ("bicycle", "use_sidepath")]),
+        Access::UseSidepath
+    );
+}
+
+#[test]
+fn conditional_restrictions_apply_at_their_time() {
+    let tags: HashMap<String, String> = [
+        ("highway", "path"),
+        ("bicycle", "yes"),
+        ("bicycle:conditional", "no @ (Mo-Fr 07:00-09:00)"),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+    let rush_hour: LocalTime = "2023-05-12T08:00".parse().unwrap();
+    let evening: LocalTime = "2023-05-12T18:00".parse().unwrap();
+    assert_eq!(bicycle_access(&tags, Some(&rush_hour), crate::rules::DEFAULT), Access::No);
+    assert_eq!(bicycle_access(&tags, Some(&evening), crate::rules::DEFAULT), Access::Yes);
+    assert_eq!(bicycle_access(&tags, None, crate::rules::DEFAULT), Access::Yes);
+}
@@ -0,0 +1,119 @@
+use super::node::Node;
+use crate::route::LatLon;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// A bounding box in degrees.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lng: f64,
+    pub max_lat: f64,
+    pub max_lng: f64,
+}
+
+impl BoundingBox {
+    pub fn contains(&self, lat: f64, lng: f64) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lng >= self.min_lng && lng <= self.max_lng
+    }
+
+    pub fn contains_node(&self, node: &Node) -> bool {
+        self.contains(node.lat(), node.lon())
+    }
+
+    /// The part of the box in `other`, when they overlap or touch.
+    pub fn intersection(&self, other: &BoundingBox) -> Option<BoundingBox> {
+        let intersection = BoundingBox {
+            min_lat: self.min_lat.max(other.min_lat),
+            min_lng: self.min_lng.max(other.min_lng),
+            max_lat: self.max_lat.min(other.max_lat),
+            max_lng: self.max_lng.min(other.max_lng),
+        };
+        let valid = intersection.min_lat <= intersection.max_lat
+            && intersection.min_lng <= intersection.max_lng;
+        valid.then_some(intersection)
+    }
+
+    /// The middle of the part of the line from `start` to `end` in the box, or the
+    /// center of the box when the line misses it.
+    pub fn crossing(&self, start: &LatLon, end: &LatLon) -> LatLon {
+        let (d_lat, d_lng) = (end.lat - start.lat, end.lng - start.lng);
+        // Liang-Barsky, clipping the line to each side in turn
+        let (mut from, mut to) = (0.0_f64, 1.0_f64);
+        let sides = [
+            (-d_lat, start.lat - self.min_lat),
+            (d_lat, self.max_lat - start.lat),
+            (-d_lng, start.lng - self.min_lng),
+            (d_lng, self.max_lng - start.lng),
+        ];
+        for (direction, room) in sides {
+            if direction == 0.0 {
+                if room < 0.0 {
+                    (from, to) = (1.0, 0.0);
+                }
+            } else if direction < 0.0 {
+                from = from.max(room / direction);
+            } else {
+                to = to.min(room / direction);
+            }
+        }
+        if from > to {
+            return LatLon {
+                lat: (self.min_lat + self.max_lat) / 2.0,
+                lng: (self.min_lng + self.max_lng) / 2.0,
+            };
+        }
+        let middle = (from + to) / 2.0;
+        LatLon {
+            lat: start.lat + middle * d_lat,
+            lng: start.lng + middle * d_lng,
+        }
+    }
+
+    /// The box as a PostGIS geometry in the projection of the osm2pgsql tables.
+    pub fn envelope(&self) -> String {
+        format!(
+            "ST_Transform(ST_MakeEnvelope({}, {}, {}, {}, 4326), 3857)",
+            self.min_lng, self.min_lat, self.max_lng, self.max_lat
+        )
+    }
+}
+
+/// Parses `min_lat,min_lng,max_lat,max_lng`.
+impl FromStr for BoundingBox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f64> = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid bounding box {s}: {e}"))?;
+        match values[..] {
+            [min_lat, min_lng, max_lat, max_lng] => Ok(BoundingBox {
+                min_lat,
+                min_lng,
+                max_lat,
+                max_lng,
+            }),
+            _ => Err(format!(
+                "Invalid bounding box {s}, expected min_lat,min_lng,max_lat,max_lng"
+            )),
+        }
+    }
+}
+
+#[test]
+fn crosses_over_where_the_boxes_overlap() {
+    let west: BoundingBox = "45.0,-74.0,46.0,-73.0".parse().unwrap();
+    let east: BoundingBox = "45.0,-73.2,46.0,-72.0".parse().unwrap();
+    let overlap = west.intersection(&east).unwrap();
+    assert_eq!((overlap.min_lng, overlap.max_lng), (-73.2, -73.0));
+    assert!(west.intersection(&"47.0,-74.0,48.0,-73.0".parse().unwrap()).is_none());
+    let point = |lat, lng| LatLon { lat, lng };
+    let crossing = overlap.crossing(&point(45.5, -73.8), &point(45.5, -72.4));
+    assert!((crossing.lat - 45.5).abs() < 1e-9 && (crossing.lng + 73.1).abs() < 1e-9);
+    // Along the boundary, the line misses the overlap
+    let crossing = overlap.crossing(&point(46.5, -73.8), &point(46.5, -72.4));
+    assert!((crossing.lat - 45.5).abs() < 1e-9 && (crossing.lng + 73.1).abs() < 1e-9);
+}
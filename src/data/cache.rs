@@ -0,0 +1,416 @@
+use super::{bbox::BoundingBox, node::Node};
+use crate::config::CONFIG;
+use lru::LruCache;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use std::{
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub bytes: usize,
+    pub max_bytes: Option<usize>,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// Which cached nodes to flush.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FlushScope {
+    #[default]
+    All,
+    Nodes(Vec<i64>),
+    BoundingBox(BoundingBox),
+}
+
+impl FlushScope {
+    fn matches(&self, node: &Node) -> bool {
+        match self {
+            FlushScope::All => true,
+            FlushScope::Nodes(ids) => ids.contains(&node.id),
+            FlushScope::BoundingBox(bbox) => bbox.contains_node(node),
+        }
+    }
+}
+
+/// A node cache bounded by entry count and, optionally, by approximate memory use.
+/// The least recently used nodes are evicted first.
+pub struct NodeCache {
+    nodes: LruCache<i64, Node>,
+    max_bytes: Option<usize>,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl NodeCache {
+    pub fn new(capacity: usize, max_bytes: Option<usize>) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        NodeCache {
+            nodes: LruCache::new(capacity),
+            max_bytes,
+            bytes: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    pub fn get(&mut self, id: i64) -> Option<Node> {
+        let node = self.nodes.get(&id).cloned();
+        match node {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        node
+    }
+
+    pub fn insert(&mut self, node: Node) {
+        let id = node.id;
+        self.bytes += node.approximate_size();
+        if let Some((old_id, old_node)) = self.nodes.push(id, node) {
+            self.bytes -= old_node.approximate_size();
+            // Pushing an already cached id replaces it, that is not an eviction
+            if old_id != id {
+                self.evictions += 1;
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            while self.bytes > max_bytes && self.nodes.len() > 1 {
+                if let Some((_, evicted)) = self.nodes.pop_lru() {
+                    self.bytes -= evicted.approximate_size();
+                    self.evictions += 1;
+                }
+            }
+        }
+    }
+
+    /// Removes the nodes in `scope`, returning how many were removed.
+    pub fn flush(&mut self, scope: &FlushScope) -> usize {
+        let ids: Vec<i64> = match scope {
+            FlushScope::Nodes(ids) => ids.clone(),
+            _ => self
+                .nodes
+                .iter()
+                .filter(|(_, node)| scope.matches(node))
+                .map(|(id, _)| *id)
+                .collect(),
+        };
+        let mut flushed = 0;
+        for id in ids {
+            if let Some(node) = self.nodes.pop(&id) {
+                self.bytes -= node.approximate_size();
+                flushed += 1;
+            }
+        }
+        flushed
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.nodes.len(),
+            capacity: self.nodes.cap().get(),
+            bytes: self.bytes,
+            max_bytes: self.max_bytes,
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+        }
+    }
+}
+
+/// A computed route: its path and cost.
+pub type CachedRoute = (Vec<Node>, i64);
+
+/// The routes already computed, by snapped ends and options, so that popular
+/// routes are not searched again until they expire.
+pub struct RouteCache {
+    routes: LruCache<String, (Instant, CachedRoute)>,
+    ttl: Duration,
+}
+
+impl RouteCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        RouteCache {
+            routes: LruCache::new(capacity),
+            ttl,
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<CachedRoute> {
+        match self.routes.get(key) {
+            Some((inserted, route)) if inserted.elapsed() < self.ttl => Some(route.clone()),
+            Some(_) => {
+                self.routes.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, key: String, route: CachedRoute) {
+        self.routes.push(key, (Instant::now(), route));
+    }
+
+    pub fn clear(&mut self) {
+        self.routes.clear();
+    }
+}
+
+/// A Redis cache shared by all the server replicas, sitting behind the in-process
+/// cache so that a freshly started replica does not have to warm up from the database.
+///
+/// Redis errors are logged and treated as cache misses, routing keeps working
+/// without it.
+pub struct SharedCache {
+    connection: ConnectionManager,
+    /// How long nodes are kept, in seconds.
+    ttl: usize,
+}
+
+impl SharedCache {
+    pub async fn connect(url: &str, ttl: usize) -> Result<Self, redis::RedisError> {
+        let client = redis::Client::open(url)?;
+        let connection = ConnectionManager::new(client).await?;
+        Ok(SharedCache { connection, ttl })
+    }
+
+    fn key(region: &str, id: i64) -> String {
+        format!("node:{region}:{id}")
+    }
+
+    pub async fn get(&self, region: &str, id: i64) -> Option<Node> {
+        let mut connection = self.connection.clone();
+        let bytes: Option<Vec<u8>> = match connection.get(Self::key(region, id)).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Cannot read node {id} from redis: {e}");
+                None
+            }
+        };
+        bincode::deserialize(&bytes?).ok()
+    }
+
+    pub async fn insert(&self, region: &str, node: &Node) {
+        let bytes = match bincode::serialize(node) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Cannot serialize node {}: {e}", node.id);
+                return;
+            }
+        };
+        let mut connection = self.connection.clone();
+        let result: Result<(), _> = connection
+            .set_ex(Self::key(region, node.id), bytes, self.ttl)
+            .await;
+        if let Err(e) = result {
+            eprintln!("Cannot write node {} to redis: {e}", node.id);
+        }
+    }
+
+    fn route_key(region: &str, key: &str) -> String {
+        format!("route:{region}:{key}")
+    }
+
+    pub async fn get_route(&self, region: &str, key: &str) -> Option<CachedRoute> {
+        let mut connection = self.connection.clone();
+        let bytes: Option<Vec<u8>> = match connection.get(Self::route_key(region, key)).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Cannot read route {key} from redis: {e}");
+                None
+            }
+        };
+        bincode::deserialize(&bytes?).ok()
+    }
+
+    pub async fn insert_route(&self, region: &str, key: &str, route: &CachedRoute, ttl: Duration) {
+        let bytes = match bincode::serialize(route) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Cannot serialize route {key}: {e}");
+                return;
+            }
+        };
+        let mut connection = self.connection.clone();
+        let result: Result<(), _> = connection
+            .set_ex(Self::route_key(region, key), bytes, ttl.as_secs() as usize)
+            .await;
+        if let Err(e) = result {
+            eprintln!("Cannot write route {key} to redis: {e}");
+        }
+    }
+
+    /// Removes every cached route of `region`.
+    pub async fn flush_routes(&self, region: &str) -> Result<(), redis::RedisError> {
+        let mut connection = self.connection.clone();
+        let mut cursor: u64 = 0;
+        loop {
+            let (next_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(format!("route:{region}:*"))
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut connection)
+                .await?;
+            if !keys.is_empty() {
+                connection.del::<_, ()>(keys).await?;
+            }
+            if next_cursor == 0 {
+                return Ok(());
+            }
+            cursor = next_cursor;
+        }
+    }
+
+    /// Removes the nodes in `scope`. Unless only some node ids are flushed, this
+    /// scans every cached key so it is meant for administration only.
+    pub async fn flush(&self, region: &str, scope: &FlushScope) -> Result<(), redis::RedisError> {
+        let mut connection = self.connection.clone();
+        if let FlushScope::Nodes(ids) = scope {
+            let keys: Vec<String> = ids.iter().map(|id| Self::key(region, *id)).collect();
+            if !keys.is_empty() {
+                connection.del::<_, ()>(keys).await?;
+            }
+            return Ok(());
+        }
+        let mut cursor: u64 = 0;
+        loop {
+            let (next_cursor, mut keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(format!("node:{region}:*"))
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut connection)
+                .await?;
+            if let FlushScope::BoundingBox(_) = scope {
+                if !keys.is_empty() {
+                    let nodes: Vec<Option<Vec<u8>>> = redis::cmd("MGET")
+                        .arg(&keys)
+                        .query_async(&mut connection)
+                        .await?;
+                    keys = keys
+                        .into_iter()
+                        .zip(nodes)
+                        .filter(|(_, bytes)| {
+                            bytes
+                                .as_ref()
+                                .and_then(|bytes| bincode::deserialize::<Node>(bytes).ok())
+                                .is_some_and(|node| scope.matches(&node))
+                        })
+                        .map(|(key, _)| key)
+                        .collect();
+                }
+            }
+            if !keys.is_empty() {
+                connection.del::<_, ()>(keys).await?;
+            }
+            if next_cursor == 0 {
+                return Ok(());
+            }
+            cursor = next_cursor;
+        }
+    }
+}
+
+/// The redis cache, `None` when it is not configured or cannot be reached.
+static SHARED_CACHE: OnceCell<Option<SharedCache>> = OnceCell::const_new();
+
+pub async fn shared_cache() -> Option<&'static SharedCache> {
+    SHARED_CACHE
+        .get_or_init(|| async {
+            let url = CONFIG.redis_url.as_ref()?;
+            match SharedCache::connect(url, CONFIG.redis_ttl).await {
+                Ok(cache) => Some(cache),
+                Err(e) => {
+                    eprintln!("Cannot connect to redis, using the local cache only: {e}");
+                    None
+                }
+            }
+        })
+        .await
+        .as_ref()
+}
+
+#[cfg(test)]
+fn test_node(id: i64) -> Node {
+    Node {
+        id,
+        lat: 0,
+        lon: 0,
+        adjacent_nodes: vec![],
+        highway: None,
+        elevation: None,
+    }
+}
+
+#[test]
+fn evicts_least_recently_used() {
+    let mut cache = NodeCache::new(2, None);
+    cache.insert(test_node(1));
+    cache.insert(test_node(2));
+    cache.get(1);
+    cache.insert(test_node(3));
+    assert!(cache.get(1).is_some());
+    assert!(cache.get(2).is_none());
+    let stats = cache.stats();
+    assert_eq!(stats.evictions, 1);
+    assert_eq!(stats.hits, 2);
+    assert_eq!(stats.misses, 1);
+}
+
+#[test]
+fn evicts_over_max_bytes() {
+    let node_size = test_node(1).approximate_size();
+    let mut cache = NodeCache::new(10, Some(node_size * 2));
+    for id in 0..5 {
+        cache.insert(test_node(id));
+    }
+    let stats = cache.stats();
+    assert_eq!(stats.entries, 2);
+    assert_eq!(stats.bytes, node_size * 2);
+    assert_eq!(stats.evictions, 3);
+}
+
+#[test]
+fn flushes_bounding_box() {
+    let mut cache = NodeCache::new(10, None);
+    cache.insert(test_node(1));
+    cache.insert(Node {
+        lat: 455_000_000,
+        lon: -735_000_000,
+        ..test_node(2)
+    });
+    let scope = FlushScope::BoundingBox(BoundingBox {
+        min_lat: 45.0,
+        min_lng: -74.0,
+        max_lat: 46.0,
+        max_lng: -73.0,
+    });
+    assert_eq!(cache.flush(&scope), 1);
+    assert!(cache.get(1).is_some());
+    assert!(cache.get(2).is_none());
+}
+
+#[test]
+fn expires_routes() {
+    let mut cache = RouteCache::new(10, Duration::from_secs(60));
+    cache.insert("1:2:Safe".to_string(), (vec![test_node(1), test_node(2)], 20));
+    assert_eq!(cache.get("1:2:Safe").map(|(_, cost)| cost), Some(20));
+    assert!(cache.get("1:2:Fast").is_none());
+
+    let mut cache = RouteCache::new(10, Duration::ZERO);
+    cache.insert("1:2:Safe".to_string(), (vec![test_node(1), test_node(2)], 20));
+    assert!(cache.get("1:2:Safe").is_none());
+}
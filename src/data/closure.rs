@@ -0,0 +1,141 @@
+//! Temporary closures of ways or areas, like street festivals or construction work,
+//! declared through the admin API and ignored once past their end.
+
+use super::bbox::BoundingBox;
+use crate::region::RegionClient;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::{collections::HashSet, error::Error, ops::DerefMut, time::Duration};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Closure {
+    #[serde(default)]
+    pub id: i32,
+    /// The closed way, or else every way crossing `bounding_box`.
+    pub way_id: Option<i64>,
+    pub bounding_box: Option<BoundingBox>,
+    pub reason: Option<String>,
+    /// When the closure ends, as a Unix timestamp in seconds.
+    pub until: i64,
+}
+
+impl Closure {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        match (self.way_id, self.bounding_box) {
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err("A closure needs either a way_id or a bounding_box".into()),
+        }
+    }
+
+    pub async fn create(&self, pg_client: RegionClient) -> Result<i32, Box<dyn Error>> {
+        let bbox = self.bounding_box;
+        let row = sqlx::query(
+            r#"
+            insert into closures (way_id, area, reason, until)
+            values (
+                $1,
+                case when $2::float8 is null then null
+                else ST_MakeEnvelope($2, $3, $4, $5, 4326) end,
+                $6,
+                to_timestamp($7)
+            )
+            returning id
+            "#,
+        )
+        .bind(self.way_id)
+        .bind(bbox.map(|b| b.min_lng))
+        .bind(bbox.map(|b| b.min_lat))
+        .bind(bbox.map(|b| b.max_lng))
+        .bind(bbox.map(|b| b.max_lat))
+        .bind(&self.reason)
+        .bind(self.until as f64)
+        .fetch_one(pg_client.lock().await?.deref_mut())
+        .await?;
+        Ok(row.get("id"))
+    }
+
+    /// The closures not over yet.
+    pub async fn active(pg_client: RegionClient) -> Result<Vec<Closure>, Box<dyn Error>> {
+        let rows = sqlx::query(
+            r#"
+            select id, way_id, reason, extract(epoch from until)::int8 as until,
+                ST_XMin(area) as min_lng, ST_YMin(area) as min_lat,
+                ST_XMax(area) as max_lng, ST_YMax(area) as max_lat
+            from closures
+            where until > now()
+            order by until
+            "#,
+        )
+        .fetch_all(pg_client.lock().await?.deref_mut())
+        .await?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let min_lat: Option<f64> = row.get("min_lat");
+                Closure {
+                    id: row.get("id"),
+                    way_id: row.get("way_id"),
+                    bounding_box: min_lat.map(|min_lat| BoundingBox {
+                        min_lat,
+                        min_lng: row.get("min_lng"),
+                        max_lat: row.get("max_lat"),
+                        max_lng: row.get("max_lng"),
+                    }),
+                    reason: row.get("reason"),
+                    until: row.get("until"),
+                }
+            })
+            .collect())
+    }
+
+    /// Deletes the closure `id`, returning whether it existed.
+    pub async fn delete(pg_client: RegionClient, id: i32) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("delete from closures where id = $1")
+            .bind(id)
+            .execute(pg_client.lock().await?.deref_mut())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes the closures over for longer than `retention`, returning how many.
+    pub async fn expire(
+        pg_client: RegionClient,
+        retention: Duration,
+    ) -> Result<u64, Box<dyn Error>> {
+        let result =
+            sqlx::query("delete from closures where until < now() - make_interval(secs => $1)")
+                .bind(retention.as_secs_f64())
+                .execute(pg_client.lock().await?.deref_mut())
+                .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+/// The ids of the ways closed right now, directly or by an area closure.
+pub async fn closed_ways(pg_client: RegionClient) -> Result<HashSet<i64>, Box<dyn Error>> {
+    if !pg_client.region.has_database() {
+        return Ok(HashSet::new());
+    }
+    let rows = sqlx::query(
+        r#"
+        select way_id as id from closures
+        where until > now() and way_id is not null
+        union
+        select pol.osm_id as id
+        from closures c
+        join planet_osm_line pol on pol.way && ST_Transform(c.area, 3857)
+        where c.until > now() and c.area is not null
+        "#,
+    )
+    .fetch_all(pg_client.lock().await?.deref_mut())
+    .await?;
+    Ok(rows.iter().map(|row| row.get("id")).collect())
+}
+
+#[test]
+fn closes_either_a_way_or_an_area() {
+    let closure: Closure = serde_json::from_str(r#"{"way_id": 1, "until": 1684000000}"#).unwrap();
+    assert!(closure.validate().is_ok());
+    let closure: Closure = serde_json::from_str(r#"{"until": 1684000000}"#).unwrap();
+    assert!(closure.validate().is_err());
+}
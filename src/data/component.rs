@@ -0,0 +1,139 @@
+//! The connected components of the routable graph, computed offline by the
+//! `compute-components` subcommand, so that routes between disconnected parts of the
+//! graph are rejected right away instead of searching until the time limit.
+//!
+//! Oneways are ignored and every way a bike may take is linked, whatever the options
+//! of the route: two nodes in different components cannot be linked by any route, two
+//! nodes in the same one may still not be.
+
+use super::node::ROUTABLE_WAY;
+use crate::region::RegionClient;
+use futures::TryStreamExt;
+use sqlx::{
+    postgres::{PgConnection, PgPoolOptions},
+    Connection, Row,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    error::Error,
+    ops::DerefMut,
+};
+
+/// How many nodes are inserted per query.
+const INSERT_BATCH: usize = 10_000;
+
+/// A union-find over node ids, the component of a node being its root.
+#[derive(Default)]
+struct Components {
+    parents: HashMap<i64, i64>,
+}
+
+impl Components {
+    fn find(&mut self, id: i64) -> i64 {
+        let mut root = id;
+        while let Some(&parent) = self.parents.get(&root) {
+            if parent == root {
+                break;
+            }
+            root = parent;
+        }
+        // Path compression
+        let mut node = id;
+        while node != root {
+            let parent = self.parents.insert(node, root).unwrap_or(root);
+            node = parent;
+        }
+        self.parents.entry(root).or_insert(root);
+        root
+    }
+
+    fn union(&mut self, a: i64, b: i64) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            // The smallest id labels the component, whatever the order of the ways
+            self.parents.insert(a.max(b), a.min(b));
+        }
+    }
+}
+
+/// Labels every node of the routable ways with its component in `node_components`.
+pub async fn compute() -> Result<(), Box<dyn Error>> {
+    let url = env::var("DATABASE_URL")?;
+    let pool = PgPoolOptions::new().max_connections(1).connect(&url).await?;
+    sqlx::migrate!().run(&pool).await?;
+    let (nodes, count) = label(pool.acquire().await?.deref_mut()).await?;
+    println!("{nodes} nodes in {count} components");
+    pool.close().await;
+    Ok(())
+}
+
+/// Labels the nodes on `connection`, returning the number of nodes and of components.
+pub async fn label(connection: &mut PgConnection) -> Result<(usize, usize), Box<dyn Error>> {
+    let query = format!(
+        r#"
+        select nodes
+        from planet_osm_ways
+        where {ROUTABLE_WAY}
+        "#
+    );
+    let mut components = Components::default();
+    // Streamed, the ways of a whole region would not fit in memory
+    let mut rows = sqlx::query(&query).fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let nodes: Vec<i64> = row.get("nodes");
+        for pair in nodes.windows(2) {
+            components.union(pair[0], pair[1]);
+        }
+    }
+    drop(rows);
+    let ids: Vec<i64> = components.parents.keys().copied().collect();
+    let labels: Vec<i64> = ids.iter().map(|&id| components.find(id)).collect();
+
+    let mut transaction = connection.begin().await?;
+    // Not truncated, which would block the searches until the commit
+    sqlx::query("delete from node_components")
+        .execute(&mut transaction)
+        .await?;
+    for (ids, labels) in ids.chunks(INSERT_BATCH).zip(labels.chunks(INSERT_BATCH)) {
+        sqlx::query(
+            "insert into node_components (node_id, component) select * from unnest($1, $2)",
+        )
+        .bind(ids)
+        .bind(labels)
+        .execute(&mut transaction)
+        .await?;
+    }
+    transaction.commit().await?;
+    let count = labels.iter().collect::<HashSet<_>>().len();
+    Ok((ids.len(), count))
+}
+
+/// The components of the `ids` nodes, the ones never labeled are left out.
+pub async fn components(
+    pg_client: RegionClient,
+    ids: &[i64],
+) -> Result<HashMap<i64, i64>, Box<dyn Error>> {
+    // Left unlabeled without a database, the closest candidates are taken
+    if !pg_client.region.has_database() {
+        return Ok(HashMap::new());
+    }
+    let rows = sqlx::query("select node_id, component from node_components where node_id = any($1)")
+        .bind(ids)
+        .fetch_all(pg_client.lock().await?.deref_mut())
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get("node_id"), row.get("component")))
+        .collect())
+}
+
+#[test]
+fn labels_components_by_their_smallest_node() {
+    let mut components = Components::default();
+    for (a, b) in [(5, 3), (3, 8), (9, 10), (8, 1)] {
+        components.union(a, b);
+    }
+    let labels: Vec<i64> = [5, 3, 8, 1, 9, 10].map(|id| components.find(id)).to_vec();
+    assert_eq!(labels, vec![1, 1, 1, 1, 9, 9]);
+}
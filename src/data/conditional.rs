@@ -0,0 +1,271 @@
+//! Time-dependent tags: `opening_hours` and the `*:conditional` access restrictions,
+//! evaluated at the departure time of a route.
+//! https://wiki.openstreetmap.org/wiki/Key:opening_hours
+//! https://wiki.openstreetmap.org/wiki/Conditional_restrictions
+//!
+//! Only the common subset is understood: months, weekdays and hours, like
+//! `Nov-Mar`, `Mo-Fr 07:00-09:00` or `sunset-sunrise`. Anything else is ignored.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+const RUSH_HOURS: &str = "Mo-Fr 07:00-09:30,16:00-18:30";
+
+/// Sunset and sunrise are approximated, there are no sun computations.
+const SUNSET: u32 = 20 * 60;
+const SUNRISE: u32 = 6 * 60;
+
+/// A local date and time in the region of the route, like `2023-05-12T18:30`. A
+/// trailing UTC offset is ignored, the times in the tags being local.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i32,
+    /// From 1 for January.
+    pub month: u32,
+    pub day: u32,
+    /// The minutes since midnight.
+    pub minutes: u32,
+}
+
+impl LocalTime {
+    /// The days since 1970-01-01, from Howard Hinnant's `days_from_civil`.
+    fn days(&self) -> i64 {
+        let (month, day) = (self.month as i64, self.day as i64);
+        let year = self.year as i64 - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// The inverse of `days`, `civil_from_days`.
+    fn from_days(days: i64, minutes: u32) -> Self {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+        LocalTime {
+            year: (year_of_era + era * 400 + i64::from(month <= 2)) as i32,
+            month,
+            day,
+            minutes,
+        }
+    }
+
+    /// From 0 for Monday.
+    pub fn weekday(&self) -> u32 {
+        // 1970-01-01 was a Thursday
+        (self.days() + 3).rem_euclid(7) as u32
+    }
+
+    /// This time `seconds` later, to the minute.
+    pub fn plus_seconds(&self, seconds: i64) -> Self {
+        let minutes = self.days() * 24 * 60 + self.minutes as i64 + (seconds + 30) / 60;
+        LocalTime::from_days(minutes.div_euclid(24 * 60), minutes.rem_euclid(24 * 60) as u32)
+    }
+
+    /// The seconds from `earlier` to this time.
+    pub fn seconds_since(&self, earlier: &LocalTime) -> i64 {
+        let minutes = |time: &LocalTime| time.days() * 24 * 60 + time.minutes as i64;
+        (minutes(self) - minutes(earlier)) * 60
+    }
+
+    /// Whether this is during the weekday rush hours, when arterials are busiest.
+    pub fn is_rush_hour(&self) -> bool {
+        selects(RUSH_HOURS, self) == Some(true)
+    }
+}
+
+impl FromStr for LocalTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid time {s}, expected YYYY-MM-DDTHH:MM");
+        let (date, time) = s.split_once(['T', ' ']).ok_or_else(invalid)?;
+        let date: Vec<&str> = date.split('-').collect();
+        let [year, month, day] = date[..] else {
+            return Err(invalid());
+        };
+        let (hour, minute) = time.get(..5).and_then(|t| t.split_once(':')).ok_or_else(invalid)?;
+        let number = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+        let (month, day) = (number(month)?, number(day)?);
+        let (hour, minute) = (number(hour)?, number(minute)?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+            return Err(invalid());
+        }
+        Ok(LocalTime {
+            year: year.parse().map_err(|_| invalid())?,
+            month,
+            day,
+            minutes: hour * 60 + minute,
+        })
+    }
+}
+
+impl fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}",
+            self.year,
+            self.month,
+            self.day,
+            self.minutes / 60,
+            self.minutes % 60
+        )
+    }
+}
+
+impl Serialize for LocalTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LocalTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Whether `value` is in the possibly wrapping range `from..=to`.
+fn in_range(value: u32, from: u32, to: u32) -> bool {
+    if from <= to {
+        (from..=to).contains(&value)
+    } else {
+        value >= from || value <= to
+    }
+}
+
+/// Evaluates a comma separated list of `names` ranges, like `Mo-Fr,Su`.
+fn in_names(list: &str, names: &[&str], value: u32) -> Option<bool> {
+    let index = |name: &str| names.iter().position(|n| *n == name).map(|i| i as u32);
+    let mut matches = false;
+    for range in list.split(',') {
+        let (from, to) = range.split_once('-').unwrap_or((range, range));
+        matches |= in_range(value, index(from)?, index(to)?);
+    }
+    Some(matches)
+}
+
+fn parse_minutes(time: &str) -> Option<u32> {
+    match time {
+        "sunset" | "dusk" => Some(SUNSET),
+        "sunrise" | "dawn" => Some(SUNRISE),
+        _ => {
+            let (hour, minute) = time.split_once(':')?;
+            Some(hour.parse::<u32>().ok()? * 60 + minute.parse::<u32>().ok()?)
+        }
+    }
+}
+
+/// Evaluates a comma separated list of time ranges, like `07:00-09:00,16:00-18:00`.
+fn in_hours(list: &str, minutes: u32) -> Option<bool> {
+    let mut matches = false;
+    for range in list.split(',') {
+        let (from, to) = range.split_once('-')?;
+        let (from, to) = (parse_minutes(from)?, parse_minutes(to)?);
+        // The end is excluded, and ranges like `22:00-06:00` go past midnight
+        matches |= if from < to {
+            minutes >= from && minutes < to
+        } else {
+            minutes >= from || minutes < to
+        };
+    }
+    Some(matches)
+}
+
+/// Whether `time` is selected by `selector`, like `Nov-Mar Mo-Fr 07:00-09:00`,
+/// `None` when it cannot be understood.
+fn selects(selector: &str, time: &LocalTime) -> Option<bool> {
+    let mut selected = true;
+    for token in selector.split_whitespace() {
+        let token = token.trim_end_matches(':');
+        let first = token.get(..2)?;
+        selected &= if token == "24/7" {
+            true
+        } else if token.contains(':') || ["sun", "dusk", "dawn"].iter().any(|t| token.contains(t)) {
+            in_hours(token, time.minutes)?
+        } else if WEEKDAYS.contains(&first) {
+            in_names(token, &WEEKDAYS, time.weekday())?
+        } else {
+            in_names(token, &MONTHS, time.month - 1)?
+        };
+    }
+    Some(selected)
+}
+
+/// Whether an `opening_hours` value is open at `time`, the last rule selecting it
+/// winning. `None` when the value cannot be understood.
+pub fn is_open(opening_hours: &str, time: &LocalTime) -> Option<bool> {
+    let mut open = false;
+    for rule in opening_hours.split(';').map(str::trim).filter(|r| !r.is_empty()) {
+        let (selector, closed) = match rule
+            .strip_suffix(" off")
+            .or_else(|| rule.strip_suffix(" closed"))
+        {
+            Some(selector) => (selector, true),
+            None => (rule, false),
+        };
+        if selects(selector, time)? {
+            open = !closed;
+        }
+    }
+    Some(open)
+}
+
+/// The value of a conditional restriction, like `no @ (Mo-Fr 07:00-09:00)`, that
+/// applies at `time`, the last one when several do.
+pub fn conditional_value<'a>(conditional: &'a str, time: &LocalTime) -> Option<&'a str> {
+    let mut value = None;
+    for restriction in conditional.split(';') {
+        let Some((restriction_value, condition)) = restriction.split_once('@') else {
+            continue;
+        };
+        let condition = condition.trim().trim_start_matches('(').trim_end_matches(')');
+        if selects(condition, time) == Some(true) {
+            value = Some(restriction_value.trim());
+        }
+    }
+    value
+}
+
+#[test]
+fn evaluates_time_conditions() {
+    // A Friday
+    let time: LocalTime = "2023-05-12T08:15".parse().unwrap();
+    assert_eq!(time.weekday(), 4);
+    assert_eq!(
+        conditional_value("no @ (Mo-Fr 07:00-09:00)", &time),
+        Some("no")
+    );
+    assert_eq!(conditional_value("no @ (Sa,Su)", &time), None);
+    assert_eq!(conditional_value("no @ (Nov-Mar)", &time), None);
+    assert_eq!(is_open("06:00-22:00", &time), Some(true));
+    assert_eq!(is_open("Mo-Su 09:00-18:00", &time), Some(false));
+    assert_eq!(is_open("24/7; Fr off", &time), Some(false));
+    let night: LocalTime = "2023-05-12T23:30".parse().unwrap();
+    assert_eq!(conditional_value("no @ (sunset-sunrise)", &night), Some("no"));
+    assert_eq!(is_open("PH off", &night), None);
+}
+
+#[test]
+fn adds_time_across_months() {
+    let time: LocalTime = "2023-02-28T23:50".parse().unwrap();
+    assert_eq!(time.plus_seconds(20 * 60).to_string(), "2023-03-01T00:10");
+    assert!(time.plus_seconds(8 * 3600).is_rush_hour());
+    assert_eq!(time.plus_seconds(20 * 60).seconds_since(&time), 20 * 60);
+}
@@ -0,0 +1,100 @@
+//! Node elevations, from a digital elevation model loaded in PostGIS, like
+//! `raster2pgsql -s 4326 -t 100x100 srtm.tif dem | psql`.
+
+use super::node::Node;
+use crate::{config::CONFIG, region::RegionClient};
+use sqlx::Row;
+use std::{collections::HashMap, error::Error, ops::DerefMut};
+
+/// The elevations of the `ids` nodes in decimeters, empty when no `DEM_TABLE` is
+/// configured. The nodes outside the model are left out.
+pub async fn elevations(
+    pg_client: RegionClient,
+    ids: &[i64],
+) -> Result<HashMap<i64, i32>, Box<dyn Error>> {
+    if let Some(store) = pg_client.region.embedded_store() {
+        let nodes = ids.iter().filter_map(|id| store.node(*id));
+        return Ok(nodes.filter_map(|node| Some((node.id, node.elevation?))).collect());
+    }
+    let Some(table) = CONFIG.dem_table.as_ref() else {
+        return Ok(HashMap::new());
+    };
+    let rows = sqlx::query(&format!(
+        r#"
+        select n.id, ST_Value(d.rast, p.point) as elevation
+        from planet_osm_nodes n
+        cross join lateral (
+            select ST_SetSRID(ST_MakePoint(n.lon / 1e7, n.lat / 1e7), 4326) as point
+        ) p
+        join {table} d on ST_Intersects(d.rast, p.point)
+        where n.id = any($1)
+        "#
+    ))
+    .bind(ids)
+    .fetch_all(pg_client.lock().await?.deref_mut())
+    .await?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            let elevation: Option<f64> = row.get("elevation");
+            Some((row.get("id"), (elevation? * 10.0).round() as i32))
+        })
+        .collect())
+}
+
+/// The total elevation gained and lost along `path`, in meters, `None` when its
+/// nodes have no elevation.
+pub fn climb(path: &[Node]) -> Option<(i32, i32)> {
+    let elevations: Vec<i32> = path.iter().filter_map(|node| node.elevation).collect();
+    if elevations.len() < 2 {
+        return None;
+    }
+    let (mut ascent, mut descent) = (0, 0);
+    for pair in elevations.windows(2) {
+        let change = pair[1] - pair[0];
+        if change > 0 {
+            ascent += change;
+        } else {
+            descent -= change;
+        }
+    }
+    // From decimeters
+    Some(((ascent + 5) / 10, (descent + 5) / 10))
+}
+
+/// The grade of an `incline` tag in percent, like `12%`, `-8%` or `5°`. The `up`
+/// and `down` values say nothing of the steepness.
+pub fn parse_incline(incline: &str) -> Option<f64> {
+    let incline = incline.trim();
+    if let Some(degrees) = incline.strip_suffix('°') {
+        let degrees: f64 = degrees.trim().parse().ok()?;
+        return Some(degrees.to_radians().tan() * 100.0);
+    }
+    incline.trim_end_matches('%').trim().parse().ok()
+}
+
+#[test]
+fn parses_inclines() {
+    assert_eq!(parse_incline("12%"), Some(12.0));
+    assert_eq!(parse_incline("-8 %"), Some(-8.0));
+    assert_eq!(parse_incline("45°").map(f64::round), Some(100.0));
+    assert_eq!(parse_incline("up"), None);
+}
+
+#[test]
+fn sums_ascent_and_descent() {
+    let path: Vec<Node> = [Some(100), Some(150), None, Some(120), Some(300)]
+        .into_iter()
+        .enumerate()
+        .map(|(id, elevation)| Node {
+            id: id as i64,
+            lat: 0,
+            lon: 0,
+            adjacent_nodes: vec![],
+            highway: None,
+            elevation,
+        })
+        .collect();
+    assert_eq!(climb(&path), Some((23, 3)));
+    assert_eq!(climb(&path[2..3]), None);
+}
@@ -1,2 +1,15 @@
+pub mod access;
+pub mod bbox;
+pub mod cache;
+pub mod closure;
+pub mod component;
+pub mod conditional;
+pub mod elevation;
 pub mod node;
+pub mod oneway;
+pub mod poi;
+pub mod retry;
+pub mod saved_route;
+pub mod snapshot;
+pub mod store;
 pub mod way;
@@ -1,12 +1,143 @@
+use super::{
+    access::{bicycle_access, dismount, Access},
+    bbox::BoundingBox,
+    component::components,
+    elevation::{elevations, parse_incline},
+    oneway::bicycle_directions,
+    retry::retry,
+    store::GraphStore,
+};
 use crate::{
-    astar::astar,
-    get_pg_client,
-    route::{Model, RouteRequest},
+    alternatives::{self, edge_key},
+    astar::{astar, dijkstra_partial, Outcome, Settled},
+    config::CONFIG,
+    error::{FieldError, RouteError},
+    instruction::turn_angle,
+    region::{Region, RegionClient},
+    route::{final_bearing, Avoid, LatLon, Model, Prefer, RouteRequest},
+    rules::Rules,
+    safety::{is_protected, is_quiet_street},
+    search_tree::SearchTree,
+    searches_cancelled,
+    throttle::search_permit,
+    weather::{Weather, Wind},
 };
+use rustc_hash::FxHasher;
 use serde::{Deserialize, Serialize};
-use sqlx::{pool::PoolConnection, Postgres, Row};
-use std::{collections::HashMap, error::Error, ops::DerefMut, sync::Arc};
-use tokio::sync::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    hash::{Hash, Hasher},
+    mem::size_of,
+    ops::DerefMut,
+    sync::{
+        atomic::{self, AtomicBool, AtomicUsize},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use utoipa::ToSchema;
+
+/// How often a search reports its progress.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How far along a route search is.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct SearchProgress {
+    /// How many nodes were expanded.
+    pub expanded: usize,
+    /// The straight-line distance to the end of the closest expanded node, in meters.
+    pub best_distance: i32,
+}
+
+/// The diagnostics of a search, for tuning the cost models.
+#[derive(Clone, Debug, Default, Serialize, ToSchema)]
+pub struct SearchStats {
+    /// How many nodes were expanded.
+    pub expanded: usize,
+    /// The nodes found in the node caches.
+    pub cache_hits: usize,
+    /// The nodes loaded from the database.
+    pub cache_misses: usize,
+    /// Whether the route came from the route cache, without searching.
+    pub cached_route: bool,
+    /// Whether the search ran out of time, the route then stopping at the node
+    /// reached closest to the end.
+    pub partial: bool,
+    /// How long the search took, in milliseconds.
+    pub search_ms: u64,
+    /// The part of `search_ms` spent loading nodes from the database.
+    pub database_ms: u64,
+    /// The rest of `search_ms`, spent computing.
+    pub cpu_ms: u64,
+    /// The cost of the route found.
+    pub cost: i64,
+    /// The heuristic at the start over `cost`, closer to 1 the better it guides the
+    /// search.
+    pub heuristic_ratio: f64,
+    /// How far the start was moved to the graph, in meters.
+    pub start_snap_distance: i32,
+    /// How far the end was moved to the graph, in meters.
+    pub end_snap_distance: i32,
+    /// Whether the route was long enough to only search the major ways away from its
+    /// ends.
+    pub hierarchical: bool,
+    /// The GeoJSON of the nodes expanded and of the edges to their successors, when
+    /// `search_tree` was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub search_tree: Option<SearchTree>,
+}
+
+impl SearchStats {
+    /// Fills in the statistics of a search over once it found a route costing `cost`,
+    /// `heuristic` being the heuristic at the start.
+    fn finish(
+        &mut self,
+        client: &RegionClient,
+        elapsed: Duration,
+        expanded: usize,
+        cost: i64,
+        heuristic: i32,
+    ) {
+        let loads = &client.loads;
+        self.expanded = expanded;
+        self.cache_hits = loads.cache_hits.load(atomic::Ordering::Relaxed);
+        self.cache_misses = loads.cache_misses.load(atomic::Ordering::Relaxed);
+        self.search_ms = elapsed.as_millis() as u64;
+        self.database_ms = loads.database_micros.load(atomic::Ordering::Relaxed) / 1000;
+        self.cpu_ms = self.search_ms.saturating_sub(self.database_ms);
+        self.cost = cost;
+        if cost > 0 {
+            self.heuristic_ratio = heuristic as f64 / cost as f64;
+        }
+    }
+
+    /// Adds the statistics of the next leg of a route.
+    pub fn add_leg(&mut self, leg: SearchStats) {
+        let cost = self.cost + leg.cost;
+        if cost > 0 {
+            self.heuristic_ratio = (self.heuristic_ratio * self.cost as f64
+                + leg.heuristic_ratio * leg.cost as f64)
+                / cost as f64;
+        }
+        self.expanded += leg.expanded;
+        self.cache_hits += leg.cache_hits;
+        self.cache_misses += leg.cache_misses;
+        self.cached_route |= leg.cached_route;
+        self.partial |= leg.partial;
+        self.hierarchical |= leg.hierarchical;
+        self.search_ms += leg.search_ms;
+        self.database_ms += leg.database_ms;
+        self.cpu_ms += leg.cpu_ms;
+        self.cost = cost;
+        self.end_snap_distance = leg.end_snap_distance;
+        match (&mut self.search_tree, leg.search_tree) {
+            (Some(tree), Some(leg_tree)) => tree.append(leg_tree),
+            (tree, leg_tree) => *tree = tree.take().or(leg_tree),
+        }
+    }
+}
 
 fn get_positions<T: PartialEq>(iter: impl Iterator<Item = T>, elem: T) -> Vec<usize> {
     iter.enumerate()
@@ -18,13 +149,25 @@ fn get_positions<T: PartialEq>(iter: impl Iterator<Item = T>, elem: T) -> Vec<us
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct AdjacentNode {
     pub node_id: i64,
+    /// The OSM way leading to the node.
+    pub way_id: i64,
     pub tags: HashMap<String, String>,
     pub distance: i32,
     pub intermediate_nodes: Option<Vec<i64>>,
+    /// How many imported GPX traces ride along the way.
+    #[serde(default)]
+    pub popularity: i32,
+    /// The collisions involving cyclists per kilometer of the way.
+    #[serde(default)]
+    pub collisions: i32,
+    /// The most important signed bike route network the way is part of, from 0 for
+    /// none to 3 for a national or international one.
+    #[serde(default)]
+    pub bike_network: i32,
 }
 
 impl AdjacentNode {
-    fn has_tag_value(&self, key: &str, value: &str) -> bool {
+    pub fn has_tag_value(&self, key: &str, value: &str) -> bool {
         if let Some(v) = self.tags.get(key) {
             return v == value;
         }
@@ -34,6 +177,224 @@ impl AdjacentNode {
     fn has_tag(&self, key: &str) -> bool {
         self.tags.contains_key(key)
     }
+
+    pub fn is_roundabout(&self) -> bool {
+        self.has_tag_value("junction", "roundabout") || self.has_tag_value("junction", "circular")
+    }
+
+    /// How much riding this edge after dark is preferred or avoided, paths away
+    /// from streets being unlit unless tagged otherwise.
+    fn night_factor(&self) -> f64 {
+        let path = ["path", "footway", "cycleway", "track", "bridleway"]
+            .iter()
+            .any(|highway| self.has_tag_value("highway", highway));
+        match self.tags.get("lit").map(String::as_str) {
+            Some("yes" | "24/7" | "automatic" | "limited") => 0.8,
+            _ if path => 3.0,
+            Some("no") => 1.5,
+            _ => 1.0,
+        }
+    }
+
+    /// Whether the edge belongs to the network of the long routes: the arterials,
+    /// the roads between villages, the signed bike routes, the cycleways and ferries.
+    fn is_major(&self) -> bool {
+        self.is_arterial()
+            || self.has_tag_value("highway", "unclassified")
+            || self.bike_network > 0
+            || is_protected(self)
+            || self.has_tag_value("route", "ferry")
+    }
+
+    pub fn is_arterial(&self) -> bool {
+        self.tags
+            .get("highway")
+            .is_some_and(|highway| ARTERIALS.contains(&highway.trim_end_matches("_link")))
+    }
+
+    fn is_unpaved(&self) -> bool {
+        self.tags
+            .get("surface")
+            .is_some_and(|surface| UNPAVED_SURFACES.contains(&surface.as_str()))
+    }
+
+    fn is_cobbled(&self) -> bool {
+        self.tags
+            .get("surface")
+            .is_some_and(|surface| COBBLED_SURFACES.contains(&surface.as_str()))
+    }
+
+    /// Whether the edge has one of the `avoid` features, ferries being left to
+    /// `allow_ferries` and steps never taken.
+    fn is_avoided(&self, avoid: &[Avoid]) -> bool {
+        avoid.iter().any(|feature| match feature {
+            Avoid::Ferries | Avoid::Steps => false,
+            Avoid::Unpaved => self.is_unpaved(),
+            Avoid::Trunk => self.has_tag_value("highway", "trunk")
+                || self.has_tag_value("highway", "trunk_link"),
+            Avoid::Bridges => self.tags.get("bridge").is_some_and(|bridge| bridge != "no"),
+        })
+    }
+
+    /// Whether the bike is pushed on the edge, or would be on a footway not open to
+    /// bikes.
+    fn is_pushed(&self) -> bool {
+        let open_to_bikes = ["yes", "designated", "permissive"]
+            .iter()
+            .any(|value| self.has_tag_value("bicycle", value));
+        dismount(&self.tags).is_some()
+            || (self.has_tag_value("highway", "footway") && !open_to_bikes)
+    }
+
+    /// The length pushed, in meters, after taking the edge having pushed `pushed`,
+    /// `None` over `max_dismount`. It is only counted when there is a maximum.
+    fn pushed_after(&self, pushed: i32, max_dismount: Option<i32>) -> Option<i32> {
+        let Some(max_dismount) = max_dismount else {
+            return Some(0);
+        };
+        let pushed = pushed + if self.is_pushed() { self.distance } else { 0 };
+        (pushed <= max_dismount).then_some(pushed)
+    }
+
+    /// Whether children may ride the edge: on a cycleway or a lane, or on a street
+    /// limited to `FAMILY_MAX_SPEED`. Arterials without a known limit are taken to
+    /// be faster.
+    fn is_for_children(&self, rules: &Rules) -> bool {
+        if is_protected(self) || self.has_cycle_lane() {
+            return true;
+        }
+        match rules.speed_limit(self) {
+            Some(speed) => speed <= FAMILY_MAX_SPEED,
+            None => !self.is_arterial() && !self.has_tag_value("highway", "trunk"),
+        }
+    }
+
+    /// How much more the edge costs road bikes than its surface says.
+    fn road_bike_factor(&self) -> f64 {
+        let curbs = ["sidewalk", "crossing"]
+            .iter()
+            .any(|footway| self.has_tag_value("footway", footway))
+            || self.tags.get("kerb").is_some_and(|kerb| !["flush", "no"].contains(&kerb.as_str()));
+        let unknown_track = !self.tags.contains_key("surface")
+            && !self.tags.contains_key("tracktype")
+            && ["track", "path"].iter().any(|highway| self.has_tag_value("highway", highway));
+        let mut factor = 1.0;
+        if curbs {
+            factor *= CURB_FACTOR;
+        }
+        if unknown_track || self.tags.get("tracktype").is_some_and(|grade| grade != "grade1") {
+            factor *= UNKNOWN_TRACK_FACTOR;
+        }
+        factor
+    }
+
+    /// How much costlier the `wind` makes riding the edge to `bearing`, or cheaper
+    /// with the wind at the back.
+    fn wind_factor(&self, wind: &Wind, bearing: f64) -> f64 {
+        let exposed = self.distance >= EXPOSED_LENGTH
+            || self.tags.get("bridge").is_some_and(|bridge| bridge != "no");
+        let felt = if exposed { 1.0 } else { SHELTERED_WIND };
+        (1.0 + HEADWIND_COST * felt * wind.headwind(bearing)).max(MIN_WIND_FACTOR)
+    }
+
+    /// How much cheaper the `prefer` features of the edge make it.
+    fn preference_factor(&self, prefer: &[Prefer]) -> f64 {
+        let preferred = prefer.iter().filter(|feature| match feature {
+            Prefer::ProtectedCycleway => is_protected(self),
+            Prefer::QuietStreets => is_quiet_street(self),
+            Prefer::SignedRoutes => self.bike_network > 0 || self.has_tag_value("route", "bicycle"),
+        });
+        PREFERENCE_FACTOR
+            .powi(preferred.count() as i32)
+            .max(MIN_PREFERENCE_FACTOR)
+    }
+
+    /// Whether the way has a lane or track of its own for bikes.
+    pub fn has_cycle_lane(&self) -> bool {
+        ["cycleway", "cycleway:left", "cycleway:right", "cycleway:both"]
+            .iter()
+            .any(|key| {
+                self.tags
+                    .get(*key)
+                    .is_some_and(|value| value.contains("lane") || value.contains("track"))
+            })
+    }
+
+    /// How long taking this edge by ferry takes, in seconds, `None` when it is not
+    /// a ferry. The crossing time comes from the way `duration` tag when it has one.
+    pub fn ferry_duration(&self) -> Option<i32> {
+        if !self.has_tag_value("route", "ferry") {
+            return None;
+        }
+        let crossing = self
+            .tags
+            .get("duration")
+            .and_then(|duration| parse_duration(duration))
+            .unwrap_or((self.distance as f64 / FERRY_SPEED) as i32);
+        // On average, half the time between departures is spent waiting
+        let wait = self
+            .tags
+            .get("interval")
+            .and_then(|interval| parse_duration(interval))
+            .map_or(0, |interval| interval / 2);
+        Some(FERRY_BOARDING_TIME + wait + crossing)
+    }
+
+    /// This edge ending at `node` when it goes through it, so that a search can
+    /// stop in the middle of a way.
+    pub fn truncated_at(&self, node: &Node) -> Option<AdjacentNode> {
+        let intermediate_nodes = self.intermediate_nodes.as_ref()?;
+        let position = intermediate_nodes.iter().position(|id| *id == node.id)?;
+        // The rest of the edge, from `node` on
+        let rest = node
+            .adjacent_nodes
+            .iter()
+            .find(|a_node| a_node.node_id == self.node_id && a_node.way_id == self.way_id)?;
+        Some(AdjacentNode {
+            node_id: node.id,
+            way_id: self.way_id,
+            tags: self.tags.clone(),
+            distance: self.distance - rest.distance,
+            intermediate_nodes: (position > 0).then(|| intermediate_nodes[..position].to_vec()),
+            popularity: self.popularity,
+            collisions: self.collisions,
+            bike_network: self.bike_network,
+        })
+    }
+
+    /// An estimate of the memory used by this adjacent node, in bytes.
+    fn approximate_size(&self) -> usize {
+        let tags_size: usize = self
+            .tags
+            .iter()
+            .map(|(k, v)| size_of::<(String, String)>() + k.capacity() + v.capacity())
+            .sum();
+        let intermediate_size = self
+            .intermediate_nodes
+            .as_ref()
+            .map_or(0, |nodes| nodes.capacity() * size_of::<i64>());
+        size_of::<Self>() + tags_size + intermediate_size
+    }
+}
+
+/// The average ferry speed, when the ferry has no duration, in meters per second.
+const FERRY_SPEED: f64 = 20.0 / 3.6;
+
+/// The average time waiting for and boarding a ferry, in seconds.
+const FERRY_BOARDING_TIME: i32 = 10 * 60;
+
+/// Parses an OSM duration, `mm`, `hh:mm` or `hh:mm:ss`, into seconds.
+fn parse_duration(duration: &str) -> Option<i32> {
+    let parts: Vec<i32> = duration
+        .split(':')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match parts[..] {
+        [minutes] => Some(minutes * 60),
+        [hours, minutes] => Some(hours * 3600 + minutes * 60),
+        [hours, minutes, seconds] => Some(hours * 3600 + minutes * 60 + seconds),
+        _ => None,
+    }
 }
 
 impl std::hash::Hash for AdjacentNode {
@@ -69,179 +430,812 @@ pub struct Node {
     /// The longitude in decimicro degrees (10⁻⁷ degrees).
     pub lon: i32,
     pub adjacent_nodes: Vec<AdjacentNode>,
+    /// The node `highway` tag, like `traffic_signals`.
+    pub highway: Option<String>,
+    /// In decimeters, when there is an elevation model.
+    #[serde(default)]
+    pub elevation: Option<i32>,
+}
+
+/// The ends of a route snapped to the graph, in the weather it is searched in.
+#[derive(Debug)]
+pub struct Snapped {
+    start: LatLon,
+    end: LatLon,
+    nodes: (Node, Node),
+    conditions: Weather,
+}
+
+/// A node reached by the search with the length pushed to reach it, and the index
+/// of the edge of the previous node it was reached by. Only the node and the
+/// length pushed tell the states of the search apart, the search keeps the edge of
+/// the cheapest way to reach them.
+#[derive(Clone, Debug)]
+struct Reached {
+    node: Node,
+    pushed: i32,
+    edge: Option<usize>,
+}
+
+impl Reached {
+    /// The nodes reached from `node` by its `edges` with their costs, having pushed
+    /// `pushed` meters before.
+    fn successors<'a>(
+        node: &'a Node,
+        edges: impl IntoIterator<Item = (&'a AdjacentNode, Node, i64)>,
+        pushed: i32,
+        max_dismount: Option<i32>,
+    ) -> Vec<(Reached, i64)> {
+        edges
+            .into_iter()
+            .filter_map(|(edge, next, cost)| {
+                let pushed = edge.pushed_after(pushed, max_dismount)?;
+                // The edges are borrowed from the node, found back by address
+                let index = node
+                    .adjacent_nodes
+                    .iter()
+                    .position(|a_node| std::ptr::eq(a_node, edge));
+                let reached = Reached {
+                    node: next,
+                    pushed,
+                    edge: index,
+                };
+                Some((reached, cost))
+            })
+            .collect()
+    }
+}
+
+impl PartialEq for Reached {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node && self.pushed == other.pushed
+    }
+}
+
+impl Eq for Reached {}
+
+impl std::hash::Hash for Reached {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.node.hash(state);
+        self.pushed.hash(state);
+    }
 }
 
-lazy_static! {
-    static ref NODE_CACHE: Arc<RwLock<HashMap<i64, Node>>> = Arc::new(RwLock::new(HashMap::new()));
+/// The edges the search took along `path`, each one from a node to the next. An
+/// edge ending in the middle of its way is truncated there.
+fn searched_edges(path: &[Reached]) -> Vec<Option<AdjacentNode>> {
+    path.windows(2)
+        .map(|pair| {
+            let (from, to) = (&pair[0].node, &pair[1].node);
+            let edge = from.adjacent_nodes.get(pair[1].edge?)?;
+            if edge.node_id == to.id {
+                Some(edge.clone())
+            } else {
+                edge.truncated_at(to)
+            }
+        })
+        .collect()
+}
+
+const ARTERIALS: [&str; 4] = ["trunk", "primary", "secondary", "tertiary"];
+
+const UNPAVED_SURFACES: [&str; 11] = [
+    "unpaved",
+    "compacted",
+    "fine_gravel",
+    "gravel",
+    "pebblestone",
+    "ground",
+    "earth",
+    "dirt",
+    "grass",
+    "sand",
+    "mud",
+];
+
+const COBBLED_SURFACES: [&str; 3] = ["sett", "cobblestone", "unhewn_cobblestone"];
+
+/// How much more the cobbled ways cost with `avoid_cobbles`.
+const COBBLES_PENALTY: i64 = 20;
+
+/// How many GPX traces make a way as popular as it gets for `prefer_popular`.
+const POPULAR_TRACES: i32 = 50;
+
+/// The bike route networks, from `network` tags, by increasing importance.
+const BIKE_NETWORKS: [&str; 4] = ["lcn", "rcn", "ncn", "icn"];
+
+/// How much cheaper the ways of each `bike_network` rank are with the strongest
+/// bike route preference.
+const BIKE_NETWORK_DISCOUNTS: [f64; 4] = [0.0, 0.2, 0.3, 0.4];
+
+/// The collisions per kilometer making a way twice as costly for the Safe model,
+/// which is as costly as collisions make it.
+const DANGEROUS_COLLISIONS: i32 = 20;
+
+/// How much more the edges steeper than the requested maximum grade cost, they
+/// are still taken when there is no way around.
+const STEEP_EDGE_PENALTY: i64 = 10;
+
+/// How much cheaper each preferred feature of an edge makes it.
+const PREFERENCE_FACTOR: f64 = 0.8;
+
+/// The cheapest preferred features make an edge, however many it has, so that a
+/// preference only nudges the route of the model.
+const MIN_PREFERENCE_FACTOR: f64 = 0.6;
+
+/// The turn from the heading of the rider at the start, in degrees, past which
+/// leaving the start is turning around.
+const U_TURN_ANGLE: f64 = 135.0;
+
+/// How much more the edges leaving the start by turning around cost, they are
+/// still taken when the rider is heading into a dead end.
+const U_TURN_PENALTY: i64 = 5;
+
+/// The fastest speed limit of the ways without cycling infrastructure the Family
+/// model takes, in km/h.
+const FAMILY_MAX_SPEED: f64 = 30.0;
+
+/// The cost of crossing an arterial for the Family model, as many meters of a
+/// quiet street.
+const FAMILY_CROSSING_COST: i64 = 300;
+
+/// How much cheaper paths apart from cars are for the Family model.
+const FAMILY_PATH_FACTOR: f64 = 0.8;
+
+/// How much more the ways with curbs cost road bikes: sidewalks, crossings and the
+/// ways with a raised `kerb`.
+const CURB_FACTOR: f64 = 2.0;
+
+/// How much more the tracks and paths without a surface tagged cost road bikes,
+/// most of them being unpaved.
+const UNKNOWN_TRACK_FACTOR: f64 = 3.0;
+
+/// The length from which an edge is exposed to the wind, long straight ways being
+/// in the open more often than not, in meters. Bridges always are.
+const EXPOSED_LENGTH: i32 = 300;
+
+/// How much of the wind is felt on the edges that are not exposed.
+const SHELTERED_WIND: f64 = 0.25;
+
+/// How much more costly each km/h of headwind makes an edge.
+const HEADWIND_COST: f64 = 0.02;
+
+/// The cheapest a tailwind makes an edge, only slightly favoring it.
+const MIN_WIND_FACTOR: f64 = 0.9;
+
+/// The average cycling speed, in meters per second.
+pub const CYCLING_SPEED: f64 = 15.0 / 3.6;
+
+/// The time lost at nodes by their `highway` tag, in seconds.
+const NODE_DELAYS: [(&str, i32); 4] = [
+    ("traffic_signals", 20),
+    ("stop", 8),
+    ("crossing", 4),
+    ("give_way", 3),
+];
+
+/// How much more the ways only open to reach a place along them cost, so that routes
+/// only take them at their ends or when there is no way around.
+const DESTINATION_PENALTY: i64 = 10;
+
+/// The condition on `planet_osm_line pol` for a line to be a highway a bike could
+/// use, its access tags are checked with `bicycle_access`. Spelled out in the
+/// `candidates` query as well.
+pub const ROUTABLE_LINE: &str = r#"
+    pol.building is NULL and
+    pol.highway is not null and
+    pol.highway != 'motorway' and
+    pol.highway != 'motorway_link' and
+    pol.highway != 'steps' and
+    pol.highway != 'track' and
+    pol.aeroway is NULL
+"#;
+
+/// The ways of `planet_osm_ways` the search may take, and more: the ones a bike may
+/// take have a `highway` or a `bicycle` tag.
+pub const ROUTABLE_WAY: &str = "(tags @> array['highway'] or tags @> array['bicycle'])";
+
+/// How many of the closest lines are checked for access when snapping a point.
+const SNAP_CANDIDATES: i64 = 10;
+
+/// A row of the node query, one per way going through the node, with the columns
+/// of the left joins missing when the node is on no way.
+struct NodeRow {
+    lat: i32,
+    lon: i32,
+    way_id: Option<i64>,
+    tags: Option<Vec<String>>,
+    nodes: Option<Vec<i64>>,
+    highway: Option<String>,
+    traces: Option<i32>,
+    collisions_per_km: Option<i32>,
+    tags_way_and_rel: Option<Vec<String>>,
+}
+
+/// A row of the node query from `node_adjacency`, one per edge.
+struct AdjacencyRow {
+    lat: i32,
+    lon: i32,
+    highway: Option<String>,
+    neighbor_id: Option<i64>,
+    way_id: Option<i64>,
+    distance: Option<i32>,
+    intermediate_nodes: Option<Vec<i64>>,
+    tags: Option<Vec<String>>,
+    traces: Option<i32>,
+    collisions_per_km: Option<i32>,
+    tags_way_and_rel: Option<Vec<String>>,
+}
+
+/// A line near a point to snap.
+struct CandidateRow {
+    nodes: Vec<i64>,
+    tags: Option<Vec<String>>,
+    distance: f64,
+}
+
+/// The `[key, value, key, value...]` tags of a `planet_osm_ways` row.
+fn parse_tags(tag_strings: &[String]) -> HashMap<String, String> {
+    let mut tags: HashMap<String, String> = HashMap::new();
+    let mut ts_iter = tag_strings.iter();
+    while let Some(tag) = ts_iter.next() {
+        match ts_iter.next() {
+            Some(v) => tags.insert(tag.clone(), v.clone()),
+            None => tags.insert(tag.clone(), "".to_string()),
+        };
+    }
+    tags
+}
+
+/// The coordinates of the `ids` nodes, in decimicro degrees.
+pub(crate) async fn coordinates(
+    pg_client: RegionClient,
+    ids: &[i64],
+) -> Result<HashMap<i64, (i32, i32)>, Box<dyn Error>> {
+    if let Some(store) = pg_client.region.embedded_store() {
+        let nodes = ids.iter().filter_map(|id| store.node(*id));
+        return Ok(nodes.map(|node| (node.id, (node.lat, node.lon))).collect());
+    }
+    let rows: Vec<(i64, i32, i32)> =
+        sqlx::query_as("select id, lat, lon from planet_osm_nodes where id = any($1)")
+            .bind(ids)
+            .fetch_all(pg_client.lock().await?.deref_mut())
+            .await?;
+    Ok(rows.into_iter().map(|(id, lat, lon)| (id, (lat, lon))).collect())
+}
+
+/// The `ids` nodes shared by several ways, where a route can change ways, or
+/// delaying riders, so that their delay is counted.
+async fn junctions(pg_client: RegionClient, ids: &[i64]) -> Result<HashSet<i64>, Box<dyn Error>> {
+    let delayed: Vec<&str> = NODE_DELAYS.iter().map(|(highway, _)| *highway).collect();
+    let ids = sqlx::query_scalar(
+        r#"
+        select n.id
+        from unnest($1::int8[]) as n(id)
+        join planet_osm_ways w
+            on w.nodes @> array[n.id]
+        group by n.id
+        having count(*) > 1
+        union
+        select osm_id as id
+        from planet_osm_point
+        where osm_id = any($1) and highway = any($2)
+        "#,
+    )
+    .bind(ids)
+    .bind(delayed)
+    .fetch_all(pg_client.lock().await?.deref_mut())
+    .await?;
+    Ok(ids.into_iter().collect())
+}
+
+/// The closest nodes of `store` with an edge open to bikes, as candidates of a single
+/// node each, since there are no lines to snap to.
+fn stored_candidates(
+    store: &dyn GraphStore,
+    lat: f64,
+    lon: f64,
+    rules: &Rules,
+) -> Vec<(Vec<i64>, i32)> {
+    // Leaving room for the nodes only on ways closed to bikes
+    let limit = SNAP_CANDIDATES as usize;
+    store
+        .closest(lat, lon, limit * 4)
+        .into_iter()
+        .filter(|(id, _)| {
+            store.node(*id).is_some_and(|node| {
+                node.adjacent_nodes
+                    .iter()
+                    .any(|edge| bicycle_access(&edge.tags, None, rules).allowed())
+            })
+        })
+        .take(limit)
+        .map(|(id, distance)| (vec![id], distance))
+        .collect()
+}
+
+/// The indices of the start and end candidate lines in the same component with the
+/// smallest total distance, the closest ones when none share a component.
+fn connected_candidates(
+    starts: &[(Vec<i64>, i32)],
+    ends: &[(Vec<i64>, i32)],
+    components: &HashMap<i64, i64>,
+) -> (usize, usize) {
+    // A way is in a single component
+    let component = |node_ids: &[i64]| node_ids.iter().find_map(|id| components.get(id));
+    starts
+        .iter()
+        .enumerate()
+        .flat_map(|s| ends.iter().enumerate().map(move |e| (s, e)))
+        .filter(|((_, (s, _)), (_, (e, _)))| {
+            component(s).is_some() && component(s) == component(e)
+        })
+        .min_by_key(|((_, (_, s)), (_, (_, e)))| s + e)
+        .map_or((0, 0), |((i, _), (j, _))| (i, j))
+}
+
+/// The rank of the most important bike route network in `[key, value...]` tags,
+/// 0 when there is none.
+pub fn bike_network(tag_strings: &[String]) -> i32 {
+    tag_strings
+        .chunks(2)
+        .filter_map(|pair| match pair {
+            [key, value] if key == "network" => BIKE_NETWORKS.iter().position(|n| n == value),
+            _ => None,
+        })
+        .map(|position| (position as i32 + 1).min(3))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Follows `nodes` from the node `id` at `from` up to the first junction, or the end
+/// of the way, returning that node, the nodes skipped on the way and the distance.
+fn walk(
+    id: i64,
+    from: (i32, i32),
+    nodes: &[i64],
+    junctions: &HashSet<i64>,
+    coords: &HashMap<i64, (i32, i32)>,
+) -> Option<(i64, Option<Vec<i64>>, i32)> {
+    let mut intermediate_nodes = vec![];
+    let mut total = 0;
+    let (mut lat, mut lon) = from;
+    for (i, node_id) in nodes.iter().enumerate() {
+        let &(next_lat, next_lon) = coords.get(node_id)?;
+        total += distance(lat, lon, next_lat, next_lon);
+        (lat, lon) = (next_lat, next_lon);
+        if junctions.contains(node_id) || *node_id == id || i == nodes.len() - 1 {
+            let intermediate_nodes =
+                (!intermediate_nodes.is_empty()).then_some(intermediate_nodes);
+            return Some((*node_id, intermediate_nodes, total));
+        }
+        intermediate_nodes.push(*node_id);
+    }
+    None
+}
+
+/// The edges from the node `id` at `from` along the way of `nodes` and `tags`, in the
+/// directions bikes may take it: the node reached, the nodes skipped on the way and
+/// the distance.
+pub fn way_edges(
+    id: i64,
+    from: (i32, i32),
+    nodes: &[i64],
+    tags: &HashMap<String, String>,
+    junctions: &HashSet<i64>,
+    coords: &HashMap<i64, (i32, i32)>,
+) -> Vec<(i64, Option<Vec<i64>>, i32)> {
+    let directions = bicycle_directions(tags);
+    let mut edges = vec![];
+    for node_index in get_positions(nodes.iter(), &id) {
+        if directions.forward {
+            edges.push(walk(id, from, &nodes[node_index + 1..], junctions, coords));
+        }
+        if directions.backward {
+            let previous: Vec<i64> = nodes[..node_index].iter().rev().copied().collect();
+            edges.push(walk(id, from, &previous, junctions, coords));
+        }
+    }
+    edges.into_iter().flatten().collect()
+}
+
+/// Whether riders are delayed at a node with this `highway` tag, which makes it a
+/// junction.
+pub fn is_delayed(highway: &str) -> bool {
+    NODE_DELAYS.iter().any(|(delayed, _)| *delayed == highway)
 }
 
 impl Node {
     pub async fn get(
-        pg_client: Arc<Mutex<PoolConnection<Postgres>>>,
+        pg_client: RegionClient,
         id: i64,
     ) -> Result<Self, Box<dyn Error>> {
         // We check if the node is in the cache
-        if let Some(node) = NODE_CACHE.read().await.get(&id) {
-            return Ok(node.clone());
+        if let Some(node) = pg_client.region.cached_node(id).await {
+            pg_client.count_cache_hit();
+            return Ok(node);
+        }
+        let loading = Instant::now();
+        let node = retry(&pg_client, || Node::load(pg_client.to_owned(), id)).await?;
+        pg_client.count_database_load(loading.elapsed());
+        pg_client.region.cache_node(&node).await;
+        Ok(node)
+    }
+
+    /// Loads the node from the database, from the edges materialized by the import
+    /// when there are some.
+    pub(crate) async fn load(pg_client: RegionClient, id: i64) -> Result<Self, Box<dyn Error>> {
+        if pg_client.region.has_adjacency(pg_client.to_owned()).await? {
+            Node::load_adjacency(pg_client, id).await
+        } else {
+            Node::load_from_ways(pg_client, id).await
+        }
+    }
+
+    /// Loads the node and its edges from `node_adjacency`, in a single lookup.
+    async fn load_adjacency(pg_client: RegionClient, id: i64) -> Result<Self, Box<dyn Error>> {
+        let rows = sqlx::query_as!(
+            AdjacencyRow,
+            r#"
+            select n.lat, n.lon, p.highway, a.neighbor_id as "neighbor_id?",
+                a.way_id as "way_id?", a.distance as "distance?", a.intermediate_nodes, w.tags,
+                wp.traces as "traces?", wc.collisions_per_km as "collisions_per_km?",
+                wl.tags_way_and_rel
+            from planet_osm_nodes n
+            left join planet_osm_point p
+                on p.osm_id = n.id
+            left join node_adjacency a
+                on a.node_id = n.id
+            left join planet_osm_ways w
+                on w.id = a.way_id
+            left join way_popularity wp
+                on wp.way_id = a.way_id
+            left join way_collisions wc
+                on wc.way_id = a.way_id
+            left join ways_length wl
+                on wl.ways_id = a.way_id
+            where n.id = $1
+            "#,
+            id
+        )
+        .fetch_all(pg_client.lock().await?.deref_mut())
+        .await?;
+        let elevation = elevations(pg_client.to_owned(), &[id]).await?.get(&id).copied();
+        let mut node = Node {
+            id,
+            lat: 0,
+            lon: 0,
+            adjacent_nodes: vec![],
+            highway: None,
+            elevation,
+        };
+        for row in rows {
+            node.lat = row.lat;
+            node.lon = row.lon;
+            node.highway = row.highway;
+            let (Some(node_id), Some(way_id), Some(distance)) =
+                (row.neighbor_id, row.way_id, row.distance)
+            else {
+                continue;
+            };
+            node.adjacent_nodes.push(AdjacentNode {
+                node_id,
+                way_id,
+                tags: parse_tags(&row.tags.unwrap_or_default()),
+                distance,
+                intermediate_nodes: row.intermediate_nodes,
+                popularity: row.traces.unwrap_or(0),
+                collisions: row.collisions_per_km.unwrap_or(0),
+                bike_network: bike_network(&row.tags_way_and_rel.unwrap_or_default()),
+            });
         }
+        Ok(node)
+    }
 
-        // We get the node from the database
-        let rows = sqlx::query(
+    /// Loads the node and follows its ways up to the next junctions, for the data
+    /// imported without the edges, like by osm2pgsql.
+    async fn load_from_ways(pg_client: RegionClient, id: i64) -> Result<Self, Box<dyn Error>> {
+        let rows = sqlx::query_as!(
+            NodeRow,
             r#"
-            select n.lat, n.lon, w.tags as tags , w.nodes
+            select n.lat, n.lon, w.id as "way_id?", w.tags, w.nodes as "nodes?", p.highway,
+                wp.traces as "traces?", wc.collisions_per_km as "collisions_per_km?",
+                wl.tags_way_and_rel
             from planet_osm_nodes n
-            left join planet_osm_ways  w 
+            left join planet_osm_ways w
                 on w.nodes @> array[n.id]
+            left join planet_osm_point p
+                on p.osm_id = n.id
+            left join way_popularity wp
+                on wp.way_id = w.id
+            left join way_collisions wc
+                on wc.way_id = w.id
+            left join ways_length wl
+                on wl.ways_id = w.id
             where
             n.id = $1
         "#,
+            id
         )
-        .bind(id)
-        .fetch_all(pg_client.lock().await.deref_mut())
+        .fetch_all(pg_client.lock().await?.deref_mut())
         .await?;
+        let mut way_nodes: Vec<i64> = rows
+            .iter()
+            .flat_map(|row| row.nodes.iter().flatten())
+            .copied()
+            .collect();
+        way_nodes.sort_unstable();
+        way_nodes.dedup();
+        let coords = coordinates(pg_client.to_owned(), &way_nodes).await?;
+        let junctions = junctions(pg_client.to_owned(), &way_nodes).await?;
+        let elevation = elevations(pg_client.to_owned(), &[id]).await?.get(&id).copied();
+
         let mut adjacent_nodes = vec![];
         let mut lat: i32 = 0;
         let mut lon: i32 = 0;
-        for row in rows.iter() {
-            lat = row.get("lat");
-            lon = row.get("lon");
+        let mut highway = None;
+        for row in rows {
+            lat = row.lat;
+            lon = row.lon;
+            highway = row.highway;
+            let way_id = row.way_id.unwrap_or(0);
             // We get all the tags
-            let mut tags: HashMap<String, String> = HashMap::new();
-            let tag_strings: Vec<String> = row.try_get("tags").unwrap_or(vec![]);
-            let mut ts_iter = tag_strings.iter();
-            while let Some(tag) = ts_iter.next() {
-                match ts_iter.next() {
-                    Some(v) => tags.insert(tag.clone(), v.clone()),
-                    None => tags.insert(tag.clone(), "".to_string()),
-                };
-            }
-            // We get all the adjacent nodes
-            let nodes: Vec<i64> = row.get("nodes");
-            let node_indexes = get_positions(nodes.iter(), &id);
-            for node_index in node_indexes {
-                if let Some(next_node) = nodes.get(node_index + 1) {
-                    let next_node_row = sqlx::query(
-                        r#"
-                        select * 
-                        from planet_osm_nodes n
-                        where 
-                        n.id = $1
-                        "#,
-                    )
-                    .bind(next_node)
-                    .fetch_one(pg_client.lock().await.deref_mut())
-                    .await?;
-                    let distance =
-                        distance(lat, lon, next_node_row.get("lat"), next_node_row.get("lon"));
-                    adjacent_nodes.push(AdjacentNode {
-                        node_id: *next_node,
-                        tags: tags.clone(),
-                        distance,
-                        intermediate_nodes: None
-                    });
-                }
-                // The previous one if we are not in a oneway
-                if node_index > 0 {
-                    let prev_node = nodes.get(node_index - 1).unwrap();
-                    if !(tags.get("oneway").unwrap_or(&"".to_string()) == "yes") {
-                        if !(tags.get("oneway:bycicle").unwrap_or(&"".to_string()) == "no") {
-                            let previous_node_row = sqlx::query(
-                                r#"
-                                select * 
-                                from planet_osm_nodes n
-                                where 
-                                n.id = $1
-                                "#,
-                            )
-                            .bind(prev_node)
-                            .fetch_one(pg_client.lock().await.deref_mut())
-                            .await?;
-                            let distance = distance(
-                                lat,
-                                lon,
-                                previous_node_row.get("lat"),
-                                previous_node_row.get("lon"),
-                            );
-                            adjacent_nodes.push(AdjacentNode {
-                                node_id: *prev_node,
-                                tags: tags.clone(),
-                                distance,
-                                intermediate_nodes: None
-                            });
-                        }
-                    }
-                }
+            let tags = parse_tags(&row.tags.unwrap_or_default());
+            let popularity = row.traces.unwrap_or(0);
+            let collisions = row.collisions_per_km.unwrap_or(0);
+            // The tags of the way followed by the tags of its route relations
+            let bike_network = bike_network(&row.tags_way_and_rel.unwrap_or_default());
+            // We follow the way up to the next junctions, in the directions bikes
+            // may take it
+            let nodes = row.nodes.unwrap_or_default();
+            let edges = way_edges(id, (lat, lon), &nodes, &tags, &junctions, &coords);
+            for (node_id, intermediate_nodes, distance) in edges {
+                adjacent_nodes.push(AdjacentNode {
+                    node_id,
+                    way_id,
+                    tags: tags.clone(),
+                    distance,
+                    intermediate_nodes,
+                    popularity,
+                    collisions,
+                    bike_network,
+                });
             }
         }
-        // let ways = Way::get(pg_client.clone(), id).await?;
-        // for way in ways {
-        //     let last_node_row = sqlx::query(
-        //         r#"
-        //         select * 
-        //         from planet_osm_nodes n
-        //         where 
-        //         n.id = $1
-        //         "#,
-        //     )
-        //     .bind(way.nodes.last().unwrap())
-        //     .fetch_one(pg_client.lock().await.deref_mut())
-        //     .await?;
-        //     let distance = distance(lat, lon, last_node_row.get("lat"), last_node_row.get("lon"));
-        //     let intermediate_nodes = Some(way.nodes);
-        //     adjacent_nodes.push(AdjacentNode {
-        //         node_id: last_node_row.get("id"),
-        //         tags: way.tags,
-        //         distance,
-        //         intermediate_nodes
-        //     });
-        // }
-        let node = Node {
+        Ok(Node {
             id,
             lat,
             lon,
             adjacent_nodes,
+            highway,
+            elevation,
+        })
+    }
+
+    /// The average grade of `a_node` leading to `other`, in percent, positive when
+    /// climbing. From the elevation model when there is one, or else the `incline`
+    /// tag, whose direction is unknown for the edges going backward on the way.
+    pub fn grade_to(&self, other: &Node, a_node: &AdjacentNode) -> Option<f64> {
+        match (self.elevation, other.elevation) {
+            (Some(from), Some(to)) if a_node.distance > 0 => {
+                Some((to - from) as f64 * 10.0 / a_node.distance as f64)
+            }
+            _ => a_node.tags.get("incline").and_then(|incline| parse_incline(incline)),
+        }
+    }
+
+    /// The edge leading from this node to the node `id`, if they are adjacent. Along
+    /// an expanded path, the first one is the edge the route takes.
+    pub fn edge_to(&self, id: i64) -> Option<&AdjacentNode> {
+        self.adjacent_nodes.iter().find(|a_node| a_node.node_id == id)
+    }
+
+    /// This node with its edges going through `node` ending there, `None` when
+    /// none does.
+    pub fn truncated_at(&self, node: &Node) -> Option<Node> {
+        let goes_through = |a_node: &AdjacentNode| {
+            a_node
+                .intermediate_nodes
+                .as_ref()
+                .is_some_and(|ids| ids.contains(&node.id))
         };
-        NODE_CACHE.write().await.insert(id, node.clone());
-        Ok(node)
+        if !self.adjacent_nodes.iter().any(goes_through) {
+            return None;
+        }
+        let adjacent_nodes = self
+            .adjacent_nodes
+            .iter()
+            .map(|a_node| a_node.truncated_at(node).unwrap_or_else(|| a_node.clone()))
+            .collect();
+        Some(Node {
+            adjacent_nodes,
+            ..self.clone()
+        })
+    }
+
+    /// Replaces the long `edges` taken along `path` by the nodes they go through,
+    /// each one with an edge to the next first, so that the path follows the full
+    /// geometry of the ways.
+    async fn expand_path(
+        pg_client: RegionClient,
+        path: Vec<Node>,
+        edges: Vec<Option<AdjacentNode>>,
+    ) -> Result<Vec<Node>, Box<dyn Error>> {
+        let ids: Vec<i64> = edges
+            .iter()
+            .flatten()
+            .flat_map(|edge| edge.intermediate_nodes.iter().flatten().copied())
+            .collect();
+        let coords = coordinates(pg_client.to_owned(), &ids).await?;
+        let intermediate_elevations = elevations(pg_client, &ids).await?;
+
+        let mut expanded = vec![];
+        for (i, node) in path.iter().enumerate() {
+            let Some(Some(edge)) = edges.get(i) else {
+                expanded.push(node.clone());
+                continue;
+            };
+            let mut chain = vec![node.clone()];
+            for id in edge.intermediate_nodes.iter().flatten() {
+                let &(lat, lon) = coords.get(id).ok_or_else(|| format!("Unknown node {id}"))?;
+                chain.push(Node {
+                    id: *id,
+                    lat,
+                    lon,
+                    adjacent_nodes: vec![],
+                    highway: None,
+                    elevation: intermediate_elevations.get(id).copied(),
+                });
+            }
+            let next = &path[i + 1];
+            for k in 0..chain.len() {
+                let (next_id, next_lat, next_lon) = match chain.get(k + 1) {
+                    Some(n) => (n.id, n.lat, n.lon),
+                    None => (next.id, next.lat, next.lon),
+                };
+                let short_edge = AdjacentNode {
+                    node_id: next_id,
+                    way_id: edge.way_id,
+                    tags: edge.tags.clone(),
+                    distance: distance(chain[k].lat, chain[k].lon, next_lat, next_lon),
+                    intermediate_nodes: None,
+                    popularity: edge.popularity,
+                    collisions: edge.collisions,
+                    bike_network: edge.bike_network,
+                };
+                chain[k].adjacent_nodes.insert(0, short_edge);
+            }
+            expanded.extend(chain);
+        }
+        Ok(expanded)
+    }
+
+    /// The time lost at this node, like waiting at traffic signals, in seconds.
+    pub fn delay(&self) -> i32 {
+        let highway = self.highway.as_deref();
+        NODE_DELAYS
+            .iter()
+            .find(|(delayed, _)| Some(*delayed) == highway)
+            .map_or(0, |(_, delay)| *delay)
+    }
+
+    /// An estimate of the memory used by this node, in bytes.
+    pub fn approximate_size(&self) -> usize {
+        size_of::<Self>()
+            + self.highway.as_ref().map_or(0, String::capacity)
+            + self
+                .adjacent_nodes
+                .iter()
+                .map(AdjacentNode::approximate_size)
+                .sum::<usize>()
+    }
+
+    /// Loads every routable node inside `bbox` into the cache, returning how many
+    /// nodes were loaded.
+    pub async fn warm_cache(
+        region: &'static Region,
+        bbox: &BoundingBox,
+    ) -> Result<usize, Box<dyn Error>> {
+        let client = region.read_client().await?;
+        let node_ids = Node::routable_ids(client.to_owned(), bbox).await?;
+        for id in &node_ids {
+            Node::get(client.to_owned(), *id).await?;
+        }
+        Ok(node_ids.len())
+    }
+
+    /// The ids of the nodes of the routable lines crossing `bbox`.
+    pub async fn routable_ids(
+        pg_client: RegionClient,
+        bbox: &BoundingBox,
+    ) -> Result<Vec<i64>, Box<dyn Error>> {
+        let node_ids = sqlx::query_scalar(&format!(
+            r#"
+            select distinct unnest(pow.nodes) as id
+            from planet_osm_line pol
+            join planet_osm_ways pow
+            on pol.osm_id = pow.id
+            where {ROUTABLE_LINE}
+            and pol.way && ST_Transform(ST_MakeEnvelope($1, $2, $3, $4, 4326), 3857)
+            "#
+        ))
+        .bind(bbox.min_lng)
+        .bind(bbox.min_lat)
+        .bind(bbox.max_lng)
+        .bind(bbox.max_lat)
+        .fetch_all(pg_client.lock().await?.as_mut())
+        .await?;
+        Ok(node_ids)
     }
 
     pub fn distance(&self, other_node: &Node) -> i32 {
         self::distance(self.lat, self.lon, other_node.lat, other_node.lon)
     }
 
-    pub async fn closest(
-        pg_client: Arc<Mutex<PoolConnection<Postgres>>>,
+    /// The nodes of the routable lines closest to a point, open to bikes, along with
+    /// the distance from the point to each line in meters, closest first.
+    async fn candidates(
+        pg_client: RegionClient,
         lat: f64,
         lon: f64,
-    ) -> Result<Self, Box<dyn Error>> {
-        let node_ids: Vec<i64> = sqlx::query(
-            r#"SELECT pow.nodes
+    ) -> Result<Vec<(Vec<i64>, i32)>, Box<dyn Error>> {
+        if let Some(store) = pg_client.region.embedded_store() {
+            return Ok(stored_candidates(store, lat, lon, pg_client.region.rules));
+        }
+        let client = &pg_client;
+        let rows = retry(client, || async move {
+            // The conditions of `ROUTABLE_LINE`, as the query is checked at build time
+            let rows = sqlx::query_as!(
+                CandidateRow,
+                r#"SELECT pow.nodes, pow.tags,
+                    ST_Distance(
+                        ST_Transform(pol.way, 4326)::geography,
+                        ST_SetSRID(ST_MakePoint($1, $2), 4326)::geography
+                    ) as "distance!"
                     FROM planet_osm_line pol
-                    join planet_osm_ways pow 
+                    join planet_osm_ways pow
                     on pol.osm_id = pow.id
-                    where 
-                        pol.building is NULL and
-                        pol.highway is not null and
-                        pol.highway != 'motorway' and
-                        pol.highway != 'motorway_link' and
-                        pol.highway != 'steps' and
-                        pol.highway != 'track' and
-                        pol.aeroway is NULL and
-                        (pol.access != 'no' or pol.access is NULL) and
-                        (pol.access != 'private' or pol.access is NULL) and
-                        (pol.bicycle != 'no' OR pol.bicycle IS NULL)
+                    where pol.building is NULL
+                    and pol.highway is not null
+                    and pol.highway != 'motorway'
+                    and pol.highway != 'motorway_link'
+                    and pol.highway != 'steps'
+                    and pol.highway != 'track'
+                    and pol.aeroway is NULL
                     ORDER BY way <-> ST_Transform(ST_SetSRID(ST_MakePoint($1, $2), 4326), 3857)
-                    LIMIT 1"#,
-        )
-        .bind(lon)
-        .bind(lat)
-        .fetch_one(pg_client.lock().await.as_mut())
-        .await?
-        .get("nodes");
+                    LIMIT $3"#,
+                lon,
+                lat,
+                SNAP_CANDIDATES
+            )
+            .fetch_all(client.lock().await?.as_mut())
+            .await?;
+            Ok(rows)
+        })
+        .await?;
+        let mut candidates: Vec<(Vec<i64>, i32)> = rows
+            .into_iter()
+            .filter(|row| {
+                let tags = parse_tags(row.tags.as_deref().unwrap_or_default());
+                bicycle_access(&tags, None, pg_client.region.rules).allowed()
+            })
+            .map(|row| (row.nodes, row.distance as i32))
+            .collect();
+        // The index orders by bounding box
+        candidates.sort_by_key(|(_, distance)| *distance);
+        Ok(candidates)
+    }
 
+    /// The node of `node_ids` closest to a point.
+    async fn nearest(
+        pg_client: RegionClient,
+        node_ids: &[i64],
+        lat: f64,
+        lon: f64,
+    ) -> Result<Self, Box<dyn Error>> {
         let mut nodes = vec![];
         for id in node_ids {
-            let node = Node::get(pg_client.to_owned(), id).await?;
+            let node = Node::get(pg_client.to_owned(), *id).await?;
             nodes.push(node);
         }
 
@@ -252,52 +1246,265 @@ impl Node {
                 ((b.lat() - lat) * (b.lat() - lat) + (b.lon() - lon) * (b.lon() - lon)).sqrt();
             a_dist.partial_cmp(&b_dist).unwrap()
         });
-        Ok(nodes[0].clone())
+        Ok(nodes.swap_remove(0))
+    }
+
+    /// The routable node closest to a point, along with the distance from the point
+    /// to the closest routable line, in meters.
+    pub async fn closest(
+        pg_client: RegionClient,
+        lat: f64,
+        lon: f64,
+    ) -> Result<(Self, i32), Box<dyn Error>> {
+        let candidates = Node::candidates(pg_client.to_owned(), lat, lon).await?;
+        let (node_ids, distance) = candidates
+            .first()
+            .ok_or("No way open to bikes near the point")?;
+        let node = Node::nearest(pg_client, node_ids, lat, lon).await?;
+        Ok((node, *distance))
     }
 
-    pub async fn successors(
+    /// The candidates of `point` within `snap_radius` meters, failing with
+    /// `POINT_NOT_SNAPPED` when there are none.
+    async fn snap_candidates(
+        pg_client: RegionClient,
+        name: &str,
+        point: &LatLon,
+        snap_radius: i32,
+    ) -> Result<Vec<(Vec<i64>, i32)>, Box<dyn Error>> {
+        let mut candidates = Node::candidates(pg_client, point.lat, point.lng).await?;
+        let distance = candidates
+            .first()
+            .ok_or("No way open to bikes near the point")?
+            .1;
+        if distance > snap_radius {
+            return Err(Box::new(RouteError::PointNotSnapped {
+                point: name.to_string(),
+                distance,
+                snap_radius,
+            }));
+        }
+        candidates.retain(|(_, distance)| *distance <= snap_radius);
+        Ok(candidates)
+    }
+
+    /// Snaps `point` to the closest routable node, failing with `POINT_NOT_SNAPPED`
+    /// farther than `snap_radius` meters from a way.
+    async fn snap_point(
+        pg_client: RegionClient,
+        name: &str,
+        point: &LatLon,
+        snap_radius: i32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let candidates =
+            Node::snap_candidates(pg_client.to_owned(), name, point, snap_radius).await?;
+        Node::nearest(pg_client, &candidates[0].0, point.lat, point.lng).await
+    }
+
+    /// Snaps the start and end of a route to the closest routable nodes in the same
+    /// connected component, so that an end snapped onto an isolated pier or service
+    /// loop does not make the route impossible. They are snapped to the closest
+    /// nodes when no nearby lines share a component.
+    async fn snap(
+        pg_client: RegionClient,
+        start: &LatLon,
+        end: &LatLon,
+        snap_radius: i32,
+    ) -> Result<(Self, Self), Box<dyn Error>> {
+        let ends = Node::snap_candidates(pg_client.to_owned(), "end", end, snap_radius).await?;
+        let starts =
+            Node::snap_candidates(pg_client.to_owned(), "start", start, snap_radius).await?;
+        let ids: Vec<i64> = starts
+            .iter()
+            .chain(&ends)
+            .flat_map(|(node_ids, _)| node_ids.iter().copied())
+            .collect();
+        let components = components(pg_client.to_owned(), &ids).await?;
+        let (i, j) = connected_candidates(&starts, &ends, &components);
+        let start = Node::nearest(pg_client.to_owned(), &starts[i].0, start.lat, start.lng).await?;
+        let end = Node::nearest(pg_client, &ends[j].0, end.lat, end.lng).await?;
+        Ok((start, end))
+    }
+
+    /// The cost of `edge` from this node for the Family model, from its `safe_cost`:
+    /// park paths are cheaper, and leaving this node on a street crossing
+    /// an arterial costs `FAMILY_CROSSING_COST` more.
+    fn family_cost(&self, edge: &AdjacentNode, safe_cost: i64) -> i64 {
+        let mut cost = safe_cost;
+        let path = ["path", "footway"].iter().any(|path| edge.has_tag_value("highway", path));
+        if path && is_protected(edge) {
+            cost = (cost as f64 * FAMILY_PATH_FACTOR) as i64;
+        }
+        let crossing = !edge.is_arterial()
+            && self
+                .adjacent_nodes
+                .iter()
+                .any(|other| other.is_arterial() && other.way_id != edge.way_id);
+        if crossing {
+            cost += FAMILY_CROSSING_COST;
+        }
+        cost
+    }
+
+    /// Makes the `successors` of this node reached by turning around from `heading`
+    /// costlier, each one heading to its point of `steps`.
+    fn penalize_u_turns(
         &self,
-        pg_client: Arc<Mutex<PoolConnection<Postgres>>>,
-        model: Model,
-    ) -> Result<Vec<(Node, i64)>, Box<dyn Error>> {
-        let mut nodes: Vec<(Node, i64)> = Vec::new();
+        heading: f64,
+        successors: &mut [(Reached, i64)],
+        steps: &[LatLon],
+    ) {
+        let here = LatLon::from(self);
+        for ((_, move_cost), step) in successors.iter_mut().zip(steps) {
+            if turn_angle(heading, here.bearing(step)).abs() > U_TURN_ANGLE {
+                *move_cost *= U_TURN_PENALTY;
+            }
+        }
+    }
+
+    /// The first point along the edge to each of `successors` of this node, where the
+    /// rider heads when taking it: its first intermediate node, or else the node it
+    /// leads to.
+    async fn first_steps(
+        &self,
+        pg_client: RegionClient,
+        successors: &[(Reached, i64)],
+    ) -> Result<Vec<LatLon>, Box<dyn Error>> {
+        let first = |reached: &Reached| {
+            let edge = self.adjacent_nodes.get(reached.edge?)?;
+            edge.intermediate_nodes.as_ref()?.first().copied()
+        };
+        let ids: Vec<i64> = successors.iter().filter_map(|(reached, _)| first(reached)).collect();
+        let coordinates = coordinates(pg_client, &ids).await?;
+        let steps = successors.iter().map(|(reached, _)| {
+            match first(reached).and_then(|id| coordinates.get(&id)) {
+                Some(&(lat, lon)) => LatLon {
+                    lat: lat as f64 / 10_000_000.0,
+                    lng: lon as f64 / 10_000_000.0,
+                },
+                None => LatLon::from(&reached.node),
+            }
+        });
+        Ok(steps.collect())
+    }
+
+    /// The edges a bike may take from this node, with the node each one leads to and
+    /// its cost.
+    pub async fn costed_edges(
+        &self,
+        pg_client: RegionClient,
+        options: &RouteRequest,
+    ) -> Result<Vec<(&AdjacentNode, Node, i64)>, Box<dyn Error>> {
+        let mut nodes = Vec::new();
+        let closed_ways = pg_client.region.closed_ways(pg_client.to_owned()).await?;
         for a_node in &self.adjacent_nodes {
-            if a_node.has_tag_value("highway", "motorway")
-                || a_node.has_tag_value("highway", "motorway_link")
-                || a_node.has_tag_value("bicycle", "no")
-                || a_node.has_tag_value("highway", "steps")
-                || a_node.has_tag_value("highway", "construction")
-                || a_node.has_tag_value("access", "private")
+            if closed_ways.contains(&a_node.way_id) {
+                continue;
+            }
+            if a_node.has_tag_value("highway", "steps")
                 || a_node.has_tag_value("source", "approximative")
                 || (!a_node.has_tag("highway") && !a_node.has_tag("bicycle"))
             {
                 continue;
             }
 
-            let winter = false;
-            if winter && a_node.has_tag_value("winter_service", "no") {
+            if options.conditions.snow && a_node.has_tag_value("winter_service", "no") {
                 continue;
             }
-            let (new_node, move_cost) = match model {
+            if !options.allow_ferries() && a_node.has_tag_value("route", "ferry") {
+                continue;
+            }
+            if a_node.is_avoided(&options.avoid) {
+                continue;
+            }
+            if options.no_dismount && a_node.is_pushed() {
+                continue;
+            }
+            let rules = pg_client.region.rules;
+            if matches!(options.model, Model::Family) && !a_node.is_for_children(rules) {
+                continue;
+            }
+            let access = bicycle_access(&a_node.tags, options.departure_time.as_ref(), rules);
+            if !access.allowed() {
+                continue;
+            }
+            let (new_node, mut move_cost) = match options.model {
                 Model::Fast => {
-                    self.calculate_cost_fast(pg_client.to_owned(), a_node)
+                    self.calculate_cost_fast(pg_client.to_owned(), a_node, &options.model)
                         .await?
                 }
+                Model::Road => {
+                    let (new_node, move_cost) = self
+                        .calculate_cost_fast(pg_client.to_owned(), a_node, &options.model)
+                        .await?;
+                    let factor = a_node.road_bike_factor();
+                    (new_node, (move_cost as f64 * factor) as i64)
+                }
                 Model::Safe => {
-                    self.calculate_cost_safe(pg_client.to_owned(), a_node)
+                    self.calculate_cost_safe(pg_client.to_owned(), a_node, options)
                         .await?
                 }
+                Model::Family => {
+                    let (new_node, move_cost) = self
+                        .calculate_cost_safe(pg_client.to_owned(), a_node, options)
+                        .await?;
+                    (new_node, self.family_cost(a_node, move_cost))
+                }
             };
-            nodes.push((new_node, move_cost as i64));
+            // Legal, but only when the cycleway next to it cannot be used
+            if access == Access::UseSidepath {
+                move_cost *= 3;
+            }
+            if access == Access::Destination {
+                move_cost *= DESTINATION_PENALTY;
+            }
+            // Arterials without a lane of their own are busiest at rush hour
+            if options.departure_time.is_some_and(|time| time.is_rush_hour())
+                && a_node.is_arterial()
+                && !a_node.has_cycle_lane()
+            {
+                move_cost = move_cost * 3 / 2;
+            }
+            if !options.penalized.is_empty() {
+                let next = a_node.intermediate_nodes.iter().flatten().next();
+                let key = edge_key(self.id, *next.unwrap_or(&a_node.node_id));
+                if options.penalized.contains(&key) {
+                    move_cost = (move_cost as f64 * alternatives::PENALTY) as i64;
+                }
+            }
+            if !options.prefer.is_empty() {
+                move_cost = (move_cost as f64 * a_node.preference_factor(&options.prefer)) as i64;
+            }
+            if let Some(wind) = &options.conditions.wind {
+                let bearing = LatLon::from(self).bearing(&LatLon::from(&new_node));
+                move_cost = (move_cost as f64 * a_node.wind_factor(wind, bearing)) as i64;
+            }
+            if options.avoid_cobbles && a_node.is_cobbled() {
+                move_cost *= COBBLES_PENALTY;
+            }
+            // Unpaved ways are muddy after heavy rain
+            if options.conditions.wet && a_node.is_unpaved() {
+                move_cost *= 2;
+            }
+            if let (Some(max_grade), Some(grade)) =
+                (options.max_grade_percent, self.grade_to(&new_node, a_node))
+            {
+                if grade.abs() > max_grade {
+                    move_cost *= STEEP_EDGE_PENALTY;
+                }
+            }
+            nodes.push((a_node, new_node, move_cost));
         }
         Ok(nodes)
     }
 
     pub async fn calculate_cost_safe(
         &self,
-        pg_client: Arc<Mutex<PoolConnection<Postgres>>>,
+        pg_client: RegionClient,
         a_node: &AdjacentNode,
+        options: &RouteRequest,
     ) -> Result<(Node, i64), Box<dyn Error>> {
+        let rules = pg_client.region.rules;
         let other_node = Node::get(pg_client.to_owned(), a_node.node_id).await?;
         let mut move_cost = a_node.distance as f64;
 
@@ -336,10 +1543,6 @@ impl Node {
             } else {
                 move_cost *= 10.0;
             }
-        } else if a_node.has_tag_value("surface", "gravel") {
-            move_cost *= 1.2;
-        } else if a_node.has_tag_value("surface", "dirt") {
-            move_cost *= 5.0;
         } else if a_node.has_tag_value("bicycle", "dismount") {
             move_cost *= 3.0;
         } else if a_node.has_tag_value("highway", "tertiary") {
@@ -358,27 +1561,48 @@ impl Node {
             move_cost *= 4.0;
         }
 
-        if a_node.has_tag_value("route", "ferry") {
-            move_cost *= 100.0;
+        if let Some(duration) = a_node.ferry_duration() {
+            move_cost = duration as f64 * CYCLING_SPEED;
         }
 
-        if let Some(speed) = a_node.tags.get("maxspeed") {
-            if let Ok(speed) = speed.parse::<f32>() {
-                if speed > 50.0 {
-                    move_cost *= 1.2;
-                }
-            }
+        if rules.speed_limit(a_node).is_some_and(|speed| speed > 50.0) {
+            move_cost *= 1.2;
+        }
+
+        move_cost *= Model::Safe.profile().surface_factor(&a_node.tags);
+
+        if options.night {
+            move_cost *= a_node.night_factor();
+        }
+        if options.prefer_popular {
+            let popularity = a_node.popularity.min(POPULAR_TRACES) as f64;
+            move_cost *= 1.0 - 0.3 * popularity / POPULAR_TRACES as f64;
+        }
+
+        let discount = BIKE_NETWORK_DISCOUNTS[a_node.bike_network as usize];
+        move_cost *= 1.0 - options.bike_route_preference() * discount;
+
+        let collisions = a_node.collisions.min(DANGEROUS_COLLISIONS) as f64;
+        move_cost *= 1.0 + collisions / DANGEROUS_COLLISIONS as f64;
+
+        // Cars cut across bikes in roundabouts, all the more with several lanes
+        if a_node.is_roundabout() && !a_node.has_cycle_lane() {
+            let lanes = a_node.tags.get("lanes").and_then(|l| l.parse::<i32>().ok());
+            move_cost *= if lanes.unwrap_or(1) > 1 { 2.5 } else { 1.3 };
         }
         Ok((other_node, move_cost as i64))
     }
 
     pub async fn calculate_cost_fast(
         &self,
-        pg_client: Arc<Mutex<PoolConnection<Postgres>>>,
+        pg_client: RegionClient,
         a_node: &AdjacentNode,
+        model: &Model,
     ) -> Result<(Node, i64), Box<dyn Error>> {
         let other_node = Node::get(pg_client, a_node.node_id).await?;
-        let mut move_cost = self.distance(&other_node) as f32;
+        // The distance that could have been ridden while stopped at the node
+        let delay_cost = other_node.delay() as f64 * CYCLING_SPEED;
+        let mut move_cost = a_node.distance as f32 + delay_cost as f32;
 
         if a_node.has_tag_value("route", "bicycle"){
             move_cost *= 0.8;
@@ -410,10 +1634,6 @@ impl Node {
             move_cost *= 0.9;
         } else if a_node.has_tag_value("highway", "footway") {
             move_cost *= 1.1;
-        } else if a_node.has_tag_value("surface", "gravel") {
-            move_cost *= 1.1;
-        } else if a_node.has_tag_value("surface", "dirt") {
-            move_cost *= 5.0;
         } else if a_node.has_tag_value("bicycle", "dismount") {
             move_cost *= 3.0;
         } else if a_node.has_tag_value("highway", "tertiary") {
@@ -432,8 +1652,15 @@ impl Node {
             move_cost *= 1.3;
         }
 
-        if a_node.has_tag_value("route", "ferry") {
-            move_cost *= 100.0;
+        move_cost *= model.profile().surface_factor(&a_node.tags) as f32;
+
+        // Yielding to the traffic already in the roundabout
+        if a_node.is_roundabout() {
+            move_cost *= 1.2;
+        }
+
+        if let Some(duration) = a_node.ferry_duration() {
+            move_cost = (duration as f64 * CYCLING_SPEED) as f32;
         }
 
         Ok((other_node, move_cost as i64))
@@ -447,29 +1674,567 @@ impl Node {
         self.lon as f64 / 10_000_000.0
     }
 
-    pub async fn route(coords: &RouteRequest) -> Result<(Vec<Node>, i64), Box<dyn Error>> {
+    pub async fn route(
+        region: &'static Region,
+        coords: &RouteRequest,
+    ) -> Result<(Vec<Node>, i64), Box<dyn Error>> {
+        let (path, cost, _stats) = Node::route_with_progress(region, coords, |_| {}).await?;
+        Ok((path, cost))
+    }
+
+    /// Finds a route like `route`, calling `on_progress` every `PROGRESS_INTERVAL`
+    /// while searching, along with the statistics of the search.
+    pub async fn route_with_progress(
+        region: &'static Region,
+        coords: &RouteRequest,
+        on_progress: impl FnMut(SearchProgress),
+    ) -> Result<(Vec<Node>, i64, SearchStats), Box<dyn Error>> {
+        let _permit = search_permit().await?;
+        // The searches of a route share its time limit
+        let deadline = coords
+            .deadline
+            .unwrap_or_else(|| Instant::now() + CONFIG.search_timeout);
+        match region.crossing(&coords.start, &coords.end) {
+            _ if !coords.locked.is_empty() => {
+                Node::route_locked(region, coords, deadline, on_progress).await
+            }
+            Some((next, crossing)) => {
+                Node::search_stitched(region, next, crossing, coords, deadline, on_progress).await
+            }
+            None => Node::search(region, coords, deadline, on_progress).await,
+        }
+    }
+
+    /// Whether the node is at `point`, a point of a path returned before.
+    fn is_at(&self, point: &LatLon) -> bool {
+        let lat = (point.lat * 10_000_000.0).round() as i32;
+        let lon = (point.lng * 10_000_000.0).round() as i32;
+        (self.lat - lat).abs() <= 1 && (self.lon - lon).abs() <= 1
+    }
+
+    /// The nodes along `points`, part of a path returned before, following the
+    /// same edges. The path may start and end in the middle of an edge.
+    async fn follow(
+        pg_client: RegionClient,
+        points: &[LatLon],
+    ) -> Result<Vec<Self>, Box<dyn Error>> {
+        let not_followed = || -> Box<dyn Error> {
+            Box::new(RouteError::InvalidRequest {
+                errors: vec![FieldError {
+                    field: "locked".to_string(),
+                    message: "must be made of consecutive points of a returned route"
+                        .to_string(),
+                }],
+            })
+        };
+        let (first, _) = Node::closest(pg_client.to_owned(), points[0].lat, points[0].lng).await?;
+        if !first.is_at(&points[0]) {
+            return Err(not_followed());
+        }
+        let last = points.len() - 1;
+        let mut path = vec![first];
+        let mut taken = vec![];
+        let mut k = 0;
+        while k < last {
+            let edges = path[path.len() - 1].adjacent_nodes.clone();
+            let mut next = None;
+            for edge in &edges {
+                let skipped = edge.intermediate_nodes.as_ref().map_or(0, Vec::len);
+                let (id, m) = if k + skipped < last {
+                    (edge.node_id, k + skipped + 1)
+                } else {
+                    // The path ends in the middle of the edge
+                    let intermediate_nodes = edge.intermediate_nodes.as_deref().unwrap_or_default();
+                    (intermediate_nodes[last - k - 1], last)
+                };
+                let node = Node::get(pg_client.to_owned(), id).await?;
+                if node.is_at(&points[m]) {
+                    let edge = if id == edge.node_id {
+                        Some(edge.clone())
+                    } else {
+                        edge.truncated_at(&node)
+                    };
+                    next = Some((node, edge, m));
+                    break;
+                }
+            }
+            let (node, edge, m) = next.ok_or_else(not_followed)?;
+            path.push(node);
+            taken.push(edge);
+            k = m;
+        }
+        Node::expand_path(pg_client, path, taken).await
+    }
+
+    /// Finds a route through the `locked` sub-paths of `coords`, searching only the
+    /// legs between them.
+    async fn route_locked(
+        region: &'static Region,
+        coords: &RouteRequest,
+        deadline: Instant,
+        mut on_progress: impl FnMut(SearchProgress),
+    ) -> Result<(Vec<Node>, i64, SearchStats), Box<dyn Error>> {
+        let client = region.read_client().await?;
+        let mut path: Vec<Node> = vec![];
+        let mut total_cost = 0;
+        let mut stats = SearchStats::default();
+        // The legs meet at the same node
+        let append = |path: &mut Vec<Node>, nodes: Vec<Node>| {
+            let skip = path.last().zip(nodes.first()).is_some_and(|(a, b)| a.id == b.id);
+            path.extend(nodes.into_iter().skip(skip as usize));
+        };
+        let mut from = coords.start.clone();
+        let mut heading = coords.start_heading;
+        for (i, locked) in coords.locked.iter().enumerate() {
+            let leg = RouteRequest {
+                start: from,
+                end: locked[0].clone(),
+                locked: vec![],
+                start_heading: heading,
+                ..coords.clone()
+            };
+            let (nodes, cost, leg_stats) =
+                Node::search(region, &leg, deadline, &mut on_progress).await?;
+            append(&mut path, nodes);
+            total_cost += cost;
+            if i == 0 {
+                stats.start_snap_distance = leg_stats.start_snap_distance;
+            }
+            stats.add_leg(leg_stats);
+            if stats.partial {
+                return Ok((path, total_cost, stats));
+            }
+            let nodes = Node::follow(client.to_owned(), locked).await?;
+            let length: i32 = nodes.windows(2).map(|pair| pair[0].distance(&pair[1])).sum();
+            total_cost += length as i64;
+            append(&mut path, nodes);
+            from = locked[locked.len() - 1].clone();
+            heading = final_bearing(locked).filter(|_| coords.continue_straight);
+        }
+        let leg = RouteRequest {
+            start: from,
+            locked: vec![],
+            start_heading: heading,
+            ..coords.clone()
+        };
+        let (nodes, cost, leg_stats) =
+            Node::search(region, &leg, deadline, &mut on_progress).await?;
+        append(&mut path, nodes);
+        stats.add_leg(leg_stats);
+        Ok((path, total_cost + cost, stats))
+    }
+
+    /// Searches the route from `region` into the `next` one, each one up to the
+    /// `crossing` in both, the paths joined there. Extracts cut from the same OSM data
+    /// share the nodes near their boundary, so both searches usually snap the
+    /// crossing to the same node, and else the second one starts again from the node
+    /// the first one ended at.
+    async fn search_stitched(
+        region: &'static Region,
+        next: &'static Region,
+        crossing: LatLon,
+        coords: &RouteRequest,
+        deadline: Instant,
+        mut on_progress: impl FnMut(SearchProgress),
+    ) -> Result<(Vec<Node>, i64, SearchStats), Box<dyn Error>> {
+        let first = RouteRequest {
+            end: crossing.clone(),
+            ..coords.clone()
+        };
+        let (mut path, cost, mut stats) =
+            Node::search(region, &first, deadline, &mut on_progress).await?;
+        if stats.partial {
+            return Ok((path, cost, stats));
+        }
+        let second = RouteRequest {
+            start: crossing,
+            start_heading: None,
+            ..coords.clone()
+        };
+        let meet = |path: &[Node], rest: &[Node]| {
+            path.last().zip(rest.first()).is_some_and(|(a, b)| a.id == b.id)
+        };
+        let mut searched = Node::search(next, &second, deadline, &mut on_progress).await?;
+        if !meet(&path, &searched.0) {
+            let Some(joint) = path.last() else {
+                return Err(Box::new(RouteError::NoRouteFound));
+            };
+            let second = RouteRequest {
+                start: LatLon::from(joint),
+                ..second
+            };
+            searched = Node::search(next, &second, deadline, &mut on_progress).await?;
+            if !meet(&path, &searched.0) {
+                return Err(format!(
+                    "The routes of the {} and {} regions do not meet at their crossing",
+                    region.name, next.name
+                )
+                .into());
+            }
+        }
+        let (rest, rest_cost, rest_stats) = searched;
+        stats.add_leg(rest_stats);
+        path.extend(rest.into_iter().skip(1));
+        Ok((path, cost + rest_cost, stats))
+    }
+
+    /// The routes between each two of `points` with the options of `coords`, `None`
+    /// where there is none: a single search from each point settles the nodes by cost
+    /// until it reached all the others. Fails with `SEARCH_TIMED_OUT` past `deadline`.
+    pub async fn routes_between(
+        region: &'static Region,
+        coords: &RouteRequest,
+        points: &[LatLon],
+        deadline: Instant,
+    ) -> Result<Vec<Vec<Option<(Vec<Node>, i64)>>>, Box<dyn Error>> {
+        let client = region.read_client().await?;
+        let options = Arc::new(Node::in_conditions(region, coords).await);
+        let snap_radius = options.snap_radius_m.unwrap_or(CONFIG.snap_radius);
+        let mut ends = vec![];
+        for (i, point) in points.iter().enumerate() {
+            let name = format!("points[{i}]");
+            ends.push(Node::snap_point(client.to_owned(), &name, point, snap_radius).await?);
+        }
+        let mut routes = vec![];
+        for start in &ends {
+            let _permit = search_permit().await?;
+            let settled = Node::settle(client.to_owned(), &options, start, &ends, deadline).await?;
+            let mut from_start = vec![];
+            for end in &ends {
+                let Some((path, cost)) = settled.path_to(|reached| reached.node.id == end.id)
+                else {
+                    from_start.push(None);
+                    continue;
+                };
+                let edges = searched_edges(&path);
+                let path = path.into_iter().map(|reached| reached.node).collect();
+                let path = Node::expand_path(client.to_owned(), path, edges).await?;
+                from_start.push(Some((path, cost)));
+            }
+            routes.push(from_start);
+        }
+        Ok(routes)
+    }
+
+    /// Settles the nodes from `start` by cost until all the `ends` are reached, within
+    /// the budget of a search.
+    async fn settle(
+        pg_client: RegionClient,
+        options: &Arc<RouteRequest>,
+        start: &Node,
+        ends: &[Node],
+        deadline: Instant,
+    ) -> Result<Settled<Reached, i64>, Box<dyn Error>> {
+        let mut left: HashSet<i64> = ends.iter().map(|end| end.id).collect();
+        let mut expanded = 0;
+        let mut stopped = None;
+        // The memory used by the nodes found, roughly
+        let memory = Arc::new(AtomicUsize::new(0));
+        // The error of the first node that failed to load, which stops the search
+        let failure = Arc::new(std::sync::Mutex::new(None));
+        let settled = dijkstra_partial(
+            &Reached {
+                node: start.clone(),
+                pushed: 0,
+                edge: None,
+            },
+            |Reached { node, pushed, .. }: &Reached| {
+                // The ends may be in the middle of long edges
+                let truncated = ends.iter().fold(None, |truncated: Option<Node>, end| {
+                    truncated.as_ref().unwrap_or(node).truncated_at(end).or(truncated)
+                });
+                let client = pg_client.to_owned();
+                let options = options.clone();
+                let memory = memory.clone();
+                let failure = failure.clone();
+                let pushed = *pushed;
+                Box::pin(async move {
+                    let node = truncated.as_ref().unwrap_or(node);
+                    let edges = match node.costed_edges(client, &options).await {
+                        Ok(edges) => edges,
+                        Err(e) => {
+                            failure.lock().unwrap().get_or_insert(RouteError::from(e));
+                            return vec![];
+                        }
+                    };
+                    let successors =
+                        Reached::successors(node, edges, pushed, options.max_dismount_m);
+                    let size = successors.iter().map(|(n, _)| n.node.approximate_size()).sum();
+                    memory.fetch_add(size, atomic::Ordering::Relaxed);
+                    successors
+                })
+            },
+            |reached| {
+                left.remove(&reached.node.id);
+                expanded += 1;
+                let memory_bytes = memory.load(atomic::Ordering::Relaxed);
+                if searches_cancelled() {
+                    stopped = Some(RouteError::ShuttingDown { retry_after: 1 });
+                } else if Instant::now() > deadline {
+                    stopped = Some(RouteError::SearchTimedOut {
+                        timeout_seconds: CONFIG.search_timeout.as_secs(),
+                    });
+                } else if expanded > CONFIG.max_search_nodes
+                    || memory_bytes > CONFIG.max_search_memory
+                {
+                    stopped = Some(RouteError::SearchBudgetExceeded {
+                        expanded,
+                        memory_bytes,
+                    });
+                }
+                left.is_empty() || stopped.is_some() || failure.lock().unwrap().is_some()
+            },
+        )
+        .await;
+        if let Some(e) = failure.lock().unwrap().take().or(stopped) {
+            return Err(Box::new(e));
+        }
+        Ok(settled)
+    }
+
+    /// Searches the route between the ends of `coords`, only along the major ways in
+    /// the middle of the routes over `CONFIG.hierarchy_distance` so that the searches
+    /// between cities finish, and again along every way when the major ones do not
+    /// connect, both before `deadline`.
+    async fn search(
+        region: &'static Region,
+        coords: &RouteRequest,
+        deadline: Instant,
+        mut on_progress: impl FnMut(SearchProgress),
+    ) -> Result<(Vec<Node>, i64, SearchStats), Box<dyn Error>> {
+        if coords.start.distance(&coords.end) > CONFIG.hierarchy_distance {
+            match Node::search_ways(region, coords, true, deadline, &mut on_progress).await {
+                Err(e) if matches!(e.downcast_ref(), Some(RouteError::NoRouteFound)) => {}
+                result => return result,
+            }
+        }
+        Node::search_ways(region, coords, false, deadline, on_progress).await
+    }
+
+    /// `coords` with the weather the route is searched in.
+    async fn in_conditions(region: &Region, coords: &RouteRequest) -> RouteRequest {
+        let mut coords = coords.to_owned();
+        if coords.weather.unwrap_or(true) {
+            coords.conditions = region.weather(&coords.start).await;
+        }
+        if coords.wind.is_some() {
+            coords.conditions.wind = coords.wind;
+        }
+        coords
+    }
+
+    /// `coords` in the weather the route is searched in with its ends snapped to the
+    /// graph, as snapped when the route was tagged when it was.
+    async fn snapped(
+        region: &'static Region,
+        pg_client: RegionClient,
+        coords: &RouteRequest,
+    ) -> Result<(RouteRequest, Node, Node), Box<dyn Error>> {
+        let snapped = coords.snapped.as_deref();
+        if let Some(snapped) = snapped.filter(|s| s.start == coords.start && s.end == coords.end) {
+            let coords = RouteRequest {
+                conditions: snapped.conditions,
+                ..coords.clone()
+            };
+            let (start, end) = snapped.nodes.clone();
+            return Ok((coords, start, end));
+        }
+        let coords = Node::in_conditions(region, coords).await;
+        let snap_radius = coords.snap_radius_m.unwrap_or(CONFIG.snap_radius);
+        let (start, end) = Node::snap(pg_client, &coords.start, &coords.end, snap_radius).await?;
+        Ok((coords, start, end))
+    }
+
+    /// A hash of the route `coords` asks for without searching it: of its ends
+    /// snapped to the graph, its options and the version of the data, so that it
+    /// only changes when the route may. The ends snapped are kept in `coords` for
+    /// its search.
+    pub async fn route_tag(
+        region: &'static Region,
+        coords: &mut RouteRequest,
+    ) -> Result<u64, Box<dyn Error>> {
+        let _permit = search_permit().await?;
+        let client = region.read_client().await?;
+        let version = region.data_version(client.to_owned()).await?;
+        let (resolved, start, end) = Node::snapped(region, client, coords).await?;
+        let conditions = resolved.conditions;
+        let request = RouteRequest {
+            start: LatLon::from(&start),
+            end: LatLon::from(&end),
+            ..resolved
+        };
+        let mut hasher = FxHasher::default();
+        let json = serde_json::to_string(&request)?;
+        (json, request.options_key(), start.id, end.id, version).hash(&mut hasher);
+        coords.snapped = Some(Arc::new(Snapped {
+            start: coords.start.clone(),
+            end: coords.end.clone(),
+            nodes: (start, end),
+            conditions,
+        }));
+        Ok(hasher.finish())
+    }
+
+    /// Searches the route, along the major ways only when `hierarchical`, once
+    /// farther than `CONFIG.hierarchy_radius` from both ends, stopping at `deadline`.
+    async fn search_ways(
+        region: &'static Region,
+        coords: &RouteRequest,
+        hierarchical: bool,
+        deadline: Instant,
+        mut on_progress: impl FnMut(SearchProgress),
+    ) -> Result<(Vec<Node>, i64, SearchStats), Box<dyn Error>> {
         let now = std::time::Instant::now();
-        let coords = coords.to_owned();
-        let client = Arc::new(Mutex::new(get_pg_client().await?));
-        let end = Node::closest(client.to_owned(), coords.end.lat, coords.end.lng).await?;
-        let start = Node::closest(client.to_owned(), coords.start.lat, coords.start.lng).await?;
-        let (path, cost) = astar(
-            &start,
-            |node: &Node| {
-                let client = client.to_owned();
-                Box::pin(async move { node.successors(client, Model::Safe).await.unwrap() })
+        let mut expanded = 0;
+        let mut best_distance = i32::MAX;
+        let mut last_progress = now;
+        let client = region.read_client().await?;
+        let (coords, start, end) = Node::snapped(region, client.to_owned(), coords).await?;
+        let options = Arc::new(coords.clone());
+        let mut stats = SearchStats {
+            start_snap_distance: coords.start.distance(&LatLon::from(&start)),
+            end_snap_distance: coords.end.distance(&LatLon::from(&end)),
+            hierarchical,
+            ..Default::default()
+        };
+        let cache_key = format!("{}:{}:{}", start.id, end.id, coords.options_key());
+        // A cached route has no search tree to show
+        let tree = (coords.debug && coords.search_tree)
+            .then(|| Arc::new(std::sync::Mutex::new(SearchTree::default())));
+        // The cache key leaves out the penalties of the alternatives
+        let cacheable = coords.penalized.is_empty() && !coords.uncached;
+        if tree.is_none() && cacheable {
+            if let Some((path, cost)) = region.cached_route(&cache_key).await {
+                stats.cached_route = true;
+                stats.cost = cost;
+                return Ok((path, cost, stats));
+            }
+        }
+        let components = components(client.to_owned(), &[start.id, end.id]).await?;
+        if let (Some(start), Some(end)) = (components.get(&start.id), components.get(&end.id)) {
+            if start != end {
+                return Err(Box::new(RouteError::NoRouteFound));
+            }
+        }
+        // The memory used by the nodes found, roughly
+        let memory = Arc::new(AtomicUsize::new(0));
+        let over_budget = AtomicBool::new(false);
+        // The error of the first node that failed to load, which stops the search
+        let failure = Arc::new(std::sync::Mutex::new(None));
+        let start_id = start.id;
+        // The nodes are searched along with the length pushed to reach them
+        let outcome = astar(
+            &Reached {
+                node: start.clone(),
+                pushed: 0,
+                edge: None,
             },
-            |node| node.distance(&end).into(),
-            |node| {
-                if now.elapsed().as_secs() > 60 {
-                    return true;
+            |Reached { node, pushed, .. }: &Reached| {
+                // The end may be in the middle of a long edge
+                let truncated = node.truncated_at(&end);
+                expanded += 1;
+                if expanded > CONFIG.max_search_nodes
+                    || memory.load(atomic::Ordering::Relaxed) > CONFIG.max_search_memory
+                {
+                    over_budget.store(true, atomic::Ordering::Relaxed);
                 }
-                node.id == end.id
+                best_distance = best_distance.min(node.distance(&end));
+                let middle = hierarchical
+                    && node.distance(&start) > CONFIG.hierarchy_radius
+                    && node.distance(&end) > CONFIG.hierarchy_radius;
+                if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                    last_progress = std::time::Instant::now();
+                    on_progress(SearchProgress {
+                        expanded,
+                        best_distance,
+                    });
+                }
+                let expanded_cost = tree
+                    .as_ref()
+                    .map(|tree| tree.lock().unwrap().expand(node, node.distance(&end).into()));
+                let client = client.to_owned();
+                let options = options.clone();
+                let memory = memory.clone();
+                let failure = failure.clone();
+                let tree = tree.clone();
+                let pushed = *pushed;
+                Box::pin(async move {
+                    let node = truncated.as_ref().unwrap_or(node);
+                    let edges = match node.costed_edges(client.to_owned(), &options).await {
+                        Ok(edges) => edges,
+                        Err(e) => {
+                            failure.lock().unwrap().get_or_insert(RouteError::from(e));
+                            return vec![];
+                        }
+                    };
+                    let edges = edges.into_iter().filter(|(edge, _, _)| !middle || edge.is_major());
+                    let mut successors =
+                        Reached::successors(node, edges, pushed, options.max_dismount_m);
+                    if let (true, Some(heading)) = (node.id == start_id, options.start_heading) {
+                        match node.first_steps(client, &successors).await {
+                            Ok(steps) => node.penalize_u_turns(heading, &mut successors, &steps),
+                            Err(e) => {
+                                failure.lock().unwrap().get_or_insert(RouteError::from(e));
+                                return vec![];
+                            }
+                        }
+                    }
+                    let size = successors.iter().map(|(n, _)| n.node.approximate_size()).sum();
+                    memory.fetch_add(size, atomic::Ordering::Relaxed);
+                    if let (Some(tree), Some(cost)) = (tree, expanded_cost) {
+                        let mut tree = tree.lock().unwrap();
+                        for (successor, move_cost) in &successors {
+                            tree.reach(node, cost, &successor.node, *move_cost);
+                        }
+                    }
+                    successors
+                })
+            },
+            |reached| reached.node.distance(&end).into(),
+            |reached| reached.node.id == end.id,
+            || {
+                Instant::now() > deadline
+                    || over_budget.load(atomic::Ordering::Relaxed)
+                    || failure.lock().unwrap().is_some()
+                    || searches_cancelled()
             },
         )
-        .await
-        .expect("Problem with astar result");
-        Ok((path, cost))
+        .await;
+        if let Some(e) = failure.lock().unwrap().take() {
+            return Err(Box::new(e));
+        }
+        // Rather than a partial route, another server can search the whole one
+        if searches_cancelled() {
+            return Err(Box::new(RouteError::ShuttingDown { retry_after: 1 }));
+        }
+        let outcome = outcome.ok_or(RouteError::NoRouteFound)?;
+        let (path, cost) = match outcome {
+            Outcome::Found(path, cost) => (path, cost),
+            Outcome::Stopped(..) if over_budget.load(atomic::Ordering::Relaxed) => {
+                return Err(Box::new(RouteError::SearchBudgetExceeded {
+                    expanded,
+                    memory_bytes: memory.load(atomic::Ordering::Relaxed),
+                }));
+            }
+            Outcome::Stopped(path, cost) if coords.allow_partial => {
+                stats.partial = true;
+                (path, cost)
+            }
+            Outcome::Stopped(..) => {
+                return Err(Box::new(RouteError::SearchTimedOut {
+                    timeout_seconds: CONFIG.search_timeout.as_secs(),
+                }));
+            }
+        };
+        let edges = searched_edges(&path);
+        let path = path.into_iter().map(|reached| reached.node).collect();
+        let route = (Node::expand_path(client.to_owned(), path, edges).await?, cost);
+        if !stats.partial && cacheable {
+            region.cache_route(&cache_key, &route).await;
+        }
+        stats.finish(&client, now.elapsed(), expanded, cost, start.distance(&end));
+        stats.search_tree = tree.map(|tree| std::mem::take(&mut *tree.lock().unwrap()));
+        Ok((route.0, route.1, stats))
     }
 }
 
@@ -494,3 +2259,241 @@ impl Node {
 
 //     assert!(false);
 // }
+
+#[test]
+fn takes_the_edges_the_search_took() {
+    let mut path: Vec<Reached> = crate::segment::test_path(&[1])
+        .into_iter()
+        .map(|node| Reached {
+            node,
+            pushed: 0,
+            edge: None,
+        })
+        .collect();
+    // A second, longer way between the same two nodes, taken by the search
+    let mut parallel = path[0].node.adjacent_nodes[0].clone();
+    parallel.way_id = 2;
+    parallel.distance = 25;
+    path[0].node.adjacent_nodes.push(parallel);
+    path[1].edge = Some(1);
+    let edges = searched_edges(&path);
+    assert_eq!(edges.len(), 1);
+    assert_eq!(edges[0].as_ref().map(|edge| (edge.way_id, edge.distance)), Some((2, 25)));
+}
+
+#[test]
+fn walks_up_to_the_next_junction() {
+    let coords: HashMap<i64, (i32, i32)> =
+        (1..=5).map(|id| (id, (0, id as i32 * 1000))).collect();
+    let junctions = HashSet::from([4]);
+    let (node_id, intermediate_nodes, total) =
+        walk(1, coords[&1], &[2, 3, 4, 5], &junctions, &coords).unwrap();
+    assert_eq!(node_id, 4);
+    assert_eq!(intermediate_nodes, Some(vec![2, 3]));
+    assert_eq!(total, distance(0, 1000, 0, 4000));
+    // Without junction, up to the end of the way
+    let (node_id, intermediate_nodes, _) =
+        walk(4, coords[&4], &[5], &junctions, &coords).unwrap();
+    assert_eq!((node_id, intermediate_nodes), (5, None));
+}
+
+#[test]
+fn materializes_the_edges_bikes_may_take() {
+    let coords: HashMap<i64, (i32, i32)> =
+        (1..=5).map(|id| (id, (0, id as i32 * 1000))).collect();
+    let junctions = HashSet::from([4]);
+    let nodes = [1, 2, 3, 4, 5];
+    let neighbors = |tags: &[(&str, &str)]| {
+        let tags = tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let edges = way_edges(3, coords[&3], &nodes, &tags, &junctions, &coords);
+        edges.into_iter().map(|(id, _, _)| id).collect::<Vec<_>>()
+    };
+    assert_eq!(neighbors(&[("highway", "residential")]), vec![4, 1]);
+    assert_eq!(neighbors(&[("highway", "residential"), ("oneway", "yes")]), vec![4]);
+    assert!(is_delayed("traffic_signals"));
+}
+
+#[test]
+fn penalizes_turning_around_at_the_start() {
+    let node = |id, lon| Node {
+        id,
+        lat: 455_000_000,
+        lon,
+        adjacent_nodes: vec![],
+        highway: None,
+        elevation: None,
+    };
+    let start = node(1, -735_700_000);
+    let reached = |node| Reached {
+        node,
+        pushed: 0,
+        edge: None,
+    };
+    let mut successors = vec![
+        (reached(node(2, -735_600_000)), 100),
+        (reached(node(3, -735_800_000)), 100),
+        (reached(node(4, -735_600_000)), 100),
+    ];
+    let step = |lng| LatLon { lat: 45.5, lng };
+    // The way to the third one leaves westwards before curving back east
+    let steps = [step(-73.56), step(-73.58), step(-73.58)];
+    // Heading east
+    start.penalize_u_turns(90.0, &mut successors, &steps);
+    assert_eq!(successors[0].1, 100);
+    assert_eq!(successors[1].1, 100 * U_TURN_PENALTY);
+    assert_eq!(successors[2].1, 100 * U_TURN_PENALTY);
+}
+
+#[test]
+fn delays_at_traffic_signals() {
+    let mut node = Node {
+        id: 1,
+        lat: 0,
+        lon: 0,
+        adjacent_nodes: vec![],
+        highway: Some("traffic_signals".to_string()),
+        elevation: None,
+    };
+    assert_eq!(node.delay(), 20);
+    node.highway = Some("bus_stop".to_string());
+    assert_eq!(node.delay(), 0);
+}
+
+#[test]
+fn parses_ferry_durations() {
+    assert_eq!(parse_duration("45"), Some(45 * 60));
+    assert_eq!(parse_duration("1:30"), Some(90 * 60));
+    assert_eq!(parse_duration("00:20:30"), Some(20 * 60 + 30));
+    assert_eq!(parse_duration("PT1H"), None);
+}
+
+#[test]
+fn avoids_unlit_paths_at_night() {
+    let edge = |tags| crate::segment::test_edge(1, tags);
+    assert_eq!(edge(&[("highway", "path"), ("lit", "yes")]).night_factor(), 0.8);
+    assert_eq!(edge(&[("highway", "path")]).night_factor(), 3.0);
+    assert_eq!(edge(&[("highway", "residential")]).night_factor(), 1.0);
+    assert_eq!(edge(&[("highway", "residential"), ("lit", "no")]).night_factor(), 1.5);
+}
+
+#[test]
+fn excludes_the_avoided_features() {
+    let edge = |tags| crate::segment::test_edge(1, tags);
+    let avoid: Vec<Avoid> = ["unpaved", "trunk", "bridges"].map(|f| f.parse().unwrap()).to_vec();
+    assert!(edge(&[("highway", "track"), ("surface", "gravel")]).is_avoided(&avoid));
+    assert!(edge(&[("highway", "trunk_link")]).is_avoided(&avoid));
+    assert!(edge(&[("highway", "cycleway"), ("bridge", "yes")]).is_avoided(&avoid));
+    assert!(!edge(&[("highway", "cycleway"), ("bridge", "no")]).is_avoided(&avoid));
+    assert!(!edge(&[("highway", "track"), ("surface", "gravel")]).is_avoided(&[]));
+    assert!("highways".parse::<Avoid>().is_err());
+    assert!(edge(&[("highway", "pedestrian"), ("surface", "sett")]).is_cobbled());
+    assert!(!edge(&[("highway", "pedestrian"), ("surface", "paving_stones")]).is_cobbled());
+    assert!(edge(&[("highway", "footway")]).is_pushed());
+    assert!(edge(&[("highway", "cycleway"), ("bicycle", "dismount")]).is_pushed());
+    assert!(!edge(&[("highway", "footway"), ("bicycle", "designated")]).is_pushed());
+    let steps = edge(&[("highway", "steps")]);
+    assert_eq!(steps.pushed_after(5, None), Some(0));
+    assert_eq!(steps.pushed_after(5, Some(15)), Some(15));
+    assert_eq!(steps.pushed_after(6, Some(15)), None);
+}
+
+#[test]
+fn keeps_children_off_fast_roads() {
+    let edge = crate::segment::test_edge;
+    let rules = crate::rules::DEFAULT;
+    assert!(!edge(1, &[("highway", "residential"), ("maxspeed", "40")]).is_for_children(rules));
+    assert!(edge(1, &[("highway", "residential")]).is_for_children(rules));
+    assert!(!edge(1, &[("highway", "secondary")]).is_for_children(rules));
+    assert!(edge(1, &[("highway", "secondary"), ("cycleway", "track")]).is_for_children(rules));
+    let park_path = edge(1, &[("highway", "path"), ("bicycle", "designated")]);
+    let mut node = Node {
+        id: 1,
+        lat: 0,
+        lon: 0,
+        adjacent_nodes: vec![park_path.clone()],
+        highway: None,
+        elevation: None,
+    };
+    assert_eq!(node.family_cost(&park_path, 100), 80);
+    node.adjacent_nodes.push(edge(2, &[("highway", "primary")]));
+    assert_eq!(node.family_cost(&park_path, 100), 80 + FAMILY_CROSSING_COST);
+}
+
+#[test]
+fn keeps_road_bikes_on_smooth_pavement() {
+    let edge = |tags| crate::segment::test_edge(1, tags);
+    assert_eq!(edge(&[("highway", "primary")]).road_bike_factor(), 1.0);
+    assert_eq!(edge(&[("highway", "track")]).road_bike_factor(), UNKNOWN_TRACK_FACTOR);
+    let sidewalk = edge(&[("highway", "footway"), ("footway", "sidewalk")]);
+    assert_eq!(sidewalk.road_bike_factor(), CURB_FACTOR);
+    let gravel = edge(&[("highway", "cycleway"), ("surface", "fine_gravel")]);
+    let factor = |model: Model| model.profile().surface_factor(&gravel.tags);
+    assert!(factor(Model::Road) > 3.0 * factor(Model::Safe));
+}
+
+#[test]
+fn nudges_the_preferred_features() {
+    let edge = |tags, bike_network| AdjacentNode {
+        bike_network,
+        ..crate::segment::test_edge(1, tags)
+    };
+    let all = [Prefer::ProtectedCycleway, Prefer::QuietStreets, Prefer::SignedRoutes];
+    assert_eq!(edge(&[("highway", "primary")], 0).preference_factor(&all), 1.0);
+    assert_eq!(edge(&[("highway", "residential")], 0).preference_factor(&all), 0.8);
+    assert_eq!(edge(&[("highway", "cycleway")], 2).preference_factor(&all[..1]), 0.8);
+    // Bounded however many features the edge has
+    let signed_track = edge(&[("highway", "residential"), ("cycleway", "track")], 1);
+    assert_eq!(signed_track.preference_factor(&all), MIN_PREFERENCE_FACTOR);
+}
+
+#[test]
+fn snaps_ends_in_the_same_component() {
+    // The closest line to the end is an isolated pier
+    let starts = vec![(vec![1, 2], 5), (vec![3, 4], 20)];
+    let ends = vec![(vec![10, 11], 3), (vec![12, 13], 40)];
+    let components = HashMap::from([(1, 1), (2, 1), (3, 1), (10, 10), (12, 1)]);
+    assert_eq!(connected_candidates(&starts, &ends, &components), (0, 1));
+    assert_eq!(connected_candidates(&starts, &ends, &HashMap::new()), (0, 0));
+}
+
+#[test]
+fn ranks_bike_route_networks() {
+    let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+    assert_eq!(bike_network(&tags(&["highway", "cycleway"])), 0);
+    assert_eq!(
+        bike_network(&tags(&["network", "lcn", "route", "bicycle", "network", "ncn"])),
+        3
+    );
+    assert_eq!(bike_network(&tags(&["name", "network", "network", "rcn"])), 2);
+}
+
+#[test]
+fn adds_the_statistics_of_legs() {
+    let mut stats = SearchStats::default();
+    let leg = |expanded, cost, heuristic_ratio, end_snap_distance| SearchStats {
+        expanded,
+        cost,
+        heuristic_ratio,
+        end_snap_distance,
+        ..Default::default()
+    };
+    stats.add_leg(leg(10, 300, 0.9, 5));
+    stats.add_leg(leg(30, 100, 0.5, 12));
+    assert_eq!(stats.expanded, 40);
+    assert_eq!(stats.cost, 400);
+    assert!((stats.heuristic_ratio - 0.8).abs() < 1e-9);
+    assert_eq!(stats.end_snap_distance, 12);
+}
+
+#[test]
+fn keeps_the_middle_of_long_routes_on_major_ways() {
+    let edge = |tags, bike_network| AdjacentNode {
+        bike_network,
+        ..crate::segment::test_edge(1, tags)
+    };
+    assert!(edge(&[("highway", "secondary")], 0).is_major());
+    assert!(edge(&[("highway", "cycleway")], 0).is_major());
+    assert!(edge(&[("highway", "residential")], 2).is_major());
+    assert!(!edge(&[("highway", "residential")], 0).is_major());
+    assert!(!edge(&[("highway", "service")], 0).is_major());
+}
@@ -0,0 +1,80 @@
+//! In which directions a bike may follow a way, with the contraflow exceptions to
+//! oneways. https://wiki.openstreetmap.org/wiki/Key:oneway
+//! https://wiki.openstreetmap.org/wiki/Key:cycleway#Cycle_lanes_in_opposite_direction
+
+use std::collections::HashMap;
+
+/// The `cycleway` values allowing bikes against the traffic of a oneway.
+const CONTRAFLOW_CYCLEWAYS: [&str; 3] = ["opposite", "opposite_lane", "opposite_track"];
+
+/// The `cycleway` keys that may carry a contraflow lane.
+const CYCLEWAY_KEYS: [&str; 4] = ["cycleway", "cycleway:left", "cycleway:right", "cycleway:both"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Directions {
+    /// Following the way nodes order.
+    pub forward: bool,
+    pub backward: bool,
+}
+
+/// The direction of traffic of a way, `Some(true)` when it only goes along its
+/// nodes, `Some(false)` against them, `None` both ways.
+fn oneway(value: Option<&str>, implied: bool) -> Option<bool> {
+    match value {
+        Some("yes" | "true" | "1") => Some(true),
+        Some("-1" | "reverse") => Some(false),
+        Some("no" | "false" | "0") => None,
+        _ => implied.then_some(true),
+    }
+}
+
+/// The directions bikes may follow a way with `tags`: its oneway, unless
+/// `oneway:bicycle` says otherwise or it has a contraflow cycle lane.
+pub fn bicycle_directions(tags: &HashMap<String, String>) -> Directions {
+    let tag = |key: &str| tags.get(key).map(String::as_str);
+    let implied = matches!(tag("junction"), Some("roundabout" | "circular"))
+        || matches!(tag("highway"), Some("motorway"));
+    let contraflow = CYCLEWAY_KEYS
+        .iter()
+        .any(|key| tag(key).is_some_and(|value| CONTRAFLOW_CYCLEWAYS.contains(&value)))
+        || ["cycleway:left:oneway", "cycleway:right:oneway"]
+            .iter()
+            .any(|key| matches!(tag(key), Some("-1" | "no")));
+    let direction = match tag("oneway:bicycle") {
+        Some(value) => oneway(Some(value), false),
+        None if contraflow => None,
+        None => oneway(tag("oneway"), implied),
+    };
+    Directions {
+        forward: direction != Some(false),
+        backward: direction != Some(true),
+    }
+}
+
+#[test]
+fn contraflow_lanes_open_oneways() {
+    let directions = |tags: &[(&str, &str)]| {
+        let tags = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let Directions { forward, backward } = bicycle_directions(&tags);
+        (forward, backward)
+    };
+    assert_eq!(directions(&[]), (true, true));
+    assert_eq!(directions(&[("oneway", "yes")]), (true, false));
+    assert_eq!(directions(&[("oneway", "-1")]), (false, true));
+    assert_eq!(directions(&[("junction", "roundabout")]), (true, false));
+    assert_eq!(
+        directions(&[("oneway", "yes"), ("oneway:bicycle", "no")]),
+        (true, true)
+    );
+    assert_eq!(
+        directions(&[("oneway", "yes"), ("cycleway:left", "opposite_lane")]),
+        (true, true)
+    );
+    assert_eq!(
+        directions(&[("oneway", "no"), ("oneway:bicycle", "yes")]),
+        (true, false)
+    );
+}
@@ -0,0 +1,206 @@
+//! The points of interest near a route, like drinking water or repair stations, from
+//! the `amenity`, `shop` and `tourism` columns of the osm2pgsql `planet_osm_point`,
+//! and the towns along it to end touring stages in.
+
+use crate::{region::RegionClient, route::LatLon};
+use serde::Serialize;
+use sqlx::{postgres::PgRow, Row};
+use std::{error::Error, ops::DerefMut};
+use utoipa::ToSchema;
+
+/// How far from the route the points may be, in meters.
+const POI_DISTANCE: f64 = 150.0;
+
+/// The most points returned for a route.
+const MAX_POIS: i64 = 200;
+
+/// The `place` values of the towns a touring stage may end in.
+const TOWN_PLACES: [&str; 3] = ["city", "town", "village"];
+
+/// How far from the route the towns may be, in meters.
+const TOWN_DISTANCE: f64 = 2000.0;
+
+/// The amenities and lodgings of a town to eat and sleep in.
+const TOWN_AMENITIES: [&str; 6] = ["restaurant", "cafe", "fast_food", "pub", "bar", "biergarten"];
+const TOWN_LODGINGS: [&str; 6] = [
+    "hotel",
+    "motel",
+    "hostel",
+    "guest_house",
+    "camp_site",
+    "alpine_hut",
+];
+
+/// How far from the center of a town its amenities may be, in meters.
+const TOWN_RADIUS: f64 = 1500.0;
+
+/// The most towns returned for a route, enough for the stages across a continent.
+const MAX_TOWNS: i64 = 5000;
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Poi {
+    pub osm_id: i64,
+    /// The tag value requested, like `drinking_water`.
+    pub kind: String,
+    pub name: Option<String>,
+    pub location: LatLon,
+    /// How far along the route the point is reached, in meters.
+    pub offset: i32,
+    /// How far the point is from the route, in meters.
+    pub distance: i32,
+}
+
+/// Parses a comma-separated list of kinds, like `drinking_water,toilets`.
+pub fn parse_kinds(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `kind` looks like an OSM tag value.
+pub fn is_valid_kind(kind: &str) -> bool {
+    !kind.is_empty() && kind.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+/// The point of a row of the queries along a route, `length` meters long.
+fn poi(row: &PgRow, length: i32) -> Poi {
+    Poi {
+        osm_id: row.get("osm_id"),
+        kind: row.get("kind"),
+        name: row.get("name"),
+        location: LatLon {
+            lat: row.get("lat"),
+            lng: row.get("lng"),
+        },
+        offset: (row.get::<f64, _>("fraction") * length as f64).round() as i32,
+        distance: row.get::<f64, _>("distance").round() as i32,
+    }
+}
+
+/// The points of the `kinds` near the route along `path`, `length` meters long, in
+/// route order.
+pub async fn along(
+    pg_client: RegionClient,
+    path: &[LatLon],
+    length: i32,
+    kinds: &[String],
+) -> Result<Vec<Poi>, Box<dyn Error>> {
+    if path.len() < 2 || kinds.is_empty() {
+        return Ok(vec![]);
+    }
+    let (lats, lngs): (Vec<f64>, Vec<f64>) = path.iter().map(|p| (p.lat, p.lng)).unzip();
+    let rows = sqlx::query(
+        r#"
+        with route as (
+            select ST_Transform(ST_SetSRID(ST_MakeLine(
+                array(
+                    select ST_MakePoint(p.lng, p.lat)
+                    from unnest($1::float8[], $2::float8[]) with ordinality as p(lat, lng, i)
+                    order by p.i
+                )
+            ), 4326), 3857) as line
+        )
+        select p.osm_id, p.name,
+            case
+                when p.amenity = any($3) then p.amenity
+                when p.shop = any($3) then p.shop
+                else p.tourism
+            end as kind,
+            ST_Y(ST_Transform(p.way, 4326)) as lat,
+            ST_X(ST_Transform(p.way, 4326)) as lng,
+            ST_LineLocatePoint(route.line, p.way) as fraction,
+            ST_Distance(route.line, p.way) as distance
+        from planet_osm_point p, route
+        where (p.amenity = any($3) or p.shop = any($3) or p.tourism = any($3))
+        and ST_DWithin(p.way, route.line, $4)
+        order by fraction
+        limit $5
+        "#,
+    )
+    .bind(lats)
+    .bind(lngs)
+    .bind(kinds)
+    .bind(POI_DISTANCE)
+    .bind(MAX_POIS)
+    .fetch_all(pg_client.lock().await?.deref_mut())
+    .await?;
+    Ok(rows.iter().map(|row| poi(row, length)).collect())
+}
+
+/// The towns near the route along `path`, `length` meters long, with both somewhere
+/// to eat and somewhere to sleep, in route order.
+pub async fn towns_along(
+    pg_client: RegionClient,
+    path: &[LatLon],
+    length: i32,
+) -> Result<Vec<Poi>, Box<dyn Error>> {
+    if path.len() < 2 {
+        return Ok(vec![]);
+    }
+    let (lats, lngs): (Vec<f64>, Vec<f64>) = path.iter().map(|p| (p.lat, p.lng)).unzip();
+    let rows = sqlx::query(
+        r#"
+        with path as (
+            select ST_SetSRID(ST_MakeLine(
+                array(
+                    select ST_MakePoint(p.lng, p.lat)
+                    from unnest($1::float8[], $2::float8[]) with ordinality as p(lat, lng, i)
+                    order by p.i
+                )
+            ), 4326) as line
+        ), route as (
+            select ST_Transform(line, 3857) as line, line::geography as geography,
+                -- The indexed Mercator units are meters stretched by 1 / cos(lat)
+                1 / cos(radians(greatest(abs(ST_YMin(line)), abs(ST_YMax(line))))) as scale
+            from path
+        ), towns as (
+            select p.*, ST_Transform(p.way, 4326)::geography as geography
+            from planet_osm_point p, route
+            where p.place = any($3)
+            and ST_DWithin(p.way, route.line, $4 * route.scale)
+        )
+        select t.osm_id, t.name, t.place as kind,
+            ST_Y(t.geography::geometry) as lat,
+            ST_X(t.geography::geometry) as lng,
+            ST_LineLocatePoint(route.line, t.way) as fraction,
+            ST_Distance(route.geography, t.geography) as distance
+        from towns t, route
+        where ST_DWithin(t.geography, route.geography, $4)
+        and exists (
+            select 1 from planet_osm_point a
+            where a.amenity = any($5)
+            and ST_DWithin(a.way, t.way, $7 * route.scale)
+            and ST_DWithin(ST_Transform(a.way, 4326)::geography, t.geography, $7)
+        )
+        and exists (
+            select 1 from planet_osm_point a
+            where a.tourism = any($6)
+            and ST_DWithin(a.way, t.way, $7 * route.scale)
+            and ST_DWithin(ST_Transform(a.way, 4326)::geography, t.geography, $7)
+        )
+        order by fraction
+        limit $8
+        "#,
+    )
+    .bind(lats)
+    .bind(lngs)
+    .bind(&TOWN_PLACES[..])
+    .bind(TOWN_DISTANCE)
+    .bind(&TOWN_AMENITIES[..])
+    .bind(&TOWN_LODGINGS[..])
+    .bind(TOWN_RADIUS)
+    .bind(MAX_TOWNS)
+    .fetch_all(pg_client.lock().await?.deref_mut())
+    .await?;
+    Ok(rows.iter().map(|row| poi(row, length)).collect())
+}
+
+#[test]
+fn parses_kind_lists() {
+    let kinds = parse_kinds("drinking_water, bicycle_repair_station,,toilets");
+    assert_eq!(kinds, vec!["drinking_water", "bicycle_repair_station", "toilets"]);
+    assert!(kinds.iter().all(|kind| is_valid_kind(kind)));
+    assert!(!is_valid_kind("toilets'; drop table"));
+}
@@ -0,0 +1,138 @@
+//! Retries the queries failing for transient reasons, like a dropped connection or a
+//! failover, and a circuit breaker failing the requests fast with a 503 while the
+//! database is down, rather than every one of them waiting on the pool.
+
+use crate::{error::RouteError, region::RegionClient};
+use std::{
+    error::Error,
+    future::Future,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How many times a query is retried.
+const RETRIES: u32 = 3;
+
+/// The wait before the first retry, doubled before each next one.
+const BACKOFF: Duration = Duration::from_millis(50);
+
+/// The consecutive failures to reach the database opening the circuit.
+const FAILURE_THRESHOLD: u32 = 5;
+
+/// How long the requests fail fast once the circuit is open, before one is let
+/// through to check whether the database is back.
+const COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Whether `error` may go away by trying again: the connection was lost, the server
+/// is restarting or full, or the transaction lost a conflict.
+pub fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    match error.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed) => {
+            true
+        }
+        Some(sqlx::Error::Database(e)) => e.code().is_some_and(|code| {
+            code.starts_with("08")
+                || ["57P01", "57P02", "57P03", "53300", "40001", "40P01"].contains(&&*code)
+        }),
+        _ => false,
+    }
+}
+
+/// Counts the consecutive failures to reach a database, and opens after
+/// `FAILURE_THRESHOLD` of them for `COOLDOWN`.
+#[derive(Default)]
+pub struct CircuitBreaker {
+    failures: AtomicU32,
+    open_until: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    /// Fails with `DATABASE_UNAVAILABLE` while the circuit is open.
+    pub fn check(&self) -> Result<(), RouteError> {
+        match *self.open_until.lock().unwrap() {
+            Some(until) if Instant::now() < until => Err(RouteError::DatabaseUnavailable {
+                retry_after: (until - Instant::now()).as_secs() + 1,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The error while the database cannot be reached, before the circuit opens too.
+    pub fn unavailable(&self) -> RouteError {
+        self.check().err().unwrap_or(RouteError::DatabaseUnavailable { retry_after: 1 })
+    }
+
+    pub fn failed(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= FAILURE_THRESHOLD {
+            *self.open_until.lock().unwrap() = Some(Instant::now() + COOLDOWN);
+        }
+    }
+
+    pub fn succeeded(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        *self.open_until.lock().unwrap() = None;
+    }
+}
+
+/// Runs `query` on `pg_client`, retrying it on a new connection with backoff while it
+/// fails for transient reasons.
+pub async fn retry<T, F, Fut>(pg_client: &RegionClient, mut query: F) -> Result<T, Box<dyn Error>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Box<dyn Error>>>,
+{
+    let breaker = pg_client.breaker;
+    let mut backoff = BACKOFF;
+    let mut attempt = 0;
+    loop {
+        breaker.check()?;
+        // The error must not live across the awaits below
+        match query().await {
+            Ok(result) => {
+                breaker.succeeded();
+                return Ok(result);
+            }
+            Err(e) if attempt < RETRIES && is_transient(&*e) => {
+                eprintln!("Retrying a query on the {} region: {e}", pg_client.region.name);
+            }
+            Err(e) => {
+                if is_transient(&*e) {
+                    breaker.failed();
+                }
+                return Err(e);
+            }
+        }
+        breaker.failed();
+        attempt += 1;
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        if let Err(e) = pg_client.reconnect().await {
+            breaker.failed();
+            return Err(e.into());
+        }
+    }
+}
+
+#[test]
+fn opens_after_consecutive_failures() {
+    let io = sqlx::Error::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+    assert!(is_transient(&io));
+    assert!(!is_transient(&sqlx::Error::RowNotFound));
+
+    let breaker = CircuitBreaker::default();
+    for _ in 0..FAILURE_THRESHOLD - 1 {
+        breaker.failed();
+    }
+    assert!(breaker.check().is_ok());
+    breaker.failed();
+    assert!(matches!(
+        breaker.check(),
+        Err(RouteError::DatabaseUnavailable { retry_after: 10 })
+    ));
+    breaker.succeeded();
+    assert!(breaker.check().is_ok());
+}
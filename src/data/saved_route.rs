@@ -0,0 +1,97 @@
+//! The routes computed, kept under a short ID for share links and for clients to
+//! fetch a route again without searching.
+
+use crate::region::{Region, RegionClient};
+use sqlx::Row;
+use std::{
+    collections::hash_map::RandomState,
+    error::Error,
+    hash::{BuildHasher, Hasher},
+    ops::DerefMut,
+    time::Duration,
+};
+
+const ID_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const ID_LENGTH: usize = 10;
+
+pub struct SavedRoute {
+    pub id: String,
+    /// The `RouteRequest` the route was found for, as JSON.
+    pub request: String,
+    /// The `RouteBody` responded, as JSON.
+    pub body: String,
+}
+
+/// A random ID like `4fTq0ZbW1c`.
+pub fn new_id() -> String {
+    let mut bits = RandomState::new().build_hasher().finish();
+    (0..ID_LENGTH)
+        .map(|_| {
+            let c = ID_ALPHABET[(bits % ID_ALPHABET.len() as u64) as usize];
+            bits /= ID_ALPHABET.len() as u64;
+            c as char
+        })
+        .collect()
+}
+
+/// Whether `id` may be one of a saved route.
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LENGTH && id.bytes().all(|c| ID_ALPHABET.contains(&c))
+}
+
+impl SavedRoute {
+    pub async fn save(&self, pg_client: RegionClient) -> Result<(), Box<dyn Error>> {
+        sqlx::query("insert into saved_routes (id, request, body) values ($1, $2, $3)")
+            .bind(&self.id)
+            .bind(&self.request)
+            .bind(&self.body)
+            .execute(pg_client.lock().await?.deref_mut())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get(pg_client: RegionClient, id: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        let row = sqlx::query("select request, body from saved_routes where id = $1")
+            .bind(id)
+            .fetch_optional(pg_client.lock().await?.deref_mut())
+            .await?;
+        Ok(row.map(|row| SavedRoute {
+            id: id.to_string(),
+            request: row.get("request"),
+            body: row.get("body"),
+        }))
+    }
+
+    /// Deletes the routes saved more than `retention` ago, returning how many.
+    pub async fn expire(
+        pg_client: RegionClient,
+        retention: Duration,
+    ) -> Result<u64, Box<dyn Error>> {
+        let result = sqlx::query(
+            "delete from saved_routes where created_at < now() - make_interval(secs => $1)",
+        )
+        .bind(retention.as_secs_f64())
+        .execute(pg_client.lock().await?.deref_mut())
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Looks the route up in every region.
+    pub async fn find(id: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        for region in Region::all() {
+            let client = region.client().await?;
+            if let Some(route) = SavedRoute::get(client, id).await? {
+                return Ok(Some(route));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[test]
+fn makes_short_ids() {
+    let (a, b) = (new_id(), new_id());
+    assert!(is_valid_id(&a) && is_valid_id(&b));
+    assert_ne!(a, b);
+    assert!(!is_valid_id("stream"));
+}
@@ -0,0 +1,281 @@
+//! A binary snapshot of the graph of a region, written by the `dump-graph` subcommand
+//! and memory-mapped at startup, so that a server starts in well under a second with
+//! its nodes available without querying Postgres.
+//!
+//! The file holds the bincode of each node, sorted by id, followed by an index of
+//! `(id, offset, lat, lon)` entries and a trailer giving where the index starts and its
+//! length. A node is only decoded when it is looked up, the points are snapped by
+//! scanning the coordinates of the index.
+
+use super::{
+    bbox::BoundingBox,
+    node::{self, Node, ROUTABLE_WAY},
+    store::GraphStore,
+};
+use crate::region::Region;
+use memmap2::Mmap;
+use std::{
+    collections::BinaryHeap,
+    error::Error,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    ops::DerefMut,
+};
+
+const MAGIC: &[u8; 8] = b"RNDGRAPH";
+const VERSION: u32 = 2;
+const HEADER_SIZE: usize = 12;
+const ENTRY_SIZE: usize = 24;
+const TRAILER_SIZE: usize = 16;
+
+pub struct Snapshot {
+    map: Mmap,
+    index_start: usize,
+    count: usize,
+    extent: Option<BoundingBox>,
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn read_i32(bytes: &[u8], at: usize) -> i32 {
+    i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+impl Snapshot {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        // The file must not change while mapped, it is only ever replaced by a new one
+        let map = unsafe { Mmap::map(&file)? };
+        Snapshot::from_map(map).map_err(|e| format!("Invalid graph snapshot {path}: {e}").into())
+    }
+
+    fn from_map(map: Mmap) -> Result<Self, &'static str> {
+        if map.len() < HEADER_SIZE + TRAILER_SIZE || &map[..8] != MAGIC {
+            return Err("not a graph snapshot");
+        }
+        if map[8..12] != VERSION.to_le_bytes() {
+            return Err("unsupported version");
+        }
+        let trailer = map.len() - TRAILER_SIZE;
+        let index_start = read_u64(&map, trailer) as usize;
+        let count = read_u64(&map, trailer + 8) as usize;
+        if index_start < HEADER_SIZE || index_start + count * ENTRY_SIZE != trailer {
+            return Err("truncated");
+        }
+        let mut snapshot = Snapshot {
+            map,
+            index_start,
+            count,
+            extent: None,
+        };
+        snapshot.extent = snapshot.compute_extent();
+        Ok(snapshot)
+    }
+
+    fn compute_extent(&self) -> Option<BoundingBox> {
+        (0..self.count).fold(None, |extent, i| {
+            let (lat, lon) = self.coordinates(i);
+            let (lat, lon) = (lat as f64 / 1e7, lon as f64 / 1e7);
+            let extent = extent.unwrap_or(BoundingBox {
+                min_lat: lat,
+                min_lng: lon,
+                max_lat: lat,
+                max_lng: lon,
+            });
+            Some(BoundingBox {
+                min_lat: extent.min_lat.min(lat),
+                min_lng: extent.min_lng.min(lon),
+                max_lat: extent.max_lat.max(lat),
+                max_lng: extent.max_lng.max(lon),
+            })
+        })
+    }
+
+    /// The number of nodes.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// The id and offset of the `i`th node.
+    fn entry(&self, i: usize) -> (i64, usize) {
+        let at = self.index_start + i * ENTRY_SIZE;
+        (read_u64(&self.map, at) as i64, read_u64(&self.map, at + 8) as usize)
+    }
+
+    /// The latitude and longitude of the `i`th node, in decimicro degrees.
+    fn coordinates(&self, i: usize) -> (i32, i32) {
+        let at = self.index_start + i * ENTRY_SIZE + 16;
+        (read_i32(&self.map, at), read_i32(&self.map, at + 4))
+    }
+}
+
+impl GraphStore for Snapshot {
+    fn node(&self, id: i64) -> Option<Node> {
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let middle = (low + high) / 2;
+            let (middle_id, offset) = self.entry(middle);
+            if middle_id < id {
+                low = middle + 1;
+            } else if middle_id > id {
+                high = middle;
+            } else {
+                let end = match middle + 1 < self.count {
+                    true => self.entry(middle + 1).1,
+                    false => self.index_start,
+                };
+                return bincode::deserialize(self.map.get(offset..end)?).ok();
+            }
+        }
+        None
+    }
+
+    fn closest(&self, lat: f64, lon: f64, limit: usize) -> Vec<(i64, i32)> {
+        let (lat, lon) = ((lat * 1e7) as i32, (lon * 1e7) as i32);
+        let scale = (lat as f64 / 1e7).to_radians().cos();
+        // The farthest of the closest nodes found so far on top, by squared flat distance
+        let mut closest = BinaryHeap::new();
+        for i in 0..self.count {
+            let (node_lat, node_lon) = self.coordinates(i);
+            let (dy, dx) = ((node_lat - lat) as f64, (node_lon - lon) as f64 * scale);
+            closest.push(((dx * dx + dy * dy) as u64, i));
+            if closest.len() > limit {
+                closest.pop();
+            }
+        }
+        closest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(_, i)| {
+                let (node_lat, node_lon) = self.coordinates(i);
+                (self.entry(i).0, node::distance(lat, lon, node_lat, node_lon))
+            })
+            .collect()
+    }
+
+    fn extent(&self) -> Option<BoundingBox> {
+        self.extent
+    }
+}
+
+/// Writes a snapshot one node at a time, keeping only the index in memory.
+struct SnapshotWriter<W: Write> {
+    writer: W,
+    offset: usize,
+    index: Vec<(i64, usize, i32, i32)>,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    fn new(mut writer: W) -> Result<Self, Box<dyn Error>> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(SnapshotWriter {
+            writer,
+            offset: HEADER_SIZE,
+            index: vec![],
+        })
+    }
+
+    /// Writes `node`, after the ones with a smaller id.
+    fn push(&mut self, node: &Node) -> Result<(), Box<dyn Error>> {
+        let bytes = bincode::serialize(node)?;
+        self.index.push((node.id, self.offset, node.lat, node.lon));
+        self.writer.write_all(&bytes)?;
+        self.offset += bytes.len();
+        Ok(())
+    }
+
+    /// Writes the index and the trailer, returning the writer and the number of nodes.
+    fn finish(mut self) -> Result<(W, usize), Box<dyn Error>> {
+        for (id, offset, lat, lon) in &self.index {
+            self.writer.write_all(&id.to_le_bytes())?;
+            self.writer.write_all(&(*offset as u64).to_le_bytes())?;
+            self.writer.write_all(&lat.to_le_bytes())?;
+            self.writer.write_all(&lon.to_le_bytes())?;
+        }
+        self.writer.write_all(&(self.offset as u64).to_le_bytes())?;
+        self.writer.write_all(&(self.index.len() as u64).to_le_bytes())?;
+        Ok((self.writer, self.index.len()))
+    }
+}
+
+/// Writes the snapshot of `nodes`, sorted by id, to `writer`.
+#[cfg(test)]
+pub(crate) fn write(
+    writer: &mut impl Write,
+    nodes: impl IntoIterator<Item = Node>,
+) -> Result<usize, Box<dyn Error>> {
+    let mut snapshot = SnapshotWriter::new(writer)?;
+    for node in nodes {
+        snapshot.push(&node)?;
+    }
+    Ok(snapshot.finish()?.1)
+}
+
+/// Writes the nodes of the ways of `region` open to bikes to the snapshot at `path`,
+/// only the ones of the ways crossing `bbox` when given. The snapshot is written
+/// next to it and then renamed, the servers mapping the previous one keep reading
+/// it unchanged.
+pub async fn dump(
+    region: &'static Region,
+    path: &str,
+    bbox: Option<&BoundingBox>,
+) -> Result<(), Box<dyn Error>> {
+    let client = region.client().await?;
+    let inside = bbox.map_or(String::new(), |bbox| {
+        let envelope = bbox.envelope();
+        format!("and id in (select osm_id from planet_osm_line where way && {envelope})")
+    });
+    let ids: Vec<i64> = sqlx::query_scalar(&format!(
+        r#"
+        select distinct unnest(nodes) as id
+        from planet_osm_ways
+        where {ROUTABLE_WAY}
+        {inside}
+        order by id
+        "#
+    ))
+    .fetch_all(client.lock().await?.deref_mut())
+    .await?;
+    println!("Dumping {} nodes of the {} region", ids.len(), region.name);
+    let partial = format!("{path}.tmp");
+    let mut snapshot = SnapshotWriter::new(BufWriter::new(File::create(&partial)?))?;
+    for (i, id) in ids.iter().enumerate() {
+        snapshot.push(&Node::load(client.to_owned(), *id).await?)?;
+        if (i + 1) % 100_000 == 0 {
+            println!("{} nodes written", i + 1);
+        }
+    }
+    let (writer, count) = snapshot.finish()?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    fs::rename(&partial, path)?;
+    println!("{count} nodes written to {path}");
+    Ok(())
+}
+
+#[test]
+fn looks_nodes_up_by_id() {
+    let node = |id: i64| Node {
+        id,
+        lat: 455_000_000 + id as i32 * 1000,
+        lon: -736_000_000,
+        adjacent_nodes: vec![],
+        highway: None,
+        elevation: None,
+    };
+    let path = std::env::temp_dir().join("routing-server-snapshot-test.bin");
+    let mut file = File::create(&path).unwrap();
+    write(&mut file, [1, 5, 8].map(node)).unwrap();
+    let snapshot = Snapshot::open(path.to_str().unwrap()).unwrap();
+    assert_eq!(snapshot.len(), 3);
+    assert_eq!(snapshot.node(5).unwrap().lat, 455_005_000);
+    assert_eq!(snapshot.node(8).unwrap().lat, 455_008_000);
+    assert!(snapshot.node(6).is_none());
+    // 11 and 44 meters south of the point
+    let closest: Vec<i64> = snapshot.closest(45.5009, -73.6, 2).iter().map(|c| c.0).collect();
+    assert_eq!(closest, vec![8, 5]);
+    assert_eq!(snapshot.extent().unwrap().max_lat, 45.5008);
+}
@@ -0,0 +1,18 @@
+//! The graph stores a region can be served from without operating Postgres, for small
+//! deployments like a single city on a Raspberry Pi. A region configured with a store
+//! but no database URL routes from the store alone: there are no closures, connected
+//! components, saved routes or points of interest then.
+
+use super::{bbox::BoundingBox, node::Node};
+
+pub trait GraphStore: Send + Sync {
+    /// The node with its edges, `None` when it is not in the store.
+    fn node(&self, id: i64) -> Option<Node>;
+
+    /// The ids of the `limit` nodes closest to a point, along with their distance to
+    /// it in meters, closest first.
+    fn closest(&self, lat: f64, lon: f64, limit: usize) -> Vec<(i64, i32)>;
+
+    /// The area covered by the nodes, `None` when there are none.
+    fn extent(&self) -> Option<BoundingBox>;
+}
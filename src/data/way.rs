@@ -1,150 +1,141 @@
-use futures::TryStreamExt;
-use sqlx::{pool::PoolConnection, Postgres, Row};
-use std::{collections::HashMap, error::Error, sync::Arc};
-use tokio::sync::Mutex;
+use serde::Serialize;
+use std::error::Error;
 
-use crate::get_pg_client;
+use crate::{
+    jobs,
+    region::{Region, RegionClient},
+};
 
-#[derive(sqlx::FromRow, Debug)]
-pub struct Way {
-    pub id: i64,
-    pub nodes: Vec<i64>,
-    pub tags: HashMap<String, String>,
-    pub distance: Option<i64>,
+/// The ways of a region, measured for the lengths of the edges.
+pub struct Way;
+
+/// The ways measured per statement, each one a transaction, well within the
+/// statement timeout.
+const LENGTH_BATCH: i64 = 1_000;
+
+/// How far the computation of the ways lengths is.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct LengthProgress {
+    /// The ways measured, including the ones of the interrupted run resumed.
+    pub measured: i64,
+    /// The ways of the region.
+    pub total: i64,
 }
 
 impl Way {
-    pub async fn get(
-        client: Arc<Mutex<PoolConnection<Postgres>>>,
-        node_id: i64,
-    ) -> Result<Vec<Way>, Box<dyn Error>> {
-        let rows = sqlx::query(
-            r#"
-                    select pow.*, wl.length  
-                    from ways_length wl 
-                    join planet_osm_ways pow
-                    on pow.id = wl.ways_id 
-                    where wl.first_node = $1
-                    and pow.tags is not null
-                "#,
-        )
-        .bind(node_id)
-        .fetch_all(client.lock().await.as_mut())
-        .await?;
-        let mut ways = vec![];
-        for row in rows {
-            let mut tags: HashMap<String, String> = HashMap::new();
-            let tag_strings: Vec<String> = row.get("tags");
-            let mut ts_iter = tag_strings.iter();
-            while let Some(tag) = ts_iter.next() {
-                match ts_iter.next() {
-                    Some(v) => tags.insert(tag.clone(), v.clone()),
-                    None => tags.insert(tag.clone(), "".to_string()),
-                };
-            }
-            ways.push(Way {
-                id: row.get("id"),
-                nodes: row.get("nodes"),
-                tags,
-                distance: row.get("length"),
-            });
+    /// Fills `ways_length` with the length of every way, computed by PostGIS on the
+    /// line of its nodes, `LENGTH_BATCH` ways per statement. The progress is saved
+    /// along with each batch, and an interrupted run resumes from it unless
+    /// `restart`. Fails when another server is computing the lengths.
+    pub async fn calculate_all_lengths(
+        client: RegionClient,
+        restart: bool,
+        on_progress: impl FnMut(LengthProgress),
+    ) -> Result<LengthProgress, Box<dyn Error>> {
+        // Reentrant for the lengths job, which already holds it
+        if !jobs::try_lock(&client, jobs::LENGTHS_LOCK).await? {
+            return Err("The lengths are already being computed by another server".into());
         }
-        Ok(ways)
+        let result = Self::measure_all(client.clone(), restart, on_progress).await;
+        jobs::unlock(&client, jobs::LENGTHS_LOCK).await?;
+        result
     }
 
-    pub async fn calculate_all_lengths(
-        client: Arc<Mutex<PoolConnection<Postgres>>>,
-    ) -> Result<(), Box<dyn Error>> {
-        let mut unlocked_client = client.lock().await;
-        let mut stream = sqlx::query(
-            r#"
-                select pow.id, nodes, pow.tags as wtags, por.tags as rtags
-                from planet_osm_ways pow
-                left join ways_length wl
-                on pow.id = wl.ways_id
-                left join planet_osm_rels por
-                on por.parts @> array[pow.id]
-            "#,
-        )
-        .fetch(unlocked_client.as_mut());
-        while let Some(row) = stream.try_next().await? {
-            let client = Arc::new(Mutex::new(get_pg_client().await?));
-            let id: i64 = row.get("id");
-            let node_ids: Vec<i64> = row.get("nodes");
-            let mut length = 0;
-            for i in 0..node_ids.len() - 1 {
-                let node1_row = sqlx::query(
-                    r#"
-                        select *
-                        from planet_osm_nodes pon
-                        where id = $1;
-                    "#,
-                )
-                .bind(node_ids[i])
-                .fetch_one(client.lock().await.as_mut())
+    async fn measure_all(
+        client: RegionClient,
+        restart: bool,
+        mut on_progress: impl FnMut(LengthProgress),
+    ) -> Result<LengthProgress, Box<dyn Error>> {
+        if restart {
+            sqlx::query("delete from ways_length_progress")
+                .execute(client.lock().await?.as_mut())
                 .await?;
-                let node2_row = sqlx::query(
-                    r#"
-                        select *
-                        from planet_osm_nodes pon
-                        where id = $1;
-                    "#,
-                )
-                .bind(node_ids[i + 1])
-                .fetch_one(client.lock().await.as_mut())
+        }
+        let resumed: Option<(i64, i64)> =
+            sqlx::query_as("select last_way_id, measured from ways_length_progress")
+                .fetch_optional(client.lock().await?.as_mut())
                 .await?;
-                length += crate::data::node::distance(
-                    node1_row.get("lat"),
-                    node1_row.get("lon"),
-                    node2_row.get("lat"),
-                    node2_row.get("lon"),
-                );
-            }
-            let mut tags: Vec<String> = row.try_get("wtags").unwrap_or(vec![]);
-            tags.append(&mut row.try_get("rtags").unwrap_or(vec![]));
-            sqlx::query(
+        let (mut last_id, measured) = resumed.unwrap_or((i64::MIN, 0));
+        let total = sqlx::query_scalar("select count(*) from planet_osm_ways")
+            .fetch_one(client.lock().await?.as_mut())
+            .await?;
+        let mut progress = LengthProgress { measured, total };
+        on_progress(progress);
+        loop {
+            let (batch_last_id, count): (Option<i64>, i64) = sqlx::query_as(
                 r#"
-                    insert into ways_length (ways_id, length, first_node, last_node, tags_way_and_rel)
-                    values ($1, $2, $3, $4, $5)
-                    on conflict (ways_id) 
+                with batch as (
+                    select id, nodes, tags
+                    from planet_osm_ways
+                    where id > $1
+                    order by id
+                    limit $2
+                ), measured as (
+                    insert into ways_length
+                        (ways_id, length, first_node, last_node, tags_way_and_rel)
+                    select b.id,
+                        round(ST_Length(ST_SetSRID(ST_MakeLine(array(
+                            select ST_MakePoint(n.lon / 1e7, n.lat / 1e7)
+                            from unnest(b.nodes) with ordinality as u(id, i)
+                            join planet_osm_nodes n on n.id = u.id
+                            order by u.i
+                        )), 4326)::geography)),
+                        b.nodes[1],
+                        b.nodes[array_length(b.nodes, 1)],
+                        coalesce(b.tags, '{}') || array(
+                            select t
+                            from planet_osm_rels r, unnest(r.tags) with ordinality as u(t, i)
+                            where r.parts @> array[b.id]
+                            order by r.id, u.i
+                        )
+                    from batch b
+                    where array_length(b.nodes, 1) > 0
+                    on conflict (ways_id)
+                    do update
+                    set length = excluded.length, first_node = excluded.first_node,
+                        last_node = excluded.last_node,
+                        tags_way_and_rel = excluded.tags_way_and_rel
+                ), progress as (
+                    insert into ways_length_progress (last_way_id, measured)
+                    select max(id), $3 + count(*) from batch
+                    having count(*) > 0
+                    on conflict (id)
                     do update
-                    set length = $2, first_node = $3, last_node = $4, tags_way_and_rel = $5
-                    where ways_length.ways_id = $1;
+                    set last_way_id = excluded.last_way_id, measured = excluded.measured,
+                        updated_at = now()
+                )
+                select max(id), count(*) from batch
                 "#,
             )
-            .bind(id)
-            .bind(length)
-            .bind(node_ids.first().unwrap())
-            .bind(node_ids.last().unwrap())
-            .bind(tags)
-            .execute(client.lock().await.as_mut())
+            .bind(last_id)
+            .bind(LENGTH_BATCH)
+            .bind(progress.measured)
+            .fetch_one(client.lock().await?.as_mut())
             .await?;
+            let Some(batch_last_id) = batch_last_id else {
+                break;
+            };
+            last_id = batch_last_id;
+            progress.measured += count;
+            on_progress(progress);
         }
-        Ok(())
+        // The next run starts over
+        sqlx::query("delete from ways_length_progress")
+            .execute(client.lock().await?.as_mut())
+            .await?;
+        Ok(progress)
     }
 }
 
-#[tokio::test]
-async fn get_way() {
-    use crate::get_pg_client;
-    let time = std::time::Instant::now();
-    let client = get_pg_client().await.unwrap();
-    let way = Way::get(Arc::new(Mutex::new(client)), 503820608)
-        .await
-        .unwrap();
-    println!("it took: {:?}", time.elapsed());
-    println!("way: {:?}", way);
-    assert_eq!(2, 1);
-}
-
-#[tokio::test]
-async fn calculate_all_lengths() {
-    use crate::get_pg_client;
-    let time = std::time::Instant::now();
-    let client = get_pg_client().await.unwrap();
-    Way::calculate_all_lengths(Arc::new(Mutex::new(client)))
-        .await
-        .unwrap();
-    println!("it took: {:?}", time.elapsed());
-    assert_eq!(2, 1);
+/// Computes the lengths of the ways of every region, for the command line.
+pub async fn recompute_lengths(restart: bool) -> Result<(), Box<dyn Error>> {
+    for region in Region::all() {
+        let client = region.client().await?;
+        let progress = Way::calculate_all_lengths(client, restart, |progress| {
+            println!("{}: {}/{} ways measured", region.name, progress.measured, progress.total);
+        })
+        .await?;
+        println!("{}: {} ways measured", region.name, progress.measured);
+    }
+    Ok(())
 }
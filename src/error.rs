@@ -0,0 +1,255 @@
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::{error::Error, fmt};
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// The errors returned to the clients of the routing endpoints, as
+/// `{"code": "...", "message": "...", ...details}`.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "code", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RouteError {
+    /// The request is malformed, with a message per invalid field.
+    InvalidRequest { errors: Vec<FieldError> },
+    /// The straight-line distance between the ends is over the configured maximum.
+    RouteTooLong { distance: i32, max_distance: i32 },
+    /// No region covers both ends of the route.
+    NoRegion,
+    UnknownRegion { region: String },
+    /// A point is outside the data loaded for its region.
+    OutsideExtent { region: String },
+    /// No routable way is within the snap radius of the `point` end.
+    PointNotSnapped {
+        point: String,
+        distance: i32,
+        snap_radius: i32,
+    },
+    /// The start and end are in parts of the graph no route links.
+    NoRouteFound,
+    /// No trip taking bikes links the stations near the start and the end that day.
+    NoTransitItinerary,
+    /// The search expanded more nodes or used more memory than allowed.
+    SearchBudgetExceeded { expanded: usize, memory_bytes: usize },
+    /// The search ran out of time before reaching the end, and no partial route was
+    /// allowed.
+    SearchTimedOut { timeout_seconds: u64 },
+    /// Too many searches are running, the client should retry after `retry_after`
+    /// seconds.
+    TooManySearches { retry_after: u64 },
+    /// No route was saved under this ID.
+    RouteNotSaved { id: String },
+    /// The database cannot be reached, the client should retry after `retry_after`
+    /// seconds.
+    DatabaseUnavailable { retry_after: u64 },
+    /// The server cancelled the search as it is shutting down, the client should retry
+    /// after `retry_after` seconds, when another server takes the request.
+    ShuttingDown { retry_after: u64 },
+    /// The `feature` the request needs is not set up on this server.
+    NotConfigured { feature: String },
+    Internal {
+        #[serde(skip)]
+        message: String,
+    },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidRequest { errors } => {
+                write!(f, "Invalid request")?;
+                for (i, error) in errors.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { ", " };
+                    write!(f, "{separator}{} {}", error.field, error.message)?;
+                }
+                Ok(())
+            }
+            RouteError::RouteTooLong {
+                distance,
+                max_distance,
+            } => write!(
+                f,
+                "The start and end are {distance} m apart, routes are limited to {max_distance} m"
+            ),
+            RouteError::NoRegion => write!(f, "No region covers both the start and the end"),
+            RouteError::UnknownRegion { region } => write!(f, "Unknown region {region}"),
+            RouteError::OutsideExtent { region } => {
+                write!(f, "The start or end is outside the {region} region data")
+            }
+            RouteError::PointNotSnapped {
+                point,
+                distance,
+                snap_radius,
+            } => write!(
+                f,
+                "The closest routable way to the {point} is {distance} m away, over the {snap_radius} m snap radius"
+            ),
+            RouteError::NoRouteFound => write!(f, "No route links the start and the end"),
+            RouteError::NoTransitItinerary => {
+                write!(f, "No trip taking bikes links the start and the end that day")
+            }
+            RouteError::SearchBudgetExceeded {
+                expanded,
+                memory_bytes,
+            } => write!(
+                f,
+                "The search gave up after expanding {expanded} nodes using {} MB",
+                memory_bytes / (1024 * 1024)
+            ),
+            RouteError::SearchTimedOut { timeout_seconds } => write!(
+                f,
+                "The search did not reach the end within {timeout_seconds} s"
+            ),
+            RouteError::TooManySearches { retry_after } => write!(
+                f,
+                "Too many routes are being searched, retry in {retry_after} s"
+            ),
+            RouteError::RouteNotSaved { id } => write!(f, "No route was saved as {id}"),
+            RouteError::DatabaseUnavailable { retry_after } => write!(
+                f,
+                "The database cannot be reached, retry in {retry_after} s"
+            ),
+            RouteError::ShuttingDown { retry_after } => write!(
+                f,
+                "The server is shutting down, retry in {retry_after} s"
+            ),
+            RouteError::NotConfigured { feature } => {
+                write!(f, "The {feature} is not configured on this server")
+            }
+            RouteError::Internal { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl Error for RouteError {}
+
+/// Keeps the route errors raised deep in the search, any other error is internal.
+impl From<Box<dyn Error>> for RouteError {
+    fn from(e: Box<dyn Error>) -> Self {
+        match e.downcast::<RouteError>() {
+            Ok(e) => *e,
+            Err(e) => RouteError::Internal {
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<sqlx::Error> for RouteError {
+    fn from(e: sqlx::Error) -> Self {
+        RouteError::Internal {
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Turns the JSON extractor errors, like an unparsable or too large body, into
+/// an `INVALID_REQUEST` error.
+pub fn json_error_handler(
+    error: actix_web::error::JsonPayloadError,
+    _: &actix_web::HttpRequest,
+) -> actix_web::Error {
+    RouteError::InvalidRequest {
+        errors: vec![FieldError {
+            field: "body".to_string(),
+            message: error.to_string(),
+        }],
+    }
+    .into()
+}
+
+/// The same for the query extractor errors, like a missing `start`.
+pub fn query_error_handler(
+    error: actix_web::error::QueryPayloadError,
+    _: &actix_web::HttpRequest,
+) -> actix_web::Error {
+    RouteError::InvalidRequest {
+        errors: vec![FieldError {
+            field: "query".to_string(),
+            message: error.to_string(),
+        }],
+    }
+    .into()
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody<'a> {
+    #[serde(flatten)]
+    error: &'a RouteError,
+    message: String,
+}
+
+impl RouteError {
+    /// The body of the error responses.
+    pub fn body(&self) -> ErrorBody<'_> {
+        ErrorBody {
+            error: self,
+            message: self.to_string(),
+        }
+    }
+}
+
+impl ResponseError for RouteError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RouteError::InvalidRequest { .. } => StatusCode::BAD_REQUEST,
+            RouteError::RouteTooLong { .. }
+            | RouteError::NoRegion
+            | RouteError::UnknownRegion { .. }
+            | RouteError::OutsideExtent { .. }
+            | RouteError::PointNotSnapped { .. }
+            | RouteError::NoRouteFound
+            | RouteError::NoTransitItinerary
+            | RouteError::SearchBudgetExceeded { .. }
+            | RouteError::SearchTimedOut { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            RouteError::RouteNotSaved { .. } => StatusCode::NOT_FOUND,
+            RouteError::TooManySearches { .. } => StatusCode::TOO_MANY_REQUESTS,
+            RouteError::DatabaseUnavailable { .. }
+            | RouteError::ShuttingDown { .. }
+            | RouteError::NotConfigured { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            RouteError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let RouteError::TooManySearches { retry_after }
+        | RouteError::DatabaseUnavailable { retry_after }
+        | RouteError::ShuttingDown { retry_after } = self
+        {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(self.body())
+    }
+}
+
+#[tokio::test]
+async fn error_body_has_code_and_details() {
+    let error = RouteError::RouteTooLong {
+        distance: 300_000,
+        max_distance: 200_000,
+    };
+    let response = error.error_response();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains(r#""code":"ROUTE_TOO_LONG""#));
+    assert!(body.contains(r#""max_distance":200000"#));
+}
+
+#[test]
+fn tells_busy_clients_when_to_retry() {
+    let response = RouteError::TooManySearches { retry_after: 5 }.error_response();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "5");
+    let response = RouteError::ShuttingDown { retry_after: 1 }.error_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "1");
+}
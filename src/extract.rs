@@ -0,0 +1,148 @@
+//! Copies the routable data of a bounding box out of a region, so that developers can
+//! run a tiny local dataset instead of the full regional import:
+//!
+//! `routing-server extract --bbox <min_lat,min_lng,max_lat,max_lng>
+//! (--schema <name> | --output <file>) [--region <name>]`
+//!
+//! A schema gets the tables of the ways crossing the box, to serve as a region with
+//! `<NAME>_SCHEMA`. The ways lengths stay shared in `public`, where the migrations
+//! create them. A file gets a graph snapshot, to serve without a database.
+
+use crate::{
+    data::{bbox::BoundingBox, snapshot},
+    region::Region,
+};
+use sqlx::Connection;
+use std::{error::Error, ops::DerefMut};
+
+const USAGE: &str = "Usage: routing-server extract --bbox <min_lat,min_lng,max_lat,max_lng> \
+    (--schema <name> | --output <file>) [--region <name>]";
+
+#[derive(Debug, PartialEq)]
+enum Target {
+    Schema(String),
+    Snapshot(String),
+}
+
+#[derive(Debug, PartialEq)]
+struct ExtractArgs {
+    bbox: BoundingBox,
+    target: Target,
+    region: String,
+}
+
+fn parse_args(args: &[String]) -> Result<ExtractArgs, String> {
+    let (mut bbox, mut target, mut region) = (None, None, "default".to_string());
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(USAGE)?.clone();
+        match flag.as_str() {
+            "--bbox" => bbox = Some(value.parse()?),
+            "--schema" if is_identifier(&value) => target = Some(Target::Schema(value)),
+            "--schema" => return Err(format!("Invalid schema name {value}")),
+            "--output" => target = Some(Target::Snapshot(value)),
+            "--region" => region = value,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok(ExtractArgs {
+        bbox: bbox.ok_or(USAGE)?,
+        target: target.ok_or(USAGE)?,
+        region,
+    })
+}
+
+/// Whether `name` can be used unquoted in the statements.
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// The tables copied and the rows kept, in an order where the filters only read the
+/// tables already copied. `{schema}` and `{envelope}` are replaced.
+const TABLES: [(&str, &str); 10] = [
+    ("planet_osm_line", "way && {envelope}"),
+    ("planet_osm_point", "way && {envelope}"),
+    ("planet_osm_polygon", "way && {envelope}"),
+    ("planet_osm_ways", "id in (select osm_id from {schema}.planet_osm_line)"),
+    ("planet_osm_nodes", "id in (select unnest(nodes) from {schema}.planet_osm_ways)"),
+    ("planet_osm_rels", "parts && array(select id from {schema}.planet_osm_ways)"),
+    ("node_adjacency", "node_id in (select id from {schema}.planet_osm_nodes)"),
+    ("node_components", "node_id in (select id from {schema}.planet_osm_nodes)"),
+    ("way_popularity", "way_id in (select id from {schema}.planet_osm_ways)"),
+    ("way_collisions", "way_id in (select id from {schema}.planet_osm_ways)"),
+];
+
+/// Copies the rows of `region` inside `bbox` into the new `schema`, skipping the
+/// tables the region does not have, like `planet_osm_polygon` after `import`.
+async fn copy_to_schema(
+    region: &'static Region,
+    bbox: &BoundingBox,
+    schema: &str,
+) -> Result<(), Box<dyn Error>> {
+    let client = region.client().await?;
+    let mut connection = client.lock().await?;
+    let mut transaction = connection.deref_mut().begin().await?;
+    // Copying a city out of a country takes longer than a query, until the commit
+    sqlx::query("SET LOCAL statement_timeout = 0")
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query(&format!("create schema {schema}"))
+        .execute(&mut transaction)
+        .await?;
+    let envelope = bbox.envelope();
+    for (table, filter) in TABLES {
+        let exists: bool = sqlx::query_scalar("select to_regclass($1) is not null")
+            .bind(table)
+            .fetch_one(&mut transaction)
+            .await?;
+        if !exists {
+            continue;
+        }
+        let filter = filter
+            .replace("{schema}", schema)
+            .replace("{envelope}", &envelope);
+        sqlx::query(&format!(
+            "create table {schema}.{table} (like {table} including all)"
+        ))
+        .execute(&mut transaction)
+        .await?;
+        let rows = sqlx::query(&format!(
+            "insert into {schema}.{table} select * from {table} where {filter}"
+        ))
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+        println!("{rows} rows copied to {schema}.{table}");
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn extract(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = parse_args(args)?;
+    let region = Region::get(&args.region)?;
+    match &args.target {
+        Target::Schema(schema) => copy_to_schema(region, &args.bbox, schema).await,
+        Target::Snapshot(path) => snapshot::dump(region, path, Some(&args.bbox)).await,
+    }
+}
+
+#[test]
+fn parses_the_extract_arguments() {
+    let args = |line: &str| parse_args(&line.split(' ').map(str::to_string).collect::<Vec<_>>());
+    assert_eq!(
+        args("--bbox 45.5,-73.6,45.52,-73.56 --schema plateau").unwrap(),
+        ExtractArgs {
+            bbox: "45.5,-73.6,45.52,-73.56".parse().unwrap(),
+            target: Target::Schema("plateau".to_string()),
+            region: "default".to_string(),
+        }
+    );
+    let parsed = args("--output plateau.graph --bbox 45.5,-73.6,45.52,-73.56 --region mtl");
+    assert_eq!(parsed.unwrap().target, Target::Snapshot("plateau.graph".to_string()));
+    assert!(args("--bbox 45.5,-73.6,45.52,-73.56 --schema public;drop").is_err());
+    assert!(args("--bbox 45.5,-73.6,45.52,-73.56").is_err());
+}
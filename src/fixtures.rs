@@ -0,0 +1,200 @@
+//! End-to-end route tests on `fixtures/tiny.osm.pbf`, served from the graph built
+//! in memory like the import builds the edges, without a database.
+//!
+//! The fixture is a few streets of a made-up neighborhood of Montréal:
+//!
+//! ```text
+//!  4 ----- cycleway 101 ----- 5
+//!  |                          |
+//!  1 ------ 2 -- primary 100 -- 3 <-- residential 102, oneway -- 6
+//!           |
+//!           residential 103
+//!           |
+//!           7
+//! ```
+//!
+//! The primary is 1 km long, the cycleway 2.4 km, so that the models disagree.
+
+use crate::{
+    alternatives,
+    config::RegionConfig,
+    data::{
+        node::{bike_network, way_edges, AdjacentNode, Node},
+        snapshot,
+    },
+    map,
+    region::Region,
+    route::{self, LatLon, Model, RouteRequest},
+    rules,
+};
+use osmpbfreader::{OsmId, OsmObj};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    sync::OnceLock,
+};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tiny.osm.pbf");
+
+/// The nodes of the ways of `objs` with their edges, as `Node::load_adjacency` reads
+/// them after an import.
+fn graph(objs: &BTreeMap<OsmId, OsmObj>) -> Vec<Node> {
+    let coords = map::coordinates(objs);
+    let junctions = map::junctions(objs);
+    let relation_tags = map::relation_tags(objs);
+    let mut nodes: BTreeMap<i64, Node> = BTreeMap::new();
+    for way in objs.values().filter_map(OsmObj::way) {
+        let tags: HashMap<String, String> = way
+            .tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut tags_way_and_rel: Vec<String> =
+            tags.iter().flat_map(|(k, v)| [k.clone(), v.clone()]).collect();
+        tags_way_and_rel.extend(relation_tags.get(&way.id.0).cloned().unwrap_or_default());
+        let way_nodes: Vec<i64> = way.nodes.iter().map(|n| n.0).collect();
+        for &id in &way_nodes {
+            let Some(&(lat, lon)) = coords.get(&id) else {
+                continue;
+            };
+            let node = nodes.entry(id).or_insert_with(|| Node {
+                id,
+                lat,
+                lon,
+                adjacent_nodes: vec![],
+                highway: node_highway(objs, id),
+                elevation: None,
+            });
+            if node.adjacent_nodes.iter().any(|edge| edge.way_id == way.id.0) {
+                continue;
+            }
+            let edges = way_edges(id, (lat, lon), &way_nodes, &tags, &junctions, &coords);
+            for (node_id, intermediate_nodes, distance) in edges {
+                node.adjacent_nodes.push(AdjacentNode {
+                    node_id,
+                    way_id: way.id.0,
+                    tags: tags.clone(),
+                    distance,
+                    intermediate_nodes,
+                    popularity: 0,
+                    collisions: 0,
+                    bike_network: bike_network(&tags_way_and_rel),
+                });
+            }
+        }
+    }
+    nodes.into_values().collect()
+}
+
+fn node_highway(objs: &BTreeMap<OsmId, OsmObj>, id: i64) -> Option<String> {
+    let node = objs.get(&OsmId::Node(osmpbfreader::NodeId(id)))?.node()?;
+    node.tags.get("highway").map(|highway| highway.to_string())
+}
+
+/// A region without a database, serving the graph of the fixture from a snapshot.
+fn region() -> &'static Region {
+    static REGION: OnceLock<Region> = OnceLock::new();
+    REGION.get_or_init(|| {
+        let objs = map::read_pbf(FIXTURE).unwrap();
+        let path = std::env::temp_dir().join("routing-server-fixture.bin");
+        snapshot::write(&mut File::create(&path).unwrap(), graph(&objs)).unwrap();
+        Region::new(&RegionConfig {
+            name: "fixture".to_string(),
+            database_url: None,
+            replica_urls: vec![],
+            schema: None,
+            bbox: None,
+            snapshot: Some(path.to_str().unwrap().to_string()),
+            rules: rules::DEFAULT,
+        })
+    })
+}
+
+fn request(start: (f64, f64), end: (f64, f64), model: Model) -> RouteRequest {
+    RouteRequest {
+        start: LatLon {
+            lat: start.0,
+            lng: start.1,
+        },
+        end: LatLon {
+            lat: end.0,
+            lng: end.1,
+        },
+        model,
+        weather: Some(false),
+        ..Default::default()
+    }
+}
+
+async fn route(start: (f64, f64), end: (f64, f64), model: Model) -> Option<Vec<i64>> {
+    let (path, _) = Node::route(region(), &request(start, end, model)).await.ok()?;
+    Some(path.iter().map(|node| node.id).collect())
+}
+
+#[tokio::test]
+async fn routes_each_model_on_its_ways() {
+    let (west, east) = ((45.5, -73.57), (45.5, -73.557184));
+    assert_eq!(route(west, east, Model::Fast).await, Some(vec![1, 2, 3]));
+    assert_eq!(route(west, east, Model::Safe).await, Some(vec![1, 4, 5, 3]));
+}
+
+#[tokio::test]
+async fn follows_the_oneway_streets() {
+    let (west, start_of_oneway) = ((45.5, -73.57), (45.5, -73.550776));
+    assert_eq!(
+        route(start_of_oneway, west, Model::Fast).await,
+        Some(vec![6, 3, 2, 1])
+    );
+    assert_eq!(route(west, start_of_oneway, Model::Fast).await, None);
+}
+
+#[tokio::test]
+async fn finds_the_alternatives_not_too_long() {
+    let (west, east) = ((45.5, -73.57), (45.5, -73.557184));
+    let mut request = request(west, east, Model::Fast);
+    request.alternatives = 2;
+    let (path, _) = Node::route(region(), &request).await.unwrap();
+    let ids = |paths: Vec<Vec<Node>>| -> Vec<Vec<i64>> {
+        paths.iter().map(|path| path.iter().map(|node| node.id).collect()).collect()
+    };
+    // The cycleway is 2.4 times as long as the primary
+    let found = alternatives::find(region(), &request, &path).await.unwrap();
+    assert!(found.is_empty());
+    request.max_detour_factor = Some(3.0);
+    let found = alternatives::find(region(), &request, &path).await.unwrap();
+    assert_eq!(ids(found), vec![vec![1, 4, 5, 3]]);
+}
+
+#[tokio::test]
+async fn splits_the_route_into_legs_at_the_waypoints() {
+    let (west, east) = ((45.5, -73.57), (45.5, -73.557184));
+    let mut request = request(west, east, Model::Fast);
+    request.waypoints = vec![LatLon {
+        lat: 45.495503,
+        lng: -73.563592,
+    }];
+    request.continue_straight = true;
+    let (path, legs, _) = route::route_legs(region(), &request, |_| {}).await.unwrap();
+    let ids: Vec<i64> = path.iter().map(|node| node.id).collect();
+    // The waypoint is at the end of a dead end, the route turns around there anyway
+    assert_eq!(ids, vec![1, 2, 7, 2, 3]);
+    assert_eq!(legs.len(), 2);
+    assert_eq!((legs[0].path.len(), legs[1].path.len()), (3, 3));
+    assert!(legs.iter().all(|leg| leg.distance > 0 && leg.cost > 0));
+}
+
+#[tokio::test]
+async fn searches_the_routes_between_each_two_points() {
+    let (west, east) = ((45.5, -73.57), (45.5, -73.557184));
+    let points = [west, east].map(|(lat, lng)| LatLon { lat, lng });
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    let request = request(west, east, Model::Fast);
+    let routes = Node::routes_between(region(), &request, &points, deadline).await.unwrap();
+    let ids = |route: &Option<(Vec<Node>, i64)>| -> Option<Vec<i64>> {
+        route.as_ref().map(|(path, _)| path.iter().map(|node| node.id).collect())
+    };
+    assert_eq!(ids(&routes[0][1]), Some(vec![1, 2, 3]));
+    assert_eq!(ids(&routes[1][0]), Some(vec![3, 2, 1]));
+    let (_, cost) = Node::route(region(), &request).await.unwrap();
+    assert_eq!(routes[0][1].as_ref().map(|(_, cost)| *cost), Some(cost));
+}
@@ -0,0 +1,240 @@
+//! Resolves addresses and place names to coordinates from the OSM data of the regions,
+//! so that clients do not need a separate geocoder.
+//!
+//! The house numbers, places and amenities come from the `planet_osm_point` and
+//! `planet_osm_polygon` tables of the osm2pgsql import, the streets from
+//! `planet_osm_line`.
+
+use crate::{
+    error::{FieldError, RouteError},
+    region::Region,
+    route::LatLon,
+};
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::ops::DerefMut;
+
+/// The number of candidates returned when the request does not say.
+const DEFAULT_LIMIT: usize = 5;
+const MAX_LIMIT: usize = 20;
+
+/// How far from its street a house number may be, in meters.
+const ADDRESS_DISTANCE: f64 = 100.0;
+
+/// How much less likely a street is to be the answer when a house number was
+/// asked for but not found on it.
+const MISSING_HOUSENUMBER_FACTOR: f64 = 0.5;
+
+#[derive(Deserialize)]
+pub struct GeocodeQuery {
+    /// An address like "4200 Rue Saint-Laurent, Montréal", or a place name.
+    q: String,
+    /// The region to search, by default all of them.
+    region: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Candidate {
+    pub name: String,
+    pub location: LatLon,
+    /// `address`, `street`, or the kind of place, like `cafe` or `park`.
+    pub kind: String,
+    /// From 0 to 1, how well the candidate matches the query.
+    pub confidence: f64,
+}
+
+#[derive(Serialize)]
+struct GeocodeResponse {
+    candidates: Vec<Candidate>,
+}
+
+/// Splits a query into its house number, if it starts with one, and the name
+/// searched, up to the first comma.
+fn parse_query(query: &str) -> (Option<&str>, &str) {
+    let name = query.split(',').next().unwrap_or_default().trim();
+    match name.split_once(char::is_whitespace) {
+        Some((number, street)) if number.starts_with(|c: char| c.is_ascii_digit()) => {
+            (Some(number), street.trim())
+        }
+        _ => (None, name),
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.to_lowercase()
+        .replace(['-', '\''], " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// How well `name` matches the `searched` one: exactly, as a prefix or anywhere.
+fn confidence(searched: &str, name: &str) -> f64 {
+    let (searched, name) = (normalize(searched), normalize(name));
+    if name == searched {
+        1.0
+    } else if name.starts_with(&searched) {
+        0.8
+    } else if name.contains(&searched) {
+        0.6
+    } else {
+        0.4
+    }
+}
+
+/// The `ILIKE` pattern matching the names containing `name`.
+fn like_pattern(name: &str) -> String {
+    let escaped = name
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+async fn search(
+    region: &'static Region,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<Candidate>, RouteError> {
+    let (housenumber, name) = parse_query(query);
+    let pattern = like_pattern(name);
+    let client = region.read_client().await?;
+    let mut connection = client.lock().await?;
+    let mut candidates = vec![];
+
+    if let Some(housenumber) = housenumber {
+        let rows = sqlx::query(
+            r#"
+            select distinct on (s.name) s.name,
+                ST_X(ST_Transform(ST_Centroid(a.way), 4326)) as lng,
+                ST_Y(ST_Transform(ST_Centroid(a.way), 4326)) as lat
+            from planet_osm_line s
+            cross join lateral (
+                select way from planet_osm_point
+                where "addr:housenumber" = $1 and ST_DWithin(way, s.way, $3)
+                union all
+                select way from planet_osm_polygon
+                where "addr:housenumber" = $1 and ST_DWithin(way, s.way, $3)
+                limit 1
+            ) a
+            where s.highway is not null and s.name ilike $2
+            limit $4
+            "#,
+        )
+        .bind(housenumber)
+        .bind(&pattern)
+        .bind(ADDRESS_DISTANCE)
+        .bind(limit as i64)
+        .fetch_all(connection.deref_mut())
+        .await?;
+        candidates.extend(rows.iter().map(|row| {
+            let street: String = row.get("name");
+            Candidate {
+                confidence: confidence(name, &street),
+                name: format!("{housenumber} {street}"),
+                location: LatLon {
+                    lat: row.get("lat"),
+                    lng: row.get("lng"),
+                },
+                kind: "address".to_string(),
+            }
+        }));
+    }
+
+    let rows = sqlx::query(
+        r#"
+        select * from (
+            select distinct on (name) name, 'street' as kind,
+                ST_X(ST_Transform(ST_LineInterpolatePoint(way, 0.5), 4326)) as lng,
+                ST_Y(ST_Transform(ST_LineInterpolatePoint(way, 0.5), 4326)) as lat
+            from planet_osm_line
+            where highway is not null and name ilike $1
+            limit $2
+        ) streets
+        union all
+        select name, coalesce(place, amenity, shop, tourism, leisure, 'building') as kind,
+            ST_X(ST_Transform(way, 4326)) as lng,
+            ST_Y(ST_Transform(way, 4326)) as lat
+        from planet_osm_point
+        where name ilike $1
+        union all
+        select name, coalesce(place, amenity, shop, tourism, leisure, 'building') as kind,
+            ST_X(ST_Transform(ST_PointOnSurface(way), 4326)) as lng,
+            ST_Y(ST_Transform(ST_PointOnSurface(way), 4326)) as lat
+        from planet_osm_polygon
+        where name ilike $1
+        limit $2 * 4
+        "#,
+    )
+    .bind(&pattern)
+    .bind(limit as i64)
+    .fetch_all(connection.deref_mut())
+    .await?;
+    candidates.extend(rows.iter().map(|row| {
+        let found: String = row.get("name");
+        let kind: String = row.get("kind");
+        let mut confidence = confidence(name, &found);
+        if housenumber.is_some() {
+            confidence *= MISSING_HOUSENUMBER_FACTOR;
+        }
+        Candidate {
+            name: found,
+            location: LatLon {
+                lat: row.get("lat"),
+                lng: row.get("lng"),
+            },
+            kind,
+            confidence,
+        }
+    }));
+    Ok(candidates)
+}
+
+/// Looks up `q` in the regions, most likely candidates first.
+#[get("/geocode")]
+async fn geocode(query: web::Query<GeocodeQuery>) -> Result<impl Responder, RouteError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let mut errors = vec![];
+    if query.q.trim().is_empty() {
+        errors.push(FieldError {
+            field: "q".to_string(),
+            message: "must not be empty".to_string(),
+        });
+    }
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        errors.push(FieldError {
+            field: "limit".to_string(),
+            message: format!("must be between 1 and {MAX_LIMIT}, got {limit}"),
+        });
+    }
+    if !errors.is_empty() {
+        return Err(RouteError::InvalidRequest { errors });
+    }
+    let regions = match &query.region {
+        Some(name) => vec![Region::get(name).map_err(|_| RouteError::UnknownRegion {
+            region: name.clone(),
+        })?],
+        None => Region::all().iter().collect(),
+    };
+    let mut candidates = vec![];
+    for region in regions {
+        candidates.extend(search(region, &query.q, limit).await?);
+    }
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    candidates.truncate(limit);
+    Ok(HttpResponse::Ok().json(GeocodeResponse { candidates }))
+}
+
+#[test]
+fn splits_house_numbers_from_streets() {
+    assert_eq!(
+        parse_query("4200 Rue Saint-Laurent, Montréal"),
+        (Some("4200"), "Rue Saint-Laurent")
+    );
+    assert_eq!(parse_query("Parc La Fontaine"), (None, "Parc La Fontaine"));
+    assert_eq!(confidence("rue saint laurent", "Rue Saint-Laurent"), 1.0);
+    assert_eq!(confidence("Saint-Laurent", "Boulevard Saint-Laurent"), 0.6);
+    assert_eq!(like_pattern("100%_"), "%100\\%\\_%");
+}
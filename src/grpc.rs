@@ -0,0 +1,238 @@
+//! The routing operations as a gRPC service, for internal services that want typed
+//! clients. The messages are in `proto/routing.proto`.
+
+use crate::{
+    data::{conditional::LocalTime, elevation::climb, node::Node},
+    error::{FieldError, RouteError},
+    region::Region,
+    route::{LatLon, Model, RouteRequest},
+    segment::{summary, way_segments, WaySegment},
+};
+use proto::routing_server::{Routing, RoutingServer};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{transport::Server, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("routing");
+}
+
+/// The maximum number of routes in a matrix.
+const MAX_MATRIX_SIZE: usize = 100;
+
+impl From<RouteError> for Status {
+    fn from(e: RouteError) -> Self {
+        let message = e.to_string();
+        match e {
+            RouteError::InvalidRequest { .. } => Status::invalid_argument(message),
+            RouteError::RouteTooLong { .. } => Status::out_of_range(message),
+            RouteError::SearchBudgetExceeded { .. } | RouteError::TooManySearches { .. } => {
+                Status::resource_exhausted(message)
+            }
+            RouteError::SearchTimedOut { .. } => Status::deadline_exceeded(message),
+            RouteError::NoRegion
+            | RouteError::UnknownRegion { .. }
+            | RouteError::OutsideExtent { .. }
+            | RouteError::PointNotSnapped { .. } => Status::failed_precondition(message),
+            RouteError::NoRouteFound
+            | RouteError::NoTransitItinerary
+            | RouteError::RouteNotSaved { .. } => Status::not_found(message),
+            RouteError::DatabaseUnavailable { .. }
+            | RouteError::ShuttingDown { .. }
+            | RouteError::NotConfigured { .. } => Status::unavailable(message),
+            RouteError::Internal { .. } => Status::internal(message),
+        }
+    }
+}
+
+impl From<proto::LatLon> for LatLon {
+    fn from(point: proto::LatLon) -> Self {
+        LatLon {
+            lat: point.lat,
+            lng: point.lng,
+        }
+    }
+}
+
+impl From<LatLon> for proto::LatLon {
+    fn from(point: LatLon) -> Self {
+        proto::LatLon {
+            lat: point.lat,
+            lng: point.lng,
+        }
+    }
+}
+
+impl From<WaySegment> for proto::WaySegment {
+    fn from(segment: WaySegment) -> Self {
+        proto::WaySegment {
+            way_id: segment.way_id,
+            start: segment.start as u32,
+            end: segment.end as u32,
+            name: segment.name,
+            r#ref: segment.reference,
+            length: segment.length,
+            roundabout: segment.roundabout,
+            safety: segment.safety,
+        }
+    }
+}
+
+fn model(model: i32) -> Model {
+    match proto::Model::from_i32(model) {
+        Some(proto::Model::Fast) => Model::Fast,
+        Some(proto::Model::Family) => Model::Family,
+        Some(proto::Model::Road) => Model::Road,
+        _ => Model::Safe,
+    }
+}
+
+fn point(point: Option<proto::LatLon>, field: &str) -> Result<LatLon, RouteError> {
+    point.map(LatLon::from).ok_or_else(|| RouteError::InvalidRequest {
+        errors: vec![FieldError {
+            field: field.to_string(),
+            message: "is required".to_string(),
+        }],
+    })
+}
+
+fn departure_time(time: Option<String>) -> Result<Option<LocalTime>, RouteError> {
+    time.map(|time| time.parse())
+        .transpose()
+        .map_err(|message| RouteError::InvalidRequest {
+            errors: vec![FieldError {
+                field: "departure_time".to_string(),
+                message,
+            }],
+        })
+}
+
+/// Finds a route, returning its path and cost.
+async fn find_route(request: RouteRequest) -> Result<(Vec<Node>, i64), RouteError> {
+    request.validate()?;
+    let region = request.region().await?;
+    Ok(Node::route(region, &request).await?)
+}
+
+pub struct RoutingService;
+
+#[tonic::async_trait]
+impl Routing for RoutingService {
+    async fn route(
+        &self,
+        request: Request<proto::RouteRequest>,
+    ) -> Result<Response<proto::RouteResponse>, Status> {
+        let request = request.into_inner();
+        let options = RouteRequest {
+            start: point(request.start, "start")?,
+            end: point(request.end, "end")?,
+            model: model(request.model),
+            region: request.region,
+            snap_radius_m: request.snap_radius_m,
+            allow_ferries: request.allow_ferries,
+            night: request.night,
+            max_grade_percent: request.max_grade_percent,
+            cruising_speed_kmh: request.cruising_speed_kmh,
+            prefer_popular: request.prefer_popular,
+            departure_time: departure_time(request.departure_time)?,
+            weather: request.weather,
+            bike_route_preference: request.bike_route_preference,
+            ..Default::default()
+        };
+        let (path, cost) = find_route(options.clone()).await?;
+        let ways = way_segments(&path);
+        let (ascent, descent) = climb(&path).unzip();
+        Ok(Response::new(proto::RouteResponse {
+            path: path.iter().map(|n| LatLon::from(n).into()).collect(),
+            cost,
+            distance: ways.iter().map(|w| w.length).sum(),
+            summary: summary(&ways),
+            ways: ways.into_iter().map(proto::WaySegment::from).collect(),
+            ascent,
+            descent,
+            duration: options.duration(&path),
+        }))
+    }
+
+    type MatrixStream = ReceiverStream<Result<proto::MatrixEntry, Status>>;
+
+    async fn matrix(
+        &self,
+        request: Request<proto::MatrixRequest>,
+    ) -> Result<Response<Self::MatrixStream>, Status> {
+        let request = request.into_inner();
+        let size = request.sources.len() * request.destinations.len();
+        if size > MAX_MATRIX_SIZE {
+            return Err(Status::invalid_argument(format!(
+                "{size} routes requested, matrices are limited to {MAX_MATRIX_SIZE}"
+            )));
+        }
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            for (i, source) in request.sources.iter().enumerate() {
+                for (j, destination) in request.destinations.iter().enumerate() {
+                    let result = find_route(RouteRequest {
+                        start: source.clone().into(),
+                        end: destination.clone().into(),
+                        model: model(request.model),
+                        region: request.region.clone(),
+                        ..Default::default()
+                    })
+                    .await;
+                    let result = match result {
+                        Ok((path, cost)) => proto::matrix_entry::Result::Route(proto::MatrixRoute {
+                            cost,
+                            distance: way_segments(&path).iter().map(|w| w.length).sum(),
+                        }),
+                        Err(e) => proto::matrix_entry::Result::Error(e.to_string()),
+                    };
+                    let entry = proto::MatrixEntry {
+                        source: i as u32,
+                        destination: j as u32,
+                        result: Some(result),
+                    };
+                    // The client went away
+                    if sender.send(Ok(entry)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn nearest(
+        &self,
+        request: Request<proto::NearestRequest>,
+    ) -> Result<Response<proto::NearestResponse>, Status> {
+        let request = request.into_inner();
+        let point = point(request.point, "point")?;
+        let mut errors = vec![];
+        point.validate("point", &mut errors);
+        if !errors.is_empty() {
+            return Err(RouteError::InvalidRequest { errors }.into());
+        }
+        let region = match &request.region {
+            Some(name) => Region::get(name).map_err(|_| RouteError::UnknownRegion {
+                region: name.clone(),
+            })?,
+            None => Region::containing(&[(point.lat, point.lng)]).ok_or(RouteError::NoRegion)?,
+        };
+        let client = region.read_client().await?;
+        let (node, distance) = Node::closest(client, point.lat, point.lng)
+            .await
+            .map_err(RouteError::from)?;
+        Ok(Response::new(proto::NearestResponse {
+            node_id: node.id,
+            location: Some(LatLon::from(&node).into()),
+            distance,
+        }))
+    }
+}
+
+pub async fn serve(port: u16) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(RoutingServer::new(RoutingService))
+        .serve(([0, 0, 0, 0], port).into())
+        .await
+}
@@ -0,0 +1,43 @@
+//! Rough estimates of what riding a route burns and saves, for display only.
+
+use crate::data::node::CYCLING_SPEED;
+
+/// The weight of the rider when the request does not say, in kilograms.
+pub const DEFAULT_RIDER_WEIGHT: f64 = 75.0;
+
+/// The weight of the bike, in kilograms.
+const BIKE_WEIGHT: f64 = 12.0;
+
+/// The metabolic equivalent of leisure cycling around 15 km/h.
+const CYCLING_MET: f64 = 6.0;
+
+/// The share of the burned energy turned into climbing.
+const MUSCLE_EFFICIENCY: f64 = 0.25;
+
+const JOULES_PER_KCAL: f64 = 4184.0;
+
+/// What an average car emits, in grams of CO2 per kilometer.
+const CAR_CO2_PER_KM: f64 = 170.0;
+
+/// The kilocalories burned riding `distance` meters while climbing `ascent` meters.
+pub fn calories(distance: i32, ascent: i32, rider_weight: f64) -> i32 {
+    let hours = distance as f64 / CYCLING_SPEED / 3600.0;
+    let riding = CYCLING_MET * rider_weight * hours;
+    let climbing = ascent as f64 * (rider_weight + BIKE_WEIGHT) * 9.81
+        / MUSCLE_EFFICIENCY
+        / JOULES_PER_KCAL;
+    (riding + climbing).round() as i32
+}
+
+/// The grams of CO2 a car would have emitted over `distance` meters.
+pub fn co2_saved(distance: i32) -> i32 {
+    (distance as f64 / 1000.0 * CAR_CO2_PER_KM).round() as i32
+}
+
+#[test]
+fn estimates_an_hour_of_riding() {
+    // 15 km on the flat is an hour at the cycling speed
+    assert_eq!(calories(15_000, 0, 75.0), 450);
+    assert_eq!(calories(15_000, 300, 75.0), 695);
+    assert_eq!(co2_saved(15_000), 2550);
+}
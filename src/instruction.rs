@@ -0,0 +1,388 @@
+use crate::{
+    data::node::Node,
+    route::{LatLon, Units},
+    segment::WaySegment,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq, ToSchema)]
+pub enum ManeuverType {
+    #[serde(rename = "depart")]
+    Depart,
+    #[serde(rename = "turn")]
+    Turn,
+    /// Turning while staying on the same street.
+    #[serde(rename = "continue")]
+    Continue,
+    /// Going on straight, onto a street with another name.
+    #[serde(rename = "new name")]
+    NewName,
+    #[serde(rename = "roundabout")]
+    Roundabout,
+    #[serde(rename = "exit roundabout")]
+    ExitRoundabout,
+    #[serde(rename = "arrive")]
+    Arrive,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq, ToSchema)]
+pub enum Modifier {
+    #[serde(rename = "uturn")]
+    UTurn,
+    #[serde(rename = "sharp right")]
+    SharpRight,
+    #[serde(rename = "right")]
+    Right,
+    #[serde(rename = "slight right")]
+    SlightRight,
+    #[serde(rename = "straight")]
+    Straight,
+    #[serde(rename = "slight left")]
+    SlightLeft,
+    #[serde(rename = "left")]
+    Left,
+    #[serde(rename = "sharp left")]
+    SharpLeft,
+}
+
+impl Modifier {
+    /// The modifier for a change of direction of `angle` degrees, positive to the right.
+    pub fn from_angle(angle: f64) -> Self {
+        let magnitude = angle.abs();
+        let right = angle > 0.0;
+        match magnitude {
+            m if m < 20.0 => Modifier::Straight,
+            m if m < 60.0 && right => Modifier::SlightRight,
+            m if m < 60.0 => Modifier::SlightLeft,
+            m if m < 140.0 && right => Modifier::Right,
+            m if m < 140.0 => Modifier::Left,
+            m if m < 170.0 && right => Modifier::SharpRight,
+            m if m < 170.0 => Modifier::SharpLeft,
+            _ => Modifier::UTurn,
+        }
+    }
+
+    fn is_straight(&self) -> bool {
+        matches!(
+            self,
+            Modifier::Straight | Modifier::SlightLeft | Modifier::SlightRight
+        )
+    }
+}
+
+/// A machine-readable maneuver, following the OSRM step maneuvers.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Maneuver {
+    #[serde(rename = "type")]
+    pub kind: ManeuverType,
+    pub modifier: Option<Modifier>,
+    /// The bearing arriving at the maneuver, in degrees clockwise from north.
+    pub bearing_before: i32,
+    /// The bearing leaving the maneuver, in degrees clockwise from north.
+    pub bearing_after: i32,
+    /// For roundabouts, the exit to take, counting from 1.
+    pub exit: Option<u32>,
+    pub location: LatLon,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Step {
+    pub maneuver: Maneuver,
+    /// The street followed after the maneuver.
+    pub name: Option<String>,
+    /// The distance until the next maneuver, in meters.
+    pub distance: i32,
+    /// The distance until the next maneuver in the requested units, like "350 m".
+    pub distance_text: String,
+    /// The index in the route path of the maneuver node.
+    pub start: usize,
+    /// The index in the route path of the next maneuver node.
+    pub end: usize,
+    /// The maneuver in words, like "Take the 2nd exit onto Main Street".
+    pub instruction: String,
+}
+
+/// The meters in feet.
+const FEET_PER_METER: f64 = 3.28084;
+
+/// The meters in a mile.
+const METERS_PER_MILE: f64 = 1609.344;
+
+/// `meters` written out in `units`, rounded the way riders read them: to 10 m or
+/// 50 ft when short, to a tenth of a kilometer or of a mile otherwise.
+pub fn format_distance(meters: i32, units: Units) -> String {
+    let round = |value: f64, to: f64| (value / to).round() * to;
+    match units {
+        Units::Metric if meters < 1000 => format!("{} m", round(meters as f64, 10.0)),
+        Units::Metric => format!("{:.1} km", meters as f64 / 1000.0),
+        Units::Imperial if meters < 305 => {
+            format!("{} ft", round(meters as f64 * FEET_PER_METER, 50.0))
+        }
+        Units::Imperial => format!("{:.1} mi", meters as f64 / METERS_PER_MILE),
+    }
+}
+
+/// "1st", "2nd", "3rd", "4th"...
+fn ordinal(n: u32) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
+
+impl Modifier {
+    fn words(&self) -> &'static str {
+        match self {
+            Modifier::UTurn => "make a U-turn",
+            Modifier::SharpRight => "turn sharp right",
+            Modifier::Right => "turn right",
+            Modifier::SlightRight => "bear right",
+            Modifier::Straight => "go straight",
+            Modifier::SlightLeft => "bear left",
+            Modifier::Left => "turn left",
+            Modifier::SharpLeft => "turn sharp left",
+        }
+    }
+}
+
+/// The instruction for `maneuver`, onto the street `name`.
+fn instruction(maneuver: &Maneuver, name: Option<&str>) -> String {
+    let onto = name.map(|name| format!(" onto {name}")).unwrap_or_default();
+    let action = match (maneuver.kind, maneuver.modifier) {
+        (ManeuverType::Depart, _) => "Head out".to_string(),
+        (ManeuverType::Arrive, _) => return "Arrive at your destination".to_string(),
+        (ManeuverType::Roundabout, _) => match maneuver.exit {
+            Some(exit) => format!("At the roundabout, take the {} exit", ordinal(exit)),
+            None => "Enter the roundabout".to_string(),
+        },
+        (ManeuverType::ExitRoundabout, _) => "Exit the roundabout".to_string(),
+        (ManeuverType::Continue, _) => "Continue".to_string(),
+        (_, Some(modifier)) => {
+            let words = modifier.words();
+            words[..1].to_uppercase() + &words[1..]
+        }
+        (_, None) => "Continue".to_string(),
+    };
+    format!("{action}{onto}")
+}
+
+/// The initial bearing from `from` to `to`, in degrees clockwise from north.
+pub fn bearing(from: &Node, to: &Node) -> f64 {
+    LatLon::from(from).bearing(&LatLon::from(to))
+}
+
+/// The change of direction from `before` to `after`, in degrees between -180 and
+/// 180, positive to the right.
+pub fn turn_angle(before: f64, after: f64) -> f64 {
+    let angle = (after - before + 360.0) % 360.0;
+    if angle > 180.0 {
+        angle - 360.0
+    } else {
+        angle
+    }
+}
+
+/// Counts the exits passed through a roundabout, from the node at `entry` to the
+/// node at `exit` of `path`, the latter included. An exit is any edge leaving the
+/// roundabout.
+fn count_exits(path: &[Node], entry: usize, exit: usize, roundabout_ways: &HashSet<i64>) -> u32 {
+    path[entry + 1..=exit]
+        .iter()
+        .filter(|node| {
+            node.adjacent_nodes
+                .iter()
+                .any(|a_node| !roundabout_ways.contains(&a_node.way_id))
+        })
+        .count() as u32
+}
+
+/// Builds the turn by turn steps of a route from its way segments. Consecutive
+/// segments going straight on the same street are merged into one step.
+pub fn steps(path: &[Node], segments: &[WaySegment], units: Units) -> Vec<Step> {
+    let mut steps: Vec<Step> = vec![];
+    if path.len() < 2 {
+        return steps;
+    }
+    for (i, segment) in segments.iter().enumerate() {
+        let n = segment.start;
+        let bearing_after = bearing(&path[n], &path[n + 1]);
+        let location = LatLon::from(&path[n]);
+        let name = segment.label().map(str::to_string);
+        let Some(previous) = steps.last_mut() else {
+            steps.push(Step {
+                maneuver: Maneuver {
+                    kind: ManeuverType::Depart,
+                    modifier: None,
+                    bearing_before: 0,
+                    bearing_after: bearing_after.round() as i32,
+                    exit: None,
+                    location,
+                },
+                name,
+                distance: segment.length,
+                distance_text: String::new(),
+                start: n,
+                end: segment.end,
+                instruction: String::new(),
+            });
+            continue;
+        };
+        let bearing_before = bearing(&path[n - 1], &path[n]);
+        let modifier = Modifier::from_angle(turn_angle(bearing_before, bearing_after));
+        let previous_roundabout = segments[i - 1].roundabout;
+
+        // The rest of a roundabout belongs to the step entering it
+        if segment.roundabout && previous_roundabout {
+            previous.distance += segment.length;
+            previous.end = segment.end;
+            continue;
+        }
+        if !segment.roundabout
+            && !previous_roundabout
+            && modifier.is_straight()
+            && name == previous.name
+        {
+            previous.distance += segment.length;
+            previous.end = segment.end;
+            continue;
+        }
+
+        let (kind, exit) = if segment.roundabout {
+            let last = segments[i..]
+                .iter()
+                .take_while(|s| s.roundabout)
+                .last()
+                .unwrap_or(segment);
+            let ways: HashSet<i64> = segments[i..]
+                .iter()
+                .take_while(|s| s.roundabout)
+                .map(|s| s.way_id)
+                .collect();
+            let exit = count_exits(path, n, last.end.min(path.len() - 1), &ways);
+            (ManeuverType::Roundabout, Some(exit))
+        } else if previous_roundabout {
+            (ManeuverType::ExitRoundabout, None)
+        } else if name == previous.name {
+            // Staying on the street through a turn
+            (ManeuverType::Continue, None)
+        } else if modifier.is_straight() {
+            (ManeuverType::NewName, None)
+        } else {
+            (ManeuverType::Turn, None)
+        };
+        steps.push(Step {
+            maneuver: Maneuver {
+                kind,
+                modifier: Some(modifier),
+                bearing_before: bearing_before.round() as i32,
+                bearing_after: bearing_after.round() as i32,
+                exit,
+                location,
+            },
+            name,
+            distance: segment.length,
+            distance_text: String::new(),
+            start: n,
+            end: segment.end,
+            instruction: String::new(),
+        });
+    }
+
+    let last = path.len() - 1;
+    steps.push(Step {
+        maneuver: Maneuver {
+            kind: ManeuverType::Arrive,
+            modifier: None,
+            bearing_before: bearing(&path[last - 1], &path[last]).round() as i32,
+            bearing_after: 0,
+            exit: None,
+            location: LatLon::from(&path[last]),
+        },
+        name: segments.last().and_then(|s| s.label().map(str::to_string)),
+        distance: 0,
+        distance_text: String::new(),
+        start: last,
+        end: last,
+        instruction: String::new(),
+    });
+    for step in &mut steps {
+        step.instruction = instruction(&step.maneuver, step.name.as_deref());
+        step.distance_text = format_distance(step.distance, units);
+    }
+    steps
+}
+
+#[test]
+fn turn_angles_wrap_around_north() {
+    assert_eq!(turn_angle(350.0, 10.0), 20.0);
+    assert_eq!(turn_angle(10.0, 350.0), -20.0);
+    assert_eq!(Modifier::from_angle(90.0), Modifier::Right);
+    assert_eq!(Modifier::from_angle(-175.0), Modifier::UTurn);
+}
+
+#[test]
+fn merges_straight_segments_of_the_same_street() {
+    use crate::segment::{test_path, way_segments};
+    // The test path goes straight east, on streets named after their way
+    let path = test_path(&[1, 1, 2]);
+    let steps = steps(&path, &way_segments(&path), Units::Metric);
+    let kinds: Vec<ManeuverType> = steps.iter().map(|s| s.maneuver.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            ManeuverType::Depart,
+            ManeuverType::NewName,
+            ManeuverType::Arrive
+        ]
+    );
+    assert_eq!(steps[0].distance, 20);
+    assert_eq!(steps[1].maneuver.bearing_before, 90);
+}
+
+#[test]
+fn counts_roundabout_exits() {
+    use crate::{
+        data::node::AdjacentNode,
+        segment::{test_path, way_segments},
+    };
+    // Entering the roundabout (way 2) at node 1, passing an exit at node 2 and
+    // leaving it at node 3
+    let mut path = test_path(&[1, 2, 2, 3]);
+    for node in &mut path[1..3] {
+        node.adjacent_nodes[0]
+            .tags
+            .insert("junction".to_string(), "roundabout".to_string());
+    }
+    path[2].adjacent_nodes.push(AdjacentNode {
+        node_id: 10,
+        way_id: 4,
+        tags: Default::default(),
+        distance: 10,
+        intermediate_nodes: None,
+        popularity: 0,
+        collisions: 0,
+        bike_network: 0,
+    });
+    let steps = steps(&path, &way_segments(&path), Units::Metric);
+    assert_eq!(steps[1].maneuver.kind, ManeuverType::Roundabout);
+    assert_eq!(steps[1].maneuver.exit, Some(2));
+    assert_eq!(
+        steps[1].instruction,
+        "At the roundabout, take the 2nd exit onto Street 2"
+    );
+    assert_eq!(steps[2].maneuver.kind, ManeuverType::ExitRoundabout);
+}
+
+#[test]
+fn writes_out_distances_in_the_requested_units() {
+    assert_eq!(format_distance(347, Units::Metric), "350 m");
+    assert_eq!(format_distance(4230, Units::Metric), "4.2 km");
+    assert_eq!(format_distance(100, Units::Imperial), "350 ft");
+    assert_eq!(format_distance(4230, Units::Imperial), "2.6 mi");
+}
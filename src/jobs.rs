@@ -0,0 +1,174 @@
+//! The maintenance jobs run periodically by the `worker` subcommand, or by the
+//! servers started with `SCHEDULED_JOBS=true`: recomputing the ways
+//! lengths and the connected components after the data changed, and deleting the
+//! old closures and saved routes.
+//!
+//! A job runs under a Postgres advisory lock and records when it ran, so that it
+//! runs once per interval whatever the number of replicas.
+
+use crate::{
+    config::CONFIG,
+    data::{cache::FlushScope, closure::Closure, component, saved_route::SavedRoute, way::Way},
+    region::{Region, RegionClient},
+};
+use sqlx::Executor;
+use std::{error::Error, ops::DerefMut, time::Duration};
+
+/// The advisory locks of the jobs are this plus the job number.
+const LOCK_KEY: i64 = 0x726f7574696e67;
+
+/// The advisory lock of the ways lengths computation, taken as well by the admin
+/// endpoint and the command line, which resume from the same progress.
+pub const LENGTHS_LOCK: i64 = LOCK_KEY + Job::Lengths as i64;
+
+#[derive(Clone, Copy, Debug)]
+enum Job {
+    Lengths,
+    Components,
+    Closures,
+    SavedRoutes,
+}
+
+const JOBS: [Job; 4] = [Job::Lengths, Job::Components, Job::Closures, Job::SavedRoutes];
+
+impl Job {
+    fn name(self) -> &'static str {
+        match self {
+            Job::Lengths => "lengths",
+            Job::Components => "components",
+            Job::Closures => "closures",
+            Job::SavedRoutes => "saved_routes",
+        }
+    }
+
+    fn interval(self) -> Duration {
+        match self {
+            Job::Lengths => CONFIG.lengths_refresh_interval,
+            Job::Components => CONFIG.components_refresh_interval,
+            Job::Closures => CONFIG.closures_expiry_interval,
+            Job::SavedRoutes => CONFIG.saved_routes_expiry_interval,
+        }
+    }
+
+    /// Runs the job on `pg_client`, describing what it did.
+    async fn run(
+        self,
+        region: &'static Region,
+        pg_client: RegionClient,
+    ) -> Result<String, Box<dyn Error>> {
+        match self {
+            Job::Lengths => {
+                let progress = Way::calculate_all_lengths(pg_client, false, |_| {}).await?;
+                // The cached nodes hold the tags of the ways relations
+                region.flush_cache(&FlushScope::All).await?;
+                Ok(format!("{} ways measured", progress.measured))
+            }
+            Job::Components => {
+                let (nodes, count) = component::label(pg_client.lock().await?.deref_mut()).await?;
+                Ok(format!("{nodes} nodes in {count} components"))
+            }
+            Job::Closures => {
+                let expired = Closure::expire(pg_client, CONFIG.closures_retention).await?;
+                if expired > 0 {
+                    region.reload_closures().await?;
+                }
+                Ok(format!("{expired} closures deleted"))
+            }
+            Job::SavedRoutes => {
+                let expired = SavedRoute::expire(pg_client, CONFIG.saved_routes_retention).await?;
+                Ok(format!("{expired} saved routes deleted"))
+            }
+        }
+    }
+}
+
+/// Takes the advisory lock `key` on the connection of `client`, false when another
+/// session holds it.
+pub async fn try_lock(client: &RegionClient, key: i64) -> Result<bool, Box<dyn Error>> {
+    let locked = sqlx::query_scalar("select pg_try_advisory_lock($1)")
+        .bind(key)
+        .fetch_one(client.lock().await?.deref_mut())
+        .await?;
+    Ok(locked)
+}
+
+/// Releases the advisory lock `key` taken by `try_lock`.
+pub async fn unlock(client: &RegionClient, key: i64) -> Result<(), Box<dyn Error>> {
+    sqlx::query("select pg_advisory_unlock($1)")
+        .bind(key)
+        .execute(client.lock().await?.deref_mut())
+        .await?;
+    Ok(())
+}
+
+/// Runs `job` on `region` unless another replica is running it.
+async fn run(job: Job, region: &'static Region) -> Result<(), Box<dyn Error>> {
+    let client = region.client().await?;
+    let lock_key = LOCK_KEY + job as i64;
+    if !try_lock(&client, lock_key).await? {
+        return Ok(());
+    }
+    let result = run_locked(job, region, client.clone()).await;
+    unlock(&client, lock_key).await?;
+    result
+}
+
+/// Runs `job` unless it ran within its interval, holding its lock.
+async fn run_locked(
+    job: Job,
+    region: &'static Region,
+    client: RegionClient,
+) -> Result<(), Box<dyn Error>> {
+    let due: bool = sqlx::query_scalar(
+        r#"
+        select not exists (
+            select 1 from job_runs
+            where job = $1 and ran_at > now() - make_interval(secs => $2)
+        )
+        "#,
+    )
+    .bind(job.name())
+    .bind(job.interval().as_secs_f64())
+    .fetch_one(client.lock().await?.deref_mut())
+    .await?;
+    if !due {
+        return Ok(());
+    }
+    // The jobs take longer than the queries of the routes
+    client.lock().await?.execute("SET statement_timeout = 0").await?;
+    let result = job.run(region, client.clone()).await;
+    let timeout = format!(
+        "SET statement_timeout = {}",
+        CONFIG.db_statement_timeout.as_millis()
+    );
+    client.lock().await?.execute(timeout.as_str()).await?;
+    let summary = result?;
+    sqlx::query(
+        r#"
+        insert into job_runs (job, ran_at) values ($1, now())
+        on conflict (job) do update set ran_at = now()
+        "#,
+    )
+    .bind(job.name())
+    .execute(client.lock().await?.deref_mut())
+    .await?;
+    println!("The {} job of the {} region ran: {summary}", job.name(), region.name);
+    Ok(())
+}
+
+/// Runs the jobs of every region at their intervals, for as long as the server runs.
+pub fn start() {
+    for region in Region::all() {
+        for job in JOBS.into_iter().filter(|job| !job.interval().is_zero()) {
+            actix_web::rt::spawn(async move {
+                loop {
+                    if let Err(e) = run(job, region).await {
+                        let name = job.name();
+                        eprintln!("The {name} job of the {} region failed: {e}", region.name);
+                    }
+                    tokio::time::sleep(job.interval()).await;
+                }
+            });
+        }
+    }
+}
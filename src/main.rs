@@ -1,53 +1,274 @@
 use actix_cors::Cors;
-use actix_web::{App, HttpServer};
-use sqlx::pool::PoolConnection;
-use sqlx::postgres::PgPoolOptions;
-use sqlx::{Pool, Postgres};
-use std::{env, thread};
+use actix_web::rt::signal;
+use actix_web::{middleware, web, App, HttpServer};
+use config::CONFIG;
+use region::Region;
+use std::env;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 #[macro_use]
 extern crate lazy_static;
 
+mod admin;
+mod alternatives;
 mod astar;
+mod bench;
+mod bikeshare;
+mod collisions;
+mod compare;
+mod composition;
+mod config;
+mod csv;
 mod data;
+mod error;
+mod extract;
+#[cfg(test)]
+mod fixtures;
+mod geocode;
+mod grpc;
+mod impact;
+mod instruction;
+mod jobs;
+mod map;
+mod metrics;
+mod openapi;
+mod osrm;
+mod popularity;
+mod profile;
+mod region;
+mod reroute;
 mod route;
+mod rules;
+mod safety;
+mod search_tree;
+mod segment;
+mod stages;
+mod throttle;
+mod tiles;
+mod transit;
+mod trip;
+mod weather;
+
+/// Searches are cancelled this long before the shutdown timeout, so that their
+/// handlers still have time to send an error response.
+const SEARCH_CANCEL_MARGIN: Duration = Duration::from_secs(2);
+
+/// The moment a termination signal was received.
+static SHUTDOWN_STARTED: OnceLock<Instant> = OnceLock::new();
 
 #[actix_web::main] // or #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(move || {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("import") => {
+            let usage = "Usage: routing-server import <file.osm.pbf | --from-overpass <area>>";
+            let imported = match (args.get(2).expect(usage).as_str(), args.get(3)) {
+                ("--from-overpass", Some(area)) => map::import_overpass(area).await,
+                ("--from-overpass", None) => panic!("{usage}"),
+                (path, _) => map::import(path).await,
+            };
+            imported.map_err(|e| std::io::Error::other(e.to_string()))
+        }
+        Some("import-gpx") if args.len() > 2 => popularity::import(&args[2..])
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string())),
+        Some("import-collisions") => {
+            let path = args
+                .get(2)
+                .expect("Usage: routing-server import-collisions <file.csv|file.geojson>");
+            collisions::import(path)
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))
+        }
+        Some("compute-components") => data::component::compute()
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string())),
+        // After an import, resuming an interrupted run unless --restart
+        Some("recompute-lengths") => {
+            let restart = args.get(2).is_some_and(|arg| arg == "--restart");
+            data::way::recompute_lengths(restart)
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))
+        }
+        Some("bench") => bench::bench(&args[2..])
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string())),
+        Some("extract") => extract::extract(&args[2..])
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string())),
+        Some("dump-graph") => {
+            let path = args
+                .get(2)
+                .expect("Usage: routing-server dump-graph <file> [region]");
+            let name = args.get(3).map_or("default", String::as_str);
+            let dumped = match Region::get(name) {
+                Ok(region) => data::snapshot::dump(region, path, None).await,
+                Err(e) => Err(e),
+            };
+            dumped.map_err(|e| std::io::Error::other(e.to_string()))
+        }
+        // Runs the maintenance jobs only, unless the servers run them with SCHEDULED_JOBS=true
+        Some("worker") => {
+            jobs::start();
+            wait_for_termination().await;
+            for region in Region::all() {
+                region.close().await;
+            }
+            Ok(())
+        }
+        _ => serve().await,
+    }
+}
+
+/// The routing endpoints of the first version of the API, where routes that were
+/// not asked to be detailed are bare coordinate arrays.
+fn api_v1(config: &mut web::ServiceConfig) {
+    config
+        .service(route::route)
+        .service(route::route_get)
+        .service(route::route_stream)
+        .service(compare::compare_routes)
+        .service(trip::trip)
+        .service(route::route_by_id)
+        .service(transit::transit_route)
+        .service(bikeshare::bikeshare_route)
+        .service(reroute::reroute_route);
+}
+
+/// The routing endpoints of the second version, where routes are always detailed
+/// objects so that their schema can evolve.
+fn api_v2(config: &mut web::ServiceConfig) {
+    config
+        .service(route::route_v2)
+        .service(route::route_get_v2)
+        .service(route::route_stream_v2)
+        .service(compare::compare_routes)
+        .service(trip::trip)
+        .service(route::route_by_id)
+        .service(transit::transit_route)
+        .service(bikeshare::bikeshare_route)
+        .service(reroute::reroute_route);
+}
+
+async fn serve() -> std::io::Result<()> {
+    // Loading the timetables takes a while, rather than on the first transit route
+    transit::feed();
+    // Mapping the graph snapshots, if any
+    Region::all();
+    if CONFIG.scheduled_jobs {
+        jobs::start();
+    }
+    let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
             .allow_any_method()
             .allow_any_header();
         App::new()
             .wrap(cors)
-            .service(route::route)
+            // Negotiated with Accept-Encoding, dense routes are hundreds of kilobytes
+            .wrap(middleware::Compress::default())
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(CONFIG.max_body_size)
+                    .error_handler(error::json_error_handler),
+            )
+            .app_data(web::QueryConfig::default().error_handler(error::query_error_handler))
+            // Unversioned for the apps deployed before the versions
+            .configure(api_v1)
+            .service(web::scope("/v1").configure(api_v1))
+            .service(web::scope("/v2").configure(api_v2))
+            .service(geocode::geocode)
+            .service(osrm::route)
+            .service(metrics::metrics)
+            .service(openapi::openapi_json)
+            .service(tiles::debug_tile)
+            .service(admin::cache)
+            .service(admin::flush_cache)
+            .service(admin::warm_cache)
+            .service(admin::create_closure)
+            .service(admin::closures)
+            .service(admin::delete_closure)
+            .service(admin::recompute_lengths)
+            .service(admin::lengths_progress)
     })
-    .bind(("0.0.0.0", 3000))?
-    .run()
-    .await
-}
+    .shutdown_timeout(CONFIG.shutdown_timeout.as_secs())
+    .disable_signals();
+    let server = match &CONFIG.unix_socket {
+        #[cfg(unix)]
+        Some(path) => {
+            // The socket of a previous run would fail the bind
+            use std::os::unix::fs::FileTypeExt;
+            if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            let server = server.bind_uds(path)?;
+            if let Some(mode) = CONFIG.unix_socket_mode {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            }
+            server
+        }
+        // Rather than listening where the proxy does not expect it
+        #[cfg(not(unix))]
+        Some(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "UNIX_SOCKET is only supported on Unix",
+            ))
+        }
+        None => server.bind(("0.0.0.0", 3000))?,
+    }
+    .run();
+
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        wait_for_termination().await;
+        SHUTDOWN_STARTED.get_or_init(Instant::now);
+        // Stops accepting connections and waits for in-flight requests to finish,
+        // up to the shutdown timeout.
+        handle.stop(true).await;
+    });
 
-lazy_static! {
-    static ref DB_POOL: Pool<Postgres> = {
-        let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-
-        thread::spawn(move || {
-            tokio::runtime::Runtime::new().unwrap().block_on(async {
-                let pool = PgPoolOptions::new()
-                    .max_connections(15)
-                    .connect(&url)
-                    .await
-                    .unwrap();
-                sqlx::migrate!().run(&pool).await.unwrap();
-                pool
-            })
+    let grpc = CONFIG.grpc_port.map(|port| {
+        actix_web::rt::spawn(async move {
+            if let Err(e) = grpc::serve(port).await {
+                eprintln!("The gRPC server stopped: {e}");
+            }
         })
-        .join()
-        .expect("Problem in the pool creation thread")
-    };
+    });
+
+    server.await?;
+    if let Some(grpc) = grpc {
+        grpc.abort();
+    }
+    for region in Region::all() {
+        region.close().await;
+    }
+    Ok(())
+}
+
+async fn wait_for_termination() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Cannot listen for SIGTERM");
+        tokio::select! {
+            _ = sigterm.recv() => {},
+            _ = signal::ctrl_c() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.expect("Cannot listen for ctrl-c");
 }
 
-async fn get_pg_client() -> Result<PoolConnection<Postgres>, sqlx::Error> {
-    DB_POOL.acquire().await
+/// Whether running searches should give up because the server is shutting down
+/// and they would not finish before the shutdown timeout.
+pub fn searches_cancelled() -> bool {
+    match SHUTDOWN_STARTED.get() {
+        Some(started) => {
+            started.elapsed() + SEARCH_CANCEL_MARGIN >= CONFIG.shutdown_timeout
+        }
+        None => false,
+    }
 }
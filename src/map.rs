@@ -0,0 +1,610 @@
+//! Imports an OpenStreetMap PBF extract, or the result of an Overpass query for a
+//! small area, into the tables read by the server, in the same layout as an
+//! `osm2pgsql -c -s` import, and precomputes the ways lengths and the edges of the
+//! graph.
+
+use crate::data::node::{distance, is_delayed, way_edges};
+use osmpbfreader::{
+    Node, NodeId, OsmId, OsmObj, OsmPbfReader, Ref, Relation, RelationId, Tags, Way, WayId,
+};
+use serde::Deserialize;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    error::Error,
+    fs::File,
+};
+
+/// How many rows are written per insert statement.
+const BATCH_SIZE: usize = 10_000;
+
+/// The Overpass API queried when `OVERPASS_URL` is not set.
+const DEFAULT_OVERPASS_URL: &str = "https://overpass-api.de/api/interpreter";
+
+/// How long the Overpass API may take to answer, in seconds.
+const OVERPASS_TIMEOUT: u64 = 180;
+
+/// The subset of the osm2pgsql slim tables that the server uses. Existing osm2pgsql
+/// tables are kept as is.
+const CREATE_TABLES: [&str; 8] = [
+    r#"
+    create extension if not exists postgis
+    "#,
+    r#"
+    create table if not exists planet_osm_nodes (
+        id int8 primary key,
+        lat int4 not null,
+        lon int4 not null
+    )
+    "#,
+    r#"
+    create table if not exists planet_osm_ways (
+        id int8 primary key,
+        nodes int8[] not null,
+        tags text[]
+    )
+    "#,
+    r#"
+    create table if not exists planet_osm_rels (
+        id int8 primary key,
+        way_off int2,
+        rel_off int2,
+        parts int8[],
+        members text[],
+        tags text[]
+    )
+    "#,
+    r#"
+    create table if not exists planet_osm_line (
+        osm_id int8,
+        access text,
+        aeroway text,
+        bicycle text,
+        building text,
+        highway text,
+        name text,
+        ref text,
+        surface text,
+        way geometry(LineString, 3857)
+    )
+    "#,
+    r#"
+    create index if not exists planet_osm_line_way_idx
+    on planet_osm_line using gist (way)
+    "#,
+    r#"
+    create table if not exists planet_osm_point (
+        osm_id int8,
+        highway text,
+        way geometry(Point, 3857)
+    )
+    "#,
+    r#"
+    create index if not exists planet_osm_point_osm_id_idx
+    on planet_osm_point (osm_id)
+    "#,
+];
+
+fn flat_tags(tags: &Tags) -> Vec<String> {
+    tags.iter()
+        .flat_map(|(k, v)| [k.to_string(), v.to_string()])
+        .collect()
+}
+
+fn tag(tags: &Tags, key: &str) -> Option<String> {
+    tags.get(key).map(|v| v.to_string())
+}
+
+/// Imports the routable ways of the PBF file at `path`, with their nodes and the
+/// bicycle route relations they belong to.
+pub async fn import(path: &str) -> Result<(), Box<dyn Error>> {
+    println!("Reading {path}");
+    import_objects(&read_pbf(path)?).await
+}
+
+/// The routable ways and bicycle route relations of the PBF file at `path`, with the
+/// objects they refer to.
+pub fn read_pbf(path: &str) -> Result<BTreeMap<OsmId, OsmObj>, Box<dyn Error>> {
+    let mut pbf = OsmPbfReader::new(File::open(path)?);
+    let objs = pbf.get_objs_and_deps(|obj| match obj {
+        OsmObj::Way(way) => way.tags.contains_key("highway"),
+        OsmObj::Relation(rel) => rel.tags.contains("route", "bicycle"),
+        OsmObj::Node(_) => false,
+    })?;
+    Ok(objs)
+}
+
+#[derive(Deserialize)]
+struct OverpassMember {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "ref")]
+    id: i64,
+    #[serde(default)]
+    role: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum OverpassElement {
+    Node {
+        id: i64,
+        lat: f64,
+        lon: f64,
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+    },
+    Way {
+        id: i64,
+        nodes: Vec<i64>,
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+    },
+    Relation {
+        id: i64,
+        members: Vec<OverpassMember>,
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct OverpassResponse {
+    elements: Vec<OverpassElement>,
+}
+
+/// The query of the routable ways in `area`, like `area[name=Verdun]`, with their
+/// nodes and the bicycle route relations they belong to.
+fn overpass_query(area: &str) -> String {
+    format!(
+        r#"[out:json][timeout:{OVERPASS_TIMEOUT}];
+        {area}->.searchArea;
+        way[highway](area.searchArea)->.ways;
+        (.ways; .ways >; rel(bw.ways)[route=bicycle];);
+        out;"#
+    )
+}
+
+fn osm_tags(tags: BTreeMap<String, String>) -> Tags {
+    let mut osm_tags = Tags::new();
+    for (key, value) in tags {
+        osm_tags.insert(key.into(), value.into());
+    }
+    osm_tags
+}
+
+/// The objects of an Overpass JSON response, as read from a PBF file.
+fn overpass_objects(response: OverpassResponse) -> BTreeMap<OsmId, OsmObj> {
+    let objs = response.elements.into_iter().map(|element| match element {
+        OverpassElement::Node { id, lat, lon, tags } => OsmObj::Node(Node {
+            id: NodeId(id),
+            tags: osm_tags(tags),
+            decimicro_lat: (lat * 1e7).round() as i32,
+            decimicro_lon: (lon * 1e7).round() as i32,
+        }),
+        OverpassElement::Way { id, nodes, tags } => OsmObj::Way(Way {
+            id: WayId(id),
+            tags: osm_tags(tags),
+            nodes: nodes.into_iter().map(NodeId).collect(),
+        }),
+        OverpassElement::Relation { id, members, tags } => OsmObj::Relation(Relation {
+            id: RelationId(id),
+            tags: osm_tags(tags),
+            refs: members
+                .into_iter()
+                .filter_map(|member| {
+                    let id = match member.kind.as_str() {
+                        "node" => OsmId::Node(NodeId(member.id)),
+                        "way" => OsmId::Way(WayId(member.id)),
+                        "relation" => OsmId::Relation(RelationId(member.id)),
+                        _ => return None,
+                    };
+                    Some(Ref {
+                        member: id,
+                        role: member.role.into(),
+                    })
+                })
+                .collect(),
+        }),
+    });
+    objs.map(|obj| (obj.id(), obj)).collect()
+}
+
+/// Imports the routable ways of `area` from the Overpass API, for demos and tests on
+/// a small area rather than a whole extract.
+pub async fn import_overpass(area: &str) -> Result<(), Box<dyn Error>> {
+    let url = env::var("OVERPASS_URL").unwrap_or_else(|_| DEFAULT_OVERPASS_URL.to_string());
+    println!("Querying {url} for {area}");
+    let response: OverpassResponse = reqwest::Client::new()
+        .post(&url)
+        .form(&[("data", overpass_query(area))])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    import_objects(&overpass_objects(response)).await
+}
+
+async fn import_objects(objs: &BTreeMap<OsmId, OsmObj>) -> Result<(), Box<dyn Error>> {
+    let url = env::var("DATABASE_URL")?;
+    let pool = PgPoolOptions::new().max_connections(1).connect(&url).await?;
+    for statement in CREATE_TABLES {
+        sqlx::query(statement).execute(&pool).await?;
+    }
+    // The migrations expect the osm2pgsql tables to exist
+    sqlx::migrate!().run(&pool).await?;
+
+    let coords = coordinates(objs);
+    import_nodes(&pool, &coords).await?;
+    import_points(&pool, objs).await?;
+    import_ways(&pool, objs, &coords).await?;
+    import_relations(&pool, objs).await?;
+    import_lengths(&pool, objs, &coords).await?;
+    import_adjacency(&pool, objs, &coords).await?;
+    // The version of the data the servers tag the routes with
+    sqlx::query(
+        r#"
+        insert into job_runs (job, ran_at) values ('import', now())
+        on conflict (job) do update set ran_at = now()
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+    pool.close().await;
+    Ok(())
+}
+
+/// The `(lat, lon)` of the nodes of `objs`, in decimicro degrees.
+pub fn coordinates(objs: &BTreeMap<OsmId, OsmObj>) -> HashMap<i64, (i32, i32)> {
+    objs.values()
+        .filter_map(OsmObj::node)
+        .map(|node| (node.id.0, (node.decimicro_lat, node.decimicro_lon)))
+        .collect()
+}
+
+/// The tags of the relations each way belongs to, flattened like the ones of the
+/// ways.
+pub fn relation_tags(objs: &BTreeMap<OsmId, OsmObj>) -> HashMap<i64, Vec<String>> {
+    let mut relation_tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for relation in objs.values().filter_map(OsmObj::relation) {
+        for r in &relation.refs {
+            if let OsmId::Way(way_id) = r.member {
+                relation_tags
+                    .entry(way_id.0)
+                    .or_default()
+                    .append(&mut flat_tags(&relation.tags));
+            }
+        }
+    }
+    relation_tags
+}
+
+/// The nodes ending the edges of the graph: the ones shared by several ways, or
+/// delaying riders.
+pub fn junctions(objs: &BTreeMap<OsmId, OsmObj>) -> HashSet<i64> {
+    let mut way_counts: HashMap<i64, usize> = HashMap::new();
+    for way in objs.values().filter_map(OsmObj::way) {
+        let nodes: HashSet<i64> = way.nodes.iter().map(|n| n.0).collect();
+        for node in nodes {
+            *way_counts.entry(node).or_default() += 1;
+        }
+    }
+    let mut junctions: HashSet<i64> = way_counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(id, _)| id)
+        .collect();
+    junctions.extend(
+        objs.values()
+            .filter_map(OsmObj::node)
+            .filter(|node| node.tags.get("highway").is_some_and(|h| is_delayed(h)))
+            .map(|node| node.id.0),
+    );
+    junctions
+}
+
+async fn import_nodes(
+    pool: &Pool<Postgres>,
+    coords: &HashMap<i64, (i32, i32)>,
+) -> Result<(), Box<dyn Error>> {
+    println!("Importing {} nodes", coords.len());
+    let nodes: Vec<(&i64, &(i32, i32))> = coords.iter().collect();
+    for batch in nodes.chunks(BATCH_SIZE) {
+        let ids: Vec<i64> = batch.iter().map(|(id, _)| **id).collect();
+        let lats: Vec<i32> = batch.iter().map(|(_, (lat, _))| *lat).collect();
+        let lons: Vec<i32> = batch.iter().map(|(_, (_, lon))| *lon).collect();
+        sqlx::query(
+            r#"
+            insert into planet_osm_nodes (id, lat, lon)
+            select * from unnest($1::int8[], $2::int4[], $3::int4[])
+            on conflict (id)
+            do update set lat = excluded.lat, lon = excluded.lon
+            "#,
+        )
+        .bind(ids)
+        .bind(lats)
+        .bind(lons)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Imports the way nodes with a `highway` tag, like traffic signals, as points.
+async fn import_points(
+    pool: &Pool<Postgres>,
+    objs: &BTreeMap<OsmId, OsmObj>,
+) -> Result<(), Box<dyn Error>> {
+    let points: Vec<&osmpbfreader::Node> = objs
+        .values()
+        .filter_map(OsmObj::node)
+        .filter(|node| node.tags.contains_key("highway"))
+        .collect();
+    println!("Importing {} points", points.len());
+    for batch in points.chunks(BATCH_SIZE) {
+        let ids: Vec<i64> = batch.iter().map(|node| node.id.0).collect();
+        let highways: Vec<String> = batch
+            .iter()
+            .map(|node| tag(&node.tags, "highway").unwrap_or_default())
+            .collect();
+        let lats: Vec<f64> = batch.iter().map(|node| node.lat()).collect();
+        let lons: Vec<f64> = batch.iter().map(|node| node.lon()).collect();
+        let mut tx = pool.begin().await?;
+        sqlx::query("delete from planet_osm_point where osm_id = any($1)")
+            .bind(&ids)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            r#"
+            insert into planet_osm_point (osm_id, highway, way)
+            select id, highway, ST_Transform(ST_SetSRID(ST_MakePoint(lon, lat), 4326), 3857)
+            from unnest($1::int8[], $2::text[], $3::float8[], $4::float8[])
+                as p(id, highway, lat, lon)
+            "#,
+        )
+        .bind(ids)
+        .bind(highways)
+        .bind(lats)
+        .bind(lons)
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+async fn import_ways(
+    pool: &Pool<Postgres>,
+    objs: &BTreeMap<OsmId, OsmObj>,
+    coords: &HashMap<i64, (i32, i32)>,
+) -> Result<(), Box<dyn Error>> {
+    let ways: Vec<&osmpbfreader::Way> = objs.values().filter_map(OsmObj::way).collect();
+    println!("Importing {} ways", ways.len());
+    for batch in ways.chunks(BATCH_SIZE) {
+        let mut tx = pool.begin().await?;
+        let ids: Vec<i64> = batch.iter().map(|way| way.id.0).collect();
+        for way in batch {
+            let nodes: Vec<i64> = way.nodes.iter().map(|n| n.0).collect();
+            sqlx::query(
+                r#"
+                insert into planet_osm_ways (id, nodes, tags)
+                values ($1, $2, $3)
+                on conflict (id)
+                do update set nodes = $2, tags = $3
+                "#,
+            )
+            .bind(way.id.0)
+            .bind(nodes)
+            .bind(flat_tags(&way.tags))
+            .execute(&mut tx)
+            .await?;
+        }
+
+        sqlx::query("delete from planet_osm_line where osm_id = any($1)")
+            .bind(&ids)
+            .execute(&mut tx)
+            .await?;
+        for way in batch {
+            let points: Vec<String> = way
+                .nodes
+                .iter()
+                .filter_map(|n| coords.get(&n.0))
+                .map(|(lat, lon)| {
+                    format!("{} {}", *lon as f64 / 10_000_000.0, *lat as f64 / 10_000_000.0)
+                })
+                .collect();
+            if points.len() < 2 || !way.tags.contains_key("highway") {
+                continue;
+            }
+            sqlx::query(
+                r#"
+                insert into planet_osm_line
+                (osm_id, access, aeroway, bicycle, building, highway, name, ref, surface, way)
+                values ($1, $2, $3, $4, $5, $6, $7, $8, $9,
+                    ST_Transform(ST_GeomFromText($10, 4326), 3857))
+                "#,
+            )
+            .bind(way.id.0)
+            .bind(tag(&way.tags, "access"))
+            .bind(tag(&way.tags, "aeroway"))
+            .bind(tag(&way.tags, "bicycle"))
+            .bind(tag(&way.tags, "building"))
+            .bind(tag(&way.tags, "highway"))
+            .bind(tag(&way.tags, "name"))
+            .bind(tag(&way.tags, "ref"))
+            .bind(tag(&way.tags, "surface"))
+            .bind(format!("LINESTRING({})", points.join(",")))
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+async fn import_relations(
+    pool: &Pool<Postgres>,
+    objs: &BTreeMap<OsmId, OsmObj>,
+) -> Result<(), Box<dyn Error>> {
+    let relations: Vec<&osmpbfreader::Relation> =
+        objs.values().filter_map(OsmObj::relation).collect();
+    println!("Importing {} relations", relations.len());
+    for relation in relations {
+        let mut parts = vec![];
+        let mut members = vec![];
+        for r in &relation.refs {
+            if let OsmId::Way(way_id) = r.member {
+                parts.push(way_id.0);
+                members.push(format!("w{}", way_id.0));
+                members.push(r.role.to_string());
+            }
+        }
+        sqlx::query(
+            r#"
+            insert into planet_osm_rels (id, way_off, rel_off, parts, members, tags)
+            values ($1, 0, $2, $3, $4, $5)
+            on conflict (id)
+            do update set way_off = 0, rel_off = $2, parts = $3, members = $4, tags = $5
+            "#,
+        )
+        .bind(relation.id.0)
+        .bind(parts.len() as i16)
+        .bind(parts)
+        .bind(members)
+        .bind(flat_tags(&relation.tags))
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Fills `ways_length` like `Way::calculate_all_lengths` does, but from the
+/// coordinates already in memory.
+async fn import_lengths(
+    pool: &Pool<Postgres>,
+    objs: &BTreeMap<OsmId, OsmObj>,
+    coords: &HashMap<i64, (i32, i32)>,
+) -> Result<(), Box<dyn Error>> {
+    let relation_tags = relation_tags(objs);
+    let ways: Vec<&osmpbfreader::Way> = objs
+        .values()
+        .filter_map(OsmObj::way)
+        .filter(|way| !way.nodes.is_empty())
+        .collect();
+    println!("Computing {} ways lengths", ways.len());
+    for batch in ways.chunks(BATCH_SIZE) {
+        let mut tx = pool.begin().await?;
+        for way in batch {
+            let length: i64 = way
+                .nodes
+                .windows(2)
+                .filter_map(|pair| Some((coords.get(&pair[0].0)?, coords.get(&pair[1].0)?)))
+                .map(|((lat1, lon1), (lat2, lon2))| distance(*lat1, *lon1, *lat2, *lon2) as i64)
+                .sum();
+            let mut tags = flat_tags(&way.tags);
+            if let Some(rtags) = relation_tags.get(&way.id.0) {
+                tags.extend(rtags.iter().cloned());
+            }
+            sqlx::query(
+                r#"
+                insert into ways_length (ways_id, length, first_node, last_node, tags_way_and_rel)
+                values ($1, $2, $3, $4, $5)
+                on conflict (ways_id)
+                do update
+                set length = $2, first_node = $3, last_node = $4, tags_way_and_rel = $5
+                "#,
+            )
+            .bind(way.id.0)
+            .bind(length)
+            .bind(way.nodes.first().map(|n| n.0))
+            .bind(way.nodes.last().map(|n| n.0))
+            .bind(tags)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+/// Fills `node_adjacency` with the edges `Node::get` would otherwise find by following
+/// the ways of each node.
+async fn import_adjacency(
+    pool: &Pool<Postgres>,
+    objs: &BTreeMap<OsmId, OsmObj>,
+    coords: &HashMap<i64, (i32, i32)>,
+) -> Result<(), Box<dyn Error>> {
+    let ways: Vec<&osmpbfreader::Way> = objs.values().filter_map(OsmObj::way).collect();
+    let junctions = junctions(objs);
+    println!("Computing the edges of {} ways", ways.len());
+    for batch in ways.chunks(BATCH_SIZE) {
+        let mut tx = pool.begin().await?;
+        let ids: Vec<i64> = batch.iter().map(|way| way.id.0).collect();
+        sqlx::query("delete from node_adjacency where way_id = any($1)")
+            .bind(&ids)
+            .execute(&mut tx)
+            .await?;
+        for way in batch {
+            let tags: HashMap<String, String> = way
+                .tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            let nodes: Vec<i64> = way.nodes.iter().map(|n| n.0).collect();
+            let distinct: HashSet<i64> = nodes.iter().copied().collect();
+            for id in distinct {
+                let Some(&from) = coords.get(&id) else {
+                    continue;
+                };
+                for (neighbor_id, intermediate_nodes, distance) in
+                    way_edges(id, from, &nodes, &tags, &junctions, coords)
+                {
+                    sqlx::query(
+                        r#"
+                        insert into node_adjacency
+                        (node_id, neighbor_id, way_id, distance, intermediate_nodes)
+                        values ($1, $2, $3, $4, $5)
+                        "#,
+                    )
+                    .bind(id)
+                    .bind(neighbor_id)
+                    .bind(way.id.0)
+                    .bind(distance)
+                    .bind(intermediate_nodes)
+                    .execute(&mut tx)
+                    .await?;
+                }
+            }
+        }
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+#[test]
+fn reads_overpass_elements_like_pbf_objects() {
+    let response: OverpassResponse = serde_json::from_str(
+        r#"{"elements": [
+            {"type": "node", "id": 1, "lat": 45.4581, "lon": -73.5706},
+            {"type": "node", "id": 2, "lat": 45.4583, "lon": -73.5702,
+                "tags": {"highway": "traffic_signals"}},
+            {"type": "way", "id": 10, "nodes": [1, 2], "tags": {"highway": "cycleway"}},
+            {"type": "relation", "id": 100, "members": [
+                {"type": "way", "ref": 10, "role": ""}
+            ], "tags": {"route": "bicycle", "network": "lcn"}}
+        ]}"#,
+    )
+    .unwrap();
+    let objs = overpass_objects(response);
+    assert_eq!(objs.len(), 4);
+    let signals = objs[&OsmId::Node(NodeId(2))].node().unwrap();
+    assert_eq!(signals.decimicro_lat, 454_583_000);
+    assert!(signals.tags.contains("highway", "traffic_signals"));
+    let way = objs[&OsmId::Way(WayId(10))].way().unwrap();
+    assert_eq!(way.nodes, vec![NodeId(1), NodeId(2)]);
+    let relation = objs[&OsmId::Relation(RelationId(100))].relation().unwrap();
+    assert_eq!(relation.refs[0].member, OsmId::Way(WayId(10)));
+}
@@ -0,0 +1,83 @@
+use crate::{data::cache::CacheStats, region::Region, throttle};
+use actix_web::{get, HttpResponse, Responder};
+use std::fmt::{Display, Write};
+
+/// Appends a metric in the Prometheus text exposition format, with one sample per
+/// `(label, value)`.
+fn write_metric<V: Display>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(String, V)],
+) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+#[get("/metrics")]
+async fn metrics() -> impl Responder {
+    let mut caches = vec![];
+    for region in Region::all() {
+        caches.push((format!("region=\"{}\"", region.name), region.cache_stats().await));
+    }
+    let cache_metric = |f: fn(&CacheStats) -> u64| -> Vec<(String, u64)> {
+        caches.iter().map(|(labels, stats)| (labels.clone(), f(stats))).collect()
+    };
+    let mut body = String::new();
+    write_metric(
+        &mut body,
+        "node_cache_entries",
+        "gauge",
+        "Number of nodes in the node cache.",
+        &cache_metric(|s| s.entries as u64),
+    );
+    write_metric(
+        &mut body,
+        "node_cache_bytes",
+        "gauge",
+        "Approximate memory used by the node cache.",
+        &cache_metric(|s| s.bytes as u64),
+    );
+    write_metric(
+        &mut body,
+        "node_cache_hits_total",
+        "counter",
+        "Node lookups served from the cache.",
+        &cache_metric(|s| s.hits),
+    );
+    write_metric(
+        &mut body,
+        "node_cache_misses_total",
+        "counter",
+        "Node lookups that went to the database.",
+        &cache_metric(|s| s.misses),
+    );
+    write_metric(
+        &mut body,
+        "node_cache_evictions_total",
+        "counter",
+        "Nodes evicted from the cache to stay within its bounds.",
+        &cache_metric(|s| s.evictions),
+    );
+    write_metric(
+        &mut body,
+        "searches_running",
+        "gauge",
+        "Route searches running.",
+        &[(String::new(), throttle::running())],
+    );
+    write_metric(
+        &mut body,
+        "searches_waiting",
+        "gauge",
+        "Route requests waiting for a search to finish.",
+        &[(String::new(), throttle::waiting())],
+    );
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
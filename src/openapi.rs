@@ -0,0 +1,91 @@
+//! The OpenAPI specification of the HTTP API, for clients to generate SDKs from.
+
+use crate::{
+    compare::{self, CompareProfile, CompareRequest, CompareResponse, ComparedRoute},
+    composition::{DismountSection, Highways, Surfaces},
+    data::{access::Dismount, node::SearchStats, poi::Poi},
+    error::{ErrorBody, FieldError, RouteError},
+    instruction::{Maneuver, ManeuverType, Modifier, Step},
+    route::{
+        self, Avoid, LatLon, Model, Prefer, RouteBody, RouteLeg, RouteRequest, RouteResponse,
+        SnappedPoint, Units,
+    },
+    safety::Safety,
+    segment::WaySegment,
+    stages::Stage,
+    trip::{self, ScheduledStop, TripRequest, TripResponse, TripStop},
+    weather::Wind,
+};
+use actix_web::{get, HttpResponse, Responder};
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        route::route,
+        route::route_get,
+        route::route_stream,
+        route::route_by_id,
+        route::route_v2,
+        route::route_get_v2,
+        route::route_stream_v2,
+        compare::compare_routes,
+        trip::trip,
+    ),
+    components(schemas(
+        Avoid,
+        ComparedRoute,
+        CompareProfile,
+        CompareRequest,
+        CompareResponse,
+        Dismount,
+        DismountSection,
+        ErrorBody,
+        FieldError,
+        Highways,
+        LatLon,
+        Maneuver,
+        ManeuverType,
+        Model,
+        Modifier,
+        Poi,
+        Prefer,
+        RouteBody,
+        RouteError,
+        RouteLeg,
+        RouteRequest,
+        RouteResponse,
+        Safety,
+        ScheduledStop,
+        SearchStats,
+        SnappedPoint,
+        Stage,
+        Step,
+        Surfaces,
+        TripRequest,
+        TripResponse,
+        TripStop,
+        Units,
+        WaySegment,
+        Wind,
+    ))
+)]
+struct ApiDoc;
+
+#[get("/openapi.json")]
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[test]
+fn documents_the_route_endpoints() {
+    let doc = ApiDoc::openapi();
+    assert!(doc.paths.paths.contains_key("/route"));
+    assert!(doc.paths.paths.contains_key("/route/stream"));
+    assert!(doc.paths.paths.contains_key("/route/compare"));
+    assert!(doc.paths.paths.contains_key("/trip"));
+    assert!(doc.paths.paths.contains_key("/v2/route"));
+    let schemas = doc.components.unwrap().schemas;
+    assert!(schemas.contains_key("RouteRequest"));
+    assert!(schemas.contains_key("RouteError"));
+}
@@ -0,0 +1,384 @@
+//! An OSRM compatible facade, so that OSRM frontends like Leaflet Routing Machine
+//! can use this server unmodified.
+//! https://project-osrm.org/docs/v5.24.0/api/#route-service
+
+use crate::{
+    data::{
+        elevation::climb,
+        node::Node,
+    },
+    error::{FieldError, RouteError},
+    instruction::{steps, ManeuverType, Modifier, Step},
+    route::{final_bearing, LatLon, Model, RouteRequest, Units},
+    segment::{summary, way_segments, WaySegment},
+};
+use actix_web::{get, web, HttpResponse, Responder, ResponseError};
+use serde::{Deserialize, Serialize};
+
+fn encode_value(value: i64, out: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 };
+    while value >= 0x20 {
+        out.push(char::from((((value & 0x1f) | 0x20) + 63) as u8));
+        value >>= 5;
+    }
+    out.push(char::from((value + 63) as u8));
+}
+
+/// Encodes points with the Google polyline algorithm, with `precision` decimals.
+pub fn encode_polyline(points: &[LatLon], precision: i32) -> String {
+    let factor = 10f64.powi(precision);
+    let mut out = String::new();
+    let (mut previous_lat, mut previous_lng) = (0, 0);
+    for point in points {
+        let lat = (point.lat * factor).round() as i64;
+        let lng = (point.lng * factor).round() as i64;
+        encode_value(lat - previous_lat, &mut out);
+        encode_value(lng - previous_lng, &mut out);
+        (previous_lat, previous_lng) = (lat, lng);
+    }
+    out
+}
+
+#[derive(Deserialize)]
+struct OsrmQuery {
+    #[serde(default)]
+    steps: bool,
+    /// `polyline` (the default), `polyline6` or `geojson`.
+    geometries: Option<String>,
+    /// `full` (the default), `simplified` or `false`.
+    overview: Option<String>,
+    /// The classes to avoid, comma separated, only `ferry` is supported.
+    exclude: Option<String>,
+    /// `true`, `false` or `default`, which is `false` for bikes.
+    continue_straight: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Geometry {
+    Polyline(String),
+    GeoJson {
+        #[serde(rename = "type")]
+        kind: &'static str,
+        coordinates: Vec<[f64; 2]>,
+    },
+}
+
+impl Geometry {
+    fn new(points: &[LatLon], format: &str) -> Self {
+        match format {
+            "geojson" => Geometry::GeoJson {
+                kind: "LineString",
+                coordinates: points.iter().map(|p| [p.lng, p.lat]).collect(),
+            },
+            "polyline6" => Geometry::Polyline(encode_polyline(points, 6)),
+            _ => Geometry::Polyline(encode_polyline(points, 5)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OsrmManeuver {
+    location: [f64; 2],
+    bearing_before: i32,
+    bearing_after: i32,
+    #[serde(rename = "type")]
+    kind: ManeuverType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modifier: Option<Modifier>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct OsrmStep {
+    distance: f64,
+    duration: f64,
+    weight: f64,
+    geometry: Geometry,
+    name: String,
+    mode: &'static str,
+    maneuver: OsrmManeuver,
+    intersections: Vec<()>,
+}
+
+impl OsrmStep {
+    fn new(step: &Step, points: &[LatLon], durations: &[f64], format: &str) -> Self {
+        let distance = step.distance as f64;
+        let duration = durations[step.start..step.end].iter().sum();
+        OsrmStep {
+            distance,
+            duration,
+            weight: duration,
+            geometry: Geometry::new(&points[step.start..=step.end], format),
+            name: step.name.clone().unwrap_or_default(),
+            mode: "cycling",
+            maneuver: OsrmManeuver {
+                location: [step.maneuver.location.lng, step.maneuver.location.lat],
+                bearing_before: step.maneuver.bearing_before,
+                bearing_after: step.maneuver.bearing_after,
+                kind: step.maneuver.kind,
+                modifier: step.maneuver.modifier,
+                exit: step.maneuver.exit,
+            },
+            intersections: vec![],
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OsrmLeg {
+    steps: Vec<OsrmStep>,
+    summary: String,
+    distance: f64,
+    duration: f64,
+    weight: f64,
+    /// Not in OSRM, the elevation gained and lost in meters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ascent: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    descent: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct OsrmRoute {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    geometry: Option<Geometry>,
+    legs: Vec<OsrmLeg>,
+    distance: f64,
+    duration: f64,
+    weight_name: &'static str,
+    weight: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ascent: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    descent: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct OsrmWaypoint {
+    hint: &'static str,
+    distance: f64,
+    name: String,
+    location: [f64; 2],
+}
+
+#[derive(Serialize)]
+struct OsrmResponse {
+    code: &'static str,
+    routes: Vec<OsrmRoute>,
+    waypoints: Vec<OsrmWaypoint>,
+}
+
+#[derive(Serialize)]
+struct OsrmError {
+    code: &'static str,
+    message: String,
+}
+
+/// Parses OSRM `lng,lat;lng,lat` coordinates.
+fn parse_coordinates(coordinates: &str) -> Result<Vec<LatLon>, RouteError> {
+    let invalid = |message: String| RouteError::InvalidRequest {
+        errors: vec![FieldError {
+            field: "coordinates".to_string(),
+            message,
+        }],
+    };
+    let points = coordinates
+        .trim_end_matches(".json")
+        .split(';')
+        .map(|pair| match pair.split_once(',') {
+            Some((lng, lat)) => Ok(LatLon {
+                lat: lat.trim().parse().map_err(|_| invalid(format!("invalid {pair}")))?,
+                lng: lng.trim().parse().map_err(|_| invalid(format!("invalid {pair}")))?,
+            }),
+            None => Err(invalid(format!("expected lng,lat, got {pair}"))),
+        })
+        .collect::<Result<Vec<LatLon>, RouteError>>()?;
+    if points.len() < 2 {
+        return Err(invalid("at least two coordinates are required".to_string()));
+    }
+    Ok(points)
+}
+
+/// Whether the route keeps going at the waypoints, from the OSRM option.
+fn continue_straight(value: Option<&str>) -> Result<bool, RouteError> {
+    match value {
+        Some("true") => Ok(true),
+        Some("false" | "default") | None => Ok(false),
+        Some(value) => Err(RouteError::InvalidRequest {
+            errors: vec![FieldError {
+                field: "continue_straight".to_string(),
+                message: format!("must be true, false or default, got {value}"),
+            }],
+        }),
+    }
+}
+
+fn model(profile: &str) -> Result<Model, RouteError> {
+    match profile {
+        "bicycle" | "cycling" | "bike" | "safe" => Ok(Model::Safe),
+        "fast" => Ok(Model::Fast),
+        "family" => Ok(Model::Family),
+        "road" => Ok(Model::Road),
+        _ => Err(RouteError::InvalidRequest {
+            errors: vec![FieldError {
+                field: "profile".to_string(),
+                message: format!("unknown profile {profile}"),
+            }],
+        }),
+    }
+}
+
+async fn osrm_route(
+    profile: &str,
+    coordinates: &str,
+    query: &OsrmQuery,
+) -> Result<OsrmResponse, RouteError> {
+    let model = model(profile)?;
+    let points = parse_coordinates(coordinates)?;
+    let format = query.geometries.as_deref().unwrap_or("polyline");
+    let excluded: Vec<&str> = query.exclude.as_deref().unwrap_or("").split(',').collect();
+    let continue_straight = continue_straight(query.continue_straight.as_deref())?;
+    let mut legs = vec![];
+    let mut waypoints = vec![];
+    let mut full_path: Vec<LatLon> = vec![];
+    let mut weight = 0;
+    let mut last_waypoint = None;
+    let mut heading = None;
+    for pair in points.windows(2) {
+        let request = RouteRequest {
+            start: pair[0].clone(),
+            end: pair[1].clone(),
+            model: model.clone(),
+            allow_ferries: Some(!excluded.contains(&"ferry")),
+            start_heading: heading,
+            continue_straight,
+            ..Default::default()
+        };
+        request.validate()?;
+        let region = request.region().await?;
+        let (path, cost) = Node::route(region, &request).await?;
+        weight += cost;
+
+        let segments = way_segments(&path);
+        let lat_lons: Vec<LatLon> = path.iter().map(LatLon::from).collect();
+        // The next leg leaves the waypoint the way this one arrives
+        heading = final_bearing(&lat_lons).filter(|_| continue_straight);
+        let distance = segments.iter().map(|s| s.length as f64).sum::<f64>();
+        let (ascent, descent) = climb(&path).unzip();
+        let durations = model.profile().durations(&path, None);
+        let name = |segment: Option<&WaySegment>| {
+            segment.and_then(|s| s.label()).unwrap_or_default().to_string()
+        };
+        let (start, end) = (&lat_lons[0], &lat_lons[lat_lons.len() - 1]);
+        waypoints.push(OsrmWaypoint {
+            hint: "",
+            distance: request.start.distance(start) as f64,
+            name: name(segments.first()),
+            location: [start.lng, start.lat],
+        });
+        // Only the end of the last leg is not the start of another
+        last_waypoint = Some(OsrmWaypoint {
+            hint: "",
+            distance: request.end.distance(end) as f64,
+            name: name(segments.last()),
+            location: [end.lng, end.lat],
+        });
+        legs.push(OsrmLeg {
+            steps: if query.steps {
+                steps(&path, &segments, Units::Metric)
+                    .iter()
+                    .map(|step| OsrmStep::new(step, &lat_lons, &durations, format))
+                    .collect()
+            } else {
+                vec![]
+            },
+            summary: summary(&segments).unwrap_or_default(),
+            distance,
+            duration: durations.iter().sum(),
+            weight: cost as f64,
+            ascent,
+            descent,
+        });
+        // The legs meet at their waypoint, which the overview goes through once
+        let joined = full_path.last().is_some_and(|last| *last == lat_lons[0]);
+        full_path.extend(lat_lons.into_iter().skip(joined as usize));
+    }
+    waypoints.extend(last_waypoint);
+    let distance: f64 = legs.iter().map(|leg| leg.distance).sum();
+    let duration: f64 = legs.iter().map(|leg| leg.duration).sum();
+    let total = |climb: fn(&OsrmLeg) -> Option<i32>| legs.iter().map(climb).sum();
+    let (ascent, descent) = (total(|leg| leg.ascent), total(|leg| leg.descent));
+    let geometry = match query.overview.as_deref() {
+        Some("false") => None,
+        _ => Some(Geometry::new(&full_path, format)),
+    };
+    Ok(OsrmResponse {
+        code: "Ok",
+        routes: vec![OsrmRoute {
+            geometry,
+            legs,
+            distance,
+            duration,
+            weight_name: "routability",
+            weight: weight as f64,
+            ascent,
+            descent,
+        }],
+        waypoints,
+    })
+}
+
+/// The OSRM error codes, https://project-osrm.org/docs/v5.24.0/api/#responses
+fn osrm_code(error: &RouteError) -> &'static str {
+    match error {
+        RouteError::InvalidRequest { .. }
+        | RouteError::UnknownRegion { .. }
+        | RouteError::RouteNotSaved { .. } => "InvalidQuery",
+        RouteError::RouteTooLong { .. } | RouteError::SearchBudgetExceeded { .. } => "TooBig",
+        RouteError::PointNotSnapped { .. } => "NoSegment",
+        RouteError::NoRegion
+        | RouteError::OutsideExtent { .. }
+        | RouteError::NoRouteFound
+        | RouteError::NoTransitItinerary
+        | RouteError::SearchTimedOut { .. } => "NoRoute",
+        // Not an OSRM code, OSRM has no limit on concurrent requests
+        RouteError::TooManySearches { .. } => "TooManyRequests",
+        RouteError::DatabaseUnavailable { .. }
+        | RouteError::ShuttingDown { .. }
+        | RouteError::NotConfigured { .. }
+        | RouteError::Internal { .. } => "InternalError",
+    }
+}
+
+#[get("/route/v1/{profile}/{coordinates}")]
+async fn route(path: web::Path<(String, String)>, query: web::Query<OsrmQuery>) -> impl Responder {
+    let (profile, coordinates) = path.into_inner();
+    match osrm_route(&profile, &coordinates, &query).await {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(error) => HttpResponse::build(error.status_code()).json(OsrmError {
+            code: osrm_code(&error),
+            message: error.to_string(),
+        }),
+    }
+}
+
+#[test]
+fn encodes_polylines() {
+    let points = [(38.5, -120.2), (40.7, -120.95), (43.252, -126.453)]
+        .map(|(lat, lng)| LatLon { lat, lng });
+    assert_eq!(encode_polyline(&points, 5), "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+}
+
+#[test]
+fn continues_straight_only_when_asked() {
+    assert!(continue_straight(Some("true")).unwrap());
+    assert!(!continue_straight(Some("default")).unwrap());
+    assert!(!continue_straight(None).unwrap());
+    assert!(continue_straight(Some("yes")).is_err());
+    let points = [(45.5, -73.57), (45.5, -73.56), (45.5, -73.56)]
+        .map(|(lat, lng)| LatLon { lat, lng });
+    assert_eq!(final_bearing(&points).map(f64::round), Some(90.0));
+    assert_eq!(final_bearing(&points[1..]), None);
+}
@@ -0,0 +1,97 @@
+//! Imports GPX traces into `way_popularity`, counting for each way how many traces
+//! ride along it, so that the Safe model can prefer the streets cyclists use.
+
+use crate::data::node::ROUTABLE_LINE;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+use std::{env, error::Error, fs, path::Path};
+
+/// How far a trace point may be from a way to count as riding it, in meters.
+const MATCH_DISTANCE: f64 = 20.0;
+
+/// The value of the `name="..."` attribute of an XML tag.
+fn attribute(tag: &str, name: &str) -> Option<f64> {
+    let start = tag.find(&format!(" {name}=\""))? + name.len() + 3;
+    let end = start + tag[start..].find('"')?;
+    tag[start..end].parse().ok()
+}
+
+/// The `(lon, lat)` of the track points of a GPX document.
+fn track_points(gpx: &str) -> Vec<(f64, f64)> {
+    gpx.split("<trkpt")
+        .skip(1)
+        .filter_map(|tag| {
+            let tag = &tag[..tag.find('>')?];
+            Some((attribute(tag, "lon")?, attribute(tag, "lat")?))
+        })
+        .collect()
+}
+
+/// Adds one trace to the popularity of the ways the `points` ride along.
+async fn import_trace(
+    pool: &Pool<Postgres>,
+    points: &[(f64, f64)],
+) -> Result<usize, Box<dyn Error>> {
+    let (lons, lats): (Vec<f64>, Vec<f64>) = points.iter().copied().unzip();
+    let result = sqlx::query(&format!(
+        r#"
+        insert into way_popularity (way_id, traces)
+        select distinct matched.osm_id, 1
+        from unnest($1::float8[], $2::float8[]) as p(lon, lat)
+        cross join lateral (
+            select pol.osm_id
+            from planet_osm_line pol
+            where {ROUTABLE_LINE}
+            and ST_DWithin(
+                pol.way,
+                ST_Transform(ST_SetSRID(ST_MakePoint(p.lon, p.lat), 4326), 3857),
+                $3
+            )
+            order by pol.way <-> ST_Transform(ST_SetSRID(ST_MakePoint(p.lon, p.lat), 4326), 3857)
+            limit 1
+        ) matched
+        on conflict (way_id) do update set traces = way_popularity.traces + 1
+        "#
+    ))
+    .bind(lons)
+    .bind(lats)
+    .bind(MATCH_DISTANCE)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() as usize)
+}
+
+/// Imports the GPX files at `paths`, or in them when they are directories.
+pub async fn import(paths: &[String]) -> Result<(), Box<dyn Error>> {
+    let url = env::var("DATABASE_URL")?;
+    let pool = PgPoolOptions::new().max_connections(1).connect(&url).await?;
+    sqlx::migrate!().run(&pool).await?;
+
+    let mut files = vec![];
+    for path in paths.iter().map(Path::new) {
+        if path.is_dir() {
+            for entry in fs::read_dir(path)? {
+                files.push(entry?.path());
+            }
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    files.retain(|file| file.extension().is_some_and(|ext| ext == "gpx"));
+    for file in files {
+        let points = track_points(&fs::read_to_string(&file)?);
+        let ways = import_trace(&pool, &points).await?;
+        println!("{}: {} points along {ways} ways", file.display(), points.len());
+    }
+    pool.close().await;
+    Ok(())
+}
+
+#[test]
+fn reads_track_points() {
+    let gpx = r#"<gpx><trk><trkseg>
+        <trkpt lat="45.52" lon="-73.58"><ele>30</ele></trkpt>
+        <trkpt lon="-73.57" lat="45.53"/>
+    </trkseg></trk></gpx>"#;
+    assert_eq!(track_points(gpx), vec![(-73.58, 45.52), (-73.57, 45.53)]);
+}
@@ -0,0 +1,287 @@
+//! The tuning of the routing models.
+
+use crate::{
+    data::{
+        access::dismount,
+        node::{Node, CYCLING_SPEED},
+    },
+    route::Model,
+    segment::edges,
+};
+use std::collections::HashMap;
+
+/// The speed pushing the bike, in meters per second.
+pub const WALKING_SPEED: f64 = 5.0 / 3.6;
+
+pub struct Profile {
+    /// The riding speed on flat and smooth ground, in meters per second.
+    pub speed: f64,
+    /// The speed multipliers by `highway` value, 1 for the others.
+    pub highway_speeds: &'static [(&'static str, f64)],
+    /// The speed multipliers by `surface` value, 1 for the others.
+    pub surface_speeds: &'static [(&'static str, f64)],
+    /// The cost multipliers by `surface` value, 1 for the unknown ones.
+    pub surfaces: &'static [(&'static str, f64)],
+    /// The cost multipliers by `smoothness` value, which is more precise than the
+    /// surface when both are tagged.
+    pub smoothness: &'static [(&'static str, f64)],
+}
+
+/// How much each surface slows bikes down.
+const SURFACE_SPEEDS: &[(&str, f64)] = &[
+    ("paving_stones", 0.95),
+    ("compacted", 0.9),
+    ("fine_gravel", 0.85),
+    ("gravel", 0.75),
+    ("unpaved", 0.75),
+    ("ground", 0.7),
+    ("earth", 0.7),
+    ("dirt", 0.7),
+    ("sett", 0.8),
+    ("cobblestone", 0.7),
+    ("unhewn_cobblestone", 0.6),
+    ("pebblestone", 0.6),
+    ("grass", 0.5),
+    ("sand", 0.4),
+    ("mud", 0.4),
+];
+
+const SAFE: Profile = Profile {
+    speed: CYCLING_SPEED,
+    highway_speeds: &[
+        ("footway", 0.7),
+        ("pedestrian", 0.6),
+        ("path", 0.85),
+        ("living_street", 0.8),
+        ("service", 0.9),
+        ("track", 0.8),
+    ],
+    surface_speeds: SURFACE_SPEEDS,
+    surfaces: &[
+        ("paving_stones", 1.1),
+        ("concrete:plates", 1.1),
+        ("concrete:lanes", 1.2),
+        ("compacted", 1.1),
+        ("fine_gravel", 1.15),
+        ("wood", 1.2),
+        ("gravel", 1.2),
+        ("unpaved", 1.3),
+        ("sett", 1.5),
+        ("pebblestone", 1.5),
+        ("ground", 1.5),
+        ("earth", 1.5),
+        ("cobblestone", 2.0),
+        ("unhewn_cobblestone", 2.5),
+        ("grass", 3.0),
+        ("sand", 4.0),
+        ("dirt", 5.0),
+        ("mud", 6.0),
+    ],
+    smoothness: &[
+        ("intermediate", 1.1),
+        ("bad", 1.5),
+        ("very_bad", 2.5),
+        ("horrible", 5.0),
+        ("very_horrible", 8.0),
+        ("impassable", 20.0),
+    ],
+};
+
+/// Faster over the compacted surfaces, but cobbles and loose ones slow it down more.
+const FAST: Profile = Profile {
+    speed: 18.0 / 3.6,
+    highway_speeds: &[
+        ("footway", 0.6),
+        ("pedestrian", 0.5),
+        ("path", 0.8),
+        ("living_street", 0.7),
+        ("service", 0.9),
+        ("track", 0.75),
+    ],
+    surface_speeds: SURFACE_SPEEDS,
+    surfaces: &[
+        ("paving_stones", 1.1),
+        ("concrete:plates", 1.1),
+        ("concrete:lanes", 1.2),
+        ("compacted", 1.05),
+        ("fine_gravel", 1.1),
+        ("wood", 1.2),
+        ("gravel", 1.1),
+        ("unpaved", 1.3),
+        ("sett", 1.6),
+        ("pebblestone", 1.6),
+        ("ground", 1.5),
+        ("earth", 1.5),
+        ("cobblestone", 2.0),
+        ("unhewn_cobblestone", 3.0),
+        ("grass", 3.0),
+        ("sand", 5.0),
+        ("dirt", 5.0),
+        ("mud", 8.0),
+    ],
+    smoothness: &[
+        ("intermediate", 1.05),
+        ("bad", 1.5),
+        ("very_bad", 3.0),
+        ("horrible", 6.0),
+        ("very_horrible", 10.0),
+        ("impassable", 20.0),
+    ],
+};
+
+/// Children ride slower, on the same ways as the Safe model.
+const FAMILY: Profile = Profile {
+    speed: 12.0 / 3.6,
+    ..SAFE
+};
+
+/// Thin tires at high pressure: loose surfaces and cobbles are to be avoided at
+/// almost any cost, and rough pavement is slow.
+const ROAD: Profile = Profile {
+    speed: 25.0 / 3.6,
+    highway_speeds: FAST.highway_speeds,
+    surface_speeds: &[
+        ("paving_stones", 0.85),
+        ("compacted", 0.7),
+        ("fine_gravel", 0.6),
+        ("gravel", 0.5),
+        ("unpaved", 0.5),
+        ("ground", 0.5),
+        ("earth", 0.5),
+        ("dirt", 0.5),
+        ("sett", 0.6),
+        ("cobblestone", 0.5),
+        ("unhewn_cobblestone", 0.4),
+        ("pebblestone", 0.4),
+        ("grass", 0.3),
+        ("sand", 0.3),
+        ("mud", 0.3),
+    ],
+    surfaces: &[
+        ("paving_stones", 1.3),
+        ("concrete:plates", 1.3),
+        ("concrete:lanes", 1.5),
+        ("wood", 1.5),
+        ("compacted", 3.0),
+        ("fine_gravel", 4.0),
+        ("gravel", 6.0),
+        ("unpaved", 6.0),
+        ("pebblestone", 6.0),
+        ("sett", 4.0),
+        ("cobblestone", 8.0),
+        ("unhewn_cobblestone", 15.0),
+        ("ground", 10.0),
+        ("earth", 10.0),
+        ("grass", 15.0),
+        ("dirt", 15.0),
+        ("sand", 20.0),
+        ("mud", 20.0),
+    ],
+    smoothness: &[
+        ("excellent", 0.9),
+        ("intermediate", 1.3),
+        ("bad", 4.0),
+        ("very_bad", 10.0),
+        ("horrible", 20.0),
+        ("very_horrible", 20.0),
+        ("impassable", 20.0),
+    ],
+};
+
+impl Model {
+    pub fn profile(&self) -> &'static Profile {
+        match self {
+            Model::Safe => &SAFE,
+            Model::Fast => &FAST,
+            Model::Family => &FAMILY,
+            Model::Road => &ROAD,
+        }
+    }
+}
+
+fn lookup(table: &[(&str, f64)], value: Option<&String>) -> Option<f64> {
+    let value = value?;
+    table
+        .iter()
+        .find(|(key, _)| key == value)
+        .map(|(_, factor)| *factor)
+}
+
+impl Profile {
+    /// The cost multiplier of a way with `tags` for its surface.
+    pub fn surface_factor(&self, tags: &HashMap<String, String>) -> f64 {
+        lookup(self.smoothness, tags.get("smoothness"))
+            .or_else(|| lookup(self.surfaces, tags.get("surface")))
+            .unwrap_or(1.0)
+    }
+
+    /// The riding speed on a way with `tags` at `grade` percent, in meters per
+    /// second. Climbs slow down and descents speed up, up to a limit.
+    pub fn speed(&self, tags: &HashMap<String, String>, grade: Option<f64>) -> f64 {
+        if dismount(tags).is_some() {
+            return WALKING_SPEED;
+        }
+        let highway = lookup(self.highway_speeds, tags.get("highway")).unwrap_or(1.0);
+        let surface = lookup(self.surface_speeds, tags.get("surface")).unwrap_or(1.0);
+        let grade = match grade.unwrap_or(0.0) {
+            grade if grade > 0.0 => 1.0 / (1.0 + grade * 0.12),
+            grade => (1.0 - grade * 0.05).min(1.5),
+        };
+        (self.speed * highway * surface * grade).max(WALKING_SPEED)
+    }
+
+    /// How long riding each edge of `path` takes, in seconds, including the time
+    /// lost at the node it leads to. The speeds are scaled to the `cruising_speed`
+    /// of the rider when given, in meters per second.
+    pub fn durations(&self, path: &[Node], cruising_speed: Option<f64>) -> Vec<f64> {
+        let factor = cruising_speed.map_or(1.0, |speed| speed / self.speed);
+        edges(path)
+            .zip(path.windows(2))
+            .map(|(edge, pair)| {
+                let Some(edge) = edge else {
+                    return 0.0;
+                };
+                let riding = match edge.ferry_duration() {
+                    Some(duration) => duration as f64,
+                    None => {
+                        let grade = pair[0].grade_to(&pair[1], edge);
+                        edge.distance as f64 / (self.speed(&edge.tags, grade) * factor)
+                    }
+                };
+                riding + pair[1].delay() as f64
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn smoothness_wins_over_surface() {
+    let tags = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    let safe = Model::Safe.profile();
+    assert_eq!(safe.surface_factor(&tags(&[("surface", "sett")])), 1.5);
+    assert_eq!(
+        safe.surface_factor(&tags(&[("surface", "sett"), ("smoothness", "bad")])),
+        1.5
+    );
+    assert_eq!(
+        Model::Fast
+            .profile()
+            .surface_factor(&tags(&[("surface", "asphalt"), ("smoothness", "horrible")])),
+        6.0
+    );
+    assert_eq!(safe.surface_factor(&tags(&[("surface", "asphalt")])), 1.0);
+}
+
+#[test]
+fn climbs_slow_down() {
+    let tags = HashMap::from([("highway".to_string(), "residential".to_string())]);
+    let safe = Model::Safe.profile();
+    assert_eq!(safe.speed(&tags, None), CYCLING_SPEED);
+    assert!(safe.speed(&tags, Some(6.0)) < CYCLING_SPEED / 1.5);
+    assert_eq!(safe.speed(&tags, Some(-20.0)), CYCLING_SPEED * 1.5);
+}
@@ -0,0 +1,583 @@
+use crate::{
+    config::{RegionConfig, CONFIG},
+    data::{
+        bbox::BoundingBox,
+        cache::{shared_cache, CacheStats, CachedRoute, FlushScope, NodeCache, RouteCache},
+        closure::closed_ways,
+        node::Node,
+        retry::CircuitBreaker,
+        snapshot::Snapshot,
+        store::GraphStore,
+    },
+    error::RouteError,
+};
+use crate::{
+    route::LatLon,
+    rules::Rules,
+    weather::{self, Weather},
+};
+use rustc_hash::FxHasher;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Executor, Pool, Postgres, Row};
+use std::{
+    collections::HashSet,
+    error::Error,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex, MutexGuard, OnceCell};
+
+/// An extract served by this server, with its own database and node cache.
+pub struct Region {
+    pub name: String,
+    /// The area covered by the extract, a region without one covers everything.
+    pub bbox: Option<BoundingBox>,
+    database_url: Option<String>,
+    /// The schema holding the region tables, when several regions share a database.
+    schema: Option<String>,
+    pool: OnceCell<Pool<Postgres>>,
+    replica_urls: Vec<String>,
+    replicas: OnceLock<Vec<Replica>>,
+    /// The replica the next read goes to, modulo their number.
+    next_replica: AtomicUsize,
+    /// Whether the primary database is reachable.
+    breaker: CircuitBreaker,
+    /// The area covered by the region data, computed once when no bbox is configured.
+    extent: OnceCell<Option<BoundingBox>>,
+    /// Whether the import materialized the edges of the nodes, checked again after
+    /// the routes are cleared.
+    adjacency: Mutex<Option<bool>>,
+    /// The nodes dumped by `dump-graph`, looked up before the caches and the database.
+    store: Option<Box<dyn GraphStore>>,
+    node_cache: Mutex<NodeCache>,
+    route_cache: Mutex<RouteCache>,
+    /// The closed ways and when they were loaded, `None` when they must be reloaded.
+    closed_ways: Mutex<(Option<Instant>, Arc<HashSet<i64>>)>,
+    /// The last weather fetched and when.
+    weather: Mutex<Option<(Instant, Weather)>>,
+    /// When the data was last updated, in seconds since the epoch, and when that
+    /// was checked.
+    data_updated: Mutex<Option<(Instant, Option<i64>)>>,
+    /// The traffic rules of the jurisdiction of the region.
+    pub rules: &'static Rules,
+}
+
+/// A read replica, with its own breaker so that a replica down does not fail the
+/// queries of the primary or of the other replicas.
+struct Replica {
+    pool: Pool<Postgres>,
+    breaker: CircuitBreaker,
+}
+
+/// How long to wait for a replica connection before trying the next one, shorter
+/// than for the primary since there is somewhere else to go.
+const REPLICA_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the closed ways are reloaded, to pick up the closures declared on
+/// other replicas and the ones that ended.
+const CLOSURES_REFRESH: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref REGIONS: Vec<Region> = CONFIG.regions.iter().map(Region::new).collect();
+}
+
+/// How the nodes of a search were loaded, for the search statistics.
+#[derive(Default)]
+pub struct NodeLoads {
+    pub cache_hits: AtomicUsize,
+    pub cache_misses: AtomicUsize,
+    /// The time spent loading the missed nodes from the database.
+    pub database_micros: AtomicU64,
+}
+
+/// A pool and the connection taken from it.
+type Database = (Pool<Postgres>, Arc<Mutex<PoolConnection<Postgres>>>);
+
+/// A database connection to a region, shared by the steps of a search.
+#[derive(Clone)]
+pub struct RegionClient {
+    pub region: &'static Region,
+    /// The pool and the connection, `None` for the regions served from a graph store
+    /// alone.
+    database: Option<Database>,
+    /// The breaker of the database the connection is from.
+    pub(crate) breaker: &'static CircuitBreaker,
+    pub loads: Arc<NodeLoads>,
+}
+
+impl RegionClient {
+    pub async fn lock(&self) -> Result<MutexGuard<'_, PoolConnection<Postgres>>, sqlx::Error> {
+        match &self.database {
+            Some((_, connection)) => Ok(connection.lock().await),
+            None => Err(sqlx::Error::Configuration(
+                format!("No database for the {} region", self.region.name).into(),
+            )),
+        }
+    }
+
+    /// Replaces the connection with a new one from the same pool, after it was lost.
+    pub async fn reconnect(&self) -> Result<(), sqlx::Error> {
+        if let Some((pool, connection)) = &self.database {
+            let new_connection = pool.acquire().await?;
+            *connection.lock().await = new_connection;
+        }
+        Ok(())
+    }
+
+    pub fn count_cache_hit(&self) {
+        self.loads.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_database_load(&self, duration: Duration) {
+        self.loads.cache_misses.fetch_add(1, Ordering::Relaxed);
+        let micros = duration.as_micros() as u64;
+        self.loads.database_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+}
+
+impl Region {
+    pub(crate) fn new(config: &RegionConfig) -> Self {
+        Region {
+            name: config.name.clone(),
+            bbox: config.bbox,
+            database_url: config.database_url.clone(),
+            schema: config.schema.clone(),
+            pool: OnceCell::new(),
+            replica_urls: config.replica_urls.clone(),
+            replicas: OnceLock::new(),
+            next_replica: AtomicUsize::new(0),
+            breaker: CircuitBreaker::default(),
+            extent: OnceCell::new(),
+            adjacency: Mutex::new(None),
+            store: config.snapshot.as_ref().map(|path| {
+                let snapshot = Snapshot::open(path).unwrap_or_else(|e| panic!("{e}"));
+                println!("{} nodes mapped from {path}", snapshot.len());
+                Box::new(snapshot) as Box<dyn GraphStore>
+            }),
+            node_cache: Mutex::new(NodeCache::new(
+                CONFIG.node_cache_capacity,
+                CONFIG.node_cache_max_bytes,
+            )),
+            route_cache: Mutex::new(RouteCache::new(
+                CONFIG.route_cache_capacity,
+                CONFIG.route_cache_ttl,
+            )),
+            closed_ways: Mutex::new((None, Arc::new(HashSet::new()))),
+            weather: Mutex::new(None),
+            data_updated: Mutex::new(None),
+            rules: config.rules,
+        }
+    }
+
+    pub fn all() -> &'static [Region] {
+        &REGIONS
+    }
+
+    pub fn get(name: &str) -> Result<&'static Region, Box<dyn Error>> {
+        REGIONS
+            .iter()
+            .find(|r| r.name == name)
+            .ok_or_else(|| format!("Unknown region {name}").into())
+    }
+
+    /// The first region covering all the `(lat, lng)` points.
+    pub fn containing(points: &[(f64, f64)]) -> Option<&'static Region> {
+        REGIONS.iter().find(|r| match r.bbox {
+            Some(bbox) => points.iter().all(|(lat, lng)| bbox.contains(*lat, *lng)),
+            None => true,
+        })
+    }
+
+    /// The region to stitch a route from `start` in this region to `end` with, and
+    /// the point to cross over at, when `end` is outside of this region but in
+    /// another one overlapping or touching it.
+    pub fn crossing(
+        &'static self,
+        start: &LatLon,
+        end: &LatLon,
+    ) -> Option<(&'static Region, LatLon)> {
+        let bbox = self.bbox?;
+        if bbox.contains(end.lat, end.lng) {
+            return None;
+        }
+        REGIONS.iter().filter(|r| !std::ptr::eq(*r, self)).find_map(|next| {
+            let next_bbox = next.bbox.filter(|bbox| bbox.contains(end.lat, end.lng))?;
+            let overlap = bbox.intersection(&next_bbox)?;
+            Some((next, overlap.crossing(start, end)))
+        })
+    }
+
+    /// The options of the region pools, setting the query timeout, none when zero, and
+    /// the schema of each connection.
+    fn pool_options(&self, statement_timeout: Duration) -> PgPoolOptions {
+        let mut settings = vec![format!(
+            "SET statement_timeout = {}",
+            statement_timeout.as_millis()
+        )];
+        if let Some(schema) = &self.schema {
+            settings.push(format!("SET search_path TO {schema}, public"));
+        }
+        PgPoolOptions::new()
+            .max_connections(CONFIG.db_pool_size)
+            .acquire_timeout(CONFIG.db_acquire_timeout)
+            .idle_timeout(CONFIG.db_idle_timeout)
+            .after_connect(move |conn, _| {
+                let settings = settings.clone();
+                Box::pin(async move {
+                    for setting in settings {
+                        conn.execute(setting.as_str()).await?;
+                    }
+                    Ok(())
+                })
+            })
+    }
+
+    /// The pool of the primary database, connecting and running the migrations on
+    /// first use, once whatever the number of requests waiting for it.
+    async fn pool(&self) -> Result<&Pool<Postgres>, sqlx::Error> {
+        self.pool
+            .get_or_try_init(|| async {
+                let url = self
+                    .database_url
+                    .as_ref()
+                    .unwrap_or_else(|| panic!("No database url for the {} region", self.name));
+                // Building the indices of a large extract takes longer than a query
+                let migrations = self.pool_options(Duration::ZERO).max_connections(1);
+                let migrations = migrations.connect(url).await?;
+                sqlx::migrate!().run(&migrations).await?;
+                migrations.close().await;
+                self.pool_options(CONFIG.db_statement_timeout).connect(url).await
+            })
+            .await
+    }
+
+    /// The pools of the read replicas, connecting on first use so that a replica
+    /// down at startup does not stop the server.
+    fn replicas(&self) -> &[Replica] {
+        self.replicas.get_or_init(|| {
+            self.replica_urls
+                .iter()
+                .filter_map(|url| {
+                    let options = self
+                        .pool_options(CONFIG.db_statement_timeout)
+                        .acquire_timeout(REPLICA_ACQUIRE_TIMEOUT);
+                    let pool = options
+                        .connect_lazy(url)
+                        .map_err(|e| eprintln!("Invalid replica of the {} region: {e}", self.name))
+                        .ok()?;
+                    Some(Replica {
+                        pool,
+                        breaker: CircuitBreaker::default(),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn client_of(
+        &'static self,
+        pool: &Pool<Postgres>,
+        connection: PoolConnection<Postgres>,
+        breaker: &'static CircuitBreaker,
+    ) -> RegionClient {
+        RegionClient {
+            region: self,
+            database: Some((pool.clone(), Arc::new(Mutex::new(connection)))),
+            breaker,
+            loads: Arc::new(NodeLoads::default()),
+        }
+    }
+
+    /// Whether the region has a database, rather than being served from its graph store
+    /// alone.
+    pub fn has_database(&self) -> bool {
+        self.database_url.is_some()
+    }
+
+    pub fn store(&self) -> Option<&dyn GraphStore> {
+        self.store.as_deref()
+    }
+
+    /// The graph store, when the region has no database to query instead.
+    pub fn embedded_store(&self) -> Option<&dyn GraphStore> {
+        self.store().filter(|_| !self.has_database())
+    }
+
+    /// A connection to the primary database, for the queries writing or needing
+    /// the latest data. Fails fast with `DATABASE_UNAVAILABLE` after repeated failures
+    /// to connect, rather than have every request wait for the acquire timeout.
+    pub async fn client(&'static self) -> Result<RegionClient, RouteError> {
+        if !self.has_database() {
+            return Ok(RegionClient {
+                region: self,
+                database: None,
+                breaker: &self.breaker,
+                loads: Arc::new(NodeLoads::default()),
+            });
+        }
+        self.breaker.check()?;
+        let connection = match self.pool().await {
+            Ok(pool) => pool.acquire().await.map(|connection| (pool, connection)),
+            Err(e) => Err(e),
+        };
+        match connection {
+            Ok((pool, connection)) => {
+                self.breaker.succeeded();
+                Ok(self.client_of(pool, connection, &self.breaker))
+            }
+            Err(e) => {
+                eprintln!("Cannot reach the database of the {} region: {e}", self.name);
+                self.breaker.failed();
+                Err(self.breaker.unavailable())
+            }
+        }
+    }
+
+    /// A connection to a read replica, taking turns between them, for the routing
+    /// queries. Falls back to the primary when there are none or none can be reached,
+    /// skipping the replicas whose circuit is open.
+    pub async fn read_client(&'static self) -> Result<RegionClient, RouteError> {
+        let replicas = self.replicas();
+        let first = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        for i in 0..replicas.len() {
+            let index = (first + i) % replicas.len();
+            let replica = &replicas[index];
+            if replica.breaker.check().is_err() {
+                continue;
+            }
+            match replica.pool.acquire().await {
+                Ok(connection) => {
+                    replica.breaker.succeeded();
+                    return Ok(self.client_of(&replica.pool, connection, &replica.breaker));
+                }
+                Err(e) => {
+                    eprintln!("Cannot reach replica {index} of the {} region: {e}", self.name);
+                    replica.breaker.failed();
+                }
+            }
+        }
+        self.client().await
+    }
+
+    /// Closes the region pools, if they were ever opened.
+    pub async fn close(&self) {
+        if let Some(pool) = self.pool.get() {
+            pool.close().await;
+        }
+        for replica in self.replicas.get().into_iter().flatten() {
+            replica.pool.close().await;
+        }
+    }
+
+    /// The area covered by the region data: its configured bbox, or else the
+    /// extent of its lines. `None` when the region has no data.
+    pub async fn extent(&'static self) -> Result<Option<BoundingBox>, sqlx::Error> {
+        if let Some(bbox) = self.bbox {
+            return Ok(Some(bbox));
+        }
+        if let Some(store) = self.embedded_store() {
+            return Ok(store.extent());
+        }
+        self.extent
+            .get_or_try_init(|| async {
+                let row = sqlx::query(
+                    r#"
+                    select
+                        ST_XMin(e) as min_lng, ST_YMin(e) as min_lat,
+                        ST_XMax(e) as max_lng, ST_YMax(e) as max_lat
+                    from (
+                        select ST_Transform(ST_SetSRID(ST_Extent(way)::geometry, 3857), 4326) as e
+                        from planet_osm_line
+                    ) extent
+                    "#,
+                )
+                .fetch_one(self.pool().await?)
+                .await?;
+                let min_lat: Option<f64> = row.get("min_lat");
+                Ok(min_lat.map(|min_lat| BoundingBox {
+                    min_lat,
+                    min_lng: row.get("min_lng"),
+                    max_lat: row.get("max_lat"),
+                    max_lng: row.get("max_lng"),
+                }))
+            })
+            .await
+            .copied()
+    }
+
+    /// Whether `node_adjacency` holds the edges of the nodes.
+    pub(crate) async fn has_adjacency(&self, pg_client: RegionClient) -> Result<bool, sqlx::Error> {
+        let mut adjacency = self.adjacency.lock().await;
+        if let Some(adjacency) = *adjacency {
+            return Ok(adjacency);
+        }
+        let exists = sqlx::query_scalar("select exists (select 1 from node_adjacency)")
+            .fetch_one(pg_client.lock().await?.as_mut())
+            .await?;
+        *adjacency = Some(exists);
+        Ok(exists)
+    }
+
+    pub(crate) async fn cached_node(&self, id: i64) -> Option<Node> {
+        if let Some(node) = self.store().and_then(|store| store.node(id)) {
+            return Some(node);
+        }
+        if let Some(node) = self.node_cache.lock().await.get(id) {
+            return Some(node);
+        }
+        // Then if another replica already fetched it
+        let node = shared_cache().await?.get(&self.name, id).await?;
+        self.node_cache.lock().await.insert(node.clone());
+        Some(node)
+    }
+
+    pub(crate) async fn cache_node(&self, node: &Node) {
+        self.node_cache.lock().await.insert(node.clone());
+        if let Some(shared_cache) = shared_cache().await {
+            shared_cache.insert(&self.name, node).await;
+        }
+    }
+
+    pub(crate) async fn cached_route(&self, key: &str) -> Option<CachedRoute> {
+        if let Some(route) = self.route_cache.lock().await.get(key) {
+            return Some(route);
+        }
+        let route = shared_cache().await?.get_route(&self.name, key).await?;
+        self.route_cache.lock().await.insert(key.to_string(), route.clone());
+        Some(route)
+    }
+
+    pub(crate) async fn cache_route(&self, key: &str, route: &CachedRoute) {
+        self.route_cache
+            .lock()
+            .await
+            .insert(key.to_string(), route.clone());
+        if let Some(shared_cache) = shared_cache().await {
+            shared_cache
+                .insert_route(&self.name, key, route, CONFIG.route_cache_ttl)
+                .await;
+        }
+    }
+
+    /// The ways closed right now. The cached routes are dropped whenever they
+    /// change, as they may go through newly closed ways or around reopened ones.
+    pub(crate) async fn closed_ways(
+        &self,
+        pg_client: RegionClient,
+    ) -> Result<Arc<HashSet<i64>>, Box<dyn Error>> {
+        let mut closed = self.closed_ways.lock().await;
+        if let (Some(loaded), ways) = &*closed {
+            if loaded.elapsed() < CLOSURES_REFRESH {
+                return Ok(ways.clone());
+            }
+        }
+        let ways = Arc::new(closed_ways(pg_client).await?);
+        if ways != closed.1 {
+            self.clear_routes().await?;
+        }
+        *closed = (Some(Instant::now()), ways.clone());
+        Ok(ways)
+    }
+
+    /// A version of the data the routes depend on, the same on every replica and
+    /// across restarts: from when the data was last imported or its lengths
+    /// recomputed, and the ways closed right now. Unknown without a database.
+    pub async fn data_version(&self, pg_client: RegionClient) -> Result<u64, Box<dyn Error>> {
+        if !self.has_database() {
+            return Err(format!("The data of the {} region has no version", self.name).into());
+        }
+        let closed = self.closed_ways(pg_client.to_owned()).await?;
+        let mut closed: Vec<i64> = closed.iter().copied().collect();
+        closed.sort_unstable();
+        let updated = self.data_updated(pg_client).await?;
+        let mut hasher = FxHasher::default();
+        (closed, updated).hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+
+    /// When the data was last imported or its lengths recomputed, as recorded in
+    /// `job_runs`, checked again every `CLOSURES_REFRESH`.
+    async fn data_updated(&self, pg_client: RegionClient) -> Result<Option<i64>, Box<dyn Error>> {
+        let mut updated = self.data_updated.lock().await;
+        if let Some((checked, updated)) = *updated {
+            if checked.elapsed() < CLOSURES_REFRESH {
+                return Ok(updated);
+            }
+        }
+        let latest: Option<i64> = sqlx::query_scalar(
+            r#"
+            select extract(epoch from max(ran_at))::int8 from job_runs
+            where job in ('import', 'lengths')
+            "#,
+        )
+        .fetch_one(pg_client.lock().await?.as_mut())
+        .await?;
+        *updated = Some((Instant::now(), latest));
+        Ok(latest)
+    }
+
+    /// The recent weather around `point`, fetched for the whole region once every
+    /// `WEATHER_TTL`. The weather is clear when it cannot be fetched.
+    pub(crate) async fn weather(&self, point: &LatLon) -> Weather {
+        {
+            let mut weather = self.weather.lock().await;
+            match *weather {
+                Some((fetched, weather)) if fetched.elapsed() < CONFIG.weather_ttl => {
+                    return weather;
+                }
+                // The other searches keep the stale weather while this one fetches it
+                Some((_, stale)) => *weather = Some((Instant::now(), stale)),
+                None => {}
+            }
+        }
+        let fetched = match weather::fetch(point.lat, point.lng).await {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                eprintln!("Cannot fetch the weather of the {} region: {e}", self.name);
+                Weather::default()
+            }
+        };
+        *self.weather.lock().await = Some((Instant::now(), fetched));
+        fetched
+    }
+
+    /// Makes the next search reload the closed ways, after they were changed, and
+    /// drops the cached routes, which may go through newly closed ways. The other
+    /// servers drop theirs when they reload the closed ways.
+    pub async fn reload_closures(&self) -> Result<(), Box<dyn Error>> {
+        self.closed_ways.lock().await.0 = None;
+        self.clear_routes().await
+    }
+
+    /// Empties the local and shared route caches, and checks again whether the edges
+    /// are materialized, as the data may have been imported again.
+    pub async fn clear_routes(&self) -> Result<(), Box<dyn Error>> {
+        self.route_cache.lock().await.clear();
+        *self.adjacency.lock().await = None;
+        if let Some(shared_cache) = shared_cache().await {
+            shared_cache.flush_routes(&self.name).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn cache_stats(&self) -> CacheStats {
+        self.node_cache.lock().await.stats()
+    }
+
+    /// Removes the nodes in `scope` from the local and shared caches, returning how
+    /// many were removed from the local one. The cached routes may go through them,
+    /// so they are all removed.
+    pub async fn flush_cache(&self, scope: &FlushScope) -> Result<usize, Box<dyn Error>> {
+        let flushed = self.node_cache.lock().await.flush(scope);
+        if let Some(shared_cache) = shared_cache().await {
+            shared_cache.flush(&self.name, scope).await?;
+        }
+        self.clear_routes().await?;
+        Ok(flushed)
+    }
+}
@@ -0,0 +1,234 @@
+//! Re-routing a rider who left the route: rather than searching again to the end, a
+//! short search leads back to the route a little ahead, and the rest of the route is
+//! kept.
+
+use crate::{
+    data::saved_route::SavedRoute,
+    error::{FieldError, RouteError},
+    instruction::turn_angle,
+    route::{route_legs, saved_path, LatLon, RouteRequest},
+};
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+/// How far from the route the rider may be while still following it, in meters.
+const ON_ROUTE_DISTANCE: f64 = 30.0;
+
+/// How far the heading may be from the direction of the route while following it,
+/// in degrees.
+const ON_ROUTE_HEADING: f64 = 90.0;
+
+/// How far ahead of the closest point the route is rejoined, in meters, leaving room
+/// to turn around.
+const REJOIN_DISTANCE: i32 = 300;
+
+#[derive(Deserialize)]
+pub struct RerouteRequest {
+    /// The ID of the saved route, instead of `request` and `path`.
+    #[serde(default)]
+    pub route_id: Option<String>,
+    /// The request the route was found for, its end is kept.
+    #[serde(default)]
+    pub request: Option<RouteRequest>,
+    /// The path of the route returned.
+    #[serde(default)]
+    pub path: Vec<LatLon>,
+    /// Where the rider is.
+    pub position: LatLon,
+    /// Where the rider is heading, in degrees clockwise from north.
+    pub heading: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct RerouteResponse {
+    /// From the position to the end of the route.
+    pub path: Vec<LatLon>,
+    /// Whether the rider was off the route and a way back was searched.
+    pub off_route: bool,
+    /// How many points at the end of `path` come from the previous route.
+    pub reused: usize,
+}
+
+/// The distance in meters from `point` to the segment from `a` to `b`.
+fn segment_distance(point: &LatLon, a: &LatLon, b: &LatLon) -> f64 {
+    // Flat around `a`, which is fine over a segment
+    let scale = a.lat.to_radians().cos();
+    let xy = |p: &LatLon| ((p.lng - a.lng) * scale * 111_320.0, (p.lat - a.lat) * 110_540.0);
+    let ((px, py), (bx, by)) = (xy(point), xy(b));
+    let length = bx * bx + by * by;
+    let t = if length > 0.0 {
+        ((px * bx + py * by) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (dx, dy) = (px - t * bx, py - t * by);
+    (dx * dx + dy * dy).sqrt()
+}
+
+/// The index of the segment of `path` closest to `point`, and its distance.
+fn closest_segment(path: &[LatLon], point: &LatLon) -> Option<(usize, f64)> {
+    path.windows(2)
+        .map(|pair| segment_distance(point, &pair[0], &pair[1]))
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Whether the rider at `position` going to `heading` still follows `path`, and
+/// from which segment.
+fn following(path: &[LatLon], position: &LatLon, heading: Option<f64>) -> Option<usize> {
+    let (i, distance) = closest_segment(path, position)?;
+    let direction = path[i].bearing(&path[i + 1]);
+    let heading_matches = heading.is_none_or(|heading| {
+        turn_angle(direction, heading).abs() <= ON_ROUTE_HEADING
+    });
+    (distance <= ON_ROUTE_DISTANCE && heading_matches).then_some(i)
+}
+
+/// The index of the point of `path` about `REJOIN_DISTANCE` meters after the
+/// segment `from`, `None` when the end is closer.
+fn rejoin_point(path: &[LatLon], from: usize) -> Option<usize> {
+    let mut distance = 0;
+    for i in from + 1..path.len() - 1 {
+        distance += path[i - 1].distance(&path[i]);
+        if distance >= REJOIN_DISTANCE {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// The `waypoints` of the route along `path` after the segment `from` and before the
+/// point `until`, which the way back to the route must still go through.
+fn waypoints_ahead(
+    path: &[LatLon],
+    waypoints: &[LatLon],
+    from: usize,
+    until: Option<usize>,
+) -> Vec<LatLon> {
+    waypoints
+        .iter()
+        .filter(|waypoint| {
+            closest_segment(path, waypoint)
+                .is_some_and(|(i, _)| i >= from && until.is_none_or(|until| i < until))
+        })
+        .cloned()
+        .collect()
+}
+
+/// The request and path of the saved route `id`.
+async fn saved(id: &str) -> Result<(RouteRequest, Vec<LatLon>), RouteError> {
+    let not_saved = || RouteError::RouteNotSaved { id: id.to_string() };
+    let saved = SavedRoute::find(id).await?.ok_or_else(not_saved)?;
+    let internal = |e: serde_json::Error| RouteError::Internal {
+        message: e.to_string(),
+    };
+    Ok((
+        serde_json::from_str(&saved.request).map_err(internal)?,
+        saved_path(&saved.body).map_err(internal)?,
+    ))
+}
+
+pub async fn reroute(request: RerouteRequest) -> Result<RerouteResponse, RouteError> {
+    let mut errors = vec![];
+    request.position.validate("position", &mut errors);
+    let (mut original, path) = match (&request.route_id, request.request) {
+        (Some(id), _) => saved(id).await?,
+        (None, Some(original)) => (original, request.path),
+        (None, None) => {
+            errors.push(FieldError {
+                field: "request".to_string(),
+                message: "is required without a route_id".to_string(),
+            });
+            Default::default()
+        }
+    };
+    // The end kept is the last of the `coordinates` sent instead
+    original.take_coordinates();
+    if path.len() < 2 {
+        errors.push(FieldError {
+            field: "path".to_string(),
+            message: "must have at least 2 points".to_string(),
+        });
+    }
+    if !errors.is_empty() {
+        return Err(RouteError::InvalidRequest { errors });
+    }
+    let path = &path;
+    if let Some(i) = following(path, &request.position, request.heading) {
+        let mut rest = vec![request.position.clone()];
+        rest.extend_from_slice(&path[i + 1..]);
+        return Ok(RerouteResponse {
+            reused: rest.len() - 1,
+            path: rest,
+            off_route: false,
+        });
+    }
+
+    let (closest, _) = closest_segment(path, &request.position).unwrap_or_default();
+    let rejoin = rejoin_point(path, closest);
+    let end = rejoin.map_or(&original.end, |i| &path[i]);
+    let search = RouteRequest {
+        start: request.position.clone(),
+        end: end.clone(),
+        waypoints: waypoints_ahead(path, &original.waypoints, closest, rejoin),
+        start_heading: request.heading,
+        ..original.clone()
+    };
+    search.validate()?;
+    let region = search.region().await?;
+    let (found, _legs, _stats) = route_legs(region, &search, |_| {}).await?;
+    let mut new_path = vec![request.position.clone()];
+    new_path.extend(found.iter().map(LatLon::from));
+    let reused = match rejoin {
+        Some(i) => {
+            new_path.extend_from_slice(&path[i..]);
+            path.len() - i
+        }
+        None => {
+            new_path.push(original.end.clone());
+            0
+        }
+    };
+    Ok(RerouteResponse {
+        path: new_path,
+        off_route: true,
+        reused,
+    })
+}
+
+#[post("/reroute")]
+async fn reroute_route(request: web::Json<RerouteRequest>) -> Result<impl Responder, RouteError> {
+    Ok(HttpResponse::Ok().json(reroute(request.into_inner()).await?))
+}
+
+#[test]
+fn keeps_the_rest_of_the_route_while_following_it() {
+    let point = |lat: f64, lng: f64| LatLon { lat, lng };
+    // Eastward along a street, then north
+    let path = vec![
+        point(45.5, -73.600),
+        point(45.5, -73.595),
+        point(45.5, -73.590),
+        point(45.505, -73.590),
+    ];
+    let position = point(45.50005, -73.5925);
+    assert_eq!(following(&path, &position, Some(85.0)), Some(1));
+    // Riding the wrong way
+    assert_eq!(following(&path, &position, Some(270.0)), None);
+    // A block away
+    assert_eq!(following(&path, &point(45.502, -73.5925), None), None);
+    assert_eq!(rejoin_point(&path, 0), Some(1));
+    assert_eq!(rejoin_point(&path, 1), Some(2));
+    assert_eq!(rejoin_point(&path, 2), None);
+}
+
+#[test]
+fn goes_back_through_the_waypoints_ahead() {
+    let point = |lat: f64, lng: f64| LatLon { lat, lng };
+    let path: Vec<LatLon> = (0..6).map(|i| point(45.5, -73.6 + i as f64 * 0.005)).collect();
+    let waypoints = [point(45.5, -73.5975), point(45.5, -73.5875), point(45.5, -73.5775)];
+    // The first one was passed, the last one is on the rest of the route kept
+    let ahead = waypoints_ahead(&path, &waypoints, 1, Some(4));
+    assert_eq!(ahead, vec![waypoints[1].clone()]);
+    assert_eq!(waypoints_ahead(&path, &waypoints, 1, None).len(), 2);
+}
@@ -1,42 +1,1184 @@
-use std::{
-    error::Error,
-    thread,
-};
+use std::{collections::HashSet, str::FromStr, sync::Arc, thread, time::Instant};
 
-use crate::{data::node::Node};
+use crate::{
+    alternatives::{self, MAX_ALTERNATIVES},
+    composition::{dismount_sections, highways, surfaces, DismountSection, Highways, Surfaces},
+    config::CONFIG,
+    data::{
+        conditional::LocalTime,
+        elevation::climb,
+        node::{distance, Node, SearchProgress, SearchStats, Snapped},
+        poi::{self, Poi},
+        saved_route::{self, SavedRoute},
+    },
+    error::{FieldError, RouteError},
+    grpc::proto,
+    impact::{calories, co2_saved, DEFAULT_RIDER_WEIGHT},
+    instruction::{format_distance, steps, Step},
+    region::Region,
+    safety::{safety, Safety},
+    segment::{summary, way_segments, WaySegment},
+    stages::{self, Stage, StageTarget},
+    weather::{Weather, Wind},
+};
 use actix_web::{
+    get,
+    http::header,
     post,
     web::{self},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, HttpResponseBuilder, Responder,
 };
-use serde::{Deserialize, Serialize};
+use prost::Message;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use tokio::sync::mpsc;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A point, also accepted as a GeoJSON-like `[lng, lat]` array.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, ToSchema)]
 pub struct LatLon {
     pub lat: f64,
     pub lng: f64,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The shapes a `LatLon` is accepted in.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LatLonJson {
+    Object { lat: f64, lng: f64 },
+    Array([f64; 2]),
+}
+
+impl<'de> Deserialize<'de> for LatLon {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match LatLonJson::deserialize(deserializer)? {
+            LatLonJson::Object { lat, lng } => Ok(LatLon { lat, lng }),
+            LatLonJson::Array([lng, lat]) if lat.abs() > 90.0 && lng.abs() <= 90.0 => {
+                Err(de::Error::custom(format!(
+                    "[{lng}, {lat}] looks like [lat, lng], expected [lng, lat]"
+                )))
+            }
+            LatLonJson::Array([lng, lat]) => Ok(LatLon { lat, lng }),
+        }
+    }
+}
+
+impl From<&Node> for LatLon {
+    fn from(node: &Node) -> Self {
+        LatLon {
+            lat: node.lat(),
+            lng: node.lon(),
+        }
+    }
+}
+
+/// Parses `lat,lng`, as in the query of `GET /route`.
+impl FromStr for LatLon {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (lat, lng) = s
+            .split_once(',')
+            .ok_or_else(|| format!("expected lat,lng, got {s}"))?;
+        Ok(LatLon {
+            lat: lat.trim().parse().map_err(|_| format!("invalid latitude {lat}"))?,
+            lng: lng.trim().parse().map_err(|_| format!("invalid longitude {lng}"))?,
+        })
+    }
+}
+
+impl LatLon {
+    /// The point of the `start` and `end` left out of a request, given as
+    /// `coordinates` instead.
+    fn missing() -> Self {
+        LatLon {
+            lat: f64::NAN,
+            lng: f64::NAN,
+        }
+    }
+
+    fn is_missing(&self) -> bool {
+        self.lat.is_nan() && self.lng.is_nan()
+    }
+
+    /// Checks the coordinates are finite and in range, adding errors for `field`.
+    pub fn validate(&self, field: &str, errors: &mut Vec<FieldError>) {
+        if !self.lat.is_finite() || !(-90.0..=90.0).contains(&self.lat) {
+            let swapped = if (-90.0..=90.0).contains(&self.lng) {
+                ", lat and lng look swapped"
+            } else {
+                ""
+            };
+            errors.push(FieldError {
+                field: format!("{field}.lat"),
+                message: format!("must be between -90 and 90, got {}{swapped}", self.lat),
+            });
+        }
+        if !self.lng.is_finite() || !(-180.0..=180.0).contains(&self.lng) {
+            errors.push(FieldError {
+                field: format!("{field}.lng"),
+                message: format!("must be between -180 and 180, got {}", self.lng),
+            });
+        }
+    }
+
+    /// The straight-line distance to `other`, in meters.
+    pub fn distance(&self, other: &LatLon) -> i32 {
+        distance(
+            (self.lat * 10_000_000.0) as i32,
+            (self.lng * 10_000_000.0) as i32,
+            (other.lat * 10_000_000.0) as i32,
+            (other.lng * 10_000_000.0) as i32,
+        )
+    }
+
+    /// The initial bearing to `other`, in degrees clockwise from north.
+    pub fn bearing(&self, other: &LatLon) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let d_lon = (other.lng - self.lng).to_radians();
+        let y = d_lon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+        (y.atan2(x).to_degrees() + 360.0) % 360.0
+    }
+}
+
+/// The bearing at the end of `points`, the one of their last segment.
+pub fn final_bearing(points: &[LatLon]) -> Option<f64> {
+    let last = points.windows(2).rev().find(|pair| pair[0].distance(&pair[1]) > 0)?;
+    Some(last[0].bearing(&last[1]))
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
 pub enum Model {
+    #[serde(alias = "fast")]
     Fast,
+    #[default]
+    #[serde(alias = "safe")]
     Safe,
+    /// For riding with children: only on cycleways and streets limited to 30 km/h,
+    /// crossing as few arterials as possible.
+    #[serde(alias = "family")]
+    Family,
+    /// For road bikes: paved and smooth ways, bigger roads rather than gravel.
+    #[serde(alias = "road")]
+    Road,
+}
+
+/// The units of the distances written out in the steps and the summary.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Units {
+    #[default]
+    Metric,
+    /// Feet and miles.
+    Imperial,
+}
+
+/// The features a route may be asked to avoid, never taking the ways having them.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Avoid {
+    Ferries,
+    /// The ways with a gravel, dirt, grass or similar surface.
+    Unpaved,
+    /// Always avoided, bikes being carried up steps.
+    Steps,
+    /// The trunk roads and their links.
+    Trunk,
+    Bridges,
+}
+
+/// Parses the name of a feature to avoid, as in `avoid` lists.
+impl FromStr for Avoid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "ferries" => Ok(Avoid::Ferries),
+            "unpaved" => Ok(Avoid::Unpaved),
+            "steps" => Ok(Avoid::Steps),
+            "trunk" => Ok(Avoid::Trunk),
+            "bridges" => Ok(Avoid::Bridges),
+            other => Err(format!(
+                "must be ferries, unpaved, steps, trunk or bridges, got {other}"
+            )),
+        }
+    }
+}
+
+/// The features a route may be asked to prefer, making the ways having them cheaper
+/// on top of the model.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Prefer {
+    /// The cycleways and cycle tracks, apart from cars.
+    ProtectedCycleway,
+    /// The residential streets, living streets and bicycle roads.
+    QuietStreets,
+    /// The ways of signed bike routes.
+    SignedRoutes,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Parses the name of a feature to prefer, as in `prefer` lists.
+impl FromStr for Prefer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "protected_cycleway" => Ok(Prefer::ProtectedCycleway),
+            "quiet_streets" => Ok(Prefer::QuietStreets),
+            "signed_routes" => Ok(Prefer::SignedRoutes),
+            other => Err(format!(
+                "must be protected_cycleway, quiet_streets or signed_routes, got {other}"
+            )),
+        }
+    }
+}
+
+/// The fastest `cruising_speed_kmh` accepted.
+const MAX_CRUISING_SPEED: f64 = 60.0;
+
+const DEFAULT_BIKE_ROUTE_PREFERENCE: f64 = 0.5;
+
+/// The most `locked` sub-paths of a request, each one adding a leg to search.
+const MAX_LOCKED: usize = 10;
+
+/// The most `waypoints` of a request, each one adding a leg to search.
+const MAX_WAYPOINTS: usize = 10;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
 pub struct RouteRequest {
+    /// Required unless `coordinates` are given.
+    #[serde(default = "LatLon::missing")]
     pub start: LatLon,
+    /// Required unless `coordinates` are given.
+    #[serde(default = "LatLon::missing")]
     pub end: LatLon,
+    /// The start, the waypoints and the end at once, like `[[lng, lat], [lng, lat]]`,
+    /// instead of `start`, `end` and `waypoints`.
+    #[serde(default, skip_serializing)]
+    pub coordinates: Vec<LatLon>,
     pub model: Model,
+    /// The region to route in, by default the first one covering both ends.
+    #[serde(default)]
+    pub region: Option<String>,
+    /// How far the ends may be from a routable way, in meters.
+    #[serde(default)]
+    pub snap_radius_m: Option<i32>,
+    /// Responds with a `RouteResponse` instead of the bare list of coordinates.
+    #[serde(default)]
+    pub detailed: bool,
+    /// Whether the route may take ferries, true by default.
+    #[serde(default)]
+    pub allow_ferries: Option<bool>,
+    /// Prefers lit streets and avoids unlit paths, for the Safe model.
+    #[serde(default)]
+    pub night: bool,
+    /// Avoids the edges steeper than this, up or down, unless there is no other way.
+    #[serde(default)]
+    pub max_grade_percent: Option<f64>,
+    /// For the calories estimate, 75 kg by default.
+    #[serde(default)]
+    pub rider_weight_kg: Option<f64>,
+    /// The speed of the rider on flat ground, scaling the duration, by default
+    /// the speed of the model.
+    #[serde(default)]
+    pub cruising_speed_kmh: Option<f64>,
+    /// Prefers the ways ridden by many imported GPX traces, for the Safe model.
+    #[serde(default)]
+    pub prefer_popular: bool,
+    /// The local time of departure, like `2023-05-12T18:30`, to only take the ways
+    /// open then. The time-dependent restrictions are ignored without it.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub departure_time: Option<LocalTime>,
+    /// Whether the recent weather changes the route, true by default when the
+    /// server has a weather provider.
+    #[serde(default)]
+    pub weather: Option<bool>,
+    /// How strongly the signed bike routes are preferred, from 0 to 1, 0.5 by
+    /// default, for the Safe model.
+    #[serde(default)]
+    pub bike_route_preference: Option<f64>,
+    /// When the search runs out of time, responds with the route to the point reached
+    /// closest to the end, detailed and marked `partial`, instead of an error.
+    #[serde(default)]
+    pub allow_partial: bool,
+    /// Responds with a detailed route including the statistics of the search.
+    #[serde(default)]
+    pub debug: bool,
+    /// Along with `debug`, adds the GeoJSON of the nodes expanded by the search, with
+    /// their g, h and f values, and of the edges to their successors to the statistics.
+    /// The route is searched again rather than taken from the cache.
+    #[serde(default)]
+    pub search_tree: bool,
+    /// Saves the route for `GET /route/{id}`, by default only the detailed routes
+    /// posted. The routes found with `debug` are never saved.
+    #[serde(default)]
+    pub save: Option<bool>,
+    /// Parts of a route returned before, each one a list of consecutive points of
+    /// its path, that the route must follow as they are, in order. Only the legs
+    /// between them are searched.
+    #[serde(default)]
+    pub locked: Vec<Vec<LatLon>>,
+    /// The points to pass through between the start and the end, in order. The
+    /// route is then detailed, with a leg from each point to the next.
+    #[serde(default)]
+    pub waypoints: Vec<LatLon>,
+    /// The kinds of points of interest to list along a detailed route, like
+    /// `drinking_water` or `bicycle_repair_station`.
+    #[serde(default)]
+    pub pois: Vec<String>,
+    /// The features the route must not take, even if it is much longer then.
+    #[serde(default)]
+    pub avoid: Vec<Avoid>,
+    /// The features the route should rather take, each one making the ways having it
+    /// cheaper, within bounds.
+    #[serde(default)]
+    pub prefer: Vec<Prefer>,
+    /// Never takes the ways where the bike would be pushed, like steps, pedestrian
+    /// zones and footways, for cargo bikes: no route is found rather.
+    #[serde(default)]
+    pub no_dismount: bool,
+    /// Makes the cobbled ways so costly, whatever the model, that they are only
+    /// taken when there is no way around.
+    #[serde(default)]
+    pub avoid_cobbles: bool,
+    /// The most the bike may be pushed along the route, in meters, however costly
+    /// the ways around are.
+    #[serde(default)]
+    pub max_dismount_m: Option<i32>,
+    /// How many other routes to find along with a detailed route, at most 3. Fewer
+    /// are returned when there are no other routes different enough.
+    #[serde(default)]
+    pub alternatives: u32,
+    /// How much longer than the route the alternatives may be, 1.4 by default.
+    #[serde(default)]
+    pub max_detour_factor: Option<f64>,
+    /// The share of the length of an alternative that must differ from each route
+    /// found before it, from 0 to 1, 0.3 by default.
+    #[serde(default)]
+    pub min_overlap_difference: Option<f64>,
+    /// Where the rider is heading at the start, in degrees clockwise from north, so
+    /// that the route does not begin with a U-turn.
+    #[serde(default)]
+    pub start_heading: Option<f64>,
+    /// Whether the route keeps going at the points where its legs meet rather than
+    /// turning around there, like the `continue_straight` of OSRM.
+    #[serde(default)]
+    pub continue_straight: bool,
+    /// The units of the distances written out in a detailed route.
+    #[serde(default)]
+    pub units: Units,
+    /// Splits a detailed route into daily stages of about this length, for
+    /// multi-day touring.
+    #[serde(default)]
+    pub stage_distance_km: Option<f64>,
+    /// Splits a detailed route into daily stages of about this much riding, instead
+    /// of a length.
+    #[serde(default)]
+    pub stage_duration_h: Option<f64>,
+    /// The edges of the routes found before an alternative, as pairs of node IDs
+    /// with the lowest first, made costlier by the search.
+    #[serde(skip)]
+    pub penalized: Arc<HashSet<(i64, i64)>>,
+    /// The wind to route for, instead of the current one of the weather.
+    #[serde(default)]
+    pub wind: Option<Wind>,
+    /// The weather the route is computed for, filled in by the search.
+    #[serde(skip)]
+    pub conditions: Weather,
+    /// The ends snapped when tagging the route, reused by its search.
+    #[serde(skip)]
+    pub snapped: Option<Arc<Snapped>>,
+    /// Searches the route again, leaving the route caches as they are, to time the
+    /// searches.
+    #[serde(skip)]
+    pub uncached: bool,
+    /// When the searches for the request must be over by, shared by its legs and its
+    /// alternatives, `SEARCH_TIMEOUT` after the first one started when unset.
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
 }
 
-#[post("/route")]
-async fn route(
-    coords: web::Json<RouteRequest>,
-) -> Result<impl Responder, Box<dyn Error>> {
-    let coords = coords.into_inner();
-    let (path, _cost) = Node::route(&coords).await?;
-    let mut response: Vec<LatLon> = thread::spawn(move || {
+/// The query of `GET /route`, like `?start=45.52,-73.58&end=45.50,-73.56&model=safe`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct RouteQuery {
+    start: String,
+    end: String,
+    /// Semicolon-separated, like `45.51,-73.57;45.52,-73.56`.
+    waypoints: Option<String>,
+    #[serde(default)]
+    model: Model,
+    region: Option<String>,
+    snap_radius_m: Option<i32>,
+    #[serde(default)]
+    detailed: bool,
+    allow_ferries: Option<bool>,
+    #[serde(default)]
+    night: bool,
+    max_grade_percent: Option<f64>,
+    rider_weight_kg: Option<f64>,
+    cruising_speed_kmh: Option<f64>,
+    #[serde(default)]
+    prefer_popular: bool,
+    #[param(value_type = Option<String>)]
+    departure_time: Option<LocalTime>,
+    weather: Option<bool>,
+    bike_route_preference: Option<f64>,
+    /// Comma-separated, like `drinking_water,toilets`.
+    pois: Option<String>,
+    /// Comma-separated, like `ferries,unpaved`.
+    avoid: Option<String>,
+    /// Comma-separated, like `protected_cycleway,quiet_streets`.
+    prefer: Option<String>,
+    #[serde(default)]
+    no_dismount: bool,
+    #[serde(default)]
+    avoid_cobbles: bool,
+    max_dismount_m: Option<i32>,
+    #[serde(default)]
+    alternatives: u32,
+    max_detour_factor: Option<f64>,
+    min_overlap_difference: Option<f64>,
+    start_heading: Option<f64>,
+    #[serde(default)]
+    continue_straight: bool,
+    #[serde(default)]
+    units: Units,
+    stage_distance_km: Option<f64>,
+    stage_duration_h: Option<f64>,
+    #[serde(default)]
+    allow_partial: bool,
+    #[serde(default)]
+    debug: bool,
+    #[serde(default)]
+    search_tree: bool,
+    save: Option<bool>,
+}
+
+/// Parses the comma-separated `list` of features of the `field` query parameter,
+/// adding errors for the unknown ones.
+fn parse_features<T: FromStr<Err = String>>(
+    field: &str,
+    list: Option<&str>,
+    errors: &mut Vec<FieldError>,
+) -> Vec<T> {
+    let features = list.map(poi::parse_kinds).unwrap_or_default();
+    features
+        .iter()
+        .filter_map(|feature| match feature.parse() {
+            Ok(feature) => Some(feature),
+            Err(message) => {
+                errors.push(FieldError {
+                    field: field.to_string(),
+                    message,
+                });
+                None
+            }
+        })
+        .collect()
+}
+
+impl TryFrom<RouteQuery> for RouteRequest {
+    type Error = RouteError;
+
+    fn try_from(query: RouteQuery) -> Result<Self, Self::Error> {
+        let mut errors = vec![];
+        let mut parse = |field: &str, value: &str| {
+            value.parse().unwrap_or_else(|message| {
+                errors.push(FieldError {
+                    field: field.to_string(),
+                    message,
+                });
+                LatLon::default()
+            })
+        };
+        let request = RouteRequest {
+            start: parse("start", &query.start),
+            end: parse("end", &query.end),
+            waypoints: query
+                .waypoints
+                .as_deref()
+                .map(|points| points.split(';').map(|point| parse("waypoints", point)).collect())
+                .unwrap_or_default(),
+            model: query.model,
+            region: query.region,
+            snap_radius_m: query.snap_radius_m,
+            detailed: query.detailed,
+            allow_ferries: query.allow_ferries,
+            night: query.night,
+            max_grade_percent: query.max_grade_percent,
+            rider_weight_kg: query.rider_weight_kg,
+            cruising_speed_kmh: query.cruising_speed_kmh,
+            prefer_popular: query.prefer_popular,
+            departure_time: query.departure_time,
+            weather: query.weather,
+            bike_route_preference: query.bike_route_preference,
+            pois: query.pois.as_deref().map(poi::parse_kinds).unwrap_or_default(),
+            avoid: parse_features("avoid", query.avoid.as_deref(), &mut errors),
+            prefer: parse_features("prefer", query.prefer.as_deref(), &mut errors),
+            no_dismount: query.no_dismount,
+            avoid_cobbles: query.avoid_cobbles,
+            max_dismount_m: query.max_dismount_m,
+            alternatives: query.alternatives,
+            max_detour_factor: query.max_detour_factor,
+            min_overlap_difference: query.min_overlap_difference,
+            start_heading: query.start_heading,
+            continue_straight: query.continue_straight,
+            units: query.units,
+            stage_distance_km: query.stage_distance_km,
+            stage_duration_h: query.stage_duration_h,
+            allow_partial: query.allow_partial,
+            debug: query.debug,
+            search_tree: query.search_tree,
+            save: query.save,
+            ..Default::default()
+        };
+        if errors.is_empty() {
+            Ok(request)
+        } else {
+            Err(RouteError::InvalidRequest { errors })
+        }
+    }
+}
+
+/// The part of a route from one of its points to the next.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct RouteLeg {
+    pub path: Vec<LatLon>,
+    /// The length of the leg, in meters.
+    pub distance: i32,
+    /// How long riding the leg takes, in seconds.
+    pub duration: i32,
+    /// The cost of the leg for the model, comparable between legs only.
+    pub cost: i64,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SnappedPoint {
+    pub requested: LatLon,
+    /// The routable node the route starts or ends at.
+    pub snapped: LatLon,
+    /// The distance between the requested and snapped points, in meters.
+    pub distance: i32,
+}
+
+impl SnappedPoint {
+    fn new(requested: &LatLon, node: &Node) -> Self {
+        let snapped = LatLon::from(node);
+        SnappedPoint {
+            distance: requested.distance(&snapped),
+            requested: requested.clone(),
+            snapped,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct RouteResponse {
+    /// The ID of the saved route, for `GET /route/{id}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Whether the search ran out of time and the route stops before the end.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    /// The route from the snapped start to the snapped end.
+    pub path: Vec<LatLon>,
+    pub start: SnappedPoint,
+    pub end: SnappedPoint,
+    /// The OSM ways followed, in order.
+    pub ways: Vec<WaySegment>,
+    /// The main streets followed, like "Via Rue Rachel and Lachine Canal".
+    pub summary: Option<String>,
+    /// The length of the route in the requested units, like "4.2 km".
+    pub distance_text: String,
+    /// The turn by turn maneuvers.
+    pub steps: Vec<Step>,
+    /// How long riding the route takes, in seconds.
+    pub duration: i32,
+    /// The local arrival time, when the departure time was given.
+    #[schema(value_type = Option<String>)]
+    pub arrival_time: Option<LocalTime>,
+    /// The elevation gained, in meters, when there is an elevation model.
+    pub ascent: Option<i32>,
+    /// The elevation lost, in meters.
+    pub descent: Option<i32>,
+    /// The kilocalories burned riding the route, roughly.
+    pub calories: Option<i32>,
+    /// The grams of CO2 a car would have emitted driving the route.
+    pub co2_saved_g: Option<i32>,
+    /// How safe and comfortable the route is overall.
+    pub safety: Safety,
+    /// The percentages of the route by surface.
+    pub surfaces: Surfaces,
+    /// The percentages of the route by kind of way.
+    pub highways: Highways,
+    /// The sections where the bike is pushed, in route order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dismounts: Vec<DismountSection>,
+    /// The points of interest of the requested kinds near the route, in route order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pois: Vec<Poi>,
+    /// The legs between the points of the route, when there were waypoints.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub legs: Vec<RouteLeg>,
+    /// The daily stages of the route, when a stage length or duration was given.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stages: Vec<Stage>,
+    /// The other routes found when `alternatives` were requested, from the best.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<RouteResponse>,
+    /// The statistics of the search, when `debug` was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<SearchStats>,
+}
+
+impl RouteRequest {
+    pub fn validate(&self) -> Result<(), RouteError> {
+        let mut errors = vec![];
+        // Taken by `take_coordinates` unless they conflict with the other fields
+        if !self.coordinates.is_empty() {
+            let message = if self.coordinates.len() < 2 {
+                "must have at least 2 points"
+            } else {
+                "cannot be combined with start, end or waypoints"
+            };
+            errors.push(FieldError {
+                field: "coordinates".to_string(),
+                message: message.to_string(),
+            });
+        }
+        for (field, point) in [("start", &self.start), ("end", &self.end)] {
+            if point.is_missing() {
+                if self.coordinates.is_empty() {
+                    errors.push(FieldError {
+                        field: field.to_string(),
+                        message: "is required unless coordinates are given".to_string(),
+                    });
+                }
+            } else {
+                point.validate(field, &mut errors);
+            }
+        }
+        if let Some(snap_radius) = self.snap_radius_m {
+            if snap_radius <= 0 {
+                errors.push(FieldError {
+                    field: "snap_radius_m".to_string(),
+                    message: format!("must be positive, got {snap_radius}"),
+                });
+            }
+        }
+        if let Some(weight) = self.rider_weight_kg {
+            if weight.is_nan() || weight <= 0.0 {
+                errors.push(FieldError {
+                    field: "rider_weight_kg".to_string(),
+                    message: format!("must be positive, got {weight}"),
+                });
+            }
+        }
+        if let Some(speed) = self.cruising_speed_kmh {
+            if !(speed > 0.0 && speed <= MAX_CRUISING_SPEED) {
+                errors.push(FieldError {
+                    field: "cruising_speed_kmh".to_string(),
+                    message: format!("must be between 0 and {MAX_CRUISING_SPEED}, got {speed}"),
+                });
+            }
+        }
+        if let Some(max_grade) = self.max_grade_percent {
+            if max_grade.is_nan() || max_grade <= 0.0 {
+                errors.push(FieldError {
+                    field: "max_grade_percent".to_string(),
+                    message: format!("must be positive, got {max_grade}"),
+                });
+            }
+        }
+        if let Some(preference) = self.bike_route_preference {
+            if !(0.0..=1.0).contains(&preference) {
+                errors.push(FieldError {
+                    field: "bike_route_preference".to_string(),
+                    message: format!("must be between 0 and 1, got {preference}"),
+                });
+            }
+        }
+        if self.locked.len() > MAX_LOCKED {
+            errors.push(FieldError {
+                field: "locked".to_string(),
+                message: format!("must have at most {MAX_LOCKED} sub-paths"),
+            });
+        }
+        for (i, locked) in self.locked.iter().enumerate() {
+            if locked.len() < 2 {
+                errors.push(FieldError {
+                    field: format!("locked[{i}]"),
+                    message: "must have at least 2 points".to_string(),
+                });
+            }
+            for (j, point) in locked.iter().enumerate() {
+                point.validate(&format!("locked[{i}][{j}]"), &mut errors);
+            }
+        }
+        if self.waypoints.len() > MAX_WAYPOINTS {
+            errors.push(FieldError {
+                field: "waypoints".to_string(),
+                message: format!("must have at most {MAX_WAYPOINTS} points"),
+            });
+        }
+        if !self.waypoints.is_empty() && !self.locked.is_empty() {
+            errors.push(FieldError {
+                field: "waypoints".to_string(),
+                message: "cannot be combined with locked sub-paths".to_string(),
+            });
+        }
+        if !self.waypoints.is_empty() && self.alternatives > 0 {
+            errors.push(FieldError {
+                field: "alternatives".to_string(),
+                message: "cannot be combined with waypoints".to_string(),
+            });
+        }
+        for (i, point) in self.waypoints.iter().enumerate() {
+            point.validate(&format!("waypoints[{i}]"), &mut errors);
+        }
+        if self.alternatives > MAX_ALTERNATIVES {
+            errors.push(FieldError {
+                field: "alternatives".to_string(),
+                message: format!("must be at most {MAX_ALTERNATIVES}, got {}", self.alternatives),
+            });
+        }
+        if let Some(factor) = self.max_detour_factor {
+            if factor.is_nan() || factor < 1.0 {
+                errors.push(FieldError {
+                    field: "max_detour_factor".to_string(),
+                    message: format!("must be at least 1, got {factor}"),
+                });
+            }
+        }
+        if let Some(difference) = self.min_overlap_difference {
+            if !(0.0..=1.0).contains(&difference) {
+                errors.push(FieldError {
+                    field: "min_overlap_difference".to_string(),
+                    message: format!("must be between 0 and 1, got {difference}"),
+                });
+            }
+        }
+        if let Some(wind) = &self.wind {
+            if wind.speed_kmh.is_nan() || wind.speed_kmh < 0.0 {
+                errors.push(FieldError {
+                    field: "wind.speed_kmh".to_string(),
+                    message: format!("must not be negative, got {}", wind.speed_kmh),
+                });
+            }
+            if !(0.0..=360.0).contains(&wind.from_degrees) {
+                errors.push(FieldError {
+                    field: "wind.from_degrees".to_string(),
+                    message: format!("must be between 0 and 360, got {}", wind.from_degrees),
+                });
+            }
+        }
+        if let Some(max_dismount) = self.max_dismount_m {
+            if max_dismount < 0 {
+                errors.push(FieldError {
+                    field: "max_dismount_m".to_string(),
+                    message: format!("must not be negative, got {max_dismount}"),
+                });
+            }
+        }
+        if let Some(heading) = self.start_heading {
+            if !(0.0..=360.0).contains(&heading) {
+                errors.push(FieldError {
+                    field: "start_heading".to_string(),
+                    message: format!("must be between 0 and 360, got {heading}"),
+                });
+            }
+        }
+        for (field, target) in [
+            ("stage_distance_km", self.stage_distance_km),
+            ("stage_duration_h", self.stage_duration_h),
+        ] {
+            if let Some(target) = target.filter(|target| target.is_nan() || *target <= 0.0) {
+                errors.push(FieldError {
+                    field: field.to_string(),
+                    message: format!("must be positive, got {target}"),
+                });
+            }
+        }
+        if self.stage_distance_km.is_some() && self.stage_duration_h.is_some() {
+            errors.push(FieldError {
+                field: "stage_duration_h".to_string(),
+                message: "cannot be combined with stage_distance_km".to_string(),
+            });
+        }
+        if let Some(kind) = self.pois.iter().find(|kind| !poi::is_valid_kind(kind)) {
+            errors.push(FieldError {
+                field: "pois".to_string(),
+                message: format!("must be OSM tag values, got {kind}"),
+            });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(RouteError::InvalidRequest { errors })
+        }
+    }
+
+    /// Moves the `coordinates` to the `start`, the `waypoints` and the `end`, when
+    /// these were left out.
+    pub fn take_coordinates(&mut self) {
+        let left_out = self.start.is_missing() && self.end.is_missing();
+        if let ([start, waypoints @ .., end], true) = (&self.coordinates[..], left_out) {
+            if self.waypoints.is_empty() {
+                self.start = start.clone();
+                self.end = end.clone();
+                self.waypoints = waypoints.to_vec();
+                self.coordinates.clear();
+            }
+        }
+    }
+
+    /// How long riding `path` takes at the requested speed, in seconds.
+    pub fn duration(&self, path: &[Node]) -> i32 {
+        self.durations(path).iter().sum::<f64>().round() as i32
+    }
+
+    /// How long riding each edge of `path` takes at the requested speed, in seconds.
+    pub fn durations(&self, path: &[Node]) -> Vec<f64> {
+        let cruising_speed = self.cruising_speed_kmh.map(|speed| speed / 3.6);
+        self.model.profile().durations(path, cruising_speed)
+    }
+
+    /// How much to ride each day when the route is split into stages.
+    pub fn stage_target(&self) -> Option<StageTarget> {
+        match (self.stage_distance_km, self.stage_duration_h) {
+            (Some(km), _) => Some(StageTarget::Distance((km * 1000.0).round() as i32)),
+            (None, Some(hours)) => Some(StageTarget::Duration((hours * 3600.0).round() as i32)),
+            (None, None) => None,
+        }
+    }
+
+    pub fn allow_ferries(&self) -> bool {
+        self.allow_ferries.unwrap_or(true) && !self.avoid.contains(&Avoid::Ferries)
+    }
+
+    pub fn bike_route_preference(&self) -> f64 {
+        self.bike_route_preference.unwrap_or(DEFAULT_BIKE_ROUTE_PREFERENCE)
+    }
+
+    /// The options changing the route found between two nodes, to tell cached
+    /// routes apart.
+    pub fn options_key(&self) -> String {
+        format!(
+            "{:?}:{}:{}:{:?}:{}:{}:{:?}:{:?}:{:?}:{:?}:{}:{}:{:?}:{:?}",
+            self.model,
+            self.allow_ferries(),
+            self.night,
+            self.max_grade_percent,
+            self.prefer_popular,
+            self.bike_route_preference(),
+            self.departure_time,
+            self.conditions,
+            self.avoid,
+            self.prefer,
+            self.no_dismount,
+            self.avoid_cobbles,
+            self.max_dismount_m,
+            self.start_heading
+        )
+    }
+
+    /// Finds the region to route in, rejecting routes too long or outside the
+    /// region data rather than letting the search run until it times out.
+    pub async fn region(&self) -> Result<&'static Region, RouteError> {
+        let distance = self.start.distance(&self.end);
+        if distance > CONFIG.max_route_distance {
+            return Err(RouteError::RouteTooLong {
+                distance,
+                max_distance: CONFIG.max_route_distance,
+            });
+        }
+        let region = match &self.region {
+            Some(name) => Region::get(name).map_err(|_| RouteError::UnknownRegion {
+                region: name.clone(),
+            })?,
+            None => Region::containing(&[
+                (self.start.lat, self.start.lng),
+                (self.end.lat, self.end.lng),
+            ])
+            // Stitched across the boundary with the region of the end
+            .or_else(|| {
+                Region::containing(&[(self.start.lat, self.start.lng)])
+                    .filter(|region| region.crossing(&self.start, &self.end).is_some())
+            })
+            .ok_or(RouteError::NoRegion)?,
+        };
+        let end_region = region.crossing(&self.start, &self.end).map_or(region, |(next, _)| next);
+        let inside = match (region.extent().await?, end_region.extent().await?) {
+            (Some(extent), Some(end_extent)) => {
+                extent.contains(self.start.lat, self.start.lng)
+                    && end_extent.contains(self.end.lat, self.end.lng)
+            }
+            _ => false,
+        };
+        if !inside {
+            return Err(RouteError::OutsideExtent {
+                region: region.name.clone(),
+            });
+        }
+        Ok(region)
+    }
+}
+
+const PROTOBUF: &str = "application/x-protobuf";
+
+/// How long the responses of `GET /route` may be cached, in seconds.
+const ROUTE_MAX_AGE: u32 = 3600;
+
+/// The body of a route response, the bare path from the snapped start to the snapped
+/// end unless `detailed` was requested.
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum RouteBody {
+    Detailed(Box<RouteResponse>),
+    Path(Vec<LatLon>),
+}
+
+impl RouteBody {
+    fn path(&self) -> &[LatLon] {
+        match self {
+            RouteBody::Detailed(response) => &response.path,
+            RouteBody::Path(path) => path,
+        }
+    }
+
+    /// The route as a `CompactRoute` protobuf message.
+    pub fn compact(&self) -> proto::CompactRoute {
+        let path = self.path();
+        let mut compact = proto::CompactRoute {
+            distance: path.windows(2).map(|pair| pair[0].distance(&pair[1])).sum(),
+            ..Default::default()
+        };
+        let (mut previous_lat, mut previous_lng) = (0, 0);
+        for point in path {
+            let lat = (point.lat * 10_000_000.0).round() as i32;
+            let lng = (point.lng * 10_000_000.0).round() as i32;
+            compact.lat_deltas.push(lat - previous_lat);
+            compact.lng_deltas.push(lng - previous_lng);
+            (previous_lat, previous_lng) = (lat, lng);
+        }
+        if let RouteBody::Detailed(response) = self {
+            compact.ways = response.ways.iter().cloned().map(Into::into).collect();
+            compact.summary = response.summary.clone();
+        }
+        compact
+    }
+
+    /// Responds with JSON, or protobuf when the client accepts it.
+    fn respond(&self, request: &HttpRequest, mut response: HttpResponseBuilder) -> HttpResponse {
+        if accepts_protobuf(request) {
+            response
+                .content_type(PROTOBUF)
+                .body(self.compact().encode_to_vec())
+        } else {
+            response.json(self)
+        }
+    }
+}
+
+fn accepts_protobuf(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(PROTOBUF))
+}
+
+/// Saves the route for `GET /route/{id}` when `coords` asks to, returning its ID,
+/// or `None` when it is not saved.
+async fn save(
+    region: &'static Region,
+    coords: &RouteRequest,
+    body: &mut RouteBody,
+) -> Option<String> {
+    // The search trees and statistics of the debugged routes are too large to keep
+    if !region.has_database() || coords.debug || coords.save != Some(true) {
+        return None;
+    }
+    let id = saved_route::new_id();
+    if let RouteBody::Detailed(response) = body {
+        response.id = Some(id.clone());
+    }
+    let saved = SavedRoute {
+        id,
+        request: serde_json::to_string(coords).ok()?,
+        body: serde_json::to_string(body).ok()?,
+    };
+    let client = region.client().await.ok()?;
+    let result = saved.save(client).await;
+    match result {
+        Ok(()) => Some(saved.id),
+        Err(e) => {
+            eprintln!("Cannot save route {}: {e}", saved.id);
+            if let RouteBody::Detailed(response) = body {
+                response.id = None;
+            }
+            None
+        }
+    }
+}
+
+/// Finds and saves the route when asked to, returning its body and the ID it was
+/// saved as.
+pub(crate) async fn find_route(
+    mut coords: RouteRequest,
+    on_progress: impl FnMut(SearchProgress),
+) -> Result<(RouteBody, Option<String>), RouteError> {
+    coords.take_coordinates();
+    coords.validate()?;
+    let region = coords.region().await?;
+    let mut body = route_body(region, coords.clone(), on_progress).await?;
+    let id = save(region, &coords, &mut body).await;
+    Ok((body, id))
+}
+
+/// The detailed response of the route along `path`, found for `coords`.
+async fn detailed_response(
+    region: &'static Region,
+    coords: &RouteRequest,
+    path: &[Node],
+) -> Result<RouteResponse, RouteError> {
+    let ways = way_segments(path);
+    let (first, last) = match (path.first(), path.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            return Err(RouteError::Internal {
+                message: "Empty route".to_string(),
+            })
+        }
+    };
+    let (ascent, descent) = climb(path).unzip();
+    let distance: i32 = ways.iter().map(|way| way.length).sum();
+    let rider_weight = coords.rider_weight_kg.unwrap_or(DEFAULT_RIDER_WEIGHT);
+    let duration = coords.duration(path);
+    let points: Vec<LatLon> = path.iter().map(LatLon::from).collect();
+    let pois = if coords.pois.is_empty() {
+        vec![]
+    } else {
+        poi::along(region.read_client().await?, &points, distance, &coords.pois).await?
+    };
+    let stages = match coords.stage_target() {
+        Some(target) => {
+            // Without a database, the stages end wherever the days should
+            let towns = if region.has_database() {
+                poi::towns_along(region.read_client().await?, &points, distance).await?
+            } else {
+                vec![]
+            };
+            stages::split(path, &coords.durations(path), &towns, target)
+        }
+        None => vec![],
+    };
+    Ok(RouteResponse {
+        id: None,
+        partial: false,
+        start: SnappedPoint::new(&coords.start, first),
+        end: SnappedPoint::new(&coords.end, last),
+        summary: summary(&ways),
+        distance_text: format_distance(distance, coords.units),
+        steps: steps(path, &ways, coords.units),
+        duration,
+        arrival_time: coords
+            .departure_time
+            .map(|departure| departure.plus_seconds(duration as i64)),
+        calories: Some(calories(distance, ascent.unwrap_or(0), rider_weight)),
+        co2_saved_g: Some(co2_saved(distance)),
+        safety: safety(path, &ways),
+        surfaces: surfaces(path, &ways),
+        highways: highways(path, &ways),
+        dismounts: dismount_sections(path),
+        ascent,
+        descent,
+        ways,
+        path: points,
+        pois,
+        legs: vec![],
+        stages,
+        alternatives: vec![],
+        debug: None,
+    })
+}
+
+/// Finds the route through the `waypoints` of `coords`, searching each leg on its
+/// own, with the path of the whole route.
+pub(crate) async fn route_legs(
+    region: &'static Region,
+    coords: &RouteRequest,
+    mut on_progress: impl FnMut(SearchProgress),
+) -> Result<(Vec<Node>, Vec<RouteLeg>, SearchStats), RouteError> {
+    let mut points = vec![coords.start.clone()];
+    points.extend(coords.waypoints.iter().cloned());
+    points.push(coords.end.clone());
+    let (mut path, mut legs): (Vec<Node>, _) = (vec![], vec![]);
+    let mut stats = SearchStats::default();
+    let mut heading = coords.start_heading;
+    for (i, ends) in points.windows(2).enumerate() {
+        let leg = RouteRequest {
+            start: ends[0].clone(),
+            end: ends[1].clone(),
+            waypoints: vec![],
+            start_heading: heading,
+            ..coords.clone()
+        };
+        let (nodes, cost, leg_stats) =
+            Node::route_with_progress(region, &leg, &mut on_progress).await?;
+        if i == 0 {
+            stats.start_snap_distance = leg_stats.start_snap_distance;
+        }
+        stats.add_leg(leg_stats);
+        let points: Vec<LatLon> = nodes.iter().map(LatLon::from).collect();
+        heading = final_bearing(&points).filter(|_| coords.continue_straight);
+        legs.push(RouteLeg {
+            distance: way_segments(&nodes).iter().map(|way| way.length).sum(),
+            duration: coords.duration(&nodes),
+            cost,
+            path: points,
+        });
+        // The legs meet at the same node
+        let skip = path.last().zip(nodes.first()).is_some_and(|(a, b)| a.id == b.id);
+        path.extend(nodes.into_iter().skip(skip as usize));
+        if stats.partial {
+            break;
+        }
+    }
+    Ok((path, legs, stats))
+}
+
+pub(crate) async fn route_body(
+    region: &'static Region,
+    mut coords: RouteRequest,
+    on_progress: impl FnMut(SearchProgress),
+) -> Result<RouteBody, RouteError> {
+    coords
+        .deadline
+        .get_or_insert_with(|| Instant::now() + CONFIG.search_timeout);
+    let (path, legs, stats) = if coords.waypoints.is_empty() {
+        let (path, _cost, stats) = Node::route_with_progress(region, &coords, on_progress).await?;
+        (path, vec![], stats)
+    } else {
+        route_legs(region, &coords, on_progress).await?
+    };
+    let staged = coords.stage_target().is_some();
+    if coords.detailed || coords.debug || stats.partial || !legs.is_empty() || staged {
+        let mut response = detailed_response(region, &coords, &path).await?;
+        response.partial = stats.partial;
+        response.legs = legs;
+        if coords.alternatives > 0 && !stats.partial {
+            for alternative in alternatives::find(region, &coords, &path).await? {
+                let alternative = detailed_response(region, &coords, &alternative).await?;
+                response.alternatives.push(alternative);
+            }
+        }
+        response.debug = coords.debug.then_some(stats);
+        return Ok(RouteBody::Detailed(Box::new(response)));
+    }
+    let response: Vec<LatLon> = thread::spawn(move || {
         let mut response = vec![];
         path.iter().for_each(|node| {
             response.push(LatLon {
@@ -49,8 +1191,441 @@ async fn route(
     .join()
     .unwrap();
 
-    response.insert(0, coords.start.clone());
-    response.push(coords.end.clone());
+    Ok(RouteBody::Path(response))
+}
+
+#[utoipa::path(
+    request_body = RouteRequest,
+    responses(
+        (status = 200, description = "The route, detailed or not", body = RouteBody),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 422, description = "No route can be searched", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+#[post("/route")]
+async fn route(
+    request: HttpRequest,
+    coords: web::Json<RouteRequest>,
+) -> Result<impl Responder, RouteError> {
+    post_route(&request, coords.into_inner()).await
+}
+
+/// `POST /route` of the v2 API, always responding with the detailed route rather
+/// than the bare coordinates.
+#[utoipa::path(
+    path = "/v2/route",
+    request_body = RouteRequest,
+    responses(
+        (status = 200, description = "The detailed route", body = RouteResponse),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 422, description = "No route can be searched", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+#[post("/route")]
+async fn route_v2(
+    request: HttpRequest,
+    coords: web::Json<RouteRequest>,
+) -> Result<impl Responder, RouteError> {
+    let coords = RouteRequest {
+        detailed: true,
+        ..coords.into_inner()
+    };
+    post_route(&request, coords).await
+}
+
+async fn post_route(
+    request: &HttpRequest,
+    mut coords: RouteRequest,
+) -> Result<HttpResponse, RouteError> {
+    // Posted by the apps showing the route, which may share it
+    coords.save.get_or_insert(coords.detailed);
+    let (body, id) = find_route(coords, |_| {}).await?;
+    let mut response = HttpResponse::Ok();
+    if let Some(id) = id {
+        response.insert_header((header::CONTENT_LOCATION, format!("/route/{id}")));
+    }
+    Ok(body.respond(request, response))
+}
+
+/// The same as `POST /route`, for links and CDN caching.
+#[utoipa::path(
+    params(RouteQuery),
+    responses(
+        (status = 200, description = "The route, detailed or not", body = RouteBody),
+        (status = 304, description = "Unchanged since the `ETag` in `If-None-Match`"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 422, description = "No route can be searched", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+#[get("/route")]
+async fn route_get(
+    request: HttpRequest,
+    query: web::Query<RouteQuery>,
+) -> Result<impl Responder, RouteError> {
+    get_route(&request, query.into_inner().try_into()?).await
+}
+
+/// `GET /route` of the v2 API, always responding with the detailed route.
+#[utoipa::path(
+    path = "/v2/route",
+    params(RouteQuery),
+    responses(
+        (status = 200, description = "The detailed route", body = RouteResponse),
+        (status = 304, description = "Unchanged since the `ETag` in `If-None-Match`"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 422, description = "No route can be searched", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+#[get("/route")]
+async fn route_get_v2(
+    request: HttpRequest,
+    query: web::Query<RouteQuery>,
+) -> Result<impl Responder, RouteError> {
+    let coords = RouteRequest {
+        detailed: true,
+        ..query.into_inner().try_into()?
+    };
+    get_route(&request, coords).await
+}
+
+async fn get_route(
+    request: &HttpRequest,
+    mut coords: RouteRequest,
+) -> Result<HttpResponse, RouteError> {
+    let etag = etag(request, &mut coords).await;
+    let if_none_match = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    let not_modified = etag
+        .as_ref()
+        .zip(if_none_match)
+        .is_some_and(|(etag, if_none_match)| matches_etag(if_none_match, etag));
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header((header::CACHE_CONTROL, format!("public, max-age={ROUTE_MAX_AGE}")))
+        .insert_header((header::VARY, "Accept"));
+    if let Some(etag) = etag {
+        response.insert_header((header::ETAG, etag));
+    }
+    if not_modified {
+        return Ok(response.finish());
+    }
+    let (body, id) = find_route(coords, |_| {}).await?;
+    if let Some(id) = id {
+        response.insert_header((header::CONTENT_LOCATION, format!("/route/{id}")));
+    }
+    Ok(body.respond(request, response))
+}
+
+/// The `ETag` of the route `coords` asks for, the same for the identical requests
+/// until the data changes. `None` when the request cannot be tagged without
+/// searching it, leaving its errors to the search.
+async fn etag(request: &HttpRequest, coords: &mut RouteRequest) -> Option<String> {
+    coords.take_coordinates();
+    coords.validate().ok()?;
+    let region = coords.region().await.ok()?;
+    let tag = Node::route_tag(region, coords).await.ok()?;
+    // The two representations of the route are tagged apart
+    let format = if accepts_protobuf(request) { "-pb" } else { "" };
+    // Weak, the bodies carry the statistics of their search and the ID they are saved
+    // under
+    Some(format!("W/\"{tag:016x}{format}\""))
+}
+
+/// Whether the `If-None-Match` header `value` lists `etag` or is `*`, comparing the
+/// tags weakly.
+fn matches_etag(value: &str, etag: &str) -> bool {
+    fn opaque(tag: &str) -> &str {
+        tag.strip_prefix("W/").unwrap_or(tag)
+    }
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || opaque(tag) == opaque(etag))
+}
+
+fn event(name: &str, data: &impl Serialize) -> web::Bytes {
+    let data = serde_json::to_string(data).unwrap_or_default();
+    web::Bytes::from(format!("event: {name}\ndata: {data}\n\n"))
+}
+
+/// Streams the search as Server-Sent Events: `progress` events while searching,
+/// then a `route` event with the `GET /route` body, or an `error` one.
+#[utoipa::path(
+    params(RouteQuery),
+    responses(
+        (status = 200, description = "`progress`, then `route` or `error` events", content_type = "text/event-stream"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+    )
+)]
+#[get("/route/stream")]
+async fn route_stream(query: web::Query<RouteQuery>) -> Result<impl Responder, RouteError> {
+    Ok(stream_route(query.into_inner().try_into()?))
+}
+
+/// `GET /route/stream` of the v2 API, the `route` event with the detailed route.
+#[utoipa::path(
+    path = "/v2/route/stream",
+    params(RouteQuery),
+    responses(
+        (status = 200, description = "`progress`, then `route` or `error` events", content_type = "text/event-stream"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+    )
+)]
+#[get("/route/stream")]
+async fn route_stream_v2(query: web::Query<RouteQuery>) -> Result<impl Responder, RouteError> {
+    let coords = RouteRequest {
+        detailed: true,
+        ..query.into_inner().try_into()?
+    };
+    Ok(stream_route(coords))
+}
+
+fn stream_route(coords: RouteRequest) -> HttpResponse {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    actix_web::rt::spawn(async move {
+        let progress = sender.clone();
+        let on_progress = move |p: SearchProgress| {
+            let _ = progress.send(event("progress", &p));
+        };
+        let last = match find_route(coords, on_progress).await {
+            Ok((body, _id)) => event("route", &body),
+            Err(e) => event("error", &e.body()),
+        };
+        let _ = sender.send(last);
+    });
+    let events = futures::stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok::<_, actix_web::Error>(event), receiver))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Compressing would hold the events back until the encoder flushes
+        .insert_header((header::CONTENT_ENCODING, "identity"))
+        .streaming(events)
+}
+
+/// The query of `GET /route/{id}`.
+#[derive(Deserialize, IntoParams)]
+pub struct SavedRouteQuery {
+    /// `json` (the default), `gpx` or `geojson`.
+    format: Option<String>,
+}
+
+/// The path of a saved route body, detailed or not.
+pub fn saved_path(body: &str) -> Result<Vec<LatLon>, serde_json::Error> {
+    let body: serde_json::Value = serde_json::from_str(body)?;
+    let path = match body.get("path") {
+        Some(path) => path.clone(),
+        None => body,
+    };
+    serde_json::from_value(path)
+}
+
+fn gpx(id: &str, path: &[LatLon]) -> String {
+    let points: String = path
+        .iter()
+        .map(|point| format!("      <trkpt lat=\"{}\" lon=\"{}\"/>\n", point.lat, point.lng))
+        .collect();
+    let namespace = "http://www.topografix.com/GPX/1/1";
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <gpx version=\"1.1\" creator=\"routing-server\" xmlns=\"{namespace}\">\n\
+        \x20 <trk>\n    <name>{id}</name>\n    <trkseg>\n{points}    </trkseg>\n  </trk>\n\
+        </gpx>\n"
+    )
+}
+
+fn geojson(id: &str, path: &[LatLon]) -> serde_json::Value {
+    let coordinates: Vec<[f64; 2]> = path.iter().map(|point| [point.lng, point.lat]).collect();
+    let distance: i32 = path.windows(2).map(|pair| pair[0].distance(&pair[1])).sum();
+    serde_json::json!({
+        "type": "Feature",
+        "geometry": {"type": "LineString", "coordinates": coordinates},
+        "properties": {"id": id, "distance": distance},
+    })
+}
+
+/// A route saved when it was found, as JSON like it was returned, GPX or GeoJSON.
+#[utoipa::path(
+    params(("id" = String, Path, description = "The ID of the saved route"), SavedRouteQuery),
+    responses(
+        (status = 200, description = "The route, as it was returned", body = RouteBody),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "No route was saved under this ID", body = ErrorBody),
+    )
+)]
+#[get("/route/{id}")]
+async fn route_by_id(
+    id: web::Path<String>,
+    query: web::Query<SavedRouteQuery>,
+) -> Result<impl Responder, RouteError> {
+    let id = id.into_inner();
+    let format = query.format.as_deref().unwrap_or("json");
+    if !["json", "gpx", "geojson"].contains(&format) {
+        return Err(RouteError::InvalidRequest {
+            errors: vec![FieldError {
+                field: "format".to_string(),
+                message: format!("must be json, gpx or geojson, got {format}"),
+            }],
+        });
+    }
+    let not_saved = || RouteError::RouteNotSaved { id: id.clone() };
+    if !saved_route::is_valid_id(&id) {
+        return Err(not_saved());
+    }
+    let saved = SavedRoute::find(&id).await?.ok_or_else(not_saved)?;
+    let mut response = HttpResponse::Ok();
+    // Saved routes never change
+    response.insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"));
+    let path = || {
+        saved_path(&saved.body).map_err(|e| RouteError::Internal {
+            message: e.to_string(),
+        })
+    };
+    Ok(match format {
+        "gpx" => response
+            .content_type("application/gpx+xml")
+            .body(gpx(&id, &path()?)),
+        "geojson" => response
+            .content_type("application/geo+json")
+            .body(geojson(&id, &path()?).to_string()),
+        _ => response
+            .content_type("application/json")
+            .body(saved.body),
+    })
+}
+
+#[test]
+fn validates_coordinates() {
+    let request = RouteRequest {
+        start: LatLon {
+            lat: 45.5,
+            lng: f64::NAN,
+        },
+        end: LatLon {
+            lat: -73.6,
+            lng: 45.5,
+        },
+        ..Default::default()
+    };
+    match request.validate() {
+        Err(RouteError::InvalidRequest { errors }) => {
+            let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+            assert_eq!(fields, vec!["start.lng"]);
+        }
+        other => panic!("unexpected validation result {other:?}"),
+    }
+}
+
+#[test]
+fn accepts_coordinate_arrays() {
+    let mut request: RouteRequest = serde_json::from_str(
+        r#"{"coordinates": [[-73.58, 45.52], [-73.57, 45.51], [-73.56, 45.5]], "model": "Safe"}"#,
+    )
+    .unwrap();
+    request.take_coordinates();
+    assert!(request.validate().is_ok());
+    assert_eq!((request.start.lat, request.end.lng), (45.52, -73.56));
+    assert_eq!(request.waypoints.len(), 1);
+    // Only told apart when the longitude is out of the range of latitudes
+    let swapped = serde_json::from_str::<LatLon>("[35.68, 139.76]").unwrap_err();
+    assert!(swapped.to_string().contains("looks like [lat, lng]"));
+    let request: RouteRequest =
+        serde_json::from_str(r#"{"start": {"lat": 139.76, "lng": 35.68}, "model": "Safe"}"#)
+            .unwrap();
+    match request.validate() {
+        Err(RouteError::InvalidRequest { errors }) => {
+            assert!(errors[0].message.ends_with("lat and lng look swapped"));
+            assert_eq!(errors[1].field, "end");
+        }
+        other => panic!("unexpected validation result {other:?}"),
+    }
+}
+
+#[test]
+fn parses_query_coordinates() {
+    let query = RouteQuery {
+        start: "45.52, -73.58".to_string(),
+        end: "45.50".to_string(),
+        ..Default::default()
+    };
+    match RouteRequest::try_from(query) {
+        Err(RouteError::InvalidRequest { errors }) => {
+            let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+            assert_eq!(fields, vec!["end"]);
+        }
+        other => panic!("unexpected parsing result {other:?}"),
+    }
+    let start: LatLon = "45.52, -73.58".parse().unwrap();
+    assert_eq!((start.lat, start.lng), (45.52, -73.58));
+}
+
+#[test]
+fn delta_encodes_compact_routes() {
+    let body = RouteBody::Path(vec![
+        LatLon { lat: 45.5, lng: -73.6 },
+        LatLon { lat: 45.5001, lng: -73.6 },
+        LatLon { lat: 45.5001, lng: -73.5998 },
+    ]);
+    let compact = body.compact();
+    assert_eq!(compact.lat_deltas, vec![455_000_000, 1000, 0]);
+    assert_eq!(compact.lng_deltas, vec![-736_000_000, 0, 2000]);
+    assert!(compact.encode_to_vec().len() < 24);
+}
+
+#[test]
+fn exports_saved_routes() {
+    let body = r#"{"id": "4fTq0ZbW1c", "path": [
+        {"lat": 45.5, "lng": -73.6}, {"lat": 45.51, "lng": -73.6}
+    ]}"#;
+    let path = saved_path(body).unwrap();
+    assert_eq!(path.len(), 2);
+    assert_eq!(saved_path(r#"[{"lat": 45.5, "lng": -73.6}]"#).unwrap().len(), 1);
+    let gpx = gpx("4fTq0ZbW1c", &path);
+    assert!(gpx.contains("  <trk>\n"));
+    assert!(gpx.contains(r#"<trkpt lat="45.51" lon="-73.6"/>"#));
+    let geojson = geojson("4fTq0ZbW1c", &path);
+    assert_eq!(geojson["geometry"]["coordinates"][1][1], 45.51);
+    assert_eq!(geojson["properties"]["distance"], path[0].distance(&path[1]));
+}
+
+#[test]
+fn validates_locked_paths() {
+    let point = |lat: f64, lng: f64| LatLon { lat, lng };
+    let request = RouteRequest {
+        start: point(45.5, -73.6),
+        end: point(45.52, -73.58),
+        locked: vec![
+            vec![point(45.51, -73.59), point(45.511, -73.59)],
+            vec![point(45.515, -73.585)],
+        ],
+        ..Default::default()
+    };
+    match request.validate() {
+        Err(RouteError::InvalidRequest { errors }) => {
+            let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+            assert_eq!(fields, vec!["locked[1]"]);
+        }
+        other => panic!("unexpected validation result {other:?}"),
+    }
+}
 
-    Ok(HttpResponse::Ok().json(response))
+#[test]
+fn matches_the_listed_etags() {
+    let etag = "W/\"00000000000000ff\"";
+    assert!(matches_etag(etag, etag));
+    assert!(matches_etag("\"other\", \"00000000000000ff\"", etag));
+    assert!(matches_etag("*", etag));
+    assert!(!matches_etag("W/\"00000000000000ff-pb\"", etag));
 }
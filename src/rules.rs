@@ -0,0 +1,134 @@
+//! The traffic rules of the jurisdictions the regions are in, for what the tags
+//! leave implicit: whether bikes may ride the footways and sidewalks, and the speed
+//! limits of the ways without `maxspeed`.
+
+use crate::{
+    data::{access::Access, node::AdjacentNode},
+    safety::speed_limit,
+};
+use std::collections::HashMap;
+
+pub struct Rules {
+    pub name: &'static str,
+    /// Whether bikes may ride the footways without a `bicycle` tag.
+    pub footway_cycling: bool,
+    /// Whether bikes may ride the sidewalks, `footway=sidewalk`, without a `bicycle`
+    /// tag.
+    pub sidewalk_cycling: bool,
+    /// The speed limits of the ways without `maxspeed` by `highway` value, in km/h,
+    /// unknown for the others.
+    pub default_speeds: &'static [(&'static str, f64)],
+}
+
+/// The rules the routing was first tuned for: footways and sidewalks are ridden
+/// unless tagged otherwise, and the speed limits only come from the tags.
+const QUEBEC: Rules = Rules {
+    name: "quebec",
+    footway_cycling: true,
+    sidewalk_cycling: true,
+    default_speeds: &[],
+};
+
+/// Footways only with a "Radfahrer frei" sign, 50 km/h in town.
+const GERMANY: Rules = Rules {
+    name: "germany",
+    footway_cycling: false,
+    sidewalk_cycling: false,
+    default_speeds: &[
+        ("living_street", 7.0),
+        ("residential", 50.0),
+        ("unclassified", 50.0),
+        ("tertiary", 50.0),
+        ("secondary", 50.0),
+        ("primary", 50.0),
+    ],
+};
+
+/// Footways closed to bikes, 50 km/h in town and 20 km/h in the "zones de rencontre".
+const FRANCE: Rules = Rules {
+    name: "france",
+    footway_cycling: false,
+    sidewalk_cycling: false,
+    default_speeds: &[
+        ("living_street", 20.0),
+        ("residential", 50.0),
+        ("unclassified", 50.0),
+        ("tertiary", 50.0),
+        ("secondary", 50.0),
+        ("primary", 50.0),
+    ],
+};
+
+/// Footways closed to bikes, 30 km/h in the residential areas.
+const NETHERLANDS: Rules = Rules {
+    name: "netherlands",
+    footway_cycling: false,
+    sidewalk_cycling: false,
+    default_speeds: &[
+        ("living_street", 15.0),
+        ("residential", 30.0),
+        ("unclassified", 50.0),
+        ("tertiary", 50.0),
+        ("secondary", 50.0),
+        ("primary", 50.0),
+    ],
+};
+
+const ALL: [&Rules; 4] = [&QUEBEC, &GERMANY, &FRANCE, &NETHERLANDS];
+
+/// The rules of the regions without `<NAME>_RULES`.
+pub const DEFAULT: &Rules = &QUEBEC;
+
+/// The rules called `name`, like `germany`.
+pub fn by_name(name: &str) -> Option<&'static Rules> {
+    ALL.into_iter().find(|rules| rules.name == name)
+}
+
+impl Rules {
+    /// The access of bikes to a way with `tags` left implicit by its access tags,
+    /// `None` when these rules say nothing about it.
+    pub fn default_access(&self, tags: &HashMap<String, String>) -> Option<Access> {
+        if tags.get("highway").map(String::as_str) != Some("footway") {
+            return None;
+        }
+        let allowed = match tags.get("footway").map(String::as_str) {
+            Some("sidewalk") => self.sidewalk_cycling,
+            _ => self.footway_cycling,
+        };
+        (!allowed).then_some(Access::No)
+    }
+
+    /// The speed limit of `edge` in km/h, from its `maxspeed` tag or else its
+    /// `highway`.
+    pub fn speed_limit(&self, edge: &AdjacentNode) -> Option<f64> {
+        speed_limit(edge).or_else(|| {
+            let highway = edge.tags.get("highway")?;
+            self.default_speeds
+                .iter()
+                .find(|(key, _)| key == highway)
+                .map(|(_, speed)| *speed)
+        })
+    }
+}
+
+#[test]
+fn applies_the_rules_of_the_jurisdiction() {
+    let tags = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    let sidewalk = tags(&[("highway", "footway"), ("footway", "sidewalk")]);
+    let germany = by_name("germany").unwrap();
+    assert_eq!(DEFAULT.default_access(&sidewalk), None);
+    assert_eq!(germany.default_access(&sidewalk), Some(Access::No));
+    assert_eq!(germany.default_access(&tags(&[("highway", "residential")])), None);
+    let edge = |pairs| crate::segment::test_edge(1, pairs);
+    let residential = edge(&[("highway", "residential")]);
+    assert_eq!(DEFAULT.speed_limit(&residential), None);
+    assert_eq!(by_name("netherlands").unwrap().speed_limit(&residential), Some(30.0));
+    let tagged = edge(&[("highway", "residential"), ("maxspeed", "30")]);
+    assert_eq!(germany.speed_limit(&tagged), Some(30.0));
+    assert!(by_name("atlantis").is_none());
+}
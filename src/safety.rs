@@ -0,0 +1,170 @@
+//! How safe and comfortable a route feels, from 0 to 100, for clients to color-code
+//! routes: protected bike infrastructure scores best, fast roads shared with cars
+//! worst, and crossing arterials costs points.
+
+use crate::{
+    data::node::{AdjacentNode, Node},
+    segment::{edges, WaySegment},
+};
+use serde::Serialize;
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+/// The speed limit above which sharing a road with cars is exposed, in km/h.
+const FAST_ROAD_SPEED: f64 = 50.0;
+
+/// The points lost crossing an arterial.
+const CROSSING_PENALTY: i32 = 3;
+
+const PATHS: [&str; 4] = ["path", "footway", "pedestrian", "bridleway"];
+const QUIET_STREETS: [&str; 4] = ["residential", "living_street", "service", "unclassified"];
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Safety {
+    /// From 0 for the most exposed to 100 for the most comfortable.
+    pub score: i32,
+    /// The share of the distance on cycleways and cycle tracks, from 0 to 1.
+    pub protected_share: f64,
+    /// The share of the distance on roads faster than 50 km/h without protection.
+    pub fast_road_share: f64,
+    /// The arterials crossed.
+    pub major_crossings: i32,
+}
+
+/// The speed limit of a way in km/h, from its `maxspeed` tag.
+pub fn speed_limit(edge: &AdjacentNode) -> Option<f64> {
+    let maxspeed = edge.tags.get("maxspeed")?;
+    match maxspeed.strip_suffix("mph") {
+        Some(mph) => mph.trim().parse::<f64>().ok().map(|mph| mph * 1.609),
+        None => maxspeed.trim().parse().ok(),
+    }
+}
+
+/// Whether bikes ride apart from cars, on a cycleway or a cycle track.
+pub fn is_protected(edge: &AdjacentNode) -> bool {
+    let separate_path = edge.has_tag_value("highway", "cycleway")
+        || (PATHS.iter().any(|path| edge.has_tag_value("highway", path))
+            && edge.has_tag_value("bicycle", "designated"));
+    let track = ["cycleway", "cycleway:left", "cycleway:right", "cycleway:both"]
+        .iter()
+        .any(|key| edge.tags.get(*key).is_some_and(|value| value == "track"));
+    separate_path || track
+}
+
+/// Whether the way is a street with little traffic, or meant for bikes.
+pub fn is_quiet_street(edge: &AdjacentNode) -> bool {
+    let highway = edge.tags.get("highway").map_or("", String::as_str);
+    QUIET_STREETS.contains(&highway)
+        || highway == "living_street"
+        || edge.has_tag_value("bicycle_road", "yes")
+}
+
+fn is_fast_road(edge: &AdjacentNode) -> bool {
+    !is_protected(edge) && speed_limit(edge).is_some_and(|speed| speed > FAST_ROAD_SPEED)
+}
+
+/// The comfort of riding a way, from 0 to 100.
+pub fn edge_score(edge: &AdjacentNode) -> i32 {
+    let highway = edge.tags.get("highway").map_or("", String::as_str);
+    if is_protected(edge) {
+        100
+    } else if is_fast_road(edge) {
+        if edge.has_cycle_lane() {
+            35
+        } else {
+            10
+        }
+    } else if edge.is_arterial() {
+        if edge.has_cycle_lane() {
+            60
+        } else {
+            30
+        }
+    } else if edge.has_tag_value("bicycle_road", "yes") || highway == "living_street" {
+        90
+    } else if PATHS.contains(&highway) {
+        85
+    } else if QUIET_STREETS.contains(&highway) {
+        75
+    } else {
+        60
+    }
+}
+
+/// The arterials crossed by the route along `path`, counting the nodes where the
+/// route meets an arterial it does not follow.
+fn major_crossings(path: &[Node]) -> i32 {
+    let taken: Vec<Option<&AdjacentNode>> = edges(path).collect();
+    let mut crossings = 0;
+    for (i, node) in path.iter().enumerate().skip(1).take(path.len().saturating_sub(2)) {
+        let (incoming, outgoing) = (taken[i - 1], taken[i]);
+        if [incoming, outgoing].iter().flatten().any(|e| e.is_arterial()) {
+            continue;
+        }
+        let followed: HashSet<i64> =
+            [incoming, outgoing].iter().flatten().map(|e| e.way_id).collect();
+        if node
+            .adjacent_nodes
+            .iter()
+            .any(|edge| edge.is_arterial() && !followed.contains(&edge.way_id))
+        {
+            crossings += 1;
+        }
+    }
+    crossings
+}
+
+/// The safety of the route along `path`, split into `segments`.
+pub fn safety(path: &[Node], segments: &[WaySegment]) -> Safety {
+    let mut length = 0;
+    let (mut protected, mut fast, mut weighted) = (0, 0, 0);
+    for segment in segments {
+        let Some(edge) = path[segment.start].edge_to(path[segment.start + 1].id) else {
+            continue;
+        };
+        length += segment.length;
+        weighted += segment.safety * segment.length;
+        if is_protected(edge) {
+            protected += segment.length;
+        }
+        if is_fast_road(edge) {
+            fast += segment.length;
+        }
+    }
+    let share = |part: i32| if length > 0 { part as f64 / length as f64 } else { 0.0 };
+    let major_crossings = major_crossings(path);
+    let average = if length > 0 { weighted / length } else { 100 };
+    Safety {
+        score: (average - CROSSING_PENALTY * major_crossings).clamp(0, 100),
+        protected_share: share(protected),
+        fast_road_share: share(fast),
+        major_crossings,
+    }
+}
+
+#[test]
+fn scores_protected_ways_and_crossings() {
+    use crate::segment::{test_path, way_segments};
+
+    let mut path = test_path(&[1, 2, 3]);
+    for (i, highway) in ["cycleway", "residential", "residential"].iter().enumerate() {
+        let tags = &mut path[i].adjacent_nodes[0].tags;
+        tags.insert("highway".to_string(), highway.to_string());
+    }
+    // A primary crosses the residential streets at the third node
+    let mut arterial = path[2].adjacent_nodes[0].clone();
+    arterial.node_id = 99;
+    arterial.way_id = 4;
+    arterial.tags.insert("highway".to_string(), "primary".to_string());
+    arterial.tags.insert("maxspeed".to_string(), "70".to_string());
+    assert_eq!(edge_score(&arterial), 10);
+    path[2].adjacent_nodes.push(arterial);
+
+    let segments = way_segments(&path);
+    let scores: Vec<i32> = segments.iter().map(|s| s.safety).collect();
+    assert_eq!(scores, vec![100, 75, 75]);
+    let safety = safety(&path, &segments);
+    assert_eq!(safety.major_crossings, 1);
+    assert_eq!(safety.score, 250 / 3 - CROSSING_PENALTY);
+    assert!((safety.protected_share - 1.0 / 3.0).abs() < 1e-9);
+}
@@ -0,0 +1,142 @@
+//! The tree of a search as GeoJSON: the nodes it expanded, in order, with their g
+//! (cost from the start), h (heuristic) and f (g + h) values, and the edges to their
+//! successors, to see why A* wandered into a neighborhood it should never have
+//! touched.
+
+use crate::data::node::Node;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// The most nodes and edges recorded, so that a long search neither responds with a
+/// huge body nor holds it in memory beyond its budget.
+const MAX_FEATURES: usize = 50_000;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchTree {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    features: Vec<Value>,
+    /// Whether the search went on past `MAX_FEATURES` nodes and edges, the others left
+    /// out.
+    truncated: bool,
+    /// The lowest cost found from the start to each node.
+    #[serde(skip)]
+    costs: HashMap<i64, i64>,
+    #[serde(skip)]
+    expanded: usize,
+}
+
+impl Default for SearchTree {
+    fn default() -> Self {
+        SearchTree {
+            kind: "FeatureCollection",
+            features: vec![],
+            truncated: false,
+            costs: HashMap::new(),
+            expanded: 0,
+        }
+    }
+}
+
+fn position(node: &Node) -> [f64; 2] {
+    [node.lon(), node.lat()]
+}
+
+impl SearchTree {
+    /// Records the expansion of `node`, returning its cost from the start. A search
+    /// expands a node once it found its lowest cost, so the one of `reach`.
+    pub fn expand(&mut self, node: &Node, heuristic: i64) -> i64 {
+        let cost = self.costs.get(&node.id).copied().unwrap_or(0);
+        self.expanded += 1;
+        if self.truncated || self.features.len() >= MAX_FEATURES {
+            self.truncated = true;
+            return cost;
+        }
+        self.features.push(json!({
+            "type": "Feature",
+            "geometry": {"type": "Point", "coordinates": position(node)},
+            "properties": {
+                "node_id": node.id,
+                "order": self.expanded,
+                "g": cost,
+                "h": heuristic,
+                "f": cost + heuristic,
+            },
+        }));
+        cost
+    }
+
+    /// Records the edge from `node`, expanded at `cost`, to `successor`.
+    pub fn reach(&mut self, node: &Node, cost: i64, successor: &Node, move_cost: i64) {
+        // The costs are only read for the nodes recorded, so they stop along with them.
+        if self.truncated || self.features.len() >= MAX_FEATURES {
+            self.truncated = true;
+            return;
+        }
+        let successor_cost = self.costs.entry(successor.id).or_insert(i64::MAX);
+        *successor_cost = (*successor_cost).min(cost + move_cost);
+        self.features.push(json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "LineString",
+                "coordinates": [position(node), position(successor)],
+            },
+            "properties": {
+                "from": node.id,
+                "to": successor.id,
+                "cost": move_cost,
+                "g": cost + move_cost,
+            },
+        }));
+    }
+
+    /// Adds the tree of the next leg of a route.
+    pub fn append(&mut self, leg: SearchTree) {
+        self.features.extend(leg.features);
+        self.truncated |= leg.truncated;
+    }
+}
+
+#[test]
+fn records_the_costs_from_the_start() {
+    let node = |id: i64| Node {
+        id,
+        lat: 455_000_000 + id as i32 * 1000,
+        lon: -736_000_000,
+        adjacent_nodes: vec![],
+        highway: None,
+        elevation: None,
+    };
+    let mut tree = SearchTree::default();
+    assert_eq!(tree.expand(&node(1), 300), 0);
+    tree.reach(&node(1), 0, &node(2), 150);
+    tree.reach(&node(1), 0, &node(3), 100);
+    assert_eq!(tree.expand(&node(3), 220), 100);
+    tree.reach(&node(3), 100, &node(2), 20);
+    assert_eq!(tree.expand(&node(2), 180), 120);
+    let geojson = serde_json::to_value(&tree).unwrap();
+    assert_eq!(geojson["type"], "FeatureCollection");
+    assert_eq!(geojson["features"].as_array().unwrap().len(), 6);
+    assert_eq!(geojson["features"][5]["properties"]["f"], 300);
+}
+
+#[test]
+fn stops_recording_past_the_limit() {
+    let node = |id: i64| Node {
+        id,
+        lat: 455_000_000,
+        lon: -736_000_000 + id as i32 * 1000,
+        adjacent_nodes: vec![],
+        highway: None,
+        elevation: None,
+    };
+    let mut tree = SearchTree::default();
+    for id in 0..MAX_FEATURES as i64 {
+        let cost = tree.expand(&node(id), 0);
+        tree.reach(&node(id), cost, &node(id + 1), 10);
+    }
+    assert!(tree.truncated);
+    assert_eq!(tree.features.len(), MAX_FEATURES);
+    assert!(tree.costs.len() <= MAX_FEATURES / 2);
+}
@@ -0,0 +1,157 @@
+use crate::{
+    data::node::{AdjacentNode, Node},
+    safety::edge_score,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::collections::HashMap;
+
+/// A stretch of a route following a single OSM way.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq, ToSchema)]
+pub struct WaySegment {
+    pub way_id: i64,
+    /// The index in the route path of the node entering the way.
+    pub start: usize,
+    /// The index in the route path of the node leaving the way.
+    pub end: usize,
+    /// The street name, from the way `name` tag.
+    pub name: Option<String>,
+    /// The route number, from the way `ref` tag.
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    /// The length in meters.
+    pub length: i32,
+    /// Whether the way is part of a roundabout.
+    pub roundabout: bool,
+    /// How comfortable riding the way is, from 0 to 100.
+    pub safety: i32,
+}
+
+impl WaySegment {
+    /// How the segment is called in instructions and summaries.
+    pub fn label(&self) -> Option<&str> {
+        self.name.as_deref().or(self.reference.as_deref())
+    }
+}
+
+/// The edges taken between consecutive nodes of `path`.
+pub fn edges(path: &[Node]) -> impl Iterator<Item = Option<&AdjacentNode>> {
+    path.windows(2).map(|pair| pair[0].edge_to(pair[1].id))
+}
+
+/// Splits `path` into the ways it follows, merging consecutive edges of the same way.
+pub fn way_segments(path: &[Node]) -> Vec<WaySegment> {
+    let mut segments: Vec<WaySegment> = vec![];
+    for (i, edge) in edges(path).enumerate() {
+        let way_id = edge.map_or(0, |edge| edge.way_id);
+        let length = edge.map_or(0, |edge| edge.distance);
+        match segments.last_mut() {
+            Some(segment) if segment.way_id == way_id => {
+                segment.end = i + 1;
+                segment.length += length;
+            }
+            _ => segments.push(WaySegment {
+                way_id,
+                start: i,
+                end: i + 1,
+                name: edge.and_then(|edge| edge.tags.get("name").cloned()),
+                reference: edge.and_then(|edge| edge.tags.get("ref").cloned()),
+                length,
+                roundabout: edge.is_some_and(AdjacentNode::is_roundabout),
+                safety: edge.map_or(0, edge_score),
+            }),
+        }
+    }
+    segments
+}
+
+/// Describes the route by its two longest named streets, in route order:
+/// "Via Rue Rachel and Lachine Canal".
+pub fn summary(segments: &[WaySegment]) -> Option<String> {
+    let mut lengths: HashMap<&str, i32> = HashMap::new();
+    let mut order: Vec<&str> = vec![];
+    for segment in segments {
+        if let Some(label) = segment.label() {
+            if !lengths.contains_key(label) {
+                order.push(label);
+            }
+            *lengths.entry(label).or_default() += segment.length;
+        }
+    }
+    let mut longest = order.clone();
+    longest.sort_by_key(|label| -lengths[label]);
+    longest.truncate(2);
+    let main: Vec<&str> = order.into_iter().filter(|l| longest.contains(l)).collect();
+    match main[..] {
+        [] => None,
+        [first] => Some(format!("Via {first}")),
+        [first, second, ..] => Some(format!("Via {first} and {second}")),
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_path(way_ids: &[i64]) -> Vec<Node> {
+    let mut path: Vec<Node> = (0..=way_ids.len() as i64)
+        .map(|id| Node {
+            id,
+            lat: 0,
+            lon: id as i32 * 1000,
+            adjacent_nodes: vec![],
+            highway: None,
+            elevation: None,
+        })
+        .collect();
+    for (i, way_id) in way_ids.iter().enumerate() {
+        let tags = HashMap::from([("name".to_string(), format!("Street {way_id}"))]);
+        path[i].adjacent_nodes.push(AdjacentNode {
+            node_id: i as i64 + 1,
+            way_id: *way_id,
+            tags,
+            distance: 10,
+            intermediate_nodes: None,
+            popularity: 0,
+            collisions: 0,
+            bike_network: 0,
+        });
+    }
+    path
+}
+
+/// An edge to node 2 along the way `way_id` with `tags`.
+#[cfg(test)]
+pub(crate) fn test_edge(way_id: i64, tags: &[(&str, &str)]) -> AdjacentNode {
+    AdjacentNode {
+        node_id: 2,
+        way_id,
+        tags: tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        distance: 10,
+        intermediate_nodes: None,
+        popularity: 0,
+        collisions: 0,
+        bike_network: 0,
+    }
+}
+
+#[test]
+fn merges_edges_of_the_same_way() {
+    let path = test_path(&[1, 1, 2, 1]);
+    let segments = way_segments(&path);
+    let ranges: Vec<(i64, usize, usize, i32)> = segments
+        .iter()
+        .map(|s| (s.way_id, s.start, s.end, s.length))
+        .collect();
+    assert_eq!(ranges, vec![(1, 0, 2, 20), (2, 2, 3, 10), (1, 3, 4, 10)]);
+    assert_eq!(segments[1].name.as_deref(), Some("Street 2"));
+}
+
+#[test]
+fn summarizes_longest_streets_in_order() {
+    let path = test_path(&[3, 1, 1, 2, 2, 2]);
+    assert_eq!(
+        summary(&way_segments(&path)).as_deref(),
+        Some("Via Street 1 and Street 2")
+    );
+}
@@ -0,0 +1,115 @@
+//! The daily stages of a long route for multi-day touring, each one ending in a
+//! town with somewhere to eat and sleep when there is one near where the day
+//! should end.
+
+use crate::{
+    data::{node::Node, poi::Poi},
+    segment::edges,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// How far from where the day should end a stage may end in a town instead, as a
+/// fraction of the daily target. The last stage takes up to this much more too,
+/// rather than leaving a short day at the end.
+const TOWN_SLACK: f64 = 0.25;
+
+/// How much to ride each day.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StageTarget {
+    /// In meters.
+    Distance(i32),
+    /// In seconds.
+    Duration(i32),
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Stage {
+    /// The index in the route path of the node starting the stage.
+    pub start: usize,
+    /// The index in the route path of the node ending the stage.
+    pub end: usize,
+    /// How far along the route the stage starts, in meters.
+    pub offset: i32,
+    /// The length of the stage, in meters.
+    pub distance: i32,
+    /// How long riding the stage takes, in seconds.
+    pub duration: i32,
+    /// The town the stage ends in, none on the last day or without a town near
+    /// where the day should end.
+    pub town: Option<Poi>,
+}
+
+/// Splits the route along `path`, riding its edges for `durations`, into stages of
+/// about `target` each, ending in the closest of the `towns` along the route to
+/// where each day should end.
+pub fn split(path: &[Node], durations: &[f64], towns: &[Poi], target: StageTarget) -> Vec<Stage> {
+    let mut offsets = vec![0];
+    for edge in edges(path) {
+        offsets.push(offsets[offsets.len() - 1] + edge.map_or(0, |edge| edge.distance));
+    }
+    let mut times = vec![0.0];
+    for duration in durations {
+        times.push(times[times.len() - 1] + duration);
+    }
+    let times: Vec<i32> = times.iter().map(|time| time.round() as i32).collect();
+    let (measure, target) = match target {
+        StageTarget::Distance(meters) => (&offsets, meters),
+        StageTarget::Duration(seconds) => (&times, seconds),
+    };
+    let last = offsets.len() - 1;
+    let slack = (target as f64 * TOWN_SLACK) as i32;
+    let stage = |start: usize, end: usize, town: Option<&Poi>| Stage {
+        start,
+        end,
+        offset: offsets[start],
+        distance: offsets[end] - offsets[start],
+        duration: times[end] - times[start],
+        town: town.cloned(),
+    };
+    let mut stages = vec![];
+    let mut start = 0;
+    while measure[last] - measure[start] > target + slack {
+        let ideal = measure[start] + target;
+        let town = towns
+            .iter()
+            .map(|town| (offsets.partition_point(|&offset| offset < town.offset).min(last), town))
+            .filter(|&(end, _)| end > start && (measure[end] - ideal).abs() <= slack)
+            .min_by_key(|&(end, _)| (measure[end] - ideal).abs());
+        let (end, town) = match town {
+            Some((end, town)) => (end, Some(town)),
+            None => (measure.partition_point(|&along| along < ideal).max(start + 1), None),
+        };
+        stages.push(stage(start, end, town));
+        start = end;
+    }
+    stages.push(stage(start, last, None));
+    stages
+}
+
+#[test]
+fn ends_the_days_in_the_towns_near_the_target() {
+    use crate::{route::LatLon, segment::test_path};
+
+    let path = test_path(&(1..=10).collect::<Vec<_>>());
+    let durations = vec![2.0; 10];
+    let town = |offset| Poi {
+        osm_id: offset as i64,
+        kind: "village".to_string(),
+        name: None,
+        location: LatLon::default(),
+        offset,
+        distance: 0,
+    };
+    // The edges are 10 m long, the first day should end at 40 m, the second at 80
+    let towns = [town(20), town(70)];
+    let stages = split(&path, &durations, &towns, StageTarget::Distance(40));
+    let ends: Vec<_> = stages
+        .iter()
+        .map(|stage| (stage.end, stage.town.as_ref().map(|town| town.offset)))
+        .collect();
+    assert_eq!(ends, vec![(4, None), (7, Some(70)), (10, None)]);
+    assert_eq!((stages[1].offset, stages[1].distance, stages[1].duration), (40, 30, 6));
+    let stages = split(&path, &durations, &[], StageTarget::Duration(100));
+    assert_eq!(stages.len(), 1);
+}
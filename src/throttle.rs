@@ -0,0 +1,61 @@
+//! Limits the searches running at once, each one holding a database connection for
+//! up to the search timeout: the requests over the limit wait in a bounded queue, and
+//! are turned away with a 429 once it is full, rather than exhausting the pools.
+
+use crate::{config::CONFIG, error::RouteError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::timeout,
+};
+
+/// When the clients turned away should try again, in seconds.
+const RETRY_AFTER: u64 = 5;
+
+lazy_static! {
+    static ref SEARCHES: Semaphore = Semaphore::new(CONFIG.max_concurrent_searches);
+}
+
+/// The requests waiting for a search to finish.
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+
+/// A place in the queue, left when dropped, even when the request waiting is
+/// dropped by a client disconnecting.
+struct Waiting;
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        WAITING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The searches running.
+pub fn running() -> usize {
+    CONFIG.max_concurrent_searches - SEARCHES.available_permits()
+}
+
+/// The requests waiting to search.
+pub fn waiting() -> usize {
+    WAITING.load(Ordering::Relaxed)
+}
+
+/// Waits for a search slot, failing with `TOO_MANY_SEARCHES` when the queue is full
+/// or the wait too long. The slot is held until the permit is dropped.
+pub async fn search_permit() -> Result<SemaphorePermit<'static>, RouteError> {
+    let busy = RouteError::TooManySearches {
+        retry_after: RETRY_AFTER,
+    };
+    if let Ok(permit) = SEARCHES.try_acquire() {
+        return Ok(permit);
+    }
+    let waiting = WAITING.fetch_add(1, Ordering::Relaxed);
+    let _place = Waiting;
+    if waiting >= CONFIG.search_queue_size {
+        return Err(busy);
+    }
+    let permit = timeout(CONFIG.search_queue_timeout, SEARCHES.acquire()).await;
+    match permit {
+        Ok(Ok(permit)) => Ok(permit),
+        _ => Err(busy),
+    }
+}
@@ -0,0 +1,249 @@
+//! Mapbox vector tiles of the routing graph, `GET /debug/tiles/{z}/{x}/{y}.mvt`, to
+//! audit the cost model on a map. Each edge a bike may take is a line of the `edges`
+//! layer, with its cost for the profile of the query over its length as
+//! `cost_factor`, to style like green below 1 and red above 2.
+
+use crate::{
+    admin::Admin,
+    data::{
+        bbox::BoundingBox,
+        node::{coordinates, Node},
+    },
+    error::{FieldError, RouteError},
+    region::Region,
+    route::{Model, RouteRequest},
+};
+use actix_web::{get, web, HttpResponse, Responder};
+use prost::Message;
+use serde::Deserialize;
+use std::{collections::HashSet, error::Error, f64::consts::PI};
+
+pub mod proto {
+    tonic::include_proto!("vector_tile");
+}
+
+use proto::tile::{Feature, GeomType, Layer, Value};
+
+/// The size of a tile in its own coordinates.
+const EXTENT: u32 = 4096;
+
+/// The lowest zoom served, a tile covering more has too many edges to cost.
+const MIN_ZOOM: u32 = 14;
+
+#[derive(Deserialize)]
+pub struct TileQuery {
+    #[serde(default)]
+    model: Model,
+    #[serde(default)]
+    night: bool,
+    #[serde(default)]
+    prefer_popular: bool,
+    bike_route_preference: Option<f64>,
+    /// The region of the tile, by default the first one covering its center.
+    region: Option<String>,
+}
+
+/// An edge of the graph, from a junction to the next one.
+struct Edge {
+    way_id: i64,
+    highway: Option<String>,
+    cost_factor: f64,
+    /// The `(lat, lon)` of the nodes along the edge, in decimicro degrees.
+    points: Vec<(i32, i32)>,
+}
+
+/// The number of tiles across the world at zoom `z`.
+fn tiles(z: u32) -> f64 {
+    2f64.powi(z as i32)
+}
+
+fn tile_bbox(z: u32, x: u32, y: u32) -> BoundingBox {
+    let lng = |x: u32| x as f64 / tiles(z) * 360.0 - 180.0;
+    let lat = |y: u32| (PI * (1.0 - 2.0 * y as f64 / tiles(z))).sinh().atan().to_degrees();
+    BoundingBox {
+        min_lat: lat(y + 1),
+        min_lng: lng(x),
+        max_lat: lat(y),
+        max_lng: lng(x + 1),
+    }
+}
+
+/// Where the point `(lat, lon)` in decimicro degrees is in the tile, from its
+/// top-left corner.
+fn tile_position(z: u32, x: u32, y: u32, (lat, lon): (i32, i32)) -> (i32, i32) {
+    let (lat, lon) = ((lat as f64 / 1e7).to_radians(), lon as f64 / 1e7);
+    let world_x = (lon + 180.0) / 360.0 * tiles(z);
+    let world_y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * tiles(z);
+    (
+        ((world_x - x as f64) * EXTENT as f64).round() as i32,
+        ((world_y - y as f64) * EXTENT as f64).round() as i32,
+    )
+}
+
+fn zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+/// The geometry commands drawing a line through `points`: a `MoveTo` to the first
+/// one, then a `LineTo` through the others, each relative to the previous one.
+fn line_geometry(points: &[(i32, i32)]) -> Vec<u32> {
+    let mut geometry = vec![];
+    let mut cursor = (0, 0);
+    for (i, &(x, y)) in points.iter().enumerate() {
+        match i {
+            0 => geometry.push(1 | (1 << 3)),
+            1 => geometry.push(2 | ((points.len() as u32 - 1) << 3)),
+            _ => {}
+        }
+        geometry.extend([zigzag(x - cursor.0), zigzag(y - cursor.1)]);
+        cursor = (x, y);
+    }
+    geometry
+}
+
+fn encode(z: u32, x: u32, y: u32, edges: &[Edge]) -> Vec<u8> {
+    let mut layer = Layer {
+        version: 2,
+        name: "edges".to_string(),
+        keys: ["cost_factor", "way_id", "highway"].map(str::to_string).to_vec(),
+        extent: Some(EXTENT),
+        ..Default::default()
+    };
+    for edge in edges {
+        let points: Vec<(i32, i32)> = edge
+            .points
+            .iter()
+            .map(|point| tile_position(z, x, y, *point))
+            .collect();
+        let first = layer.values.len() as u32;
+        let mut tags = vec![0, first, 1, first + 1];
+        layer.values.push(Value {
+            double_value: Some(edge.cost_factor),
+            ..Default::default()
+        });
+        layer.values.push(Value {
+            int_value: Some(edge.way_id),
+            ..Default::default()
+        });
+        if let Some(highway) = &edge.highway {
+            tags.extend([2, first + 2]);
+            layer.values.push(Value {
+                string_value: Some(highway.clone()),
+                ..Default::default()
+            });
+        }
+        layer.features.push(Feature {
+            tags,
+            r#type: Some(GeomType::Linestring as i32),
+            geometry: line_geometry(&points),
+            ..Default::default()
+        });
+    }
+    proto::Tile {
+        layers: vec![layer],
+    }
+    .encode_to_vec()
+}
+
+/// The edges of `region` crossing `bbox`, costed with `options`.
+async fn tile_edges(
+    region: &'static Region,
+    bbox: &BoundingBox,
+    options: &RouteRequest,
+) -> Result<Vec<Edge>, Box<dyn Error>> {
+    let client = region.read_client().await?;
+    let mut nodes = vec![];
+    for id in Node::routable_ids(client.to_owned(), bbox).await? {
+        nodes.push(Node::get(client.to_owned(), id).await?);
+    }
+    // The nodes between two junctions have edges too, overlapping the ones of the
+    // junctions on both sides
+    let junctions: HashSet<i64> = nodes
+        .iter()
+        .flat_map(|node| &node.adjacent_nodes)
+        .map(|edge| edge.node_id)
+        .collect();
+    let mut edges = vec![];
+    let mut intermediate_edges = vec![];
+    for node in nodes.iter().filter(|node| junctions.contains(&node.id)) {
+        for (edge, next, cost) in node.costed_edges(client.to_owned(), options).await? {
+            let intermediate_nodes = edge.intermediate_nodes.clone().unwrap_or_default();
+            edges.push(Edge {
+                way_id: edge.way_id,
+                highway: edge.tags.get("highway").cloned(),
+                cost_factor: cost as f64 / edge.distance.max(1) as f64,
+                points: vec![(node.lat, node.lon), (next.lat, next.lon)],
+            });
+            intermediate_edges.push(intermediate_nodes);
+        }
+    }
+    let ids: Vec<i64> = intermediate_edges.iter().flatten().copied().collect();
+    let coords = coordinates(client, &ids).await?;
+    for (edge, intermediate_nodes) in edges.iter_mut().zip(intermediate_edges) {
+        let along = intermediate_nodes.iter().filter_map(|id| coords.get(id).copied());
+        edge.points.splice(1..1, along);
+    }
+    Ok(edges)
+}
+
+/// The edges of tile `x`, `y` at zoom `z` as a Mapbox vector tile, for the
+/// profile of the query.
+#[get("/debug/tiles/{z}/{x}/{y}.mvt")]
+async fn debug_tile(
+    _: Admin,
+    path: web::Path<(u32, u32, u32)>,
+    query: web::Query<TileQuery>,
+) -> Result<impl Responder, RouteError> {
+    let (z, x, y) = path.into_inner();
+    let mut errors = vec![];
+    if !(MIN_ZOOM..=22).contains(&z) {
+        errors.push(FieldError {
+            field: "z".to_string(),
+            message: format!("must be between {MIN_ZOOM} and 22, got {z}"),
+        });
+    } else if x as f64 >= tiles(z) || y as f64 >= tiles(z) {
+        errors.push(FieldError {
+            field: "x".to_string(),
+            message: format!("tile {x}, {y} does not exist at zoom {z}"),
+        });
+    }
+    if !errors.is_empty() {
+        return Err(RouteError::InvalidRequest { errors });
+    }
+    let bbox = tile_bbox(z, x, y);
+    let center = (
+        (bbox.min_lat + bbox.max_lat) / 2.0,
+        (bbox.min_lng + bbox.max_lng) / 2.0,
+    );
+    let region = match &query.region {
+        Some(name) => Region::get(name).map_err(|_| RouteError::UnknownRegion {
+            region: name.clone(),
+        })?,
+        None => Region::containing(&[center]).ok_or(RouteError::NoRegion)?,
+    };
+    let options = RouteRequest {
+        model: query.model.clone(),
+        night: query.night,
+        prefer_popular: query.prefer_popular,
+        bike_route_preference: query.bike_route_preference,
+        ..Default::default()
+    };
+    let edges = tile_edges(region, &bbox, &options).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.mapbox-vector-tile")
+        .body(encode(z, x, y, &edges)))
+}
+
+#[test]
+fn encodes_edges_in_tile_coordinates() {
+    let bbox = tile_bbox(1, 0, 0);
+    assert_eq!((bbox.min_lat, bbox.min_lng, bbox.max_lng), (0.0, -180.0, 0.0));
+    assert!((bbox.max_lat - 85.0511).abs() < 1e-4);
+    // The center of the world is the bottom-right corner of the top-left tile
+    assert_eq!(tile_position(1, 0, 0, (0, 0)), (4096, 4096));
+    assert_eq!(
+        line_geometry(&[(0, 0), (10, 5), (10, 10)]),
+        vec![9, 0, 0, 18, 20, 10, 0, 10]
+    );
+    assert_eq!(zigzag(-1), 1);
+}
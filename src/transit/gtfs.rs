@@ -0,0 +1,261 @@
+//! Loads a GTFS feed, extracted in a directory, keeping only the trips taking bikes.
+//! https://gtfs.org/schedule/reference/
+
+use crate::{csv::Table, route::LatLon};
+use std::{collections::HashMap, error::Error, path::Path};
+
+pub struct Stop {
+    pub name: String,
+    pub location: LatLon,
+}
+
+pub struct Trip {
+    /// The short name of the route, like "exo 1", or else its long name.
+    pub route: String,
+    pub headsign: Option<String>,
+    service: String,
+}
+
+/// A vehicle going from a stop to the next one without stopping.
+pub struct Connection {
+    pub from: usize,
+    pub to: usize,
+    /// In seconds after midnight of the service day, past 24:00:00 after midnight.
+    pub departure: u32,
+    pub arrival: u32,
+    pub trip: usize,
+}
+
+/// The days a service runs, from `calendar.txt`.
+struct Calendar {
+    weekdays: [bool; 7],
+    /// Dates like 20230512.
+    start: u32,
+    end: u32,
+}
+
+pub struct Feed {
+    pub stops: Vec<Stop>,
+    pub trips: Vec<Trip>,
+    /// Sorted by departure.
+    pub connections: Vec<Connection>,
+    calendars: HashMap<String, Calendar>,
+    /// The services added or removed on a date, from `calendar_dates.txt`.
+    exceptions: HashMap<(String, u32), bool>,
+}
+
+/// Parses `HH:MM:SS` into seconds, the hours going past 24.
+fn parse_time(time: &str) -> Option<u32> {
+    let mut parts = time.split(':').map(|part| part.parse::<u32>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+impl Feed {
+    pub fn load(dir: &Path) -> Result<Feed, Box<dyn Error>> {
+        let table = Table::read(&dir.join("stops.txt"))?;
+        let mut stop_indices = HashMap::new();
+        let mut stops = vec![];
+        for row in &table.rows {
+            let (Ok(lat), Ok(lng)) = (
+                table.get(row, "stop_lat").parse(),
+                table.get(row, "stop_lon").parse(),
+            ) else {
+                continue;
+            };
+            stop_indices.insert(table.get(row, "stop_id").to_string(), stops.len());
+            stops.push(Stop {
+                name: table.get(row, "stop_name").to_string(),
+                location: LatLon { lat, lng },
+            });
+        }
+
+        let table = Table::read(&dir.join("routes.txt"))?;
+        let routes: HashMap<&str, &str> = table
+            .rows
+            .iter()
+            .map(|row| {
+                let name = match table.get(row, "route_short_name") {
+                    "" => table.get(row, "route_long_name"),
+                    name => name,
+                };
+                (table.get(row, "route_id"), name)
+            })
+            .collect();
+
+        let table = Table::read(&dir.join("trips.txt"))?;
+        let mut trip_indices = HashMap::new();
+        let mut trips = vec![];
+        for row in &table.rows {
+            // 1 when bikes are allowed, 0 or empty when unknown
+            if table.get(row, "bikes_allowed") != "1" {
+                continue;
+            }
+            trip_indices.insert(table.get(row, "trip_id").to_string(), trips.len());
+            let headsign = table.get(row, "trip_headsign");
+            trips.push(Trip {
+                route: routes
+                    .get(table.get(row, "route_id"))
+                    .unwrap_or(&"")
+                    .to_string(),
+                headsign: (!headsign.is_empty()).then(|| headsign.to_string()),
+                service: table.get(row, "service_id").to_string(),
+            });
+        }
+
+        let table = Table::read(&dir.join("stop_times.txt"))?;
+        let mut stop_times: HashMap<usize, Vec<(u32, usize, u32, u32)>> = HashMap::new();
+        for row in &table.rows {
+            let Some(&trip) = trip_indices.get(table.get(row, "trip_id")) else {
+                continue;
+            };
+            let Some(&stop) = stop_indices.get(table.get(row, "stop_id")) else {
+                continue;
+            };
+            let (Some(arrival), Some(departure), Ok(sequence)) = (
+                parse_time(table.get(row, "arrival_time")),
+                parse_time(table.get(row, "departure_time")),
+                table.get(row, "stop_sequence").parse(),
+            ) else {
+                continue;
+            };
+            stop_times
+                .entry(trip)
+                .or_default()
+                .push((sequence, stop, arrival, departure));
+        }
+        let mut connections = vec![];
+        for (trip, mut times) in stop_times {
+            times.sort_unstable();
+            connections.extend(times.windows(2).map(|pair| Connection {
+                from: pair[0].1,
+                to: pair[1].1,
+                departure: pair[0].3,
+                arrival: pair[1].2,
+                trip,
+            }));
+        }
+        connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+
+        let table = Table::read_optional(&dir.join("calendar.txt"))?;
+        let calendars = table
+            .rows
+            .iter()
+            .map(|row| {
+                let day = |column| table.get(row, column) == "1";
+                let calendar = Calendar {
+                    weekdays: [
+                        day("monday"),
+                        day("tuesday"),
+                        day("wednesday"),
+                        day("thursday"),
+                        day("friday"),
+                        day("saturday"),
+                        day("sunday"),
+                    ],
+                    start: table.get(row, "start_date").parse().unwrap_or(0),
+                    end: table.get(row, "end_date").parse().unwrap_or(u32::MAX),
+                };
+                (table.get(row, "service_id").to_string(), calendar)
+            })
+            .collect();
+
+        let table = Table::read_optional(&dir.join("calendar_dates.txt"))?;
+        let exceptions = table
+            .rows
+            .iter()
+            .filter_map(|row| {
+                let date = table.get(row, "date").parse().ok()?;
+                let added = table.get(row, "exception_type") == "1";
+                Some(((table.get(row, "service_id").to_string(), date), added))
+            })
+            .collect();
+
+        Ok(Feed {
+            stops,
+            trips,
+            connections,
+            calendars,
+            exceptions,
+        })
+    }
+
+    /// Whether `trip` runs on `date`, like 20230512, a `weekday` from 0 for Monday.
+    pub fn runs(&self, trip: usize, date: u32, weekday: u32) -> bool {
+        let service = &self.trips[trip].service;
+        if let Some(&added) = self.exceptions.get(&(service.clone(), date)) {
+            return added;
+        }
+        self.calendars.get(service).is_some_and(|calendar| {
+            calendar.weekdays[weekday as usize] && (calendar.start..=calendar.end).contains(&date)
+        })
+    }
+
+    /// The stops within `radius` meters of `point`, with their distance.
+    pub fn stops_near(&self, point: &LatLon, radius: i32) -> Vec<(usize, i32)> {
+        self.stops
+            .iter()
+            .enumerate()
+            .map(|(i, stop)| (i, point.distance(&stop.location)))
+            .filter(|&(_, distance)| distance <= radius)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_feed(dir: &Path) {
+    let files = [
+        (
+            "stops.txt",
+            "stop_id,stop_name,stop_lat,stop_lon\n\
+             a,\"Gare A\",45.50,-73.60\n\
+             b,Gare B,45.50,-73.50\n\
+             c,Gare C,45.50,-73.40\n",
+        ),
+        (
+            "routes.txt",
+            "route_id,route_short_name,route_long_name\n1,exo 1,\n2,,Ligne 2\n",
+        ),
+        (
+            "trips.txt",
+            "route_id,service_id,trip_id,trip_headsign,bikes_allowed\n\
+             1,week,t1,Gare B,1\n\
+             2,week,t2,Gare C,1\n\
+             2,week,t3,Gare C,2\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+             t1,08:00:00,08:00:00,a,1\n\
+             t1,08:20:00,08:20:00,b,2\n\
+             t2,08:30:00,08:30:00,b,1\n\
+             t2,08:50:00,08:50:00,c,2\n\
+             t3,08:21:00,08:21:00,b,1\n\
+             t3,08:40:00,08:40:00,c,2\n",
+        ),
+        (
+            "calendar.txt",
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,\
+             start_date,end_date\n\
+             week,1,1,1,1,1,0,0,20230101,20231231\n",
+        ),
+    ];
+    std::fs::create_dir_all(dir).unwrap();
+    for (name, content) in files {
+        std::fs::write(dir.join(name), content).unwrap();
+    }
+}
+
+#[test]
+fn keeps_the_trips_taking_bikes() {
+    let dir = std::env::temp_dir().join("routing-server-gtfs-load");
+    test_feed(&dir);
+    let feed = Feed::load(&dir).unwrap();
+    assert_eq!(feed.stops[0].name, "Gare A");
+    assert_eq!(feed.trips.len(), 2);
+    assert_eq!(feed.trips[1].route, "Ligne 2");
+    assert_eq!(feed.connections.len(), 2);
+    // Friday, then Saturday
+    assert!(feed.runs(0, 20230512, 4));
+    assert!(!feed.runs(0, 20230513, 5));
+}
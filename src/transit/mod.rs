@@ -0,0 +1,182 @@
+//! Bike and transit itineraries: riding to a station, taking trains or buses that
+//! allow bikes, with transfers, and riding from the last station to the end. The
+//! timetables come from the GTFS feed at `GTFS_PATH`.
+//!
+//! Only the service day of the departure is searched, and transfers are between
+//! trips at the same stop.
+
+pub mod gtfs;
+pub mod scan;
+
+use crate::{
+    config::CONFIG,
+    data::{conditional::LocalTime, node::Node},
+    error::{FieldError, RouteError},
+    route::{LatLon, RouteRequest},
+    segment::way_segments,
+};
+use actix_web::{post, web, HttpResponse, Responder};
+use gtfs::Feed;
+use serde::Serialize;
+use std::{path::Path, sync::OnceLock};
+
+/// How far from the start and end the stations may be, in meters.
+const ACCESS_RADIUS: i32 = 3000;
+
+/// How much longer riding is than the straight-line distance, to pick stations
+/// before routing to them.
+const DETOUR_FACTOR: f64 = 1.3;
+
+static FEED: OnceLock<Option<Feed>> = OnceLock::new();
+
+/// The feed at `GTFS_PATH`, loaded on the first call.
+pub fn feed() -> Option<&'static Feed> {
+    FEED.get_or_init(|| {
+        let path = CONFIG.gtfs_path.as_ref()?;
+        match Feed::load(Path::new(path)) {
+            Ok(feed) => Some(feed),
+            Err(e) => {
+                eprintln!("Cannot load the GTFS feed: {e}");
+                None
+            }
+        }
+    })
+    .as_ref()
+}
+
+#[derive(Serialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Leg {
+    Walk {
+        path: Vec<LatLon>,
+        /// In meters.
+        distance: i32,
+        /// In seconds.
+        duration: i32,
+    },
+    Bike {
+        path: Vec<LatLon>,
+        /// In meters.
+        distance: i32,
+        /// In seconds.
+        duration: i32,
+    },
+    Transit {
+        route: String,
+        headsign: Option<String>,
+        from: String,
+        to: String,
+        departure_time: LocalTime,
+        arrival_time: LocalTime,
+    },
+}
+
+#[derive(Serialize)]
+pub struct Itinerary {
+    pub legs: Vec<Leg>,
+    pub departure_time: LocalTime,
+    pub arrival_time: LocalTime,
+}
+
+/// The bike leg from `start` to `end`, leaving at `departure`, with its duration.
+pub(crate) async fn bike_leg(
+    request: &RouteRequest,
+    start: &LatLon,
+    end: &LatLon,
+    departure: Option<LocalTime>,
+) -> Result<(Leg, i32), RouteError> {
+    let request = RouteRequest {
+        start: start.clone(),
+        end: end.clone(),
+        departure_time: departure,
+        ..request.clone()
+    };
+    let region = request.region().await?;
+    let (path, _cost) = Node::route(region, &request).await?;
+    let duration = request.duration(&path);
+    let leg = Leg::Bike {
+        distance: way_segments(&path).iter().map(|way| way.length).sum(),
+        path: path.iter().map(LatLon::from).collect(),
+        duration,
+    };
+    Ok((leg, duration))
+}
+
+/// Finds the itinerary arriving the earliest, leaving at the `departure_time`.
+pub async fn itinerary(request: &RouteRequest) -> Result<Itinerary, RouteError> {
+    request.validate()?;
+    let Some(departure) = request.departure_time else {
+        return Err(RouteError::InvalidRequest {
+            errors: vec![FieldError {
+                field: "departure_time".to_string(),
+                message: "is required for transit routes".to_string(),
+            }],
+        });
+    };
+    // Unloadable, the feed is as missing until it is fixed and the server restarted
+    let feed = feed().ok_or_else(|| RouteError::NotConfigured {
+        feature: "GTFS feed".to_string(),
+    })?;
+    let speed = request
+        .cruising_speed_kmh
+        .map_or(request.model.profile().speed, |speed| speed / 3.6);
+    let riding = |distance: i32| (distance as f64 * DETOUR_FACTOR / speed) as u32;
+    let start_time = departure.minutes * 60;
+    let origins: Vec<(usize, u32)> = feed
+        .stops_near(&request.start, ACCESS_RADIUS)
+        .into_iter()
+        .map(|(stop, distance)| (stop, start_time + riding(distance)))
+        .collect();
+    let destinations: Vec<(usize, u32)> = feed
+        .stops_near(&request.end, ACCESS_RADIUS)
+        .into_iter()
+        .map(|(stop, distance)| (stop, riding(distance)))
+        .collect();
+    let date = departure.year as u32 * 10_000 + departure.month * 100 + departure.day;
+    let (rides, _) =
+        scan::earliest_arrival(feed, &origins, &destinations, date, departure.weekday())
+            .ok_or(RouteError::NoTransitItinerary)?;
+
+    let midnight = LocalTime {
+        minutes: 0,
+        ..departure
+    };
+    let at = |seconds: u32| midnight.plus_seconds(seconds as i64);
+    let mut legs = vec![];
+    let mut location = request.start.clone();
+    let mut time = departure;
+    for ride in &rides {
+        let (from, to) = (&feed.stops[ride.from], &feed.stops[ride.to]);
+        if location.distance(&from.location) > 0 {
+            let (leg, _) = bike_leg(request, &location, &from.location, Some(time)).await?;
+            legs.push(leg);
+        }
+        let trip = &feed.trips[ride.trip];
+        legs.push(Leg::Transit {
+            route: trip.route.clone(),
+            headsign: trip.headsign.clone(),
+            from: from.name.clone(),
+            to: to.name.clone(),
+            departure_time: at(ride.departure),
+            arrival_time: at(ride.arrival),
+        });
+        location = to.location.clone();
+        time = at(ride.arrival);
+    }
+    let (leg, duration) = bike_leg(request, &location, &request.end, Some(time)).await?;
+    legs.push(leg);
+    Ok(Itinerary {
+        legs,
+        departure_time: departure,
+        arrival_time: time.plus_seconds(duration as i64),
+    })
+}
+
+/// Takes the options of `POST /route`, the `departure_time` being required.
+#[post("/route/transit")]
+async fn transit_route(request: web::Json<RouteRequest>) -> Result<impl Responder, RouteError> {
+    let mut request = request.into_inner();
+    request.take_coordinates();
+    let itinerary = itinerary(&request).await?;
+    Ok(HttpResponse::Ok().json(itinerary))
+}
@@ -0,0 +1,113 @@
+//! The earliest arrival search over the transit connections, by the Connection Scan
+//! Algorithm: connections are scanned once in departure order, which handles
+//! transfers between trips at the same stop.
+//! https://arxiv.org/abs/1703.05997
+
+use super::gtfs::Feed;
+
+/// The time to get on a train with a bike, in seconds.
+pub const BOARDING_TIME: u32 = 120;
+
+/// The time to change trips at a stop, in seconds.
+pub const TRANSFER_TIME: u32 = 180;
+
+/// A ride on a trip, between the stops where it is boarded and left.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ride {
+    pub trip: usize,
+    pub from: usize,
+    pub to: usize,
+    pub departure: u32,
+    pub arrival: u32,
+}
+
+/// The rides reaching the end the earliest, and when the end is reached.
+///
+/// `origins` are the stops reached from the start with the time they are reached,
+/// `destinations` the stops the end is reached from with how long it takes, all in
+/// seconds after midnight of `date` (like 20230512), `weekday` from 0 for Monday.
+pub fn earliest_arrival(
+    feed: &Feed,
+    origins: &[(usize, u32)],
+    destinations: &[(usize, u32)],
+    date: u32,
+    weekday: u32,
+) -> Option<(Vec<Ride>, u32)> {
+    // When a trip can be boarded from each stop
+    let mut ready = vec![u32::MAX; feed.stops.len()];
+    for &(stop, time) in origins {
+        ready[stop] = ready[stop].min(time + BOARDING_TIME);
+    }
+    let egress: Vec<Option<u32>> = {
+        let mut egress = vec![None; feed.stops.len()];
+        for &(stop, duration) in destinations {
+            egress[stop] = Some(duration);
+        }
+        egress
+    };
+    // The connection each boarded trip was boarded at
+    let mut boarded: Vec<Option<usize>> = vec![None; feed.trips.len()];
+    // The connections a stop was reached by, boarding and leaving the trip
+    let mut reached_by: Vec<Option<(usize, usize)>> = vec![None; feed.stops.len()];
+    let mut best: Option<(usize, u32)> = None;
+
+    let earliest = *ready.iter().min()?;
+    let first = feed.connections.partition_point(|c| c.departure < earliest);
+    for (i, connection) in feed.connections.iter().enumerate().skip(first) {
+        if best.is_some_and(|(_, arrival)| connection.departure >= arrival) {
+            break;
+        }
+        let boarded_at = match boarded[connection.trip] {
+            Some(boarded_at) => boarded_at,
+            None if ready[connection.from] <= connection.departure
+                && feed.runs(connection.trip, date, weekday) =>
+            {
+                boarded[connection.trip] = Some(i);
+                i
+            }
+            None => continue,
+        };
+        let to = connection.to;
+        if connection.arrival + TRANSFER_TIME < ready[to] {
+            ready[to] = connection.arrival + TRANSFER_TIME;
+            reached_by[to] = Some((boarded_at, i));
+            if let Some(egress) = egress[to] {
+                let arrival = connection.arrival + egress;
+                if best.is_none_or(|(_, best)| arrival < best) {
+                    best = Some((to, arrival));
+                }
+            }
+        }
+    }
+
+    let (mut stop, arrival) = best?;
+    let mut rides = vec![];
+    while let Some((boarded_at, left_at)) = reached_by[stop] {
+        let (boarding, leaving) = (&feed.connections[boarded_at], &feed.connections[left_at]);
+        rides.push(Ride {
+            trip: boarding.trip,
+            from: boarding.from,
+            to: stop,
+            departure: boarding.departure,
+            arrival: leaving.arrival,
+        });
+        stop = boarding.from;
+    }
+    rides.reverse();
+    Some((rides, arrival))
+}
+
+#[test]
+fn transfers_between_trips_taking_bikes() {
+    let dir = std::env::temp_dir().join("routing-server-gtfs-scan");
+    super::gtfs::test_feed(&dir);
+    let feed = Feed::load(&dir).unwrap();
+    let (rides, arrival) =
+        earliest_arrival(&feed, &[(0, 7 * 3600 + 50 * 60)], &[(2, 600)], 20230512, 4).unwrap();
+    // The 8:21 from Gare B does not take bikes, the 8:30 does
+    let stops: Vec<(usize, usize)> = rides.iter().map(|r| (r.from, r.to)).collect();
+    assert_eq!(stops, vec![(0, 1), (1, 2)]);
+    assert_eq!(arrival, 9 * 3600);
+    assert!(earliest_arrival(&feed, &[(0, 7 * 3600 + 59 * 60)], &[(2, 600)], 20230512, 4)
+        .is_none());
+}
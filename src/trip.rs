@@ -0,0 +1,403 @@
+//! `POST /trip`: the order to visit stops in, for bicycle couriers and cargo-bike
+//! deliveries, with the route through them and when each stop is reached. The costs
+//! between each two stops are searched like a matrix, then the order is found by
+//! nearest neighbor and improved by 2-opt and moving stops, keeping to the time
+//! windows of the stops first.
+
+use crate::{
+    config::CONFIG,
+    data::{conditional::LocalTime, node::Node},
+    error::{FieldError, RouteError},
+    route::{route_body, LatLon, Model, RouteBody, RouteRequest, RouteResponse},
+};
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use utoipa::ToSchema;
+
+/// The most stops of a trip, the matrix searching from each of them.
+const MAX_STOPS: usize = 25;
+
+/// The cost between stops without a route, high enough to never be chosen when
+/// there is another order, low enough to add up without overflowing.
+const UNREACHABLE: i64 = i64::MAX / (MAX_STOPS as i64 + 1);
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TripStop {
+    #[serde(flatten)]
+    pub point: LatLon,
+    /// The local time the stop may be reached from, waiting until then otherwise.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub earliest: Option<LocalTime>,
+    /// The local time the stop must be reached by.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub latest: Option<LocalTime>,
+    /// How long is spent at the stop, like unloading, in seconds.
+    #[serde(default)]
+    pub service_s: i32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TripRequest {
+    /// The stops to visit, starting at the first one.
+    pub stops: Vec<TripStop>,
+    /// Whether to come back to the first stop after the last one.
+    #[serde(default)]
+    pub roundtrip: bool,
+    #[serde(default)]
+    pub model: Model,
+    /// The region to route in, by default the one of the first pair of stops.
+    #[serde(default)]
+    pub region: Option<String>,
+    /// The local time of departure from the first stop, required by the time
+    /// windows.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub departure_time: Option<LocalTime>,
+}
+
+/// When a stop of the trip is reached and left.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct ScheduledStop {
+    /// The index of the stop in the request.
+    pub stop: usize,
+    /// When the stop is reached, in seconds since the departure.
+    pub arrival: i32,
+    /// When the stop is left, after waiting for its window and its service, in
+    /// seconds since the departure.
+    pub departure: i32,
+    #[schema(value_type = Option<String>)]
+    pub arrival_time: Option<LocalTime>,
+    #[schema(value_type = Option<String>)]
+    pub departure_time: Option<LocalTime>,
+    /// How long after the end of its window the stop is reached, in seconds.
+    pub late: i32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TripResponse {
+    /// The indices of the stops in the order to visit them, the first stop first.
+    pub order: Vec<usize>,
+    /// The stops in that order, back to the first one on a roundtrip. The order
+    /// keeps to the windows when it can, the stops still late otherwise.
+    pub schedule: Vec<ScheduledStop>,
+    /// The route through the stops in that order, with a leg between each two.
+    pub route: RouteResponse,
+}
+
+impl TripRequest {
+    fn validate(&self) -> Result<(), RouteError> {
+        let mut errors = vec![];
+        if !(2..=MAX_STOPS).contains(&self.stops.len()) {
+            errors.push(FieldError {
+                field: "stops".to_string(),
+                message: format!("must have between 2 and {MAX_STOPS} stops"),
+            });
+        }
+        for (i, stop) in self.stops.iter().enumerate() {
+            stop.point.validate(&format!("stops[{i}]"), &mut errors);
+            if let (Some(earliest), Some(latest)) = (stop.earliest, stop.latest) {
+                if latest.seconds_since(&earliest) < 0 {
+                    errors.push(FieldError {
+                        field: format!("stops[{i}].latest"),
+                        message: "must not be before earliest".to_string(),
+                    });
+                }
+            }
+            if stop.service_s < 0 {
+                errors.push(FieldError {
+                    field: format!("stops[{i}].service_s"),
+                    message: "must not be negative".to_string(),
+                });
+            }
+        }
+        let windows = self.stops.iter().any(|s| s.earliest.is_some() || s.latest.is_some());
+        if windows && self.departure_time.is_none() {
+            errors.push(FieldError {
+                field: "departure_time".to_string(),
+                message: "is required by the time windows".to_string(),
+            });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(RouteError::InvalidRequest { errors })
+        }
+    }
+
+    /// The request of the route from `start` to `end`, through the `waypoints`.
+    fn request(&self, start: &LatLon, end: &LatLon, waypoints: Vec<LatLon>) -> RouteRequest {
+        RouteRequest {
+            start: start.clone(),
+            end: end.clone(),
+            waypoints,
+            model: self.model.clone(),
+            region: self.region.clone(),
+            departure_time: self.departure_time,
+            detailed: true,
+            ..Default::default()
+        }
+    }
+
+    /// The window of each stop and its service duration, in seconds since the
+    /// departure.
+    fn windows(&self) -> Vec<Window> {
+        let since = |time: Option<LocalTime>| {
+            time.zip(self.departure_time)
+                .map(|(time, departure)| time.seconds_since(&departure) as i32)
+        };
+        self.stops
+            .iter()
+            .map(|stop| Window {
+                earliest: since(stop.earliest),
+                latest: since(stop.latest),
+                service: stop.service_s,
+            })
+            .collect()
+    }
+}
+
+/// The time window of a stop, in seconds since the departure.
+#[derive(Clone, Copy, Debug, Default)]
+struct Window {
+    earliest: Option<i32>,
+    latest: Option<i32>,
+    service: i32,
+}
+
+/// The costs and durations of the routes from each stop to each other, the costs
+/// `UNREACHABLE` without one. The searches from all the stops share the time of a
+/// single search.
+async fn matrix(request: &TripRequest) -> Result<(Vec<Vec<i64>>, Vec<Vec<i32>>), RouteError> {
+    let deadline = Instant::now() + CONFIG.search_timeout;
+    let stops = &request.stops;
+    let options = request.request(&stops[0].point, &stops[1].point, vec![]);
+    let region = options.region().await?;
+    let points: Vec<LatLon> = stops.iter().map(|stop| stop.point.clone()).collect();
+    let routes = Node::routes_between(region, &options, &points, deadline).await?;
+    let mut costs = vec![vec![0; stops.len()]; stops.len()];
+    let mut durations = vec![vec![0; stops.len()]; stops.len()];
+    for (i, from_stop) in routes.iter().enumerate() {
+        for (j, route) in from_stop.iter().enumerate() {
+            match route {
+                _ if i == j => {}
+                Some((path, cost)) => {
+                    costs[i][j] = *cost;
+                    durations[i][j] = options.duration(path);
+                }
+                None => costs[i][j] = UNREACHABLE,
+            }
+        }
+    }
+    Ok((costs, durations))
+}
+
+/// The cost of visiting the stops in `order`, back to the first one on a `roundtrip`.
+fn tour_cost(costs: &[Vec<i64>], order: &[usize], roundtrip: bool) -> i64 {
+    let back = match (order.first(), order.last()) {
+        (Some(&first), Some(&last)) if roundtrip => costs[last][first],
+        _ => 0,
+    };
+    order.windows(2).map(|pair| costs[pair[0]][pair[1]]).sum::<i64>() + back
+}
+
+/// When the stops are reached and left visiting them in `order`, leaving the first
+/// one at 0, with the arrival back at it on a `roundtrip`.
+fn schedule(
+    durations: &[Vec<i32>],
+    windows: &[Window],
+    order: &[usize],
+    roundtrip: bool,
+) -> Vec<ScheduledStop> {
+    let mut stops: Vec<ScheduledStop> = vec![];
+    let home = order.first().filter(|_| roundtrip);
+    for &stop in order.iter().chain(home) {
+        let arrival = stops.last().map_or(0, |previous| {
+            previous.departure + durations[previous.stop][stop]
+        });
+        let back = stops.len() == order.len();
+        let window = if back { Window::default() } else { windows[stop] };
+        let start = window.earliest.map_or(arrival, |earliest| arrival.max(earliest));
+        stops.push(ScheduledStop {
+            stop,
+            arrival,
+            departure: start + window.service,
+            arrival_time: None,
+            departure_time: None,
+            late: window.latest.map_or(0, |latest| (arrival - latest).max(0)),
+        });
+    }
+    stops
+}
+
+/// How good visiting the stops in `order` is, the lowest best: reaching every stop
+/// first, since the legs without a route take no time and would make the stops
+/// after them look on time, then keeping to the windows, then the cost.
+fn rank(
+    costs: &[Vec<i64>],
+    durations: &[Vec<i32>],
+    windows: &[Window],
+    order: &[usize],
+    roundtrip: bool,
+) -> (bool, i32, i64) {
+    let cost = tour_cost(costs, order, roundtrip);
+    let late: i32 = schedule(durations, windows, order, roundtrip)
+        .iter()
+        .map(|stop| stop.late)
+        .sum();
+    (cost >= UNREACHABLE, late, cost)
+}
+
+/// A near-optimal order to visit the stops of the `costs` matrix in, starting at
+/// the first one, the lowest by `key` among the orders tried. The costs may differ
+/// both ways, along one-way streets.
+fn solve<K: Ord>(costs: &[Vec<i64>], key: impl Fn(&[usize]) -> K) -> Vec<usize> {
+    let mut order = vec![0];
+    let mut left: Vec<usize> = (1..costs.len()).collect();
+    while let Some(&last) = order.last() {
+        let Some(nearest) = (0..left.len()).min_by_key(|&i| costs[last][left[i]]) else {
+            break;
+        };
+        order.push(left.swap_remove(nearest));
+    }
+    let mut best = key(&order);
+    let mut improved = true;
+    while improved {
+        improved = false;
+        // 2-opt, reversing the stops between i and j
+        for i in 1..order.len() {
+            for j in i + 1..order.len() {
+                order[i..=j].reverse();
+                let cost = key(&order);
+                if cost < best {
+                    (best, improved) = (cost, true);
+                } else {
+                    order[i..=j].reverse();
+                }
+            }
+        }
+        // Moving the stop at i to j
+        for i in 1..order.len() {
+            for j in 1..order.len() {
+                let stop = order.remove(i);
+                order.insert(j, stop);
+                let cost = key(&order);
+                if cost < best {
+                    (best, improved) = (cost, true);
+                } else {
+                    let stop = order.remove(j);
+                    order.insert(i, stop);
+                }
+            }
+        }
+    }
+    order
+}
+
+/// Finds the order to visit the stops in and the route through them, not saved.
+#[utoipa::path(
+    request_body = TripRequest,
+    responses(
+        (status = 200, description = "The order of the stops and the route", body = TripResponse),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 422, description = "No route can be searched", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody),
+    )
+)]
+#[post("/trip")]
+async fn trip(request: web::Json<TripRequest>) -> Result<impl Responder, RouteError> {
+    let request = request.into_inner();
+    request.validate()?;
+    let (costs, durations) = matrix(&request).await?;
+    let (windows, roundtrip) = (request.windows(), request.roundtrip);
+    let order = solve(&costs, |order| rank(&costs, &durations, &windows, order, roundtrip));
+    if tour_cost(&costs, &order, roundtrip) >= UNREACHABLE {
+        return Err(RouteError::NoRouteFound);
+    }
+    let mut schedule = schedule(&durations, &windows, &order, roundtrip);
+    if let Some(departure) = request.departure_time {
+        for stop in &mut schedule {
+            stop.arrival_time = Some(departure.plus_seconds(stop.arrival as i64));
+            stop.departure_time = Some(departure.plus_seconds(stop.departure as i64));
+        }
+    }
+    let mut stops: Vec<LatLon> = order.iter().map(|&i| request.stops[i].point.clone()).collect();
+    if roundtrip {
+        stops.push(request.stops[0].point.clone());
+    }
+    let (start, end) = (&stops[0], &stops[stops.len() - 1]);
+    let route = request.request(start, end, stops[1..stops.len() - 1].to_vec());
+    let region = route.region().await?;
+    match route_body(region, route, |_| {}).await? {
+        RouteBody::Detailed(route) => Ok(HttpResponse::Ok().json(TripResponse {
+            order,
+            schedule,
+            route: *route,
+        })),
+        RouteBody::Path(_) => Err(RouteError::Internal {
+            message: "Undetailed route".to_string(),
+        }),
+    }
+}
+
+#[test]
+fn orders_the_stops_along_the_one_way_streets() {
+    // Going to the nearest stop first, 1, leaves 2 to reach the long way
+    let costs = vec![
+        vec![0, 5, 6, 50],
+        vec![50, 0, 100, 6],
+        vec![50, 6, 0, 100],
+        vec![50, 50, 50, 0],
+    ];
+    assert_eq!(tour_cost(&costs, &[0, 1, 3, 2], false), 61);
+    assert_eq!(solve(&costs, |order| tour_cost(&costs, order, false)), vec![0, 2, 1, 3]);
+    assert_eq!(tour_cost(&costs, &[0, 2, 1, 3], true), 68);
+    let request: TripRequest =
+        serde_json::from_str(r#"{"stops": [{"lat": 45.5, "lng": -73.57}]}"#).unwrap();
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn keeps_to_the_time_windows_of_the_stops() {
+    let durations = vec![vec![0, 600, 500], vec![600, 0, 300], vec![500, 300, 0]];
+    let costs: Vec<Vec<i64>> = durations
+        .iter()
+        .map(|row| row.iter().map(|&duration| duration as i64).collect())
+        .collect();
+    let mut windows = vec![Window::default(); 3];
+    // 2 is the nearest but opens at 0:20, 1 must be reached by 0:15
+    windows[2].earliest = Some(1200);
+    windows[1] = Window {
+        latest: Some(900),
+        service: 120,
+        ..Window::default()
+    };
+    let order = solve(&costs, |order| rank(&costs, &durations, &windows, order, true));
+    assert_eq!(order, vec![0, 1, 2]);
+    let visits: Vec<_> = schedule(&durations, &windows, &order, true)
+        .iter()
+        .map(|stop| (stop.stop, stop.arrival, stop.departure, stop.late))
+        .collect();
+    assert_eq!(
+        visits,
+        vec![(0, 0, 0, 0), (1, 600, 720, 0), (2, 1020, 1200, 0), (0, 1700, 1700, 0)]
+    );
+    assert_eq!(schedule(&durations, &windows, &[0, 2, 1], true)[2].late, 600);
+}
+
+#[test]
+fn reaches_every_stop_before_keeping_to_the_windows() {
+    // 1 cannot reach 2, which would take no time and bring 2 on time
+    let durations = vec![vec![0, 600, 1000], vec![600, 0, 0], vec![600, 600, 0]];
+    let mut costs: Vec<Vec<i64>> = durations
+        .iter()
+        .map(|row| row.iter().map(|&duration| duration as i64).collect())
+        .collect();
+    costs[1][2] = UNREACHABLE;
+    let mut windows = vec![Window::default(); 3];
+    windows[2].latest = Some(700);
+    let order = solve(&costs, |order| rank(&costs, &durations, &windows, order, false));
+    assert_eq!(order, vec![0, 2, 1]);
+}
@@ -0,0 +1,124 @@
+//! The recent weather, from a forecast API in the Open-Meteo format, so that routes
+//! avoid the unplowed ways after snowfall and the muddy ones after heavy rain, and
+//! the exposed ones into a strong headwind.
+//! https://open-meteo.com/en/docs
+
+use crate::config::CONFIG;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, time::Duration};
+use utoipa::ToSchema;
+
+/// The snowfall over the last two days above which it is winter, in centimeters.
+const SNOWFALL_THRESHOLD: f64 = 1.0;
+
+/// The rain over the last two days above which unpaved ways are muddy, in millimeters.
+const RAIN_THRESHOLD: f64 = 10.0;
+
+/// How long the searches may wait for the forecast, before going on in clear weather.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct Wind {
+    pub speed_kmh: f64,
+    /// Where the wind blows from, in degrees clockwise from north.
+    pub from_degrees: f64,
+}
+
+impl Wind {
+    /// The wind against a rider going to `bearing`, in km/h, negative with the wind
+    /// at the back.
+    pub fn headwind(&self, bearing: f64) -> f64 {
+        self.speed_kmh * (bearing - self.from_degrees).to_radians().cos()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Weather {
+    pub snow: bool,
+    pub wet: bool,
+    /// The current wind, when the forecast has the `current_weather`.
+    pub wind: Option<Wind>,
+}
+
+#[derive(Deserialize)]
+struct Daily {
+    #[serde(default)]
+    rain_sum: Vec<Option<f64>>,
+    #[serde(default)]
+    snowfall_sum: Vec<Option<f64>>,
+}
+
+#[derive(Deserialize)]
+struct CurrentWeather {
+    windspeed: f64,
+    winddirection: f64,
+}
+
+#[derive(Deserialize)]
+struct Forecast {
+    daily: Daily,
+    current_weather: Option<CurrentWeather>,
+}
+
+impl From<Forecast> for Weather {
+    fn from(forecast: Forecast) -> Self {
+        let total = |values: &[Option<f64>]| values.iter().flatten().sum::<f64>();
+        Weather {
+            snow: total(&forecast.daily.snowfall_sum) >= SNOWFALL_THRESHOLD,
+            wet: total(&forecast.daily.rain_sum) >= RAIN_THRESHOLD,
+            wind: forecast.current_weather.map(|current| Wind {
+                speed_kmh: current.windspeed,
+                from_degrees: current.winddirection,
+            }),
+        }
+    }
+}
+
+/// The weather of yesterday and today around `lat`, `lng`, from `WEATHER_URL`
+/// with its `{lat}` and `{lng}` placeholders replaced. Clear without `WEATHER_URL`.
+pub async fn fetch(lat: f64, lng: f64) -> Result<Weather, Box<dyn Error + Send + Sync>> {
+    let Some(url) = &CONFIG.weather_url else {
+        return Ok(Weather::default());
+    };
+    let url = url
+        .replace("{lat}", &format!("{lat:.2}"))
+        .replace("{lng}", &format!("{lng:.2}"));
+    let forecast: Forecast = reqwest::Client::new()
+        .get(url)
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(forecast.into())
+}
+
+#[test]
+fn sums_the_last_days() {
+    let forecast: Forecast = serde_json::from_str(
+        r#"{"daily": {"rain_sum": [8.0, 4.5], "snowfall_sum": [0.0, null]}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        Weather::from(forecast),
+        Weather {
+            snow: false,
+            wet: true,
+            wind: None,
+        }
+    );
+}
+
+#[test]
+fn reads_the_current_wind() {
+    let forecast: Forecast = serde_json::from_str(
+        r#"{"daily": {}, "current_weather": {"windspeed": 20.0, "winddirection": 270.0}}"#,
+    )
+    .unwrap();
+    let wind = Weather::from(forecast).wind.unwrap();
+    // Riding west into the west wind, then east with it at the back
+    assert_eq!(wind.headwind(270.0), 20.0);
+    assert!((wind.headwind(90.0) + 20.0).abs() < 1e-9);
+    assert!(wind.headwind(0.0).abs() < 1e-9);
+}